@@ -12,7 +12,15 @@ use tracing::debug;
 use tracing::error;
 use tracing::warn;
 
-const NUM_AUDIO_CHANNELS: usize = 4;
+pub const NUM_AUDIO_CHANNELS: usize = 4;
+
+/// Maximum value of a channel's pan, meaning it is only heard on the right side. 0 means only on
+/// the left side.
+pub const PAN_MAX: u8 = 0x40;
+
+/// Panning used by the Amiga version of the game, which hard-panned channels 0 and 3 to the left,
+/// and channels 1 and 2 to the right.
+pub const AMIGA_PANNING: [u8; NUM_AUDIO_CHANNELS] = [0, PAN_MAX, PAN_MAX, 0];
 
 /// Header of a sound sample.
 ///
@@ -46,6 +54,9 @@ pub struct SoundSample {
 }
 
 impl SoundSample {
+    /// Size of the header preceding the sample data in sound resources.
+    pub const HEADER_LEN: usize = size_of::<SoundSampleHeader>();
+
     /// Create a new `SoundSample` by reinterpreting a resource's byte data.
     ///
     /// This is highly unsafe and must only be called on resource data which type is
@@ -62,14 +73,24 @@ impl SoundSample {
         header.len = u16::from_be(header.len);
         header.loop_len = u16::from_be(header.loop_len);
 
-        // Consistency check.
+        // Consistency check. The actual data is authoritative: the header is fixed to not describe
+        // more than what is available, so the mixer never reads beyond the sample.
         if header.len() != data_len {
             warn!(
                 "sound resource reported a length of {} bytes, but header says {}",
-                header.len(),
-                data_len
+                data_len,
+                header.len()
             );
-            data_len = std::cmp::min(header.len(), data_len);
+            // A number of words that does not fit in a header field is larger than what the
+            // field says, so it does not need to be clamped.
+            let max_len = data_len / 2;
+            if let Ok(max_len) = u16::try_from(max_len) {
+                header.len = std::cmp::min(header.len, max_len);
+            }
+            if let Ok(max_loop_len) = u16::try_from(max_len - header.len as usize) {
+                header.loop_len = std::cmp::min(header.loop_len, max_loop_len);
+            }
+            data_len = header.len();
         }
 
         let slice = core::slice::from_raw_parts(ptr as *const (), data_len);
@@ -80,9 +101,21 @@ impl SoundSample {
 
     /// Return the starting position of the loop, if any.
     pub fn loop_pos(&self) -> Option<usize> {
-        match self.header.loop_len {
+        let loop_pos = match self.header.loop_len {
             0 => None,
             _ => Some(self.header.len as usize * 2),
+        };
+        debug_assert!(loop_pos.is_none_or(|pos| pos < self.data.len()));
+
+        loop_pos
+    }
+
+    /// Returns the position of the sample data following `pos`, which is the loop point at the end
+    /// of a looping sample. The last position is returned as-is if the sample does not loop.
+    fn next_pos(&self, pos: usize) -> usize {
+        match pos + 1 {
+            next if next >= self.len() => self.loop_pos().unwrap_or(pos),
+            next => next,
         }
     }
 
@@ -161,124 +194,351 @@ pub trait MusicPlayer {
     fn resume(&mut self);
 
     fn take_value_of_0xf4(&self) -> Option<i16>;
+
+    /// Returns the index in the order table of the pattern being played, and the next line to be
+    /// played in it, or `None` if no music is playing.
+    fn playback_position(&self) -> Option<(u16, u8)>;
+    /// Returns the length of the order table of the music being played, or `None` if no music is
+    /// playing.
+    fn num_orders(&self) -> Option<u16>;
+
+    /// Mute or unmute the music. Muted music keeps playing silently, so it stays in sync with the
+    /// game.
+    fn set_music_muted(&mut self, muted: bool);
+    fn music_muted(&self) -> bool;
+}
+
+/// Audio output that discards everything, for running the game without a sound device.
+pub struct NullAudio;
+
+impl Mixer for NullAudio {
+    fn add_sample(&mut self, _id: u8, _sample: Box<SoundSample>) {}
+    fn play(&mut self, _sample_id: u8, _channel: u8, _freq: u16, _volume: u8) {}
+    fn stop(&mut self, _channel: u8) {}
+    fn reset(&mut self) {}
+}
+
+impl MusicPlayer for NullAudio {
+    fn play_music(&mut self, _music: Box<MusicModule>, _tempo: usize, _pos: u16) {}
+    fn update_tempo(&mut self, _tempo: usize) {}
+    fn stop_music(&mut self) {}
+    fn pause(&mut self) {}
+    fn resume(&mut self) {}
+    fn take_value_of_0xf4(&self) -> Option<i16> {
+        None
+    }
+    fn playback_position(&self) -> Option<(u16, u8)> {
+        None
+    }
+    fn num_orders(&self) -> Option<u16> {
+        None
+    }
+    fn set_music_muted(&mut self, _muted: bool) {}
+    fn music_muted(&self) -> bool {
+        false
+    }
+}
+
+/// How the mixer computes the values between two samples when their playback rate differs from
+/// the output rate.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum InterpolationMode {
+    /// Use the value of the previous sample, for the aliased sound of the original hardware.
+    None,
+    /// Interpolate linearly between the previous and next samples.
+    #[default]
+    Linear,
+    /// Interpolate along a cubic curve going through the surrounding samples, for a smoother
+    /// sound.
+    Cubic,
+}
+
+/// Number of output frames over which a channel is faded out when stopped, to avoid the click
+/// of the waveform being cut abruptly.
+const FADE_OUT_FRAMES: usize = 32;
+
+/// A sample being played on a channel.
+struct Voice {
+    /// ID of the sample being played.
+    sample_id: u8,
+    /// Playback volume.
+    volume: u8,
+    /// We multiply the current sample position by 256 in order to perform sub-sample
+    /// arithmetic. This is the current position times 256, plus an offset between the current
+    /// and the next sample.
+    chunk_pos: usize,
+    /// How much `chunk_pos` should be increased by unit of output. This is a function of the
+    /// sample playback rate as well as the audio output rate.
+    chunk_inc: usize,
+    /// Whether playback has wrapped around the loop point at least once.
+    looped: bool,
+}
+
+impl Voice {
+    /// Returns the next value of `sample` interpolated according to `interpolation`, before volume
+    /// is applied, and advances the playback position. Returns `None` if the end of a non-looping
+    /// sample has been reached.
+    fn next_value(
+        &mut self,
+        sample: &SoundSample,
+        interpolation: InterpolationMode,
+    ) -> Option<i16> {
+        let loop_pos = sample.loop_pos();
+        let mut sample_pos = self.chunk_pos >> 8;
+        let delta = self.chunk_pos & 0xff;
+
+        if sample_pos >= sample.len() {
+            let p = loop_pos?;
+            debug!("sample {:02x}: looping", self.sample_id);
+            sample_pos = p + sample_pos - sample.len();
+            self.chunk_pos = (sample_pos << 8) + delta;
+            self.looped = true;
+        }
+
+        let s1 = sample.data[sample_pos];
+        let s = match interpolation {
+            InterpolationMode::None => s1 as i16,
+            InterpolationMode::Linear => {
+                let s1 = s1 as isize;
+                let s2 = sample.data[sample.next_pos(sample_pos)] as isize;
+                let ilc = delta as isize;
+                ((s1 * (0x100 - ilc) + (s2 * ilc)) >> 8) as i16
+            }
+            InterpolationMode::Cubic => {
+                // The sample before the first one is considered to be the first one, and the one
+                // before the loop point is the last one once we have looped.
+                let s0_pos = match sample.loop_pos() {
+                    Some(p) if self.looped && sample_pos == p => sample.len() - 1,
+                    _ => sample_pos.saturating_sub(1),
+                };
+                let s0 = sample.data[s0_pos] as f32;
+                let s2_pos = sample.next_pos(sample_pos);
+                let s2 = sample.data[s2_pos] as f32;
+                let s3 = sample.data[sample.next_pos(s2_pos)] as f32;
+                let s1 = s1 as f32;
+                let t = delta as f32 / 256.0;
+
+                // Catmull-Rom spline going through `s1` and `s2`.
+                let a = -0.5 * s0 + 1.5 * s1 - 1.5 * s2 + 0.5 * s3;
+                let b = s0 - 2.5 * s1 + 2.0 * s2 - 0.5 * s3;
+                let c = -0.5 * s0 + 0.5 * s2;
+                let s = ((a * t + b) * t + c) * t + s1;
+                // The curve can overshoot the range of the samples around sharp edges.
+                s.round().clamp(i8::MIN as f32, i8::MAX as f32) as i16
+            }
+        };
+
+        self.chunk_pos += self.chunk_inc;
+
+        Some(s)
+    }
 }
 
 /// Single channel or a mixer, which can currently be playing something or not.
+#[derive(Default)]
 enum MixerChannel {
     /// Nothing is being played on this channel.
+    #[default]
     Inactive,
     /// Something is being played on this channel.
-    Active {
-        /// ID of the sample currently being played.
-        sample_id: u8,
-        /// Playback volume.
-        volume: u8,
-        /// We multiply the current sample position by 256 in order to perform sub-sample
-        /// arithmetic. This is the current position times 256, plus an offset between the current
-        /// and the next sample.
-        chunk_pos: usize,
-        /// How much `chunk_pos` should be increased by unit of output. This is a function of the
-        /// sample playback rate as well as the audio output rate.
-        chunk_inc: usize,
+    Active(Voice),
+    /// Something is being faded out on this channel, after which `next` starts playing if set.
+    Stopping {
+        voice: Voice,
+        /// Number of output frames left before the fade out completes.
+        remaining: usize,
+        /// Voice preempting the one being faded out.
+        next: Option<Voice>,
     },
 }
 
-impl Default for MixerChannel {
-    fn default() -> Self {
-        Self::Inactive
+impl MixerChannel {
+    /// Stop the voice being played, starting the preempting one if any.
+    fn end_voice(&mut self) {
+        *self = match std::mem::take(self) {
+            MixerChannel::Stopping {
+                next: Some(next), ..
+            } => MixerChannel::Active(next),
+            _ => MixerChannel::Inactive,
+        };
+    }
+
+    /// Start fading out the voice being played, and play `next` once this is done.
+    fn fade_out(&mut self, next: Option<Voice>) {
+        *self = match std::mem::take(self) {
+            MixerChannel::Inactive => match next {
+                Some(next) => MixerChannel::Active(next),
+                None => MixerChannel::Inactive,
+            },
+            MixerChannel::Active(voice) => MixerChannel::Stopping {
+                voice,
+                remaining: FADE_OUT_FRAMES,
+                next,
+            },
+            MixerChannel::Stopping {
+                voice, remaining, ..
+            } => MixerChannel::Stopping {
+                voice,
+                remaining,
+                next,
+            },
+        };
     }
 }
 
+/// Mix `v` into the output sample `c`, clamping the result into the valid range.
+fn mix_sample(c: &mut i8, v: i16) {
+    let b = v + *c as i16;
+    *c = match b {
+        v if v < i8::MIN as i16 => i8::MIN,
+        v if v > i8::MAX as i16 => i8::MAX,
+        _ => b as i8,
+    };
+}
+
 impl ClassicMixer {
     /// Fill `out` with the next chunk of mixed audio from all our active channels.
+    ///
+    /// If the mixer is in stereo mode, `out` is filled with interleaved left and right samples.
     #[tracing::instrument(level = "debug", skip(self, out), fields(size = out.len(), buffer = tracing::field::debug(out.as_ptr())))]
     fn fill_buffer(&mut self, out: &mut [i8]) {
+        let frame_len = if self.stereo { 2 } else { 1 };
+
         for (ch_id, channel) in &mut self.channels.iter_mut().enumerate() {
-            if let MixerChannel::Active {
-                sample_id,
-                volume,
-                chunk_pos,
-                chunk_inc,
-            } = channel
-            {
-                let sample = match self.samples.get(sample_id) {
+            let pan = self.pan[ch_id] as i16;
+
+            for frame in out.chunks_exact_mut(frame_len) {
+                let (voice, fade) = match channel {
+                    MixerChannel::Inactive => break,
+                    MixerChannel::Active(voice) => (voice, FADE_OUT_FRAMES),
+                    MixerChannel::Stopping {
+                        voice, remaining, ..
+                    } => (voice, *remaining),
+                };
+
+                let sample = match self.samples.get(&voice.sample_id) {
                     Some(sample) => sample,
                     None => {
-                        warn!("sample {:02x} is not loaded, aborting playback", sample_id);
-                        *channel = MixerChannel::Inactive;
+                        warn!(
+                            "sample {:02x} is not loaded, aborting playback",
+                            voice.sample_id
+                        );
+                        channel.end_voice();
                         continue;
                     }
                 };
-                let loop_pos = sample.loop_pos();
-
-                'chan: for c in out.iter_mut() {
-                    let mut sample_pos = *chunk_pos >> 8;
-                    let delta = *chunk_pos & 0xff;
-
-                    if sample_pos >= sample.len() {
-                        match loop_pos {
-                            None => {
-                                debug!("channel {}: stop as end of sample reached", ch_id);
-                                *channel = MixerChannel::Inactive;
-                                break 'chan;
-                            }
-                            Some(p) => {
-                                debug!("channel {}: looping", ch_id,);
-                                sample_pos = p + sample_pos - sample.len();
-                                *chunk_pos = (sample_pos << 8) + delta;
-                            }
-                        }
+                let s = match voice.next_value(sample, self.interpolation) {
+                    Some(s) => s,
+                    None => {
+                        debug!("channel {}: stop as end of sample reached", ch_id);
+                        channel.end_voice();
+                        continue;
                     }
+                };
+
+                // Apply volume and fade out.
+                let v = s * voice.volume as i16 / 0x40 * fade as i16 / FADE_OUT_FRAMES as i16;
+                // Pan, mix and clamp.
+                match frame {
+                    [c] => mix_sample(c, v),
+                    [l, r] => {
+                        mix_sample(l, v * (PAN_MAX as i16 - pan) / PAN_MAX as i16);
+                        mix_sample(r, v * pan / PAN_MAX as i16);
+                    }
+                    _ => unreachable!(),
+                }
 
-                    // Get following sample for interpolation.
-                    let next_sample_pos = match sample_pos + 1 {
-                        pos if pos >= sample.len() => match loop_pos {
-                            None => sample_pos,
-                            Some(p) => p,
-                        },
-                        pos => pos,
-                    };
-
-                    // Interpolate.
-                    let ilc = (*chunk_pos & 0xff) as isize;
-                    let s1 = sample.data[sample_pos] as isize;
-                    let s2 = sample.data[next_sample_pos] as isize;
-                    let s = (s1 * (0x100 - ilc) + (s2 * ilc)) >> 8;
-                    // Apply volume.
-                    let v = s as i16 * *volume as i16 / 0x40;
-                    // Mix and clamp.
-                    let b = v + *c as i16;
-                    *c = match b {
-                        v if v < i8::MIN as i16 => i8::MIN,
-                        v if v > i8::MAX as i16 => i8::MAX,
-                        _ => b as i8,
-                    };
-
-                    *chunk_pos += *chunk_inc;
+                if let MixerChannel::Stopping { remaining, .. } = channel {
+                    *remaining -= 1;
+                    if *remaining == 0 {
+                        channel.end_voice();
+                    }
                 }
             }
         }
     }
 }
 
+/// State of a mixer channel playing a sample, as returned by [`ClassicMixer::channel_snapshot`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelInfo {
+    /// ID of the sample being played.
+    pub sample_id: u8,
+    /// Playback volume.
+    pub volume: u8,
+    /// Position of the next value of the sample to be played.
+    pub position: usize,
+    /// Length of the sample, or `None` if no sample with that ID has been loaded.
+    pub len: Option<usize>,
+    /// Whether the sample is being faded out.
+    pub stopping: bool,
+}
+
 /// Basic 4-channel mixer that mimics the original behavior of the game.
 pub struct ClassicMixer {
     /// Channels that can be played onto.
     channels: [MixerChannel; NUM_AUDIO_CHANNELS],
     /// Output frequency at which we will mix.
     output_freq: u32,
+    /// Whether we produce interleaved stereo output instead of mono.
+    stereo: bool,
+    /// Pan of each channel, between 0 (left) and `PAN_MAX` (right). Only used in stereo mode.
+    pan: [u8; NUM_AUDIO_CHANNELS],
+    /// How samples are interpolated when resampled to the output frequency.
+    interpolation: InterpolationMode,
 
     samples: BTreeMap<u8, Box<SoundSample>>,
 }
 
 impl ClassicMixer {
-    pub fn new(output_freq: u32) -> Self {
+    /// Create a new mixer producing samples at `output_freq`.
+    ///
+    /// If `stereo` is `true`, the output is made of interleaved left and right samples, with the
+    /// channels panned like the Amiga version of the game did. Otherwise the output is mono like
+    /// the DOS version.
+    pub fn new(output_freq: u32, stereo: bool) -> Self {
         Self {
             channels: Default::default(),
             output_freq,
+            stereo,
+            pan: AMIGA_PANNING,
+            interpolation: Default::default(),
             samples: Default::default(),
         }
     }
+
+    /// Set the pan of `channel`, between 0 (left) and `PAN_MAX` (right).
+    #[cfg(test)]
+    pub fn set_pan(&mut self, channel: u8, pan: u8) {
+        match self.pan.get_mut(channel as usize) {
+            None => error!("invalid channel index {}", channel),
+            Some(p) => *p = std::cmp::min(pan, PAN_MAX),
+        }
+    }
+
+    /// Set how samples are interpolated when resampled to the output frequency.
+    pub fn set_interpolation(&mut self, interpolation: InterpolationMode) {
+        self.interpolation = interpolation;
+    }
+
+    /// Returns what each channel is currently playing, or `None` for inactive channels.
+    pub fn channel_snapshot(&self) -> [Option<ChannelInfo>; NUM_AUDIO_CHANNELS] {
+        std::array::from_fn(|i| {
+            let (voice, stopping) = match &self.channels[i] {
+                MixerChannel::Inactive => return None,
+                MixerChannel::Active(voice) => (voice, false),
+                MixerChannel::Stopping { voice, .. } => (voice, true),
+            };
+
+            Some(ChannelInfo {
+                sample_id: voice.sample_id,
+                volume: voice.volume,
+                position: voice.chunk_pos >> 8,
+                len: self.samples.get(&voice.sample_id).map(|s| s.len()),
+                stopping,
+            })
+        })
+    }
 }
 
 impl Mixer for ClassicMixer {
@@ -297,12 +557,14 @@ impl Mixer for ClassicMixer {
             Some(channel) => channel,
         };
 
-        *channel = MixerChannel::Active {
+        // Fade out whatever is playing before starting the new sample.
+        channel.fade_out(Some(Voice {
             sample_id,
             volume,
             chunk_inc: ((freq as usize) << 8) / self.output_freq as usize,
             chunk_pos: 8, // Skip header.
-        };
+            looped: false,
+        }));
     }
 
     #[tracing::instrument(level = "trace", skip(self))]
@@ -317,7 +579,7 @@ impl Mixer for ClassicMixer {
             Some(channel) => channel,
         };
 
-        *channel = MixerChannel::Inactive;
+        channel.fade_out(None);
     }
 
     #[tracing::instrument(level = "trace", skip(self))]
@@ -344,6 +606,21 @@ mod tests {
 
     use super::*;
 
+    /// Build a sound sample resource from `data`, with an optional loop starting at `loop_start`.
+    fn make_sample(data: &[i8], loop_start: Option<usize>) -> Box<SoundSample> {
+        let (len, loop_len) = match loop_start {
+            None => (data.len() / 2, 0),
+            Some(p) => (p / 2, (data.len() - p) / 2),
+        };
+        let mut res = Vec::new();
+        res.extend((len as u16).to_be_bytes());
+        res.extend((loop_len as u16).to_be_bytes());
+        res.extend([0u8; 4]);
+        res.extend(data.iter().map(|&s| s as u8));
+
+        unsafe { SoundSample::from_raw_resource(res) }
+    }
+
     /// Check that the layout of the [`SoundSample`] structure is as expected.
     #[test]
     fn test_sample_layout() {
@@ -351,4 +628,257 @@ mod tests {
         assert_eq!(offset_of!(SoundSampleHeader, len), 0x0);
         assert_eq!(offset_of!(SoundSampleHeader, loop_len), 0x2);
     }
+
+    /// Check that the state of the channels is reported as they start and stop playing.
+    #[test]
+    fn test_channel_snapshot() {
+        let mut mixer = ClassicMixer::new(8000, false);
+        mixer.add_sample(1, make_sample(&[0x10; 64], None));
+        mixer.play(1, 1, 8000, 0x30);
+        mixer.play(2, 2, 8000, 0x3f);
+
+        let snapshot = mixer.channel_snapshot();
+        assert_eq!(snapshot[0], None);
+        assert_eq!(
+            snapshot[1],
+            Some(ChannelInfo {
+                sample_id: 1,
+                volume: 0x30,
+                position: 0,
+                len: Some(64),
+                stopping: false,
+            })
+        );
+        assert_eq!(snapshot[2].unwrap().len, None);
+        assert_eq!(snapshot[3], None);
+
+        // Samples that are not loaded are not played.
+        mixer.fill_buffer(&mut [0i8; 16]);
+        let snapshot = mixer.channel_snapshot();
+        assert_eq!(snapshot[1].unwrap().position, 16);
+        assert_eq!(snapshot[2], None);
+
+        mixer.stop(1);
+        assert!(mixer.channel_snapshot()[1].unwrap().stopping);
+    }
+
+    /// Check that stereo output is interleaved and that hard-panned channels only output on one
+    /// side.
+    #[test]
+    fn test_stereo_panning() {
+        let mut mixer = ClassicMixer::new(8000, true);
+        mixer.add_sample(1, make_sample(&[0x40; 64], Some(0)));
+
+        // Channel 0 is hard-panned to the left.
+        mixer.play(1, 0, 8000, 0x40);
+        let mut out = [0i8; 32];
+        mixer.fill_buffer(&mut out);
+        for frame in out.chunks_exact(2) {
+            assert_eq!(frame, [0x40, 0]);
+        }
+
+        // Channel 1 is hard-panned to the right.
+        mixer.stop(0);
+        mixer.play(1, 1, 8000, 0x40);
+        // Let channel 0 fade out.
+        let mut out = [0i8; FADE_OUT_FRAMES * 2];
+        mixer.fill_buffer(&mut out);
+        let mut out = [0i8; 32];
+        mixer.fill_buffer(&mut out);
+        for frame in out.chunks_exact(2) {
+            assert_eq!(frame, [0, 0x40]);
+        }
+
+        // Centered channel is heard on both sides at half volume.
+        mixer.set_pan(1, PAN_MAX / 2);
+        let mut out = [0i8; 32];
+        mixer.fill_buffer(&mut out);
+        for frame in out.chunks_exact(2) {
+            assert_eq!(frame, [0x20, 0x20]);
+        }
+    }
+
+    /// Check that the mono mixer fills every output sample.
+    #[test]
+    fn test_mono_output() {
+        let mut mixer = ClassicMixer::new(8000, false);
+        mixer.add_sample(1, make_sample(&[0x40; 64], Some(0)));
+        mixer.play(1, 1, 8000, 0x40);
+
+        let mut out = [0i8; 32];
+        mixer.fill_buffer(&mut out);
+        assert_eq!(out, [0x40; 32]);
+    }
+
+    /// Largest difference allowed between two consecutive output samples when a channel is
+    /// stopped or preempted.
+    const MAX_STEP: i16 = 8;
+
+    /// Returns the largest difference between two consecutive samples of `out`.
+    fn max_step(out: &[i8]) -> i16 {
+        out.windows(2)
+            .map(|w| (w[1] as i16 - w[0] as i16).abs())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Check that stopping a channel fades it out instead of cutting it abruptly.
+    #[test]
+    fn test_stop_fade_out() {
+        let mut mixer = ClassicMixer::new(8000, false);
+        mixer.add_sample(1, make_sample(&[0x7f; 64], Some(0)));
+        mixer.play(1, 0, 8000, 0x40);
+
+        let mut out = vec![0i8; 64];
+        let (before, after) = out.split_at_mut(16);
+        mixer.fill_buffer(before);
+        mixer.stop(0);
+        mixer.fill_buffer(after);
+
+        assert!(max_step(&out) <= MAX_STEP, "{:?}", out);
+        assert_eq!(out[15], 0x7f);
+        assert!(out[16 + FADE_OUT_FRAMES..].iter().all(|&s| s == 0));
+        assert!(matches!(mixer.channels[0], MixerChannel::Inactive));
+    }
+
+    /// Check that a sample preempting another one starts once the latter is faded out.
+    #[test]
+    fn test_play_preempt_fade_out() {
+        let mut mixer = ClassicMixer::new(8000, false);
+        mixer.add_sample(1, make_sample(&[0x7f; 64], Some(0)));
+        mixer.add_sample(2, make_sample(&[0x00; 64], Some(0)));
+        mixer.play(1, 0, 8000, 0x40);
+
+        let mut out = vec![0i8; 64];
+        let (before, after) = out.split_at_mut(16);
+        mixer.fill_buffer(before);
+        mixer.play(2, 0, 8000, 0x40);
+        mixer.fill_buffer(after);
+
+        assert!(max_step(&out) <= MAX_STEP, "{:?}", out);
+        assert!(matches!(
+            mixer.channels[0],
+            MixerChannel::Active(Voice { sample_id: 2, .. })
+        ));
+    }
+
+    /// Play `data` at half the output rate with `interpolation` and return the output.
+    fn play_half_rate(data: &[i8], interpolation: InterpolationMode) -> Vec<i8> {
+        let mut mixer = ClassicMixer::new(8000, false);
+        mixer.set_interpolation(interpolation);
+        mixer.add_sample(1, make_sample(data, None));
+        mixer.play(1, 0, 4000, 0x40);
+
+        let mut out = vec![0i8; data.len() * 2];
+        mixer.fill_buffer(&mut out);
+        out
+    }
+
+    /// Check that without interpolation every sample is simply repeated.
+    #[test]
+    fn test_interpolation_none() {
+        let data = (0..32).map(|i| (i * 3 - 48) as i8).collect::<Vec<_>>();
+        let out = play_half_rate(&data, InterpolationMode::None);
+
+        for (pair, s) in out.chunks_exact(2).zip(&data) {
+            assert_eq!(pair, [*s, *s]);
+        }
+    }
+
+    /// Check that the cubic interpolation follows a linear ramp like the linear one does.
+    #[test]
+    fn test_interpolation_cubic() {
+        let data = (0..32).map(|i| (i * 4 - 64) as i8).collect::<Vec<_>>();
+        let linear = play_half_rate(&data, InterpolationMode::Linear);
+        let cubic = play_half_rate(&data, InterpolationMode::Cubic);
+
+        // The first and last samples lack neighbours to follow the ramp.
+        for (l, c) in linear.iter().zip(&cubic).skip(2).take(data.len() * 2 - 6) {
+            assert!((*l as i16 - *c as i16).abs() <= 1, "{} {}", l, c);
+        }
+    }
+
+    /// Check that the cubic interpolation wraps around the loop point.
+    #[test]
+    fn test_interpolation_cubic_loop() {
+        let mut mixer = ClassicMixer::new(8000, false);
+        mixer.set_interpolation(InterpolationMode::Cubic);
+        mixer.add_sample(1, make_sample(&[0x40; 16], Some(8)));
+        mixer.play(1, 0, 3000, 0x40);
+
+        let mut out = [0i8; 128];
+        mixer.fill_buffer(&mut out);
+        assert_eq!(out, [0x40; 128]);
+    }
+
+    /// Check that once looped, the cubic interpolation at the loop point uses the end of the loop
+    /// as the preceding sample, like it would if the loop was unrolled.
+    #[test]
+    fn test_interpolation_cubic_loop_wrap() {
+        // An intro that does not follow the ramp of the loop.
+        let intro = [0x7f; 8];
+        let ramp = (0..8).map(|i| (i * 8 - 32) as i8).collect::<Vec<_>>();
+        let play = |data: &[i8], loop_start| {
+            let mut mixer = ClassicMixer::new(8000, false);
+            mixer.set_interpolation(InterpolationMode::Cubic);
+            mixer.add_sample(1, make_sample(data, loop_start));
+            mixer.play(1, 0, 4000, 0x40);
+
+            let mut out = [0i8; 48];
+            mixer.fill_buffer(&mut out);
+            out
+        };
+
+        let looped = play(&[&intro[..], &ramp].concat(), Some(intro.len()));
+        let unrolled = play(&[&intro[..], &ramp, &ramp, &ramp].concat(), None);
+
+        // Second pass of the loop, two output frames per sample.
+        assert_eq!(looped[32..48], unrolled[32..48]);
+    }
+
+    /// Check that a sample which header claims more data than available is clamped to its actual
+    /// size, and played without reading past it.
+    #[test]
+    fn test_truncated_sample() {
+        // Header claims 32 bytes before the loop point and 32 after, but only 40 are present.
+        let mut res = Vec::new();
+        res.extend(16u16.to_be_bytes());
+        res.extend(16u16.to_be_bytes());
+        res.extend([0u8; 4]);
+        res.extend([0x40u8; 40]);
+        let sample = unsafe { SoundSample::from_raw_resource(res) };
+        assert_eq!(sample.len(), 40);
+        assert_eq!(sample.loop_pos(), Some(32));
+
+        // Header claims more data before the loop point than there is: the loop is dropped.
+        let mut res = Vec::new();
+        res.extend(32u16.to_be_bytes());
+        res.extend(16u16.to_be_bytes());
+        res.extend([0u8; 4]);
+        res.extend([0x40u8; 40]);
+        let sample = unsafe { SoundSample::from_raw_resource(res) };
+        assert_eq!(sample.len(), 40);
+        assert_eq!(sample.loop_pos(), None);
+
+        // Playback stops at the end of the available data.
+        let mut mixer = ClassicMixer::new(8000, false);
+        mixer.add_sample(1, sample);
+        mixer.play(1, 0, 8000, 0x40);
+        let mut out = [0i8; 64];
+        mixer.fill_buffer(&mut out);
+        assert_eq!(out[..40], [0x40; 40]);
+        assert_eq!(out[40..], [0; 24]);
+        assert!(matches!(mixer.channels[0], MixerChannel::Inactive));
+
+        // Header claims the largest loop possible, but there is more data than a header field can
+        // describe.
+        let mut res = Vec::new();
+        res.extend(0xffffu16.to_be_bytes());
+        res.extend(0xffffu16.to_be_bytes());
+        res.extend([0u8; 4]);
+        res.extend(vec![0x40u8; 0x20002]);
+        let sample = unsafe { SoundSample::from_raw_resource(res) };
+        assert_eq!(sample.len(), 0x20002);
+        assert_eq!(sample.loop_pos(), Some(0x1fffe));
+    }
 }
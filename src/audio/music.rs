@@ -14,6 +14,10 @@ use tracing::debug;
 
 use crate::audio::Mixer;
 
+mod protracker;
+
+pub use protracker::write_protracker;
+
 /// Information about instruments in a music module.
 #[repr(C)]
 #[derive(Debug)]
@@ -25,6 +29,17 @@ pub struct InstrumentInfo {
 const NUM_INSTRUMENTS: usize = 15;
 const ORDER_TABLE_LEN: u16 = 0x80;
 
+/// Shortest interval between two lines of music, in milliseconds.
+pub const MIN_TEMPO: usize = 1;
+
+/// Converts the delay of a music module into the interval between two of its lines, in
+/// milliseconds.
+///
+/// Small delays would otherwise round down to 0, and make the music timer fire continuously.
+pub fn delay_to_tempo(delay: u16) -> usize {
+    (delay as usize * 60 / 7050).max(MIN_TEMPO)
+}
+
 /// Header of a music module.
 ///
 /// Separated from the rest so we can use `std::mem::size_of` and `memoffset::offset_of` on it.
@@ -106,7 +121,8 @@ impl PatternNote {
 
 pub type PatternLine = [PatternNote; 4];
 
-const LINES_PER_PATTERN: u8 = 64;
+/// Number of lines of every pattern.
+pub const LINES_PER_PATTERN: u8 = 64;
 
 #[repr(C)]
 #[derive(Debug)]
@@ -280,6 +296,28 @@ impl ClassicMusicPlayer {
             _ => None,
         }
     }
+
+    /// Returns the index in the order table of the pattern being played, and the next line to be
+    /// played in it, or `None` if no music is playing.
+    pub fn playback_position(&self) -> Option<(u16, u8)> {
+        match self {
+            ClassicMusicPlayer::Playing {
+                current_order,
+                current_line,
+                ..
+            } => Some((*current_order, *current_line)),
+            _ => None,
+        }
+    }
+
+    /// Returns the number of entries of the order table of the music being played, or `None` if
+    /// no music is playing.
+    pub fn num_orders(&self) -> Option<u16> {
+        match self {
+            ClassicMusicPlayer::Playing { music, .. } => Some(music.header.num_order),
+            _ => None,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -312,4 +350,43 @@ mod tests {
         assert_eq!(offset_of!(MusicModuleHeader, num_order), 0x3e);
         assert_eq!(offset_of!(MusicModuleHeader, order_table), 0x40);
     }
+
+    #[test]
+    fn test_delay_to_tempo() {
+        for delay in 0..=200 {
+            assert!(delay_to_tempo(delay) >= MIN_TEMPO, "delay {}", delay);
+        }
+        assert_eq!(delay_to_tempo(0), MIN_TEMPO);
+        assert_eq!(delay_to_tempo(117), MIN_TEMPO);
+        assert_eq!(delay_to_tempo(235), 2);
+        assert_eq!(delay_to_tempo(0x4000), 139);
+    }
+
+    #[test]
+    fn test_playback_position() {
+        // Module with a single silent pattern, played twice.
+        let mut data = vec![0u8; size_of::<MusicModuleHeader>() + size_of::<MusicPattern>()];
+        data[offset_of!(MusicModuleHeader, num_order) + 1] = 2;
+        let music = unsafe { MusicModule::from_raw_resource(data) };
+        let mut mixer = crate::audio::ClassicMixer::new(22050, false);
+
+        let mut player = ClassicMusicPlayer::default();
+        assert_eq!(player.playback_position(), None);
+        assert_eq!(player.num_orders(), None);
+
+        player.load_module(music, 0);
+        assert_eq!(player.playback_position(), Some((0, 0)));
+        assert_eq!(player.num_orders(), Some(2));
+
+        player.process(&mut mixer);
+        assert_eq!(player.playback_position(), Some((0, 1)));
+        for _ in 1..LINES_PER_PATTERN {
+            player.process(&mut mixer);
+        }
+        assert_eq!(player.playback_position(), Some((1, 0)));
+        for _ in 0..LINES_PER_PATTERN {
+            player.process(&mut mixer);
+        }
+        assert_eq!(player.playback_position(), None);
+    }
 }
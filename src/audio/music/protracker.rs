@@ -0,0 +1,245 @@
+//! Export of music modules into the ProTracker MOD format, so they can be opened in trackers.
+//!
+//! The game's modules are close to MOD files to begin with: 4 channels, patterns of 64 lines, an
+//! order table, and notes given as Amiga periods. Only the header needs to be rebuilt, with the
+//! sound resources used as instruments becoming the samples of the MOD file.
+
+use std::io;
+use std::io::Write;
+
+use super::delay_to_tempo;
+use super::MusicModule;
+use super::NoteType;
+use super::SampleEffect;
+use super::LINES_PER_PATTERN;
+use crate::audio::SoundSample;
+
+/// Number of samples described in the header of a MOD file.
+const NUM_SAMPLES: usize = 31;
+/// Maximum volume of a MOD sample.
+const MAX_VOLUME: u16 = 64;
+/// Number of ticks per line ProTracker starts with.
+const DEFAULT_SPEED: u16 = 6;
+
+const EFFECT_SET_VOLUME: u8 = 0xc;
+const EFFECT_SET_SPEED: u8 = 0xf;
+
+/// Returns the speed (ticks per line) and tempo (BPM) making each line of the music last
+/// `line_ms` milliseconds, or `None` if ProTracker cannot play it that fast or slow.
+fn speed_and_tempo(line_ms: usize) -> Option<(u8, u8)> {
+    // A line lasts `speed * 2500 / bpm` milliseconds.
+    std::iter::once(DEFAULT_SPEED)
+        .chain(1..=31)
+        .map(|speed| (speed, speed as usize * 2500 / line_ms))
+        .find(|(_, bpm)| (32..=255).contains(bpm))
+        .map(|(speed, bpm)| (speed as u8, bpm as u8))
+}
+
+/// Write `music` as a ProTracker module named `title` into `w`.
+///
+/// `samples` contains the sound sample of each instrument of `music`, in order. Instruments
+/// without a sample are left empty.
+///
+/// The game plays notes using the NTSC clock of the Amiga while most trackers use the PAL one, so
+/// the exported music sounds about one percent lower. Notes setting the `0xf4` register have no
+/// equivalent and are dropped.
+pub fn write_protracker<W: Write>(
+    w: &mut W,
+    title: &str,
+    music: &MusicModule,
+    samples: &[Option<Box<SoundSample>>],
+) -> io::Result<()> {
+    let header = &music.header;
+
+    let mut name = [0u8; 20];
+    let len = title.len().min(name.len());
+    name[..len].copy_from_slice(&title.as_bytes()[..len]);
+    w.write_all(&name)?;
+
+    // Sample data is limited to 64K words.
+    let samples = (0..NUM_SAMPLES)
+        .map(|i| {
+            let instrument = header.instruments.get(i)?;
+            let sample = samples.get(i)?.as_deref()?;
+            let len = sample.len().min(u16::MAX as usize * 2) & !1;
+            Some((instrument, sample, len))
+        })
+        .collect::<Vec<_>>();
+
+    for sample in &samples {
+        let mut entry = [0u8; 30];
+        if let Some((instrument, sample, len)) = sample {
+            let name = format!("resource 0x{:02x}", instrument.res_num);
+            entry[..name.len()].copy_from_slice(name.as_bytes());
+            entry[22..24].copy_from_slice(&((len / 2) as u16).to_be_bytes());
+            entry[25] = instrument.volume.min(MAX_VOLUME) as u8;
+            let (loop_start, loop_len) = match sample.loop_pos() {
+                Some(pos) if pos < *len => (pos / 2, (len - pos) / 2),
+                _ => (0, 1),
+            };
+            entry[26..28].copy_from_slice(&(loop_start as u16).to_be_bytes());
+            entry[28..30].copy_from_slice(&(loop_len as u16).to_be_bytes());
+        } else {
+            // Empty samples still have a loop length of one word.
+            entry[29] = 1;
+        }
+        w.write_all(&entry)?;
+    }
+
+    // Orders referring to missing patterns would make the player panic, so they are never played.
+    let num_orders = (header.num_order as usize).min(header.order_table.len());
+    let order_table = header.order_table.map(|pattern| match pattern as usize {
+        pattern if pattern < music.patterns.len() => pattern as u8,
+        _ => 0,
+    });
+    w.write_all(&[num_orders as u8, 0x7f])?;
+    w.write_all(&order_table)?;
+    w.write_all(b"M.K.")?;
+
+    // Trackers deduce the number of patterns from the order table.
+    let num_patterns = order_table.iter().max().map_or(0, |&p| p as usize + 1);
+    let mut patterns = vec![[[0u8; 4]; 4]; num_patterns * LINES_PER_PATTERN as usize];
+    for (pattern, lines) in music
+        .patterns
+        .iter()
+        .zip(patterns.chunks_mut(LINES_PER_PATTERN as usize))
+    {
+        for (line, cells) in pattern.lines.iter().zip(lines.iter_mut()) {
+            for (note, cell) in line.iter().zip(cells.iter_mut()) {
+                *cell = match note.parse() {
+                    Some(NoteType::Stop) => [0, 0, EFFECT_SET_VOLUME, 0],
+                    Some(NoteType::Play {
+                        sample: sample @ 1..,
+                        effect,
+                        ..
+                    }) => {
+                        let period = note.0;
+                        let (effect, param) = match effect {
+                            None => (0, 0),
+                            Some(effect) => {
+                                let volume = header.instruments[sample as usize - 1].volume as i16;
+                                let volume = match effect {
+                                    SampleEffect::VolumeUp(param) => volume + param as i16,
+                                    SampleEffect::VolumeDown(param) => volume - param as i16,
+                                };
+                                (EFFECT_SET_VOLUME, volume.clamp(0, 0x3f) as u8)
+                            }
+                        };
+                        [
+                            (sample & 0xf0) | (period >> 8) as u8 & 0xf,
+                            period as u8,
+                            (sample & 0xf) << 4 | effect,
+                            param,
+                        ]
+                    }
+                    _ => [0; 4],
+                };
+            }
+        }
+    }
+
+    // Set the speed of the music in the first line played, using the channels without effect.
+    match speed_and_tempo(delay_to_tempo(header.delay)) {
+        None => tracing::warn!("music delay {} cannot be exported", header.delay),
+        Some((speed, bpm)) => {
+            let first_line = order_table[0] as usize * LINES_PER_PATTERN as usize;
+            let mut effects = [bpm]
+                .into_iter()
+                .chain((speed as u16 != DEFAULT_SPEED).then_some(speed))
+                .peekable();
+            for cell in patterns.get_mut(first_line).into_iter().flatten() {
+                if cell[2] & 0xf == 0 && cell[3] == 0 {
+                    if let Some(param) = effects.next() {
+                        cell[2] |= EFFECT_SET_SPEED;
+                        cell[3] = param;
+                    }
+                }
+            }
+            if effects.peek().is_some() {
+                tracing::warn!("no room left to set the speed of the music");
+            }
+        }
+    }
+    w.write_all(patterns.as_flattened().as_flattened())?;
+
+    for (_, sample, len) in samples.iter().flatten() {
+        let data = sample.data[..*len]
+            .iter()
+            .map(|&s| s as u8)
+            .collect::<Vec<_>>();
+        w.write_all(&data)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem::size_of;
+
+    use memoffset::offset_of;
+
+    use super::*;
+    use crate::audio::MusicModuleHeader;
+    use crate::audio::MusicPattern;
+
+    /// Offset of the patterns in a MOD file with 31 samples.
+    const PATTERNS_OFFSET: usize = 1084;
+
+    #[test]
+    fn test_speed_and_tempo() {
+        assert_eq!(speed_and_tempo(139), Some((6, 107)));
+        assert_eq!(speed_and_tempo(20), Some((1, 125)));
+        assert_eq!(speed_and_tempo(1000), Some((13, 32)));
+        assert_eq!(speed_and_tempo(1), None);
+    }
+
+    #[test]
+    fn test_write_protracker() {
+        let header_len = size_of::<MusicModuleHeader>();
+        let mut data = vec![0u8; header_len + size_of::<MusicPattern>()];
+        data[0..2].copy_from_slice(&0x4000u16.to_be_bytes());
+        // Instrument 1 is resource 0x10 with volume 0x20.
+        data[2..6].copy_from_slice(&[0x00, 0x10, 0x00, 0x20]);
+        data[offset_of!(MusicModuleHeader, num_order) + 1] = 1;
+        // Line 0, channel 0: period 0x1ac with instrument 1, volume up by 0x10.
+        data[header_len..header_len + 4].copy_from_slice(&[0x01, 0xac, 0x15, 0x10]);
+        // Line 1, channel 1: stop.
+        data[header_len + 20..header_len + 22].copy_from_slice(&[0xff, 0xfe]);
+        let music = unsafe { MusicModule::from_raw_resource(data) };
+
+        // 8 bytes sample, looping over its last 4 bytes.
+        let mut sample = vec![0x00, 0x02, 0x00, 0x02, 0, 0, 0, 0];
+        sample.extend(1..=8);
+        let sample = unsafe { SoundSample::from_raw_resource(sample) };
+
+        let mut out = Vec::new();
+        write_protracker(&mut out, "test", &music, &[Some(sample)]).unwrap();
+
+        assert_eq!(&out[0..5], b"test\0");
+        // Sample 1: 4 words at volume 0x20, looping over the last 2 words.
+        assert_eq!(&out[20..37], b"resource 0x10\0\0\0\0");
+        assert_eq!(
+            &out[42..50],
+            &[0x00, 0x04, 0x00, 0x20, 0x00, 0x02, 0x00, 0x02]
+        );
+        // Sample 2 is empty.
+        assert_eq!(&out[72..80], &[0, 0, 0, 0, 0, 0, 0, 1]);
+        // One order, playing pattern 0.
+        assert_eq!(&out[950..953], &[1, 0x7f, 0]);
+        assert_eq!(&out[1080..1084], b"M.K.");
+
+        let cell = |line: usize, chan: usize| {
+            let offset = PATTERNS_OFFSET + (line * 4 + chan) * 4;
+            &out[offset..offset + 4]
+        };
+        assert_eq!(cell(0, 0), &[0x01, 0xac, 0x1c, 0x30]);
+        // The tempo is set in the first free channel.
+        assert_eq!(cell(0, 1), &[0x00, 0x00, 0x0f, 107]);
+        assert_eq!(cell(1, 1), &[0x00, 0x00, 0x0c, 0x00]);
+        assert_eq!(cell(1, 0), &[0; 4]);
+
+        assert_eq!(out.len(), PATTERNS_OFFSET + 1024 + 8);
+        assert_eq!(&out[PATTERNS_OFFSET + 1024..], &[1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+}
@@ -1,8 +1,12 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::sync::PoisonError;
 use std::time::Duration;
 use std::time::Instant;
 
+use crate::audio::ChannelInfo;
 use crate::audio::ClassicMusicPlayer;
 use crate::audio::MusicPlayer;
 use crate::audio::ProtectedMixer;
@@ -11,7 +15,9 @@ use crate::audio::SoundSample;
 use anyhow::anyhow;
 
 use super::ClassicMixer;
+use super::InterpolationMode;
 use super::Mixer;
+use super::NUM_AUDIO_CHANNELS;
 
 impl sdl2::audio::AudioCallback for ProtectedMixer<ClassicMixer> {
     type Channel = i8;
@@ -26,6 +32,65 @@ impl sdl2::audio::AudioCallback for ProtectedMixer<ClassicMixer> {
     }
 }
 
+/// Mixer adapter that plays everything at volume zero.
+///
+/// Muted sounds are still sent to the mixer, so they replace whatever was playing on their channel
+/// as they would have if they were audible.
+struct MutedMixer<'a, M: Mixer>(&'a mut M);
+
+impl<M: Mixer> Mixer for MutedMixer<'_, M> {
+    fn add_sample(&mut self, id: u8, sample: Box<SoundSample>) {
+        self.0.add_sample(id, sample)
+    }
+
+    fn play(&mut self, sample_id: u8, channel: u8, freq: u16, _volume: u8) {
+        self.0.play(sample_id, channel, freq, 0)
+    }
+
+    fn stop(&mut self, channel: u8) {
+        self.0.stop(channel)
+    }
+
+    fn reset(&mut self) {
+        self.0.reset()
+    }
+}
+
+/// Play the next line of the music from the music timer, unless `stopped` is set, and record
+/// the time it was played at into `current_interval`.
+///
+/// Returns the delay until the timer must fire again in milliseconds, or 0 if it must not.
+fn music_timer_tick(
+    player: &Mutex<ClassicMusicPlayer>,
+    mixer: &Mutex<ClassicMixer>,
+    muted: &AtomicBool,
+    stopped: &AtomicBool,
+    current_interval: &Mutex<Instant>,
+    delay: Duration,
+) -> u32 {
+    let mut player = player.lock().unwrap();
+    // Removing a SDL timer does not wait for a callback that is already about to run, so the
+    // timer is also stopped by setting `stopped` with the player locked.
+    if stopped.load(Ordering::Relaxed) {
+        return 0;
+    }
+    *current_interval.lock().unwrap() = Instant::now();
+
+    let mut mixer = mixer.lock().unwrap();
+    // Keep playing while muted, so the music stays in sync with the game.
+    if muted.load(Ordering::Relaxed) {
+        player.process(&mut MutedMixer(&mut *mixer));
+    } else {
+        player.process(&mut *mixer);
+    }
+
+    if let ClassicMusicPlayer::Playing { .. } = &*player {
+        delay.as_millis() as u32
+    } else {
+        0
+    }
+}
+
 enum MusicTimerState {
     Stopped,
     Running {
@@ -35,6 +100,10 @@ enum MusicTimerState {
         delay: Duration,
         /// Timestamp of the start of the current interval.
         current_interval: Arc<Mutex<Instant>>,
+        /// Player the timer plays, locked to stop the timer.
+        player: Arc<Mutex<ClassicMusicPlayer>>,
+        /// Set once the timer is stopped, so a callback already under way does not play.
+        stopped: Arc<AtomicBool>,
     },
     Paused {
         /// Interval at which the timer will fire.
@@ -58,50 +127,73 @@ impl MusicTimer {
         })
     }
 
+    /// Stop the timer if it is running, making sure it does not play any more line of the music
+    /// once this returns. Returns the previous state.
+    fn stop(&mut self) -> MusicTimerState {
+        let old_state = std::mem::replace(&mut self.state, MusicTimerState::Stopped);
+        if let MusicTimerState::Running {
+            player, stopped, ..
+        } = &old_state
+        {
+            // This is called when dropping, so do not panic if a callback did while holding the
+            // lock: waiting for the callback to complete is all we need it for.
+            let _player = player.lock().unwrap_or_else(PoisonError::into_inner);
+            stopped.store(true, Ordering::Relaxed);
+        }
+
+        old_state
+    }
+
     fn set_timer(
         &mut self,
         delay: Duration,
         initial_delay: Duration,
         player: Arc<Mutex<ClassicMusicPlayer>>,
         mixer: Arc<Mutex<ClassicMixer>>,
+        muted: Arc<AtomicBool>,
     ) {
         let current_interval = Arc::new(Mutex::new(Instant::now()));
         let current_interval_cb = Arc::clone(&current_interval);
+        let stopped = Arc::new(AtomicBool::new(false));
+        let stopped_cb = Arc::clone(&stopped);
+        let player_cb = Arc::clone(&player);
 
         // Make sure to stop any currently running timer.
-        self.state = MusicTimerState::Stopped;
+        self.stop();
 
         let timer = self.timer_sys.add_timer(
             initial_delay.as_millis() as u32,
             Box::new(move || {
-                *current_interval_cb.lock().unwrap() = Instant::now();
-
-                let mut player = player.lock().unwrap();
-                let mut mixer = mixer.lock().unwrap();
-                player.process(&mut *mixer);
-
-                if let ClassicMusicPlayer::Playing { .. } = &*player {
-                    delay.as_millis() as u32
-                } else {
-                    0
-                }
+                music_timer_tick(
+                    &player_cb,
+                    &mixer,
+                    &muted,
+                    &stopped_cb,
+                    &current_interval_cb,
+                    delay,
+                )
             }),
         );
 
         self.state = MusicTimerState::Running {
-            // Safe because we are keeping `timer_sys` alive for as long as `timer` is, and there
-            // is no direct reference between the two - only a lifetime requirement.
+            // Safe because we are keeping `timer_sys` alive for as long as `timer` is: the timer
+            // is dropped by `cancel` at the latest when we are dropped, before `timer_sys` is.
+            // There is no direct reference between the two - only a lifetime requirement.
             // Also the callback steals all the data it uses and has no external reference.
             _timer: unsafe {
                 std::mem::transmute::<sdl2::timer::Timer<'_, '_>, sdl2::timer::Timer<'_, '_>>(timer)
             },
             delay,
             current_interval,
+            player,
+            stopped,
         };
     }
 
+    /// Stop the timer, remembering how long it had been waiting for so it can resume where it
+    /// was. The music does not advance until the timer is resumed.
     fn pause(&mut self) {
-        let old_state = std::mem::replace(&mut self.state, MusicTimerState::Stopped);
+        let old_state = self.stop();
         self.state = match old_state {
             MusicTimerState::Running {
                 delay,
@@ -119,15 +211,26 @@ impl MusicTimer {
         }
     }
 
-    fn resume(&mut self, player: Arc<Mutex<ClassicMusicPlayer>>, mixer: Arc<Mutex<ClassicMixer>>) {
-        let old_state = std::mem::replace(&mut self.state, MusicTimerState::Stopped);
-        if let MusicTimerState::Paused { delay, elapsed } = old_state {
-            self.set_timer(delay, delay.saturating_sub(elapsed), player, mixer);
+    fn resume(
+        &mut self,
+        player: Arc<Mutex<ClassicMusicPlayer>>,
+        mixer: Arc<Mutex<ClassicMixer>>,
+        muted: Arc<AtomicBool>,
+    ) {
+        if let MusicTimerState::Paused { delay, elapsed } = self.state {
+            self.set_timer(delay, delay.saturating_sub(elapsed), player, mixer, muted);
         }
     }
 
     fn cancel(&mut self) {
-        self.state = MusicTimerState::Stopped;
+        self.stop();
+    }
+}
+
+impl Drop for MusicTimer {
+    fn drop(&mut self) {
+        // The timer must be removed before `timer_sys` is dropped.
+        self.cancel();
     }
 }
 
@@ -136,28 +239,58 @@ pub struct Sdl2Audio {
     music_player: Arc<Mutex<ClassicMusicPlayer>>,
     audio_device: sdl2::audio::AudioDevice<ProtectedMixer<ClassicMixer>>,
     timer: MusicTimer,
+    /// Whether the music is played at volume zero. Shared with the music timer.
+    music_muted: Arc<AtomicBool>,
+    /// Whether sound effects are played at volume zero.
+    sfx_muted: bool,
+    /// Whether the output has been paused using `MusicPlayer::pause`.
+    paused: bool,
+    /// Whether all audio output is stopped, regardless of `paused`.
+    muted: bool,
 }
 
 impl Sdl2Audio {
     /// Create a new SDL2 audio device from a SDL context.
     ///
     /// `output_freq` is the desired output frequency of the audio playback. SDL may choose a
-    /// different one if it is not supported by the audio system.
-    pub fn new(sdl_context: &sdl2::Sdl, output_freq: usize) -> anyhow::Result<Self> {
+    /// different one if it is not supported by the audio system. Since the mixer steps through
+    /// samples according to the actual output frequency, this only affects quality and latency,
+    /// not pitch.
+    ///
+    /// `buffer_samples` is the desired size of the audio buffer. Larger buffers can prevent
+    /// crackling, at the cost of latency. If `None`, a size matching about 10ms of audio is used.
+    ///
+    /// If `stereo` is `true`, a stereo output is requested and the mixer channels are panned.
+    ///
+    /// `interpolation` selects how the mixer resamples the sound effects and music.
+    pub fn new(
+        sdl_context: &sdl2::Sdl,
+        output_freq: usize,
+        buffer_samples: Option<u16>,
+        stereo: bool,
+        interpolation: InterpolationMode,
+    ) -> anyhow::Result<Self> {
         let audio = sdl_context.audio().map_err(|s| anyhow!(s))?;
 
         // Compute buffer size that prevents audio lag. E.g for 22050Hz this will be 256 bytes.
-        let samples = (output_freq / 100).checked_next_power_of_two().unwrap();
+        let samples = buffer_samples.unwrap_or_else(|| {
+            (output_freq / 100)
+                .checked_next_power_of_two()
+                .unwrap()
+                .min(u16::MAX as usize) as u16
+        });
 
         let desired_spec = sdl2::audio::AudioSpecDesired {
             freq: Some(output_freq as i32),
-            channels: Some(1), // mono
-            samples: Some(samples as u16),
+            channels: Some(if stereo { 2 } else { 1 }),
+            samples: Some(samples),
         };
 
         let mut audio_device = audio
             .open_playback(None, &desired_spec, |spec| {
-                ProtectedMixer::new(ClassicMixer::new(spec.freq as u32))
+                let mut mixer = ClassicMixer::new(spec.freq as u32, spec.channels == 2);
+                mixer.set_interpolation(interpolation);
+                ProtectedMixer::new(mixer)
             })
             .map_err(|s| anyhow!(s))?;
         audio_device.resume();
@@ -169,8 +302,70 @@ impl Sdl2Audio {
             music_player: Default::default(),
             audio_device,
             timer: MusicTimer::new(sdl_context)?,
+            music_muted: Default::default(),
+            sfx_muted: false,
+            paused: false,
+            muted: false,
         })
     }
+
+    /// Mute or unmute the sound effects played by the game, without affecting the music.
+    pub fn set_sfx_muted(&mut self, muted: bool) {
+        self.sfx_muted = muted;
+    }
+
+    pub fn sfx_muted(&self) -> bool {
+        self.sfx_muted
+    }
+
+    /// Mute or unmute all audio output. Muting stops the device and the music timer like
+    /// `MusicPlayer::pause` does, so the music resumes from where it was when unmuted. Audio
+    /// stays stopped if it is also paused.
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+        self.update_output();
+    }
+
+    pub fn muted(&self) -> bool {
+        self.muted
+    }
+
+    /// Returns what each channel of the mixer is currently playing.
+    pub fn channel_snapshot(&self) -> [Option<ChannelInfo>; NUM_AUDIO_CHANNELS] {
+        self.mixer.lock().unwrap().channel_snapshot()
+    }
+
+    /// Stop or restart the device and music timer according to whether we are paused or muted.
+    fn update_output(&mut self) {
+        if self.paused || self.muted {
+            self.timer.pause();
+            self.audio_device.pause();
+        } else {
+            self.audio_device.resume();
+            self.timer.resume(
+                Arc::clone(&self.music_player),
+                Arc::clone(&self.mixer),
+                Arc::clone(&self.music_muted),
+            );
+        }
+    }
+
+    /// Stop all audio output: the music timer is removed, and the device stops requesting
+    /// samples.
+    ///
+    /// This is done automatically when the device is dropped, but calling it before the rest of
+    /// the system is torn down avoids hearing the last samples played in a loop.
+    pub fn shutdown(&mut self) {
+        // Also waits for the timer callback to complete if it was running.
+        self.timer.cancel();
+        self.audio_device.pause();
+    }
+}
+
+impl Drop for Sdl2Audio {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
 }
 
 impl Mixer for Sdl2Audio {
@@ -181,10 +376,12 @@ impl Mixer for Sdl2Audio {
 
     #[tracing::instrument(level = "trace", skip(self))]
     fn play(&mut self, sample_id: u8, channel: u8, freq: u16, volume: u8) {
-        self.mixer
-            .lock()
-            .unwrap()
-            .play(sample_id, channel, freq, volume)
+        let mut mixer = self.mixer.lock().unwrap();
+        if self.sfx_muted {
+            MutedMixer(&mut *mixer).play(sample_id, channel, freq, volume)
+        } else {
+            mixer.play(sample_id, channel, freq, volume)
+        }
     }
 
     #[tracing::instrument(level = "trace", skip(self))]
@@ -213,7 +410,12 @@ impl MusicPlayer for Sdl2Audio {
             delay,
             Arc::clone(&self.music_player),
             Arc::clone(&self.mixer),
-        )
+            Arc::clone(&self.music_muted),
+        );
+        // Music started while the output is stopped must wait until it restarts.
+        if self.paused || self.muted {
+            self.timer.pause();
+        }
     }
 
     fn stop_music(&mut self) {
@@ -222,17 +424,80 @@ impl MusicPlayer for Sdl2Audio {
     }
 
     fn pause(&mut self) {
-        self.timer.pause();
-        self.audio_device.pause();
+        self.paused = true;
+        self.update_output();
     }
 
     fn resume(&mut self) {
-        self.audio_device.resume();
-        self.timer
-            .resume(Arc::clone(&self.music_player), Arc::clone(&self.mixer));
+        self.paused = false;
+        self.update_output();
     }
 
     fn take_value_of_0xf4(&self) -> Option<i16> {
         self.music_player.lock().unwrap().take_value_of_0xf4()
     }
+
+    fn playback_position(&self) -> Option<(u16, u8)> {
+        self.music_player.lock().unwrap().playback_position()
+    }
+
+    fn num_orders(&self) -> Option<u16> {
+        self.music_player.lock().unwrap().num_orders()
+    }
+
+    fn set_music_muted(&mut self, muted: bool) {
+        self.music_muted.store(muted, Ordering::Relaxed);
+    }
+
+    fn music_muted(&self) -> bool {
+        self.music_muted.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem::size_of;
+
+    use memoffset::offset_of;
+
+    use super::*;
+    use crate::audio::MusicModule;
+    use crate::audio::MusicModuleHeader;
+    use crate::audio::MusicPattern;
+
+    #[test]
+    fn test_stopped_timer_does_not_play() {
+        // Module with a single silent pattern.
+        let mut data = vec![0u8; size_of::<MusicModuleHeader>() + size_of::<MusicPattern>()];
+        data[offset_of!(MusicModuleHeader, num_order) + 1] = 1;
+        let player = Mutex::new(ClassicMusicPlayer::default());
+        player
+            .lock()
+            .unwrap()
+            .load_module(unsafe { MusicModule::from_raw_resource(data) }, 0);
+        let mixer = Mutex::new(ClassicMixer::new(22050, false));
+        let stopped = AtomicBool::new(false);
+        let current_interval = Mutex::new(Instant::now());
+        let delay = Duration::from_millis(20);
+        let tick = || {
+            music_timer_tick(
+                &player,
+                &mixer,
+                &AtomicBool::new(false),
+                &stopped,
+                &current_interval,
+                delay,
+            )
+        };
+
+        assert_eq!(tick(), 20);
+        assert_eq!(player.lock().unwrap().playback_position(), Some((0, 1)));
+        let played_at = *current_interval.lock().unwrap();
+
+        // Once stopped, the music does not advance, and the interval is not restarted.
+        stopped.store(true, Ordering::Relaxed);
+        assert_eq!(tick(), 0);
+        assert_eq!(player.lock().unwrap().playback_position(), Some((0, 1)));
+        assert_eq!(*current_interval.lock().unwrap(), played_at);
+    }
 }
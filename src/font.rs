@@ -1,3 +1,6 @@
+use std::io;
+use std::path::Path;
+
 pub const CHAR_WIDTH: usize = 8;
 pub const CHAR_HEIGHT: usize = 8;
 pub const FONT_FIRST_CHAR: u8 = b' ';
@@ -59,3 +62,100 @@ pub const FONT: [u8; NB_CHARS * CHAR_HEIGHT] = [
     0xFE, 0xFE, 0xFE, 0xFE, 0xFE, 0xFE, 0xFE, 0x00, 0x38, 0x44, 0xBA, 0xA2, 0xBA, 0x44, 0x38, 0x00,
     0x38, 0x44, 0x82, 0x82, 0x44, 0x28, 0xEE, 0x00, 0x55, 0xAA, 0x55, 0xAA, 0x55, 0xAA, 0x55, 0xAA,
 ];
+
+/// Number of glyphs in a font file, i.e. one for every character code.
+pub const NB_GLYPHS: usize = 256;
+
+/// Glyph of the built-in font for the characters it does not cover: an empty box.
+pub const MISSING_GLYPH: [u8; CHAR_HEIGHT] = [0x00, 0x7E, 0x42, 0x42, 0x42, 0x42, 0x7E, 0x00];
+
+/// Character code drawn in place of characters that cannot be encoded on a single byte. The
+/// built-in font draws it as [`MISSING_GLYPH`].
+pub const FALLBACK_CHAR: u8 = 0x00;
+
+/// Font used to draw the game's text.
+///
+/// Glyphs are encoded like in [`FONT`], and indexed by their character code.
+#[derive(Clone)]
+pub struct Font {
+    glyphs: Box<[u8; NB_GLYPHS * CHAR_HEIGHT]>,
+}
+
+/// The built-in font, covering the printable ASCII characters and drawing the other ones as
+/// [`MISSING_GLYPH`].
+impl Default for Font {
+    fn default() -> Self {
+        let mut glyphs = Box::new([0u8; NB_GLYPHS * CHAR_HEIGHT]);
+        for glyph in glyphs.chunks_exact_mut(CHAR_HEIGHT) {
+            glyph.copy_from_slice(&MISSING_GLYPH);
+        }
+        let start = FONT_FIRST_CHAR as usize * CHAR_HEIGHT;
+        glyphs[start..start + FONT.len()].copy_from_slice(&FONT);
+
+        Font { glyphs }
+    }
+}
+
+impl Font {
+    /// Load a font from the file at `path`, which must contain the glyphs of all the
+    /// [`NB_GLYPHS`] characters one after the other.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let data = std::fs::read(path)?;
+        let glyphs = Box::<[u8; NB_GLYPHS * CHAR_HEIGHT]>::try_from(data.into_boxed_slice())
+            .map_err(|data| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "invalid font file size {}, expected {}",
+                        data.len(),
+                        NB_GLYPHS * CHAR_HEIGHT
+                    ),
+                )
+            })?;
+
+        Ok(Font { glyphs })
+    }
+
+    /// Returns the glyph of character `c`, with one byte per line.
+    pub fn glyph(&self, c: u8) -> &[u8] {
+        let start = c as usize * CHAR_HEIGHT;
+        &self.glyphs[start..start + CHAR_HEIGHT]
+    }
+
+    /// Returns the glyphs of all the characters.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.glyphs[..]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_font() {
+        let font = Font::default();
+        let a = (b'A' - FONT_FIRST_CHAR) as usize * CHAR_HEIGHT;
+        assert_eq!(font.glyph(b'A'), &FONT[a..a + CHAR_HEIGHT]);
+        assert_eq!(font.glyph(0x10), MISSING_GLYPH);
+        assert_eq!(font.glyph(0x80), MISSING_GLYPH);
+        assert_eq!(font.glyph(FALLBACK_CHAR), MISSING_GLYPH);
+    }
+
+    #[test]
+    fn test_load_font() {
+        let path = std::env::temp_dir().join(format!("awer-font-{}", std::process::id()));
+
+        std::fs::write(&path, [0x55u8; 100]).unwrap();
+        assert!(Font::load(&path).is_err());
+
+        let mut data = vec![0u8; NB_GLYPHS * CHAR_HEIGHT];
+        data[0x90 * CHAR_HEIGHT] = 0xff;
+        std::fs::write(&path, &data).unwrap();
+        let font = Font::load(&path).unwrap();
+        assert_eq!(font.glyph(0x90), [0xff, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(font.glyph(0x10), [0; CHAR_HEIGHT]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
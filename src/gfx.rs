@@ -1,5 +1,8 @@
+pub mod ansi;
 mod game_renderer;
+pub mod headless;
 pub mod polygon;
+pub mod recorder;
 
 #[cfg(feature = "gl3")]
 pub mod gl3;
@@ -18,6 +21,7 @@ use byteorder::ReadBytesExt;
 use byteorder::BE;
 use tracing::debug;
 use tracing::error;
+use tracing::warn;
 use zerocopy::FromBytes;
 use zerocopy::SizeError;
 
@@ -41,13 +45,32 @@ pub enum PolySegment {
     Video,
 }
 
+/// Largest color of a polygon: `0x0..=0xf` are palette indices, `0x10` makes the pixels below the
+/// polygon brighter, and `0x11` copies them from page 0.
+pub const MAX_POLY_COLOR: u8 = 0x11;
+
+/// Options controlling how polygons are drawn.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PolygonOptions {
+    /// Whether large polygons are filled using several threads, each one of them filling a
+    /// different range of rows.
+    pub parallel: bool,
+    /// Whether the edges of polygons are rounded outwards to close the gaps between them.
+    pub conservative: bool,
+    /// Whether to panic on polygons of invalid colors instead of skipping them.
+    pub strict: bool,
+}
+
 /// Trait for filling a single [`Polygon`].
 pub trait PolygonFiller {
-    /// Fill `poly` with color index `color_idx` on page `dst_page_id`.
+    /// Fill `poly` with color index `color_idx` on page `dst_page_id`. `color_idx` is never larger
+    /// than [`MAX_POLY_COLOR`].
     ///
     /// `pos` is the coordinates of the center of the polygon on the page, and `offset` its local
     /// displacement. `zoom` is a zoom factor by which every point of the polygon must be
     /// multiplied by, and then divided by 64.
+    ///
+    /// Fillers ignore the `options` they do not support.
     #[allow(clippy::too_many_arguments)]
     fn fill_polygon(
         &mut self,
@@ -57,6 +80,7 @@ pub trait PolygonFiller {
         pos: (i16, i16),
         offset: (i16, i16),
         zoom: u16,
+        options: &PolygonOptions,
     );
 }
 
@@ -70,14 +94,16 @@ pub struct SimplePolygonRenderer {
     cinematic: Vec<u8>,
     /// Video segment.
     video: Vec<u8>,
+    /// Options passed to the filler along with each polygon.
+    options: PolygonOptions,
 }
 
 impl InitForScene for SimplePolygonRenderer {
     #[tracing::instrument(skip(self, resman))]
     fn init_from_scene(&mut self, resman: &ResourceManager, scene: &Scene) -> std::io::Result<()> {
-        self.cinematic = resman.load_resource(scene.video1)?.data;
+        self.cinematic = resman.load_resource(scene.video1)?.into_data();
         self.video = if scene.video2 != 0 {
-            resman.load_resource(scene.video2)?.data
+            resman.load_resource(scene.video2)?.into_data()
         } else {
             Default::default()
         };
@@ -97,9 +123,13 @@ impl SimplePolygonRenderer {
         offset: (i16, i16),
         zoom: u16,
         color: Option<u8>,
+        options: &PolygonOptions,
         filler: &mut F,
     ) {
-        let op = segment[start_offset as usize];
+        let Some(&op) = segment.get(start_offset as usize) else {
+            error!("poly offset 0x{:x} out of range of segment", start_offset);
+            return;
+        };
         match op {
             op if op & 0xc0 == 0xc0 => {
                 // TODO: match other properties of the color (e.g. blend) from op
@@ -109,29 +139,46 @@ impl SimplePolygonRenderer {
                     // Otherwise take the color from the op.
                     None => op & 0x3f,
                 };
+                if color > MAX_POLY_COLOR {
+                    if options.strict {
+                        panic!("Unexpected color 0x{:x}", color);
+                    }
+                    warn!(
+                        "unexpected color 0x{:x} for polygon at offset 0x{:x}, skipping it",
+                        color, start_offset
+                    );
+                    return;
+                }
 
                 let poly_slice = &segment[start_offset as usize + 1..];
-                let nb_points = poly_slice[2] as usize;
-                let Ok((poly, _)) = Polygon::ref_from_prefix_with_elems(poly_slice, nb_points)
-                    .map_err(SizeError::from)
-                else {
+                let Some(Ok((poly, _))) = poly_slice.get(2).map(|&nb_points| {
+                    Polygon::ref_from_prefix_with_elems(poly_slice, nb_points as usize)
+                        .map_err(SizeError::from)
+                }) else {
                     tracing::error!("poly data out of range of segment");
                     return;
                 };
+                if let Err(e) = poly.validate() {
+                    error!("invalid polygon at offset 0x{:x}: {}", start_offset, e);
+                    return;
+                }
 
-                filler.fill_polygon(poly, color, render_buffer, pos, offset, zoom);
+                filler.fill_polygon(poly, color, render_buffer, pos, offset, zoom, options);
             }
             0x02 => {
-                Self::draw_polygon_hierarchy(
+                if let Err(e) = Self::draw_polygon_hierarchy(
                     segment,
                     render_buffer,
                     pos,
                     offset,
                     zoom,
                     color,
+                    options,
                     start_offset + 1,
                     filler,
-                );
+                ) {
+                    error!("poly hierarchy data out of range of segment: {}", e);
+                }
             }
             _ => tracing::warn!("invalid draw_polygon op 0x{:x}", op),
         };
@@ -146,36 +193,31 @@ impl SimplePolygonRenderer {
         offset: (i16, i16),
         zoom: u16,
         color: Option<u8>,
+        options: &PolygonOptions,
         start_offset: u16,
         filler: &mut F,
-    ) {
+    ) -> std::io::Result<()> {
         let mut cursor = Cursor::new(segment);
-        match cursor.seek(SeekFrom::Start(start_offset as u64)) {
-            Ok(_) => (),
-            Err(e) => {
-                error!("error while seeking to draw polygon: {}", e);
-                return;
-            }
-        }
+        cursor.seek(SeekFrom::Start(start_offset as u64))?;
 
         let offset = (
-            offset.0 - cursor.read_u8().unwrap() as i16,
-            offset.1 - cursor.read_u8().unwrap() as i16,
+            offset.0 - cursor.read_u8()? as i16,
+            offset.1 - cursor.read_u8()? as i16,
         );
-        let nb_childs = cursor.read_u8().unwrap() + 1;
+        let nb_childs = cursor.read_u8()? as usize + 1;
 
         for _i in 0..nb_childs {
-            let word = cursor.read_u16::<BE>().unwrap();
+            let word = cursor.read_u16::<BE>()?;
             let (read_color, poly_offset) = (word & 0x8000 != 0, (word & 0x7fff) * 2);
             let offset = (
-                offset.0 + cursor.read_u8().unwrap() as i16,
-                offset.1 + cursor.read_u8().unwrap() as i16,
+                offset.0 + cursor.read_u8()? as i16,
+                offset.1 + cursor.read_u8()? as i16,
             );
 
             let color = if read_color {
-                let color = Some(cursor.read_u8().unwrap() & 0x7f);
+                let color = Some(cursor.read_u8()? & 0x7f);
                 // This is a "mask number" apparently?
-                cursor.read_u8().unwrap();
+                cursor.read_u8()?;
                 color
             } else {
                 color
@@ -189,9 +231,17 @@ impl SimplePolygonRenderer {
                 offset,
                 zoom,
                 color,
+                options,
                 filler,
             );
         }
+
+        Ok(())
+    }
+
+    /// Returns the options used to draw polygons, which can be changed.
+    pub fn options_mut(&mut self) -> &mut PolygonOptions {
+        &mut self.options
     }
 
     #[tracing::instrument(level = "trace", skip(self, segment, filler))]
@@ -220,15 +270,76 @@ impl SimplePolygonRenderer {
             offset,
             zoom,
             None,
+            &self.options,
             filler,
         );
     }
 }
 
+/// Whether presenting a frame waits for the vertical sync of the screen.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Vsync {
+    /// Present frames immediately, at the risk of tearing.
+    Off,
+    /// Wait for the vertical sync before presenting a frame.
+    #[default]
+    On,
+    /// Wait for the vertical sync, unless the frame is late in which case it is presented
+    /// immediately. Falls back to `On` if not supported.
+    Adaptive,
+}
+
+/// Shape given to the game's image when it is displayed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AspectRatio {
+    /// 4:3, the shape of the screens of the time, on which the pixels were taller than wide.
+    Classic,
+    /// 8:5, the shape of the 320x200 pixels when they are square.
+    #[default]
+    SquarePixels,
+    /// Fill the whole window, whatever its shape.
+    Stretch,
+}
+
+impl AspectRatio {
+    /// Returns the position and size `(x, y, width, height)` of the game's image in a window of
+    /// `width`x`height` pixels: as large as possible, and centered.
+    pub fn fit(self, width: u32, height: u32) -> (i32, i32, u32, u32) {
+        let (num, den) = match self {
+            AspectRatio::Classic => (4, 3),
+            AspectRatio::SquarePixels => (8, 5),
+            AspectRatio::Stretch => return (0, 0, width, height),
+        };
+
+        if width * den / num < height {
+            let h = width * den / num;
+            (0, (height - h) as i32 / 2, width, h)
+        } else {
+            let w = height * num / den;
+            ((width - w) as i32 / 2, 0, w, height)
+        }
+    }
+}
+
 /// Trait for displaying an indexed-color buffer using a given palette on the screen.
 pub trait Display {
     /// Show `page_id` on the screen, using `palette` to render its actual colors.
     fn blitframebuffer(&mut self, page_id: usize, palette: &Palette);
+
+    /// Returns the frame currently shown on the screen, at the resolution the game is rendered
+    /// at, or `None` if this display cannot read it back.
+    fn read_framebuffer_rgb(&self) -> Option<RgbFrame> {
+        None
+    }
+}
+
+/// A frame read back from a display, as tightly-packed RGB pixels starting from the top-left
+/// corner.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RgbFrame {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
 }
 
 /// Proxy implementation for containers of `Display`.
@@ -236,6 +347,10 @@ impl<D: Display + ?Sized, C: DerefMut<Target = D>> Display for C {
     fn blitframebuffer(&mut self, page_id: usize, palette: &Palette) {
         self.deref_mut().blitframebuffer(page_id, palette)
     }
+
+    fn read_framebuffer_rgb(&self) -> Option<RgbFrame> {
+        self.deref().read_framebuffer_rgb()
+    }
 }
 
 /// Trait providing the methods necessary for the VM to render the game.
@@ -244,6 +359,41 @@ pub trait Gfx: InitForScene + GameRenderer + Display + Snapshotable<State = Box<
 /// Proxy implementation for containers of `Gfx`.
 impl<G: Gfx + ?Sized, C: DerefMut<Target = G>> Gfx for C {}
 
+/// Returns `lines` of text drawn in the top-left corner of an image of the size of the game
+/// screen, as tightly-packed RGBA pixels starting from the top-left corner.
+///
+/// The text is white with a black shadow so it is readable over any frame, and the rest of the
+/// image is transparent. Displays draw this image on top of the game to show information without
+/// touching the game's pages.
+pub fn overlay_text_rgba(lines: &[&str]) -> Vec<u8> {
+    use crate::font::Font;
+    use crate::font::CHAR_HEIGHT;
+    use crate::font::CHAR_WIDTH;
+
+    let [width, height] = SCREEN_RESOLUTION;
+    let font = Font::default();
+    let mut rgba = vec![0u8; width * height * 4];
+
+    for (color, shift) in [([0x00, 0x00, 0x00, 0xff], 1), ([0xff, 0xff, 0xff, 0xff], 0)] {
+        for (row, line) in lines.iter().enumerate() {
+            for (col, c) in line.bytes().enumerate() {
+                let x0 = (col + 1) * CHAR_WIDTH + shift;
+                let y0 = (row + 1) * CHAR_HEIGHT + shift;
+                for (y, bits) in (y0..).zip(font.glyph(c)) {
+                    for x in (x0..x0 + CHAR_WIDTH).filter(|x| bits & (0x80 >> (x - x0)) != 0) {
+                        if x < width && y < height {
+                            let offset = (y * width + x) * 4;
+                            rgba[offset..offset + 4].copy_from_slice(&color);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    rgba
+}
+
 /// A single color from a game's palette which components have been normalized to cover the u8
 /// range.
 ///
@@ -256,6 +406,19 @@ pub struct Color {
     pub b: u8,
 }
 
+impl Color {
+    /// Returns the color halfway between `self` and `other`.
+    pub fn blend(&self, other: &Color) -> Color {
+        let avg = |a: u8, b: u8| ((a as u16 + b as u16) / 2) as u8;
+
+        Color {
+            r: avg(self.r, other.r),
+            g: avg(self.g, other.g),
+            b: avg(self.b, other.b),
+        }
+    }
+}
+
 pub const PALETTE_SIZE: usize = 16;
 
 #[repr(C)]
@@ -284,9 +447,21 @@ impl Palette {
         }
     }
 
+    /// Returns the raw PALETTE resource data giving this palette, as expected by `set`.
+    pub fn raw(&self) -> [u8; 32] {
+        let mut palette = [0u8; 32];
+        for (raw, col) in palette.chunks_exact_mut(2).zip(&self.0) {
+            raw[0] = col.r >> 4;
+            raw[1] = (col.g & 0xf0) | (col.b >> 4);
+        }
+
+        palette
+    }
+
     /// Return the RGB color corresponding to |color_idx|.
     /// A palette only has 16 colors, so this method will panic if |color_idx|
-    /// is bigger than 0xf.
+    /// is bigger than 0xf. Pixels made transparent by color 0x10 are within
+    /// that range, as transparency only sets bit 0x8 of a 4-bit index.
     pub fn lookup(&self, color_idx: u8) -> &Color {
         assert!(color_idx <= 0xf);
 
@@ -298,3 +473,147 @@ impl Palette {
         self.0.as_ptr()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aspect_ratio_fit() {
+        // Wider window: bars on the sides.
+        assert_eq!(AspectRatio::SquarePixels.fit(1000, 500), (100, 0, 800, 500));
+        assert_eq!(AspectRatio::Classic.fit(1000, 600), (100, 0, 800, 600));
+        // Taller window: bars above and below.
+        assert_eq!(AspectRatio::SquarePixels.fit(640, 600), (0, 100, 640, 400));
+        assert_eq!(AspectRatio::Classic.fit(640, 600), (0, 60, 640, 480));
+        assert_eq!(AspectRatio::Stretch.fit(640, 600), (0, 0, 640, 600));
+    }
+
+    /// Filler that only counts the polygons it is asked to fill.
+    #[derive(Default)]
+    struct CountingFiller(usize);
+
+    impl PolygonFiller for CountingFiller {
+        fn fill_polygon(
+            &mut self,
+            _poly: &Polygon,
+            _color_idx: u8,
+            _dst_page_id: usize,
+            _pos: (i16, i16),
+            _offset: (i16, i16),
+            _zoom: u16,
+            _options: &PolygonOptions,
+        ) {
+            self.0 += 1;
+        }
+    }
+
+    fn draw_strict(segment: &[u8], start_offset: u16, strict: bool) -> usize {
+        let mut filler = CountingFiller::default();
+        SimplePolygonRenderer::draw_polygon(
+            segment,
+            start_offset,
+            0,
+            (0, 0),
+            (0, 0),
+            64,
+            None,
+            &PolygonOptions {
+                strict,
+                ..Default::default()
+            },
+            &mut filler,
+        );
+        filler.0
+    }
+
+    fn draw(segment: &[u8], start_offset: u16) -> usize {
+        draw_strict(segment, start_offset, false)
+    }
+
+    #[test]
+    fn test_draw_polygon_out_of_range() {
+        // A 4 points polygon, then a hierarchy of one child pointing to it.
+        let segment = [
+            0xc1, 2, 2, 4, 2, 0, 2, 2, 0, 2, 0, 0, 0x02, 0, 0, 0, 0x00, 0x00, 0, 0,
+        ];
+        assert_eq!(draw(&segment, 0), 1);
+        assert_eq!(draw(&segment, 12), 1);
+
+        // None of these must read past the segment.
+        assert_eq!(draw(&segment, segment.len() as u16), 0);
+        assert_eq!(draw(&segment[0..3], 0), 0);
+        assert_eq!(draw(&segment[0..8], 0), 0);
+        assert_eq!(draw(&segment[0..17], 12), 0);
+        // Child pointing past the end of the segment.
+        let segment = [0x02, 0, 0, 0, 0x7f, 0xff, 0, 0];
+        assert_eq!(draw(&segment, 0), 0);
+    }
+
+    #[test]
+    fn test_draw_invalid_polygon() {
+        // Opposite points with different y, then a polygon with too few points.
+        let segment = [
+            0xc1, 2, 2, 4, 2, 0, 2, 2, 0, 1, 0, 0, 0xc1, 2, 2, 2, 2, 0, 0, 0,
+        ];
+        assert_eq!(draw(&segment, 0), 0);
+        assert_eq!(draw(&segment, 12), 0);
+    }
+
+    /// A polygon of color 0x12, then a hierarchy drawing it with color 0x11 and 0x20.
+    const INVALID_COLOR_SEGMENT: [u8; 28] = [
+        0xd2, 2, 2, 4, 2, 0, 2, 2, 0, 2, 0, 0, 0x02, 0, 0, 1, 0x80, 0x00, 0, 0, 0x11, 0, 0x80,
+        0x00, 0, 0, 0x20, 0,
+    ];
+
+    #[test]
+    fn test_draw_invalid_color() {
+        // Polygons of invalid colors are skipped, whether the color comes from the polygon or
+        // from its parent.
+        assert_eq!(draw(&INVALID_COLOR_SEGMENT, 0), 0);
+        assert_eq!(draw(&INVALID_COLOR_SEGMENT, 12), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unexpected color 0x12")]
+    fn test_draw_invalid_color_strict() {
+        draw_strict(&INVALID_COLOR_SEGMENT, 0, true);
+    }
+
+    #[test]
+    fn test_overlay_text_rgba() {
+        // The glyph of `|` is a 7x7 block in the top-left corner of the character.
+        let rgba = overlay_text_rgba(&["|"]);
+        let pixel = |x: usize, y: usize| {
+            let offset = (y * SCREEN_RESOLUTION[0] + x) * 4;
+            &rgba[offset..offset + 4]
+        };
+
+        assert_eq!(rgba.len(), SCREEN_RESOLUTION[0] * SCREEN_RESOLUTION[1] * 4);
+        // Text is drawn from the second character cell, in white.
+        assert_eq!(pixel(8, 8), [0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(pixel(14, 14), [0xff, 0xff, 0xff, 0xff]);
+        // Its shadow is one pixel below and to the right, in black.
+        assert_eq!(pixel(15, 15), [0x00, 0x00, 0x00, 0xff]);
+        assert_eq!(pixel(9, 15), [0x00, 0x00, 0x00, 0xff]);
+        // The rest of the image is transparent.
+        assert_eq!(pixel(15, 8)[3], 0);
+        assert_eq!(pixel(7, 7)[3], 0);
+        assert_eq!(pixel(100, 100)[3], 0);
+    }
+
+    #[test]
+    fn test_color_blend() {
+        let black = Color { r: 0, g: 0, b: 0 };
+        let color = Color {
+            r: 0xff,
+            g: 0x11,
+            b: 0x80,
+        };
+
+        let blended = color.blend(&black);
+        assert_eq!((blended.r, blended.g, blended.b), (0x7f, 0x08, 0x40));
+        let blended = color.blend(&color);
+        assert_eq!((blended.r, blended.g, blended.b), (0xff, 0x11, 0x80));
+    }
+}
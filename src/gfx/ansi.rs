@@ -0,0 +1,84 @@
+//! Rendering of frames into text colored with ANSI escape codes, to preview the game in a
+//! terminal.
+
+use crate::gfx::RgbFrame;
+
+/// Character drawn in every cell: its upper half takes the foreground color, and its lower half
+/// the background one, which gives two pixels per character.
+const UPPER_HALF_BLOCK: char = '\u{2580}';
+
+/// Returns the index of the closest color of the 6x6x6 color cube of the 256-color ANSI palette.
+fn ansi_color([r, g, b]: [u8; 3]) -> u8 {
+    let level = |v: u8| ((v as u16 * 5 + 127) / 255) as u8;
+
+    16 + 36 * level(r) + 6 * level(g) + level(b)
+}
+
+/// Returns the average color of the pixels of `frame` in `xs` and `ys`.
+fn average(frame: &RgbFrame, xs: std::ops::Range<usize>, ys: std::ops::Range<usize>) -> [u8; 3] {
+    let mut sum = [0usize; 3];
+    for y in ys.clone() {
+        for x in xs.clone() {
+            let offset = (y * frame.width + x) * 3;
+            for (sum, &v) in sum.iter_mut().zip(&frame.pixels[offset..offset + 3]) {
+                *sum += v as usize;
+            }
+        }
+    }
+    let count = (xs.len() * ys.len()).max(1);
+
+    sum.map(|sum| (sum / count) as u8)
+}
+
+/// Returns `frame` downsampled to `columns` characters wide text, with as many lines as needed to
+/// keep the pixels square. Each line ends by resetting the colors.
+pub fn frame_to_ansi(frame: &RgbFrame, columns: usize) -> String {
+    let columns = columns.clamp(1, frame.width);
+    // Each line shows two rows of downsampled pixels.
+    let rows = ((frame.height * columns / frame.width) & !1).max(2);
+    let xs = |column: usize| column * frame.width / columns..(column + 1) * frame.width / columns;
+    let ys = |row: usize| row * frame.height / rows..(row + 1) * frame.height / rows;
+
+    let mut text = String::new();
+    for line in 0..rows / 2 {
+        for column in 0..columns {
+            let top = ansi_color(average(frame, xs(column), ys(line * 2)));
+            let bottom = ansi_color(average(frame, xs(column), ys(line * 2 + 1)));
+            text.push_str(&format!(
+                "\x1b[38;5;{}m\x1b[48;5;{}m{}",
+                top, bottom, UPPER_HALF_BLOCK
+            ));
+        }
+        text.push_str("\x1b[0m\n");
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_to_ansi() {
+        // 4x4 frame, red on top and blue at the bottom.
+        let frame = RgbFrame {
+            width: 4,
+            height: 4,
+            pixels: (0..16)
+                .flat_map(|i| if i < 8 { [255, 0, 0] } else { [0, 0, 255] })
+                .collect(),
+        };
+
+        let cell = "\x1b[38;5;196m\x1b[48;5;21m\u{2580}";
+        assert_eq!(
+            frame_to_ansi(&frame, 2),
+            format!("{}{}\x1b[0m\n", cell, cell)
+        );
+        // There cannot be more columns than pixels.
+        let text = frame_to_ansi(&frame, 8);
+        assert_eq!(text.lines().count(), 2);
+        assert_eq!(text.matches('\u{2580}').count(), 8);
+        assert_eq!(ansi_color([128, 128, 128]), 16 + 36 * 3 + 6 * 3 + 3);
+    }
+}
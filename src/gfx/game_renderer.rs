@@ -11,11 +11,21 @@ use crate::gfx::PolySegment;
 /// Implementors receive the VM rendering commands as-is. They are also expected to implement
 /// [`InitForScene`] in order to receive and process the graphics segments which the rendering
 /// commands depend on.
+///
+/// Pages contain 4-bit indices into the current palette. The transparency color `0x10` of
+/// polygons sets bit `0x8` of the pixels it covers, which selects the upper half of the palette:
+/// the resulting `0x8..=0xf` indices are regular colors from then on, so they are copied as-is
+/// by [`copyvideopage`](Self::copyvideopage) and looked up like any other index when the page is
+/// displayed.
 pub trait GameRenderer {
     /// Fill video page `page_id` entirely with color `color_idx`.
     fn fillvideopage(&mut self, page_id: usize, color_idx: u8);
     /// Copy video page `src_page_id` into `dst_page_id`. `vscroll` is a vertical offset
     /// for the copy.
+    ///
+    /// Lines of the source that are scrolled out of the screen are not copied, so a `vscroll` of
+    /// 200 lines or more in either direction copies nothing and leaves `dst_page_id` as it is, like
+    /// the original game does.
     fn copyvideopage(&mut self, src_page_id: usize, dst_page_id: usize, vscroll: i16);
     /// Draw the polygons which data starts at `offset` of `segment`.
     ///
@@ -86,6 +86,25 @@ pub(crate) fn link_program(vertex_shader: GLuint, fragment_shader: GLuint) -> GL
     }
 }
 
+/// Set the `truecolor` and `palette` uniforms of `program`, which must be in use.
+///
+/// `palette` is `Some` when rendering in true-color mode, `None` when rendering color indices.
+pub(crate) fn set_palette_uniforms(program: GLuint, palette: Option<&gfx::Palette>) {
+    unsafe {
+        let truecolor_uniform = get_uniform_location(program, c"truecolor");
+        gl::Uniform1i(truecolor_uniform, palette.is_some() as GLint);
+
+        if let Some(palette) = palette {
+            let palette_uniform = get_uniform_location(program, c"palette");
+            gl::Uniform1uiv(
+                palette_uniform,
+                gfx::PALETTE_SIZE as GLint,
+                palette.as_ptr() as *const u32,
+            );
+        }
+    }
+}
+
 /// Implemented by potential sources for the texture of `IndexedTexture`.
 pub trait IndexedTextureSource {
     /// Return the (width, height) dimensions of the source frame.
@@ -96,7 +115,7 @@ pub trait IndexedTextureSource {
 
 impl IndexedTextureSource for IndexedImage {
     fn dimensions(&self) -> (usize, usize) {
-        (gfx::SCREEN_RESOLUTION[0], gfx::SCREEN_RESOLUTION[1])
+        IndexedImage::dimensions(self)
     }
 
     fn data(&self) -> *const u8 {
@@ -107,11 +126,15 @@ impl IndexedTextureSource for IndexedImage {
 /// An OpenGL texture which format is similar to that of `IndexedImage`, i.e.
 /// 4-bpp indexed colors. It can be rendered into by a shader, or be used as
 /// a shader input.
+///
+/// For the enhanced true-color rendering mode, the texture can also be created to contain RGBA
+/// pixels instead of color indices.
 #[derive(Debug)]
 pub struct IndexedTexture {
     texture: GLuint,
     width: usize,
     height: usize,
+    truecolor: bool,
 }
 
 impl Drop for IndexedTexture {
@@ -124,6 +147,15 @@ impl Drop for IndexedTexture {
 
 impl IndexedTexture {
     pub fn new(width: usize, height: usize) -> Self {
+        Self::new_with_format(width, height, false)
+    }
+
+    /// Create a texture containing RGBA pixels instead of color indices.
+    pub fn new_truecolor(width: usize, height: usize) -> Self {
+        Self::new_with_format(width, height, true)
+    }
+
+    pub fn new_with_format(width: usize, height: usize, truecolor: bool) -> Self {
         let mut texture = 0;
         unsafe {
             gl::GenTextures(1, &mut texture);
@@ -131,11 +163,11 @@ impl IndexedTexture {
             gl::TexImage2D(
                 gl::TEXTURE_2D,
                 0,
-                gl::RED as i32,
+                Self::gl_format(truecolor) as i32,
                 width as GLint,
                 height as GLint,
                 0,
-                gl::RED,
+                Self::gl_format(truecolor),
                 gl::UNSIGNED_BYTE,
                 std::ptr::null(),
             );
@@ -148,6 +180,15 @@ impl IndexedTexture {
             texture,
             width,
             height,
+            truecolor,
+        }
+    }
+
+    fn gl_format(truecolor: bool) -> GLenum {
+        if truecolor {
+            gl::RGBA
+        } else {
+            gl::RED
         }
     }
 
@@ -155,16 +196,31 @@ impl IndexedTexture {
         (self.width, self.height)
     }
 
+    /// Whether this texture contains RGBA pixels rather than color indices.
+    pub fn is_truecolor(&self) -> bool {
+        self.truecolor
+    }
+
     pub fn as_tex_id(&self) -> GLuint {
         self.texture
     }
 
     pub fn set_data<S: IndexedTextureSource>(&mut self, source: &S, xoffset: i32, yoffset: i32) {
+        assert!(!self.truecolor);
         let dimensions = source.dimensions();
 
         self.set_raw_data(source.data(), dimensions.0, dimensions.1, xoffset, yoffset)
     }
 
+    /// Set the content of a true-color texture from `rgba`, which must contain 4 bytes for each
+    /// pixel of the `width`x`height` area to update.
+    pub fn set_rgba_data(&mut self, rgba: &[u8], width: usize, height: usize) {
+        assert!(self.truecolor);
+        assert_eq!(rgba.len(), width * height * 4);
+
+        self.set_raw_data(rgba.as_ptr(), width, height, 0, 0)
+    }
+
     fn set_raw_data(
         &mut self,
         data: *const u8,
@@ -182,7 +238,7 @@ impl IndexedTexture {
                 yoffset as GLint,
                 width as GLint,
                 height as GLint,
-                gl::RED,
+                Self::gl_format(self.truecolor),
                 gl::UNSIGNED_BYTE,
                 data as *const _,
             );
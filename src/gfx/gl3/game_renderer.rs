@@ -1,16 +1,21 @@
 mod programs;
 
+use std::io;
+use std::io::Write;
+
 use gl::types::GLint;
 use gl::types::GLuint;
 
 // TODO: not elegant, but needed for now.
 pub use programs::PolyRenderingMode;
 
+use crate::font::Font;
 use crate::gfx::gl3::GlRenderer;
 use crate::gfx::gl3::IndexedTexture;
 use crate::gfx::polygon::OwnedPolygon;
 use crate::gfx::polygon::Polygon;
 use crate::gfx::sw::IndexedImage;
+use crate::gfx::Palette;
 use crate::gfx::SimplePolygonRenderer;
 use crate::gfx::{self};
 use crate::scenes::InitForScene;
@@ -94,9 +99,98 @@ enum DrawCommand {
     Char(CharDrawCommand),
 }
 
+impl DrawCommand {
+    /// Returns a copy of this command that draws `vscroll` lines lower on the screen (or higher if
+    /// `vscroll` is negative).
+    ///
+    /// Contrary to the raster renderer, the lines uncovered by the scrolling are not preserved:
+    /// screen fills still cover the whole screen, and scrolled bitmaps leave these lines black.
+    fn vscrolled(&self, vscroll: i16) -> DrawCommand {
+        if vscroll == 0 {
+            return self.clone();
+        }
+
+        match self {
+            DrawCommand::Fill(_) => self.clone(),
+            DrawCommand::Poly(poly) => DrawCommand::Poly(PolyDrawCommand {
+                pos: (poly.pos.0, poly.pos.1 + vscroll),
+                ..poly.clone()
+            }),
+            DrawCommand::BlitBuffer(buffer) => {
+                let mut image: IndexedImage = Default::default();
+                image.copy_from_vscrolled(&buffer.image, vscroll);
+                DrawCommand::BlitBuffer(image.into())
+            }
+            DrawCommand::Char(c) => DrawCommand::Char(CharDrawCommand {
+                pos: (c.pos.0, c.pos.1 + vscroll),
+                ..c.clone()
+            }),
+        }
+    }
+}
+
+impl DrawCommand {
+    /// Write this command as a single-line JSON object into `w`.
+    fn write_json<W: Write + ?Sized>(&self, w: &mut W) -> io::Result<()> {
+        match self {
+            DrawCommand::Fill(fill) => write!(w, r#"{{"type":"fill","color":{}}}"#, fill.color),
+            DrawCommand::Poly(poly) => {
+                let bb = poly.poly.bb();
+                write!(
+                    w,
+                    r#"{{"type":"poly","pos":[{},{}],"offset":[{},{}],"zoom":{},"color":{},"bb":[{},{}],"points":["#,
+                    poly.pos.0,
+                    poly.pos.1,
+                    poly.offset.0,
+                    poly.offset.1,
+                    poly.zoom,
+                    poly.color,
+                    bb.0,
+                    bb.1
+                )?;
+                for (i, point) in poly.poly.points_iter().enumerate() {
+                    let sep = if i == 0 { "" } else { "," };
+                    write!(w, "{}[{},{}]", sep, point.x, point.y)?;
+                }
+                // Area and centroid are given in the polygon's own coordinates, before zooming.
+                write!(w, r#"],"area":{},"centroid":"#, poly.poly.area())?;
+                match poly.poly.centroid() {
+                    Some(c) => write!(w, "[{},{}]}}", c.x, c.y),
+                    None => write!(w, "null}}"),
+                }
+            }
+            DrawCommand::BlitBuffer(_) => write!(w, r#"{{"type":"blit"}}"#),
+            DrawCommand::Char(c) => write!(
+                w,
+                r#"{{"type":"char","pos":[{},{}],"color":{},"char":{}}}"#,
+                c.pos.0, c.pos.1, c.color, c.c
+            ),
+        }
+    }
+}
+
 #[derive(Default, Clone)]
 struct DrawCommands([Vec<DrawCommand>; 4]);
 
+impl DrawCommands {
+    /// Write the commands of all pages as JSON into `w`, along with the page being displayed.
+    ///
+    /// Each command is on its own line, so the output of two frames can be compared with a
+    /// regular diff tool.
+    fn write_json<W: Write + ?Sized>(&self, displayed_page: usize, w: &mut W) -> io::Result<()> {
+        writeln!(w, r#"{{"displayed_page":{},"pages":["#, displayed_page)?;
+        for (i, commands) in self.0.iter().enumerate() {
+            writeln!(w, "[")?;
+            for (j, command) in commands.iter().enumerate() {
+                command.write_json(w)?;
+                writeln!(w, "{}", if j + 1 < commands.len() { "," } else { "" })?;
+            }
+            writeln!(w, "]{}", if i + 1 < self.0.len() { "," } else { "" })?;
+        }
+        writeln!(w, "]}}")
+    }
+}
+
 impl gfx::PolygonFiller for DrawCommands {
     fn fill_polygon(
         &mut self,
@@ -106,6 +200,7 @@ impl gfx::PolygonFiller for DrawCommands {
         pos: (i16, i16),
         offset: (i16, i16),
         zoom: u16,
+        _options: &gfx::PolygonOptions,
     ) {
         let command = &mut self.0[dst_page_id];
         command.push(DrawCommand::Poly(PolyDrawCommand::new(
@@ -118,19 +213,100 @@ impl gfx::PolygonFiller for DrawCommands {
     }
 }
 
+/// Records the draw operations of the game into a list of commands for each page.
+///
+/// This is the CPU side of [`GlGameRenderer`], which replays these commands on the GPU when a
+/// page needs to be displayed.
+#[derive(Default, Clone)]
+struct CommandRecorder {
+    renderer: SimplePolygonRenderer,
+    draw_commands: DrawCommands,
+}
+
+impl InitForScene for CommandRecorder {
+    fn init_from_scene(
+        &mut self,
+        resman: &crate::res::ResourceManager,
+        scene: &crate::scenes::Scene,
+    ) -> std::io::Result<()> {
+        self.renderer.init_from_scene(resman, scene)
+    }
+}
+
+#[cfg(test)]
+impl crate::gfx::headless::PageReader for CommandRecorder {}
+
+impl gfx::GameRenderer for CommandRecorder {
+    fn fillvideopage(&mut self, page_id: usize, color_idx: u8) {
+        let commands = &mut self.draw_commands.0[page_id];
+        commands.clear();
+
+        commands.push(DrawCommand::Fill(FillScreenCommand::new(color_idx)));
+    }
+
+    fn copyvideopage(&mut self, src_page_id: usize, dst_page_id: usize, vscroll: i16) {
+        // Nothing of the source page would end up on screen.
+        if vscroll.unsigned_abs() as usize >= gfx::SCREEN_RESOLUTION[1] {
+            return;
+        }
+
+        let src_polys = self.draw_commands.0[src_page_id]
+            .iter()
+            .map(|command| command.vscrolled(vscroll))
+            .collect();
+        self.draw_commands.0[dst_page_id] = src_polys;
+    }
+
+    fn draw_char(&mut self, dst_page_id: usize, pos: (i16, i16), color: u8, c: u8) {
+        let command_queue = &mut self.draw_commands.0[dst_page_id];
+        command_queue.push(DrawCommand::Char(CharDrawCommand::new(pos, color, c)));
+    }
+
+    fn blit_buffer(&mut self, dst_page_id: usize, buffer: &[u8]) {
+        let mut image: IndexedImage = Default::default();
+        image
+            .set_content(buffer)
+            .unwrap_or_else(|e| tracing::error!("blit_buffer failed: {}", e));
+
+        self.draw_commands.0[dst_page_id].clear();
+        self.draw_commands.0[dst_page_id].push(DrawCommand::BlitBuffer(image.into()));
+    }
+
+    fn draw_polygons(
+        &mut self,
+        segment: gfx::PolySegment,
+        start_offset: u16,
+        dst_page_id: usize,
+        pos: (i16, i16),
+        offset: (i16, i16),
+        zoom: u16,
+    ) {
+        self.renderer.draw_polygons(
+            segment,
+            start_offset,
+            dst_page_id,
+            pos,
+            offset,
+            zoom,
+            &mut self.draw_commands,
+        )
+    }
+}
+
 /// A renderer that uses the GPU to render the game into a 16 colors indexed buffer of any size.
 pub struct GlGameRenderer {
-    renderer: SimplePolygonRenderer,
+    commands: CommandRecorder,
 
     rendering_mode: PolyRenderingMode,
 
-    draw_commands: DrawCommands,
     framebuffer_index: usize,
 
     target_fbo: GLuint,
 
     render_texture_buffer0: IndexedTexture,
     render_texture_framebuffer: IndexedTexture,
+    /// Whether we render actual colors instead of color indices.
+    truecolor: bool,
 
     renderers: Programs,
 }
@@ -150,15 +326,21 @@ impl InitForScene for GlGameRenderer {
         resman: &crate::res::ResourceManager,
         scene: &crate::scenes::Scene,
     ) -> std::io::Result<()> {
-        self.renderer.init_from_scene(resman, scene)
+        self.commands.init_from_scene(resman, scene)
     }
 }
 
 impl GlGameRenderer {
+    /// Create a new renderer rendering into textures of `width`x`height`.
+    ///
+    /// If `truecolor` is set, the render textures contain the actual colors of the current palette
+    /// (see [`GlGameRenderer::set_palette`]) instead of color indices. This allows effects that
+    /// cannot be done with 16 colors, at the cost of faithfulness.
     pub fn new(
         rendering_mode: PolyRenderingMode,
         width: usize,
         height: usize,
+        truecolor: bool,
     ) -> Result<GlGameRenderer> {
         let mut target_fbo = 0;
 
@@ -170,13 +352,13 @@ impl GlGameRenderer {
         }
 
         Ok(GlGameRenderer {
-            renderer: Default::default(),
+            commands: Default::default(),
             rendering_mode,
-            draw_commands: Default::default(),
             framebuffer_index: 0,
             target_fbo,
-            render_texture_buffer0: IndexedTexture::new(width, height),
-            render_texture_framebuffer: IndexedTexture::new(width, height),
+            render_texture_buffer0: IndexedTexture::new_with_format(width, height, truecolor),
+            render_texture_framebuffer: IndexedTexture::new_with_format(width, height, truecolor),
+            truecolor,
             renderers: Programs::new(
                 FillRenderer::new(),
                 PolyRenderer::new()?,
@@ -191,16 +373,63 @@ impl GlGameRenderer {
         self.rendering_mode = rendering_mode;
     }
 
+    /// Use `font` to draw characters.
+    pub fn set_font(&mut self, font: &Font) {
+        self.renderers.font_mut().set_font(font);
+    }
+
+    /// Panic when a polygon has an invalid color if `strict` is set, instead of logging a warning
+    /// and skipping it.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.commands.renderer.options_mut().strict = strict;
+    }
+
+    /// Set the width of the lines in [`PolyRenderingMode::Line`] mode.
+    pub fn set_line_width(&mut self, width: f32) {
+        self.renderers.poly_mut().set_line_width(width);
+    }
+
+    pub fn line_width(&self) -> f32 {
+        self.renderers.poly().line_width()
+    }
+
+    /// Enable or disable anti-aliasing of the lines in [`PolyRenderingMode::Line`] mode.
+    pub fn set_line_smooth(&mut self, smooth: bool) {
+        self.renderers.poly_mut().set_line_smooth(smooth);
+    }
+
+    pub fn line_smooth(&self) -> bool {
+        self.renderers.poly().line_smooth()
+    }
+
+    /// Set the palette to render with in true-color mode. Has no effect in indexed mode, where the
+    /// palette is applied when displaying the rendered texture.
+    pub fn set_palette(&mut self, palette: &Palette) {
+        if self.truecolor {
+            self.renderers.set_palette(Some(palette.clone()));
+        }
+    }
+
     #[tracing::instrument(level = "debug", skip(self))]
     pub fn resize_render_textures(&mut self, width: usize, height: usize) {
-        self.render_texture_buffer0 = IndexedTexture::new(width, height);
-        self.render_texture_framebuffer = IndexedTexture::new(width, height);
+        self.render_texture_buffer0 =
+            IndexedTexture::new_with_format(width, height, self.truecolor);
+        self.render_texture_framebuffer =
+            IndexedTexture::new_with_format(width, height, self.truecolor);
         self.redraw();
     }
 
+    /// Write the draw commands of every page as JSON into `w`, for inspecting what makes up the
+    /// current frame.
+    pub fn write_drawlist<W: Write + ?Sized>(&self, w: &mut W) -> io::Result<()> {
+        self.commands
+            .draw_commands
+            .write_json(self.framebuffer_index, w)
+    }
+
     #[tracing::instrument(level = "debug", skip(self))]
     fn run_command_list(&mut self, commands_index: usize, rendering_mode: PolyRenderingMode) {
-        let draw_commands = &self.draw_commands.0[commands_index];
+        let draw_commands = &self.commands.draw_commands.0[commands_index];
         let mut draw_runner = self.renderers.start_drawing(
             &self.render_texture_framebuffer,
             &self.render_texture_buffer0,
@@ -272,30 +501,20 @@ impl GlGameRenderer {
 
 impl gfx::GameRenderer for GlGameRenderer {
     fn fillvideopage(&mut self, page_id: usize, color_idx: u8) {
-        let commands = &mut self.draw_commands.0[page_id];
-        commands.clear();
-
-        commands.push(DrawCommand::Fill(FillScreenCommand::new(color_idx)));
+        self.commands.fillvideopage(page_id, color_idx)
     }
 
-    fn copyvideopage(&mut self, src_page_id: usize, dst_page_id: usize, _vscroll: i16) {
-        let src_polys = self.draw_commands.0[src_page_id].clone();
-        self.draw_commands.0[dst_page_id] = src_polys;
+    fn copyvideopage(&mut self, src_page_id: usize, dst_page_id: usize, vscroll: i16) {
+        self.commands
+            .copyvideopage(src_page_id, dst_page_id, vscroll)
     }
 
     fn draw_char(&mut self, dst_page_id: usize, pos: (i16, i16), color: u8, c: u8) {
-        let command_queue = &mut self.draw_commands.0[dst_page_id];
-        command_queue.push(DrawCommand::Char(CharDrawCommand::new(pos, color, c)));
+        self.commands.draw_char(dst_page_id, pos, color, c)
     }
 
     fn blit_buffer(&mut self, dst_page_id: usize, buffer: &[u8]) {
-        let mut image: IndexedImage = Default::default();
-        image
-            .set_content(buffer)
-            .unwrap_or_else(|e| tracing::error!("blit_buffer failed: {}", e));
-
-        self.draw_commands.0[dst_page_id].clear();
-        self.draw_commands.0[dst_page_id].push(DrawCommand::BlitBuffer(image.into()));
+        self.commands.blit_buffer(dst_page_id, buffer)
     }
 
     fn draw_polygons(
@@ -307,15 +526,8 @@ impl gfx::GameRenderer for GlGameRenderer {
         offset: (i16, i16),
         zoom: u16,
     ) {
-        self.renderer.draw_polygons(
-            segment,
-            start_offset,
-            dst_page_id,
-            pos,
-            offset,
-            zoom,
-            &mut self.draw_commands,
-        )
+        self.commands
+            .draw_polygons(segment, start_offset, dst_page_id, pos, offset, zoom)
     }
 }
 
@@ -331,14 +543,14 @@ impl Snapshotable for GlGameRenderer {
     #[tracing::instrument(level = "debug", skip(self))]
     fn take_snapshot(&self) -> Self::State {
         GlPolyRendererSnapshot {
-            draw_commands: self.draw_commands.clone(),
+            draw_commands: self.commands.draw_commands.clone(),
             framebuffer_index: self.framebuffer_index,
         }
     }
 
     #[tracing::instrument(level = "debug", skip(self, snapshot))]
     fn restore_snapshot(&mut self, snapshot: &Self::State) -> bool {
-        self.draw_commands = snapshot.draw_commands.clone();
+        self.commands.draw_commands = snapshot.draw_commands.clone();
         self.framebuffer_index = snapshot.framebuffer_index;
         true
     }
@@ -357,3 +569,250 @@ impl GlRenderer for GlGameRenderer {
         self.redraw();
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gfx::polygon::Point;
+
+    /// Render `commands` on the CPU at the original resolution, starting from `image`.
+    ///
+    /// `page0` is the page that polygons of color 0x11 copy their pixels from, or `None` if
+    /// `image` is page 0 itself, in which case these polygons leave it unchanged.
+    fn rasterize(
+        mut image: IndexedImage,
+        page0: Option<&IndexedImage>,
+        commands: &[DrawCommand],
+    ) -> IndexedImage {
+        for command in commands {
+            match command {
+                DrawCommand::Fill(fill) => image.fill(fill.color),
+                DrawCommand::Poly(poly) => image.fill_polygon(
+                    &poly.poly,
+                    poly.pos,
+                    poly.offset,
+                    poly.zoom,
+                    &Default::default(),
+                    |line, off| match poly.color {
+                        0x10 => line.iter_mut().for_each(|pixel| *pixel |= 0x8),
+                        0x11 => {
+                            if let Some(page0) = page0 {
+                                line.copy_from_slice(&page0.pixels()[off..off + line.len()]);
+                            }
+                        }
+                        color => line.fill(color),
+                    },
+                ),
+                DrawCommand::BlitBuffer(buffer) => image = (*buffer.image).clone(),
+                // The test scene draws no text, so there is no need for a font here.
+                DrawCommand::Char(_) => panic!("test commands are not expected to draw text"),
+            }
+        }
+        image
+    }
+
+    /// Check that scrolling the draw commands of a page gives the same result as the raster
+    /// renderer does when it copies a rendered page with vscroll.
+    #[test]
+    fn test_vscrolled_commands_match_raster() {
+        let mut sprite = IndexedImage::default();
+        sprite.fill_polygon(
+            &OwnedPolygon::new(
+                (40, 40),
+                vec![
+                    Point::new(40, 0),
+                    Point::new(40, 40),
+                    Point::new(0, 40),
+                    Point::new(0, 0),
+                ],
+            ),
+            (100, 60),
+            (0, 0),
+            64,
+            &Default::default(),
+            |line, _| line.fill(0x3),
+        );
+
+        let commands = vec![
+            DrawCommand::BlitBuffer(sprite.into()),
+            DrawCommand::Poly(PolyDrawCommand::new(
+                OwnedPolygon::new(
+                    (60, 30),
+                    vec![
+                        Point::new(30, 0),
+                        Point::new(60, 30),
+                        Point::new(0, 30),
+                        Point::new(30, 0),
+                    ],
+                ),
+                (160, 100),
+                (0, 0),
+                64,
+                0x5,
+            )),
+            DrawCommand::Poly(PolyDrawCommand::new(
+                OwnedPolygon::new(
+                    (20, 20),
+                    vec![
+                        Point::new(20, 0),
+                        Point::new(20, 20),
+                        Point::new(0, 20),
+                        Point::new(0, 0),
+                    ],
+                ),
+                (300, 190),
+                (0, 0),
+                64,
+                0x7,
+            )),
+        ];
+        let rendered = rasterize(Default::default(), None, &commands);
+
+        for vscroll in [-30, -1, 0, 5, 60] {
+            let mut raster = IndexedImage::default();
+            raster.copy_from_vscrolled(&rendered, vscroll);
+
+            let scrolled_commands = commands
+                .iter()
+                .map(|command| command.vscrolled(vscroll))
+                .collect::<Vec<_>>();
+            let poly = rasterize(Default::default(), None, &scrolled_commands);
+
+            assert!(
+                raster.pixels() == poly.pixels(),
+                "output mismatch for vscroll {}",
+                vscroll
+            );
+        }
+    }
+
+    /// Check that the commands recorded while running a scene are the ones expected, and render
+    /// the same image as the reference renderer.
+    #[test]
+    fn test_commands_match_reference() {
+        use crate::testing::*;
+
+        let reference = render_scene_to_image(test_scene_resources(), TEST_SCENE, 2);
+        let (vm, recorder) = run_scene(
+            CommandRecorder::default(),
+            test_scene_resources(),
+            TEST_SCENE,
+            2,
+        );
+        let commands = &recorder.draw_commands.0[vm.front_buffer()];
+
+        let queued = commands
+            .iter()
+            .map(|command| match command {
+                DrawCommand::Fill(fill) => (None, (0, 0), (0, 0), fill.color),
+                DrawCommand::Poly(poly) => {
+                    (Some(poly.poly.bb()), poly.pos, poly.offset, poly.color)
+                }
+                _ => panic!("unexpected command"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            queued,
+            vec![
+                (None, (0, 0), (0, 0), 0x2),
+                (Some((40, 40)), (160, 100), (0, 0), 0x5),
+                (Some((40, 40)), (64, 48), (-10, -10), 0x7),
+                (Some((20, 20)), (64, 48), (20, 20), 0x10),
+                (Some((20, 20)), (160, 100), (0, 0), 0x10),
+            ]
+        );
+
+        let page0 = rasterize(Default::default(), None, &recorder.draw_commands.0[0]);
+        let page0 = (vm.front_buffer() != 0).then_some(&page0);
+        let rendered = rasterize(Default::default(), page0, commands);
+        assert!(reference.pixels() == rendered.pixels());
+    }
+
+    /// Check that polygons of color 0x11 are rasterized with the pixels of page 0.
+    #[test]
+    fn test_rasterize_copy_from_page0() {
+        let square = || {
+            OwnedPolygon::new(
+                (20, 20),
+                vec![
+                    Point::new(20, 0),
+                    Point::new(20, 20),
+                    Point::new(0, 20),
+                    Point::new(0, 0),
+                ],
+            )
+        };
+        let page0 = rasterize(
+            Default::default(),
+            None,
+            &[
+                DrawCommand::Fill(FillScreenCommand::new(0x4)),
+                DrawCommand::Poly(PolyDrawCommand::new(square(), (10, 10), (0, 0), 64, 0x6)),
+            ],
+        );
+
+        let commands = [
+            DrawCommand::Fill(FillScreenCommand::new(0x2)),
+            DrawCommand::Poly(PolyDrawCommand::new(square(), (20, 20), (0, 0), 64, 0x11)),
+        ];
+        let rendered = rasterize(Default::default(), Some(&page0), &commands);
+        let pixel = |image: &IndexedImage, x: usize, y: usize| image.pixels()[y * 320 + x];
+        // Covered by the square of page 0.
+        assert_eq!(pixel(&rendered, 15, 15), 0x6);
+        // Covered by the background of page 0.
+        assert_eq!(pixel(&rendered, 25, 25), 0x4);
+        // Outside of the copying polygon.
+        assert_eq!(pixel(&rendered, 5, 5), 0x2);
+        assert_eq!(pixel(&rendered, 40, 40), 0x2);
+
+        // Page 0 cannot copy from itself.
+        let rendered = rasterize(Default::default(), None, &commands);
+        assert_eq!(pixel(&rendered, 15, 15), 0x2);
+    }
+
+    #[test]
+    fn test_write_drawlist_json() {
+        let mut commands = DrawCommands::default();
+        commands.0[1] = vec![
+            DrawCommand::Fill(FillScreenCommand::new(0x2)),
+            DrawCommand::Poly(PolyDrawCommand::new(
+                OwnedPolygon::new(
+                    (4, 2),
+                    vec![
+                        Point::new(4, 0),
+                        Point::new(4, 2),
+                        Point::new(0, 2),
+                        Point::new(0, 0),
+                    ],
+                ),
+                (160, 100),
+                (-1, 1),
+                64,
+                0x5,
+            )),
+            DrawCommand::Char(CharDrawCommand::new((8, 16), 0xf, b'A')),
+        ];
+
+        let mut out = Vec::new();
+        commands.write_json(1, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert_eq!(
+            out,
+            concat!(
+                "{\"displayed_page\":1,\"pages\":[\n",
+                "[\n],\n",
+                "[\n",
+                "{\"type\":\"fill\",\"color\":2},\n",
+                "{\"type\":\"poly\",\"pos\":[160,100],\"offset\":[-1,1],\"zoom\":64,\"color\":5,",
+                "\"bb\":[4,2],\"points\":[[4,0],[4,2],[0,2],[0,0]],",
+                "\"area\":8,\"centroid\":[2,1]},\n",
+                "{\"type\":\"char\",\"pos\":[8,16],\"color\":15,\"char\":65}\n",
+                "],\n",
+                "[\n],\n",
+                "[\n]\n",
+                "]}\n",
+            )
+        );
+    }
+}
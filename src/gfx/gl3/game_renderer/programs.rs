@@ -12,6 +12,7 @@ pub use poly_renderer::PolyRenderingMode;
 use crate::gfx::gl3::IndexedTexture;
 use crate::gfx::polygon::Polygon;
 use crate::gfx::sw::IndexedImage;
+use crate::gfx::Palette;
 
 /// Trait for a GL program that can draw a certain class of object from the game (e.g. polygons or
 /// font).
@@ -19,7 +20,16 @@ pub trait Program {
     /// Activate the program, i.e. make it ready to draw. `target_texture` is where incoming draw
     /// commands should be rendered, while `buffer0` is a texture with framebuffer 0 (which is used
     /// as a source for some commands).
-    fn activate(&mut self, _target_texture: &IndexedTexture, _buffer0: &IndexedTexture) {}
+    ///
+    /// `palette` is only set when rendering in true-color mode, in which case the programs must
+    /// write the actual colors instead of color indices.
+    fn activate(
+        &mut self,
+        _target_texture: &IndexedTexture,
+        _buffer0: &IndexedTexture,
+        _palette: Option<&Palette>,
+    ) {
+    }
     /// Deactivate the program, flushing any pending operations.
     fn deactivate(&mut self) {}
 }
@@ -37,6 +47,8 @@ enum CurrentProgram {
 /// it through a `DrawCommandRunner`.
 pub struct Programs {
     current: CurrentProgram,
+    /// Palette to render with in true-color mode, `None` in indexed mode.
+    palette: Option<Palette>,
     fill: FillRenderer,
     poly: PolyRenderer,
     bitmap: BitmapRenderer,
@@ -58,6 +70,7 @@ impl Programs {
     ) -> Self {
         Self {
             current: CurrentProgram::None,
+            palette: None,
             fill,
             poly,
             bitmap,
@@ -73,6 +86,29 @@ impl Programs {
         DrawCommandRunner::new(self, target, buffer0)
     }
 
+    /// Set the palette to render actual colors with, or `None` to render color indices.
+    pub fn set_palette(&mut self, palette: Option<Palette>) {
+        self.deactivate();
+        self.palette = palette;
+    }
+
+    pub fn poly(&self) -> &PolyRenderer {
+        &self.poly
+    }
+
+    /// Returns the poly renderer for configuration. Any pending drawing is flushed first, so the new
+    /// settings only apply to the next draw commands.
+    pub fn poly_mut(&mut self) -> &mut PolyRenderer {
+        self.deactivate();
+        &mut self.poly
+    }
+
+    /// Returns the font renderer for configuration. Any pending drawing is flushed first.
+    pub fn font_mut(&mut self) -> &mut FontRenderer {
+        self.deactivate();
+        &mut self.font
+    }
+
     fn deactivate(&mut self) {
         match self.current {
             CurrentProgram::None => (),
@@ -93,7 +129,8 @@ impl Programs {
             CurrentProgram::Fill => (),
             _ => {
                 self.deactivate();
-                self.fill.activate(target_texture, buffer0);
+                self.fill
+                    .activate(target_texture, buffer0, self.palette.as_ref());
                 self.current = CurrentProgram::Fill;
             }
         }
@@ -109,7 +146,8 @@ impl Programs {
             CurrentProgram::Poly => (),
             _ => {
                 self.deactivate();
-                self.poly.activate(target_texture, buffer0);
+                self.poly
+                    .activate(target_texture, buffer0, self.palette.as_ref());
                 self.current = CurrentProgram::Poly;
             }
         }
@@ -125,7 +163,8 @@ impl Programs {
             CurrentProgram::Bitmap => (),
             _ => {
                 self.deactivate();
-                self.bitmap.activate(target_texture, buffer0);
+                self.bitmap
+                    .activate(target_texture, buffer0, self.palette.as_ref());
                 self.current = CurrentProgram::Bitmap;
             }
         }
@@ -141,7 +180,8 @@ impl Programs {
             CurrentProgram::Font => (),
             _ => {
                 self.deactivate();
-                self.font.activate(target_texture, buffer0);
+                self.font
+                    .activate(target_texture, buffer0, self.palette.as_ref());
                 self.current = CurrentProgram::Font;
             }
         }
@@ -5,6 +5,7 @@ use gl::types::GLuint;
 
 use crate::gfx::gl3::IndexedTexture;
 use crate::gfx::sw::IndexedImage;
+use crate::gfx::Palette;
 use crate::gfx::SCREEN_RESOLUTION;
 
 use super::Program;
@@ -15,7 +16,11 @@ use anyhow::Result;
 /// or some of the hard-coded backgrounds by the end of the game.
 pub struct BitmapRenderer {
     source_texture: RefCell<IndexedTexture>,
+    /// Source texture for true-color mode.
+    truecolor_source_texture: RefCell<IndexedTexture>,
     source_fbo: GLuint,
+    /// Palette to convert the bitmap with, if rendering in true-color mode.
+    palette: Option<Palette>,
 }
 
 impl Drop for BitmapRenderer {
@@ -26,7 +31,16 @@ impl Drop for BitmapRenderer {
     }
 }
 
-impl Program for BitmapRenderer {}
+impl Program for BitmapRenderer {
+    fn activate(
+        &mut self,
+        _target_texture: &IndexedTexture,
+        _buffer0: &IndexedTexture,
+        palette: Option<&Palette>,
+    ) {
+        self.palette = palette.cloned();
+    }
+}
 
 impl BitmapRenderer {
     pub fn new() -> Result<BitmapRenderer> {
@@ -45,6 +59,11 @@ impl BitmapRenderer {
                 SCREEN_RESOLUTION[0],
                 SCREEN_RESOLUTION[1],
             )),
+            truecolor_source_texture: RefCell::new(IndexedTexture::new_truecolor(
+                SCREEN_RESOLUTION[0],
+                SCREEN_RESOLUTION[1],
+            )),
+            palette: None,
         })
     }
 
@@ -52,7 +71,28 @@ impl BitmapRenderer {
     pub fn draw_bitmap(&self, image: &IndexedImage) {
         // TODO: super inefficient as we do this for every frame!
         // The texture should rather be in the command, and be refcounted?
-        self.source_texture.borrow_mut().set_data(image, 0, 0);
+        let source_texture = match &self.palette {
+            None => {
+                self.source_texture.borrow_mut().set_data(image, 0, 0);
+                &self.source_texture
+            }
+            Some(palette) => {
+                let rgba = image
+                    .pixels()
+                    .iter()
+                    .flat_map(|&c| {
+                        let color = palette.lookup(c & 0xf);
+                        [color.r, color.g, color.b, 0xff]
+                    })
+                    .collect::<Vec<u8>>();
+                self.truecolor_source_texture.borrow_mut().set_rgba_data(
+                    &rgba,
+                    SCREEN_RESOLUTION[0],
+                    SCREEN_RESOLUTION[1],
+                );
+                &self.truecolor_source_texture
+            }
+        };
 
         unsafe {
             // We draw the bitmap over the entire viewport - get the correct
@@ -64,7 +104,7 @@ impl BitmapRenderer {
             gl::FramebufferTexture(
                 gl::READ_FRAMEBUFFER,
                 gl::COLOR_ATTACHMENT0,
-                self.source_texture.borrow().as_tex_id(),
+                source_texture.borrow().as_tex_id(),
                 0,
             );
             if gl::CheckFramebufferStatus(gl::READ_FRAMEBUFFER) != gl::FRAMEBUFFER_COMPLETE {
@@ -1,17 +1,43 @@
+use crate::gfx::gl3::IndexedTexture;
+use crate::gfx::Palette;
+
 use super::Program;
 
-pub struct FillRenderer;
+pub struct FillRenderer {
+    /// Palette to fill with actual colors, if rendering in true-color mode.
+    palette: Option<Palette>,
+}
 
-impl Program for FillRenderer {}
+impl Program for FillRenderer {
+    fn activate(
+        &mut self,
+        _target_texture: &IndexedTexture,
+        _buffer0: &IndexedTexture,
+        palette: Option<&Palette>,
+    ) {
+        self.palette = palette.cloned();
+    }
+}
 
 impl FillRenderer {
     pub fn new() -> Self {
-        Self
+        Self { palette: None }
     }
 
     pub fn fill(&mut self, color: u8) {
         unsafe {
-            gl::ClearColor(color as f32 / 256.0, 0.0, 0.0, 0.0);
+            match &self.palette {
+                None => gl::ClearColor(color as f32 / 256.0, 0.0, 0.0, 0.0),
+                Some(palette) => {
+                    let color = palette.lookup(color & 0xf);
+                    gl::ClearColor(
+                        color.r as f32 / 255.0,
+                        color.g as f32 / 255.0,
+                        color.b as f32 / 255.0,
+                        1.0,
+                    )
+                }
+            }
             gl::Clear(gl::COLOR_BUFFER_BIT);
         }
     }
@@ -4,9 +4,16 @@ in vec2 char_pos;
 flat in uint char_color;
 flat in uint char_off;
 
-uniform uint font[192];
+uniform uint font[512];
+uniform bool truecolor;
+uniform uint palette[16];
 
-layout (location = 0) out float pix_color;
+layout (location = 0) out vec4 pix_color;
+
+vec4 palette_color(uint idx) {
+    uint color = palette[idx];
+    return vec4(color & 0xffu, (color >> 8u) & 0xffu, (color >> 16u) & 0xffu, 255u) / 255.0;
+}
 
 void main() {
     uvec2 char_upos = uvec2(char_pos.x, char_pos.y);
@@ -16,7 +23,11 @@ void main() {
     uint bit = (byte >> (8u - char_upos.x)) & 1u;
 
     if (bit != 0u) {
-        pix_color = (char_color & 0xfu) / 256.0;
+        if (truecolor) {
+            pix_color = palette_color(char_color & 0xfu);
+        } else {
+            pix_color = vec4((char_color & 0xfu) / 256.0, 0.0, 0.0, 1.0);
+        }
     } else {
         discard;
     }
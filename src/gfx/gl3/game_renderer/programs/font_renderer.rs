@@ -31,9 +31,15 @@ pub struct FontRenderer {
 }
 
 impl Program for FontRenderer {
-    fn activate(&mut self, _target_texture: &IndexedTexture, _buffer0: &IndexedTexture) {
+    fn activate(
+        &mut self,
+        _target_texture: &IndexedTexture,
+        _buffer0: &IndexedTexture,
+        palette: Option<&Palette>,
+    ) {
         unsafe {
             gl::UseProgram(self.program);
+            set_palette_uniforms(self.program, palette);
         }
     }
 }
@@ -100,19 +106,33 @@ impl FontRenderer {
 
             gl::BindVertexArray(0);
             gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+
+        let mut renderer = FontRenderer { vao, vbo, program };
+        renderer.set_font(&Default::default());
+
+        Ok(renderer)
+    }
+
+    /// Use `font` to draw characters.
+    pub fn set_font(&mut self, font: &Font) {
+        let glyphs = font.as_bytes();
 
-            gl::UseProgram(program);
-            let font_uniform = get_uniform_location(program, c"font");
-            gl::Uniform1uiv(font_uniform, 192, FONT.as_ptr() as *const GLuint);
+        unsafe {
+            gl::UseProgram(self.program);
+            let font_uniform = get_uniform_location(self.program, c"font");
+            gl::Uniform1uiv(
+                font_uniform,
+                (glyphs.len() / mem::size_of::<GLuint>()) as GLsizei,
+                glyphs.as_ptr() as *const GLuint,
+            );
             gl::UseProgram(0);
         }
-
-        Ok(FontRenderer { vao, vbo, program })
     }
 
     #[tracing::instrument(level = "trace", skip(self))]
     pub fn draw_char(&self, pos: (i16, i16), color: u8, c: u8) {
-        let char_offset = (c - FONT_FIRST_CHAR) as u16;
+        let char_offset = c as u16;
         let color = color as u16;
         // Looks like we are 1 pixel off horizontally?
         let pos = (pos.0 - 1, pos.1);
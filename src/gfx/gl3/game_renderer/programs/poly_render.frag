@@ -5,18 +5,45 @@ flat in uint color_idx;
 uniform sampler2D self;
 uniform sampler2D buffer0;
 uniform vec2 viewport_size;
+// Enhanced mode where actual colors are written instead of palette indices.
+uniform bool truecolor;
+uniform uint palette[16];
+// Color and opacity of transparent polygons in true-color mode, which are blended over the pixels
+// they cover.
+uniform vec3 transparency_color;
+uniform float transparency_alpha;
 
-layout (location = 0) out float color;
+// Alpha is only used when blending anti-aliased lines.
+layout (location = 0) out vec4 color;
 
 void main() {
+    if (truecolor) {
+        vec2 tex_pos = gl_FragCoord.xy / viewport_size;
+
+        if (color_idx == 0x10u) {
+            vec3 dst = texture(self, tex_pos).rgb;
+            color = vec4(mix(dst, transparency_color, transparency_alpha), 1.0);
+        } else if (color_idx == 0x11u) {
+            color = texture(buffer0, tex_pos);
+        } else {
+            uint c = palette[color_idx & 0xfu];
+            color = vec4(c & 0xffu, (c >> 8u) & 0xffu, (c >> 16u) & 0xffu, 255u) / 255.0;
+        }
+        return;
+    }
+
+    float index;
+
     if (color_idx == 0x10u) {
         uint source_color = uint(texture(self, gl_FragCoord.xy / viewport_size).r * 256.0);
-        color = (source_color | 0x8u) / 256.0;
+        index = (source_color | 0x8u) / 256.0;
     }
     else if (color_idx == 0x11u) {
-        color = texture(buffer0, gl_FragCoord.xy / viewport_size).r;
+        index = texture(buffer0, gl_FragCoord.xy / viewport_size).r;
     }
     else {
-        color = (color_idx & 0xfu) / 256.0;
+        index = (color_idx & 0xfu) / 256.0;
     }
+
+    color = vec4(index, 0.0, 0.0, 1.0);
 }
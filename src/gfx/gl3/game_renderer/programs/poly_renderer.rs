@@ -9,6 +9,7 @@ use gl::types::GLvoid;
 
 use crate::gfx::gl3::*;
 use crate::gfx::polygon::Polygon;
+use crate::gfx::Palette;
 
 use super::Program;
 
@@ -33,6 +34,32 @@ impl VertexShaderInput {
     }
 }
 
+/// Default width of the lines in [`PolyRenderingMode::Line`] mode.
+const DEFAULT_LINE_WIDTH: f32 = 5.0;
+const MIN_LINE_WIDTH: f32 = 1.0;
+const MAX_LINE_WIDTH: f32 = 10.0;
+
+/// Opacity of transparent polygons (color `0x10`) in true-color mode.
+const TRANSPARENCY_ALPHA: f32 = 0.5;
+
+/// Returns the color that transparent polygons (color `0x10`) are blended with in true-color
+/// mode, with components between 0 and 1.
+///
+/// The original game makes these pixels use the upper half of the palette, which usually contains
+/// brighter versions of the lower half. We emulate this by blending the pixels with the average
+/// color of that upper half, with an opacity of [`TRANSPARENCY_ALPHA`].
+fn transparency_color(palette: &Palette) -> [f32; 3] {
+    let mut color = [0f32; 3];
+    for i in 0x8..0x10 {
+        let bright = palette.lookup(i);
+        color[0] += bright.r as f32;
+        color[1] += bright.g as f32;
+        color[2] += bright.b as f32;
+    }
+
+    color.map(|c| c / (8.0 * 255.0))
+}
+
 /// How to render the polygons - either filled polygons, or outlines only.
 #[derive(Clone, Copy, Debug)]
 pub enum PolyRenderingMode {
@@ -54,6 +81,11 @@ pub struct PolyRenderer {
     vertices: Vec<VertexShaderInput>,
     indices: Vec<u16>,
     draw_type: GLuint,
+
+    /// Width of the lines when rendering outlines.
+    line_width: f32,
+    /// Whether outlines should be anti-aliased.
+    line_smooth: bool,
 }
 
 impl Drop for PolyRenderer {
@@ -68,10 +100,23 @@ impl Drop for PolyRenderer {
 
 impl Program for PolyRenderer {
     #[tracing::instrument(level = "debug", skip(self))]
-    fn activate(&mut self, target_texture: &IndexedTexture, buffer0: &IndexedTexture) {
+    fn activate(
+        &mut self,
+        target_texture: &IndexedTexture,
+        buffer0: &IndexedTexture,
+        palette: Option<&Palette>,
+    ) {
         let dimensions = target_texture.dimensions();
         unsafe {
             gl::UseProgram(self.program);
+            set_palette_uniforms(self.program, palette);
+            if let Some(palette) = palette {
+                let color = transparency_color(palette);
+                let color_uniform = get_uniform_location(self.program, c"transparency_color");
+                gl::Uniform3f(color_uniform, color[0], color[1], color[2]);
+                let alpha_uniform = get_uniform_location(self.program, c"transparency_alpha");
+                gl::Uniform1f(alpha_uniform, TRANSPARENCY_ALPHA);
+            }
 
             // Setup target texture to self (for transparency effect)
             gl::Uniform1i(self.self_uniform, 0);
@@ -86,12 +131,26 @@ impl Program for PolyRenderer {
 
             let viewport_uniform = get_uniform_location(self.program, c"viewport_size");
             gl::Uniform2f(viewport_uniform, dimensions.0 as f32, dimensions.1 as f32);
+
+            gl::LineWidth(self.line_width);
+            if self.line_smooth {
+                gl::Enable(gl::LINE_SMOOTH);
+                gl::Enable(gl::BLEND);
+                gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+            }
         }
     }
 
     #[tracing::instrument(level = "debug", skip(self))]
     fn deactivate(&mut self) {
         self.draw();
+
+        if self.line_smooth {
+            unsafe {
+                gl::Disable(gl::BLEND);
+                gl::Disable(gl::LINE_SMOOTH);
+            }
+        }
     }
 }
 
@@ -187,9 +246,32 @@ impl PolyRenderer {
             vertices: Default::default(),
             indices: Default::default(),
             draw_type: gl::TRIANGLE_STRIP,
+            line_width: DEFAULT_LINE_WIDTH,
+            line_smooth: false,
         })
     }
 
+    /// Set the width of the lines drawn in [`PolyRenderingMode::Line`] mode.
+    pub fn set_line_width(&mut self, width: f32) {
+        self.line_width = width.clamp(MIN_LINE_WIDTH, MAX_LINE_WIDTH);
+    }
+
+    pub fn line_width(&self) -> f32 {
+        self.line_width
+    }
+
+    /// Enable or disable anti-aliasing of the lines drawn in [`PolyRenderingMode::Line`] mode.
+    ///
+    /// Since we render indexed colors, the edges of the lines are blended into intermediate color
+    /// indices. This looks best when the palette's neighboring colors are similar.
+    pub fn set_line_smooth(&mut self, smooth: bool) {
+        self.line_smooth = smooth;
+    }
+
+    pub fn line_smooth(&self) -> bool {
+        self.line_smooth
+    }
+
     #[tracing::instrument(level = "trace", skip(self))]
     pub fn draw_poly(
         &mut self,
@@ -291,3 +373,31 @@ impl PolyRenderer {
 
 static VERTEX_SHADER: &str = std::include_str!("poly_render.vert");
 static FRAGMENT_SHADER: &str = std::include_str!("poly_render.frag");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transparency_blend() {
+        let mut raw_palette = [0u8; 32];
+        // Upper half of the palette: four pure red and four pure blue colors, i.e. a purple
+        // average. Colors are stored as 0x0RGB with 4 bits per component.
+        for i in 0x8..0x10 {
+            let color: u16 = if i < 0xc { 0x0f00 } else { 0x000f };
+            raw_palette[i * 2..i * 2 + 2].copy_from_slice(&color.to_be_bytes());
+        }
+        let mut palette = Palette::default();
+        palette.set(&raw_palette);
+
+        let color = transparency_color(&palette);
+        assert_eq!(color, [0.5, 0.0, 0.5]);
+
+        // Same computation as the fragment shader's `mix`.
+        let dst = [0.0, 1.0, 0.2];
+        let blended: Vec<f32> = (0..3)
+            .map(|i| dst[i] * (1.0 - TRANSPARENCY_ALPHA) + color[i] * TRANSPARENCY_ALPHA)
+            .collect();
+        assert_eq!(blended, [0.25, 0.5, 0.35]);
+    }
+}
@@ -75,12 +75,60 @@ impl IndexedFrameRenderer {
 
     /// Renders `source` using the color `palette` into `target_framebuffer`.
     ///
+    /// If `source` is a true-color texture, its pixels are rendered as-is and `palette` is unused.
+    ///
+    /// If `grid` is set, a faint grid is drawn on top of the game with lines spaced by that many
+    /// game pixels horizontally and vertically.
+    ///
     /// `target_framebuffer` must either be a valid FBO, or `0` in which case the default
     /// framebuffer will be used.
     pub fn render(
         &self,
         source: &IndexedTexture,
         palette: &Palette,
+        grid: Option<[usize; 2]>,
+        target_framebuffer: GLuint,
+        viewport: &Viewport,
+    ) {
+        self.draw(source, palette, grid, false, target_framebuffer, viewport);
+    }
+
+    /// Renders the true-color `source` on top of what `target_framebuffer` already contains,
+    /// blending it according to the alpha of its pixels.
+    pub fn render_overlay(
+        &self,
+        source: &IndexedTexture,
+        target_framebuffer: GLuint,
+        viewport: &Viewport,
+    ) {
+        assert!(source.is_truecolor());
+
+        unsafe {
+            gl::Enable(gl::BLEND);
+            // Only blend the colors, and keep the alpha of the framebuffer.
+            gl::BlendFuncSeparate(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA, gl::ZERO, gl::ONE);
+        }
+        self.draw(
+            source,
+            &Palette::default(),
+            None,
+            true,
+            target_framebuffer,
+            viewport,
+        );
+        unsafe {
+            gl::Disable(gl::BLEND);
+        }
+    }
+
+    /// Renders `source` like [`Self::render`], keeping the alpha of its pixels if `keep_alpha` is
+    /// set and it is a true-color texture.
+    fn draw(
+        &self,
+        source: &IndexedTexture,
+        palette: &Palette,
+        grid: Option<[usize; 2]>,
+        keep_alpha: bool,
         target_framebuffer: GLuint,
         viewport: &Viewport,
     ) {
@@ -91,6 +139,12 @@ impl IndexedFrameRenderer {
             let texture_uniform = get_uniform_location(self.program, c"game_scene");
             gl::Uniform1i(texture_uniform, 0);
 
+            let truecolor_uniform = get_uniform_location(self.program, c"truecolor");
+            gl::Uniform1i(truecolor_uniform, source.is_truecolor() as GLint);
+
+            let keep_alpha_uniform = get_uniform_location(self.program, c"keep_alpha");
+            gl::Uniform1i(keep_alpha_uniform, keep_alpha as GLint);
+
             let palette_uniform = get_uniform_location(self.program, c"palette");
             gl::Uniform1uiv(
                 palette_uniform,
@@ -98,6 +152,17 @@ impl IndexedFrameRenderer {
                 palette.as_ptr() as *const u32,
             );
 
+            let grid_cells_uniform = get_uniform_location(self.program, c"grid_cells");
+            let grid_cells = grid
+                .map(|size| {
+                    [
+                        gfx::SCREEN_RESOLUTION[0] as GLfloat / size[0] as GLfloat,
+                        gfx::SCREEN_RESOLUTION[1] as GLfloat / size[1] as GLfloat,
+                    ]
+                })
+                .unwrap_or([0.0, 0.0]);
+            gl::Uniform2f(grid_cells_uniform, grid_cells[0], grid_cells[1]);
+
             gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, target_framebuffer);
             gl::Viewport(viewport.x, viewport.y, viewport.width, viewport.height);
             gl::BindVertexArray(self.vao);
@@ -111,6 +176,85 @@ impl IndexedFrameRenderer {
             gl::BindTexture(gl::TEXTURE_2D, 0);
         }
     }
+
+    /// Renders `source` using the color `palette` into an offscreen framebuffer of the same size,
+    /// and returns its pixels.
+    pub fn read_rgb(&self, source: &IndexedTexture, palette: &Palette) -> gfx::RgbFrame {
+        let (width, height) = source.dimensions();
+        let mut pixels = vec![0u8; width * height * 3];
+
+        unsafe {
+            let mut texture = 0;
+            gl::GenTextures(1, &mut texture);
+            gl::BindTexture(gl::TEXTURE_2D, texture);
+            gl::TexImage2D(
+                gl::TEXTURE_2D,
+                0,
+                gl::RGB8 as GLint,
+                width as GLint,
+                height as GLint,
+                0,
+                gl::RGB,
+                gl::UNSIGNED_BYTE,
+                std::ptr::null(),
+            );
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+
+            let mut fbo = 0;
+            gl::GenFramebuffers(1, &mut fbo);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, fbo);
+            gl::FramebufferTexture2D(
+                gl::FRAMEBUFFER,
+                gl::COLOR_ATTACHMENT0,
+                gl::TEXTURE_2D,
+                texture,
+                0,
+            );
+
+            self.render(
+                source,
+                palette,
+                None,
+                fbo,
+                &Viewport {
+                    x: 0,
+                    y: 0,
+                    width: width as GLsizei,
+                    height: height as GLsizei,
+                },
+            );
+
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, fbo);
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+            gl::ReadPixels(
+                0,
+                0,
+                width as GLsizei,
+                height as GLsizei,
+                gl::RGB,
+                gl::UNSIGNED_BYTE,
+                pixels.as_mut_ptr() as *mut _,
+            );
+
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            gl::DeleteFramebuffers(1, &fbo);
+            gl::DeleteTextures(1, &texture);
+        }
+
+        // GL returns the lines from the bottom up.
+        let pixels = pixels
+            .chunks_exact(width * 3)
+            .rev()
+            .flatten()
+            .copied()
+            .collect();
+
+        gfx::RgbFrame {
+            width,
+            height,
+            pixels,
+        }
+    }
 }
 
 const VERTICES_STRIDE: GLsizei = 4 * mem::size_of::<GLfloat>() as GLsizei;
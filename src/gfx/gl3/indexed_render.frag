@@ -4,14 +4,34 @@ in vec2 scene_pos;
 
 uniform sampler2D game_scene;
 uniform uint palette[16];
+// The scene already contains RGB pixels and needs no palette lookup.
+uniform bool truecolor;
+// Output the alpha of true-color pixels, so they can be blended over what is already drawn.
+uniform bool keep_alpha;
+// Number of cells of the pixel grid across the scene, or zero to draw no grid.
+uniform vec2 grid_cells;
 
 layout (location = 0) out vec4 color;
 
 void main() {
-    uint pixel = uint(texture(game_scene, scene_pos).r * 256.0);
-    uint palette_color = palette[pixel];
-    uint r = (palette_color >> 0u) % 256u;
-    uint g = (palette_color >> 8u) % 256u;
-    uint b = (palette_color >> 16u) % 256u;
-    color = vec4(r / 255.0, g / 255.0, b / 255.0, 1.0);
+    if (truecolor) {
+        vec4 pixel = texture(game_scene, scene_pos);
+        color = vec4(pixel.rgb, keep_alpha ? pixel.a : 1.0);
+    } else {
+        uint pixel = uint(texture(game_scene, scene_pos).r * 256.0);
+        uint palette_color = palette[pixel];
+        uint r = (palette_color >> 0u) % 256u;
+        uint g = (palette_color >> 8u) % 256u;
+        uint b = (palette_color >> 16u) % 256u;
+        color = vec4(r / 255.0, g / 255.0, b / 255.0, 1.0);
+    }
+
+    if (grid_cells.x > 0.0) {
+        // Distance to the closest grid line, in screen pixels.
+        vec2 cell = scene_pos * grid_cells;
+        vec2 dist = abs(fract(cell + 0.5) - 0.5) / fwidth(cell);
+        if (min(dist.x, dist.y) < 0.5) {
+            color = vec4(mix(color.rgb, vec3(1.0), 0.25), 1.0);
+        }
+    }
 }
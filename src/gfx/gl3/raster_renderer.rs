@@ -20,9 +20,15 @@ pub struct GlRasterRenderer {
 }
 
 impl GlRasterRenderer {
-    pub fn new() -> Result<GlRasterRenderer> {
+    /// Create a new raster renderer. If `parallel_fill` is `true`, large polygons are filled using
+    /// several threads.
+    pub fn new(parallel_fill: bool) -> Result<GlRasterRenderer> {
         Ok(GlRasterRenderer {
-            raster: RasterGameRenderer::new(),
+            raster: {
+                let mut raster = RasterGameRenderer::new();
+                raster.set_parallel_fill(parallel_fill);
+                raster
+            },
             framebuffer_texture: IndexedTexture::new(SCREEN_RESOLUTION[0], SCREEN_RESOLUTION[1]),
         })
     }
@@ -0,0 +1,151 @@
+//! A `Gfx` that renders the game without displaying it, for running the VM without a window.
+
+use std::any::Any;
+
+use crate::gfx::sw::RasterGameRenderer;
+use crate::gfx::Palette;
+use crate::gfx::PolySegment;
+use crate::gfx::RgbFrame;
+use crate::gfx::{self};
+use crate::res::ResourceManager;
+use crate::scenes;
+use crate::scenes::InitForScene;
+use crate::sys::Snapshotable;
+
+/// Renderers which pages can be read back, so [`HeadlessGfx`] can return the frame it shows.
+pub trait PageReader {
+    /// Returns the pixels of `page_id` with the colors of `palette`, or `None` if this renderer
+    /// cannot read its pages back.
+    fn read_page_rgb(&self, _page_id: usize, _palette: &Palette) -> Option<RgbFrame> {
+        None
+    }
+}
+
+impl PageReader for RasterGameRenderer {
+    fn read_page_rgb(&self, page_id: usize, palette: &Palette) -> Option<RgbFrame> {
+        Some(self.get_buffer(page_id).to_rgb(palette))
+    }
+}
+
+/// A `Gfx` rendering with `R` without displaying anything.
+pub struct HeadlessGfx<R> {
+    pub renderer: R,
+    /// Page and palette of the last frame that would have been displayed.
+    framebuffer: Option<(usize, Palette)>,
+}
+
+impl<R> HeadlessGfx<R> {
+    pub fn new(renderer: R) -> Self {
+        HeadlessGfx {
+            renderer,
+            framebuffer: None,
+        }
+    }
+}
+
+impl<R: gfx::GameRenderer> gfx::GameRenderer for HeadlessGfx<R> {
+    fn fillvideopage(&mut self, page_id: usize, color_idx: u8) {
+        self.renderer.fillvideopage(page_id, color_idx)
+    }
+
+    fn copyvideopage(&mut self, src_page_id: usize, dst_page_id: usize, vscroll: i16) {
+        self.renderer
+            .copyvideopage(src_page_id, dst_page_id, vscroll)
+    }
+
+    fn draw_polygons(
+        &mut self,
+        segment: PolySegment,
+        start_offset: u16,
+        dst_page_id: usize,
+        pos: (i16, i16),
+        offset: (i16, i16),
+        zoom: u16,
+    ) {
+        self.renderer
+            .draw_polygons(segment, start_offset, dst_page_id, pos, offset, zoom)
+    }
+
+    fn draw_char(&mut self, dst_page_id: usize, pos: (i16, i16), color_idx: u8, c: u8) {
+        self.renderer.draw_char(dst_page_id, pos, color_idx, c)
+    }
+
+    fn blit_buffer(&mut self, dst_page_id: usize, buffer: &[u8]) {
+        self.renderer.blit_buffer(dst_page_id, buffer)
+    }
+}
+
+impl<R: PageReader> gfx::Display for HeadlessGfx<R> {
+    fn blitframebuffer(&mut self, page_id: usize, palette: &Palette) {
+        self.framebuffer = Some((page_id, palette.clone()));
+    }
+
+    fn read_framebuffer_rgb(&self) -> Option<RgbFrame> {
+        let (page_id, palette) = self.framebuffer.as_ref()?;
+        self.renderer.read_page_rgb(*page_id, palette)
+    }
+}
+
+type HeadlessGfxSnapshot<R> = (R, Option<(usize, Palette)>);
+
+impl<R: Clone + 'static> Snapshotable for HeadlessGfx<R> {
+    type State = Box<dyn Any>;
+
+    fn take_snapshot(&self) -> Self::State {
+        Box::new((self.renderer.clone(), self.framebuffer.clone()))
+    }
+
+    fn restore_snapshot(&mut self, snapshot: &Self::State) -> bool {
+        match snapshot.downcast_ref::<HeadlessGfxSnapshot<R>>() {
+            Some((renderer, framebuffer)) => {
+                self.renderer = renderer.clone();
+                self.framebuffer = framebuffer.clone();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl<R: InitForScene> InitForScene for HeadlessGfx<R> {
+    fn init_from_scene(
+        &mut self,
+        resman: &ResourceManager,
+        scene: &scenes::Scene,
+    ) -> std::io::Result<()> {
+        self.renderer.init_from_scene(resman, scene)
+    }
+}
+
+impl<R: gfx::GameRenderer + PageReader + InitForScene + Clone + 'static> gfx::Gfx
+    for HeadlessGfx<R>
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::NullAudio;
+    use crate::gfx::Display;
+    use crate::testing;
+    use crate::vm::Vm;
+
+    #[test]
+    fn test_read_framebuffer_rgb() {
+        let mut vm = Vm::new_with_resman(testing::test_scene_resources());
+        let mut gfx = HeadlessGfx::new(RasterGameRenderer::new());
+        assert_eq!(gfx.read_framebuffer_rgb(), None);
+
+        vm.request_scene(testing::TEST_SCENE);
+        vm.process_round(&mut gfx, &mut NullAudio).unwrap();
+        let frame = gfx.read_framebuffer_rgb().unwrap();
+        assert_eq!((frame.width, frame.height), (320, 200));
+        assert!(frame.pixels.iter().any(|&c| c != 0));
+        assert_eq!(
+            frame,
+            gfx.renderer
+                .get_buffer(vm.front_buffer())
+                .to_rgb(vm.palette())
+        );
+    }
+}
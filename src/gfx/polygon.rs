@@ -15,6 +15,7 @@
 //! These quads are guaranteed to be convex.
 use std::borrow::Borrow;
 use std::fmt::Debug;
+use std::fmt::Display;
 use std::fmt::Formatter;
 use std::fmt::Result;
 use std::ops::Deref;
@@ -108,11 +109,54 @@ impl ToOwned for Polygon {
     }
 }
 
+/// Reason why a [`Polygon`] does not respect its invariants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolyError {
+    /// The polygon has less than 4 points.
+    NotEnoughPoints(usize),
+    /// The polygon has an odd number of points.
+    OddPointCount(usize),
+    /// The points at these indices are opposite, but do not have the same `y` coordinate.
+    MismatchedOppositeY(usize, usize),
+}
+
+impl Display for PolyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            PolyError::NotEnoughPoints(n) => write!(f, "only {} points", n),
+            PolyError::OddPointCount(n) => write!(f, "odd number of points ({})", n),
+            PolyError::MismatchedOppositeY(i, j) => {
+                write!(f, "opposite points {} and {} have different y", i, j)
+            }
+        }
+    }
+}
+
 impl Polygon {
     pub fn bb(&self) -> (u8, u8) {
         (self.bb[0], self.bb[1])
     }
 
+    /// Check that the polygon respects the invariants listed in its documentation, which the
+    /// other methods rely on.
+    pub fn validate(&self) -> std::result::Result<(), PolyError> {
+        let nb_points = self.points.len();
+        if nb_points < 4 {
+            return Err(PolyError::NotEnoughPoints(nb_points));
+        }
+        if !nb_points.is_multiple_of(2) {
+            return Err(PolyError::OddPointCount(nb_points));
+        }
+        for i in 0..nb_points / 2 {
+            let j = nb_points - 1 - i;
+            if self.points[i].y != self.points[j].y {
+                return Err(PolyError::MismatchedOppositeY(i, j));
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn points_iter(&self) -> impl DoubleEndedIterator<Item = Point<u8>> + '_ {
         self.points.iter().cloned()
     }
@@ -133,6 +177,46 @@ impl Polygon {
             iter,
         }
     }
+
+    /// Returns the sums of the shoelace formula over the corners of all the trapezoids of the
+    /// polygon: twice the area, and the terms of the centroid's coordinates multiplied by six
+    /// times the area.
+    fn shoelace_sums(&self) -> (f64, f64, f64) {
+        self.trapezoid_iter()
+            .flat_map(|t| {
+                let corners = [
+                    (*t.top.x_range.start(), t.top.y),
+                    (*t.top.x_range.end(), t.top.y),
+                    (*t.bot.x_range.end(), t.bot.y),
+                    (*t.bot.x_range.start(), t.bot.y),
+                ]
+                .map(|(x, y)| (x as f64, y as f64));
+                (0..4).map(move |i| (corners[i], corners[(i + 1) % 4]))
+            })
+            .fold((0.0, 0.0, 0.0), |(a, cx, cy), ((x0, y0), (x1, y1))| {
+                let cross = x0 * y1 - x1 * y0;
+                (a + cross, cx + (x0 + x1) * cross, cy + (y0 + y1) * cross)
+            })
+    }
+
+    /// Returns the area covered by the polygon, computed as the sum of the areas of its
+    /// trapezoids. Polygons reduced to a line or a point have an area of zero.
+    pub fn area(&self) -> f64 {
+        self.shoelace_sums().0 / 2.0
+    }
+
+    /// Returns the center of mass of the polygon, or `None` if its area is zero.
+    pub fn centroid(&self) -> Option<Point<f64>> {
+        let (area2, cx, cy) = self.shoelace_sums();
+        if area2 == 0.0 {
+            return None;
+        }
+
+        Some(Point {
+            x: cx / (3.0 * area2),
+            y: cy / (3.0 * area2),
+        })
+    }
 }
 
 /// Owned version of [`Polygon`]. Useful for renderers that need to put polygon data aside.
@@ -224,8 +308,11 @@ where
     fn next(&mut self) -> Option<Self::Item> {
         let (p1, p2) = (self.iter.next_back()?, self.iter.next()?);
 
-        // Opposite points are supposed to have the same `y` coordinate.
-        assert_eq!(p1.y, p2.y);
+        // Opposite points are supposed to have the same `y` coordinate. Stop here if they don't,
+        // as the rest of the polygon cannot be trusted. `Polygon::validate` reports this case.
+        if p1.y != p2.y {
+            return None;
+        }
         let y = p1.y;
 
         let (left, right) = if p1.x <= p2.x {
@@ -331,7 +418,7 @@ mod test {
     }
 
     impl OwnedPolygon {
-        fn new(bb: (u8, u8), points: Vec<Point<u8>>) -> OwnedPolygon {
+        pub fn new(bb: (u8, u8), points: Vec<Point<u8>>) -> OwnedPolygon {
             let mut data = vec![bb.0, bb.1, 0];
             data.extend(points.as_bytes());
             OwnedPolygon { data }
@@ -386,7 +473,10 @@ mod test {
                 top: expected_lines[0].clone(),
                 bot: expected_lines[1].clone(),
             }]
-        )
+        );
+
+        assert_eq!(poly.area(), 0.0);
+        assert_eq!(poly.centroid(), None);
     }
 
     #[test]
@@ -464,7 +554,10 @@ mod test {
                 top: expected_lines[0].clone(),
                 bot: expected_lines[1].clone(),
             }]
-        )
+        );
+
+        assert_eq!(poly.area(), 2.0);
+        assert_eq!(poly.centroid(), Some(Point::new(1.0, 4.0 / 3.0)));
     }
 
     #[test]
@@ -516,7 +609,10 @@ mod test {
                 top: expected_lines[0].clone(),
                 bot: expected_lines[1].clone(),
             }]
-        )
+        );
+
+        assert_eq!(poly.area(), 4.0);
+        assert_eq!(poly.centroid(), Some(Point::new(1.0, 1.0)));
     }
 
     #[test]
@@ -561,6 +657,36 @@ mod test {
                     bot: expected_lines[3].clone(),
                 },
             ]
-        )
+        );
+
+        assert_eq!(poly.area(), 7.0);
+        assert_eq!(poly.centroid(), Some(Point::new(1.5, 1.5)));
+    }
+
+    #[test]
+    fn polygon_validate() {
+        let square = vec![
+            Point::new(2, 0),
+            Point::new(2, 2),
+            Point::new(0, 2),
+            Point::new(0, 0),
+        ];
+        assert_eq!(OwnedPolygon::new((2, 2), square.clone()).validate(), Ok(()));
+        assert_eq!(
+            OwnedPolygon::new((2, 2), square[0..2].to_vec()).validate(),
+            Err(PolyError::NotEnoughPoints(2))
+        );
+        let mut odd = square.clone();
+        odd.push(Point::new(1, 0));
+        assert_eq!(
+            OwnedPolygon::new((2, 2), odd).validate(),
+            Err(PolyError::OddPointCount(5))
+        );
+        let mut mismatched = square;
+        mismatched[2].y = 1;
+        let poly = OwnedPolygon::new((2, 2), mismatched);
+        assert_eq!(poly.validate(), Err(PolyError::MismatchedOppositeY(1, 2)));
+        // Iterating over an invalid polygon stops instead of panicking.
+        assert_eq!(poly.line_iter().count(), 1);
     }
 }
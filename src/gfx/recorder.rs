@@ -0,0 +1,277 @@
+//! Recording of the displayed frames into an animated GIF.
+
+use std::any::Any;
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::path::Path;
+use std::time::Duration;
+use std::time::Instant;
+
+use tracing::error;
+use tracing::warn;
+
+use crate::gfx::sw::RasterGameRenderer;
+use crate::gfx::Palette;
+use crate::gfx::PolySegment;
+use crate::gfx::SCREEN_RESOLUTION;
+use crate::gfx::{self};
+use crate::gif::GifWriter;
+use crate::scenes::InitForScene;
+use crate::sys::Snapshotable;
+
+/// Maximum number of frames recorded, to prevent the file from growing indefinitely if the
+/// recording is left running. That's 10 minutes at 25 frames per second.
+const MAX_FRAMES: usize = 15000;
+/// GIF delays are expressed in hundredths of seconds, and most viewers slow down frames shorter
+/// than 2 of them.
+const MAX_FPS: u32 = 50;
+
+/// A frame waiting to be written, since its duration is only known once the next one comes.
+struct PendingFrame {
+    pixels: Vec<u8>,
+    palette: [[u8; 3]; gfx::PALETTE_SIZE],
+    time: Instant,
+}
+
+/// A `Gfx` that forwards everything to `inner`, and records the frames it displays into an
+/// animated GIF.
+///
+/// The frames are rendered by a dedicated CPU renderer, so the recording always shows the game at
+/// its original resolution, whatever `inner` does. They are written to the file as they come, and
+/// the animation is finalized when the recorder is dropped.
+pub struct GifRecorder<D> {
+    inner: D,
+    renderer: RasterGameRenderer,
+    /// Page and palette of the last frame presented by the game.
+    displayed: Option<(usize, Palette)>,
+
+    writer: Option<GifWriter<BufWriter<File>>>,
+    frame_interval: Duration,
+    pending: Option<PendingFrame>,
+    num_frames: usize,
+}
+
+impl<D> GifRecorder<D> {
+    /// Record the frames displayed by `inner` into the GIF file at `path`, at a rate of at most
+    /// `fps` frames per second.
+    pub fn new(inner: D, path: &Path, fps: u32) -> io::Result<Self> {
+        let writer = GifWriter::new(
+            BufWriter::new(File::create(path)?),
+            SCREEN_RESOLUTION[0],
+            SCREEN_RESOLUTION[1],
+        )?;
+
+        Ok(Self {
+            inner,
+            renderer: RasterGameRenderer::new(),
+            displayed: None,
+            writer: Some(writer),
+            frame_interval: Duration::from_secs(1) / fps.clamp(1, MAX_FPS),
+            pending: None,
+            num_frames: 0,
+        })
+    }
+
+    /// Write the pending frame, if any, making it last for `duration`.
+    fn flush_pending(&mut self, duration: Duration) {
+        let (Some(writer), Some(frame)) = (&mut self.writer, self.pending.take()) else {
+            return;
+        };
+
+        let delay = (duration.as_millis() / 10).clamp(1, u16::MAX as u128) as u16;
+        if let Err(e) = writer.write_frame(&frame.pixels, &frame.palette, delay) {
+            error!("Failed to write GIF frame, stopping recording: {}", e);
+            self.writer = None;
+            return;
+        }
+
+        self.num_frames += 1;
+        if self.num_frames >= MAX_FRAMES {
+            warn!("Recorded {} frames, stopping recording", MAX_FRAMES);
+            self.finish();
+        }
+    }
+
+    /// Capture `page_id` as the next frame of the animation.
+    fn capture(&mut self, page_id: usize, palette: &Palette) {
+        if self.writer.is_none() {
+            return;
+        }
+
+        let now = Instant::now();
+        let pixels = self.renderer.get_buffer(page_id).pixels().to_vec();
+        let palette = std::array::from_fn(|i| {
+            let color = palette.lookup(i as u8);
+            [color.r, color.g, color.b]
+        });
+
+        match &mut self.pending {
+            // Too early for a new frame, but keep the latest state of the screen.
+            Some(frame) if now - frame.time < self.frame_interval => {
+                frame.pixels = pixels;
+                frame.palette = palette;
+            }
+            pending => {
+                let duration = pending.as_ref().map(|frame| now - frame.time);
+                if let Some(duration) = duration {
+                    self.flush_pending(duration);
+                }
+                self.pending = Some(PendingFrame {
+                    pixels,
+                    palette,
+                    time: now,
+                });
+            }
+        }
+    }
+
+    /// Write the pending frame and finalize the animation.
+    fn finish(&mut self) {
+        self.flush_pending(self.frame_interval);
+        if let Some(writer) = self.writer.take() {
+            if let Err(e) = writer.finish() {
+                error!("Failed to finalize GIF recording: {}", e);
+            }
+        }
+    }
+}
+
+impl<D> Drop for GifRecorder<D> {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+impl<D: gfx::Gfx> gfx::GameRenderer for GifRecorder<D> {
+    fn fillvideopage(&mut self, page_id: usize, color_idx: u8) {
+        self.renderer.fillvideopage(page_id, color_idx);
+        self.inner.fillvideopage(page_id, color_idx);
+    }
+
+    fn copyvideopage(&mut self, src_page_id: usize, dst_page_id: usize, vscroll: i16) {
+        self.renderer
+            .copyvideopage(src_page_id, dst_page_id, vscroll);
+        self.inner.copyvideopage(src_page_id, dst_page_id, vscroll);
+    }
+
+    fn draw_polygons(
+        &mut self,
+        segment: PolySegment,
+        start_offset: u16,
+        dst_page_id: usize,
+        pos: (i16, i16),
+        offset: (i16, i16),
+        zoom: u16,
+    ) {
+        self.renderer
+            .draw_polygons(segment, start_offset, dst_page_id, pos, offset, zoom);
+        self.inner
+            .draw_polygons(segment, start_offset, dst_page_id, pos, offset, zoom);
+    }
+
+    fn draw_char(&mut self, dst_page_id: usize, pos: (i16, i16), color_idx: u8, c: u8) {
+        self.renderer.draw_char(dst_page_id, pos, color_idx, c);
+        self.inner.draw_char(dst_page_id, pos, color_idx, c);
+    }
+
+    fn blit_buffer(&mut self, dst_page_id: usize, buffer: &[u8]) {
+        self.renderer.blit_buffer(dst_page_id, buffer);
+        self.inner.blit_buffer(dst_page_id, buffer);
+    }
+}
+
+impl<D: gfx::Gfx> gfx::Display for GifRecorder<D> {
+    fn blitframebuffer(&mut self, page_id: usize, palette: &Palette) {
+        self.inner.blitframebuffer(page_id, palette);
+        self.capture(page_id, palette);
+        self.displayed = Some((page_id, palette.clone()));
+    }
+
+    fn read_framebuffer_rgb(&self) -> Option<gfx::RgbFrame> {
+        self.inner.read_framebuffer_rgb()
+    }
+}
+
+struct GifRecorderSnapshot {
+    inner: Box<dyn Any>,
+    renderer: RasterGameRenderer,
+    displayed: Option<(usize, Palette)>,
+}
+
+impl<D: gfx::Gfx> Snapshotable for GifRecorder<D> {
+    type State = Box<dyn Any>;
+
+    fn take_snapshot(&self) -> Self::State {
+        Box::new(GifRecorderSnapshot {
+            inner: self.inner.take_snapshot(),
+            renderer: self.renderer.take_snapshot(),
+            displayed: self.displayed.clone(),
+        })
+    }
+
+    fn restore_snapshot(&mut self, snapshot: &Self::State) -> bool {
+        if let Some(state) = snapshot.downcast_ref::<GifRecorderSnapshot>() {
+            self.renderer.restore_snapshot(&state.renderer);
+            self.displayed = state.displayed.clone();
+            // The restored frame replaces whatever was displayed since the snapshot was taken.
+            if let Some((page_id, palette)) = &state.displayed {
+                self.capture(*page_id, palette);
+            }
+            self.inner.restore_snapshot(&state.inner)
+        } else {
+            error!("Attempting to restore invalid gfx snapshot, ignoring");
+            false
+        }
+    }
+}
+
+impl<D: gfx::Gfx> InitForScene for GifRecorder<D> {
+    fn init_from_scene(
+        &mut self,
+        resman: &crate::res::ResourceManager,
+        scene: &crate::scenes::Scene,
+    ) -> std::io::Result<()> {
+        self.renderer.init_from_scene(resman, scene)?;
+        self.inner.init_from_scene(resman, scene)
+    }
+}
+
+impl<D: gfx::Gfx> gfx::Gfx for GifRecorder<D> {}
+
+#[cfg(feature = "sdl2-sys")]
+impl<D: gfx::sdl2::Sdl2Gfx> gfx::sdl2::Sdl2Gfx for GifRecorder<D> {
+    fn show_game_framebuffer(&mut self, dst: &sdl2::rect::Rect, overlay: &[String]) {
+        self.inner.show_game_framebuffer(dst, overlay)
+    }
+
+    fn present(&mut self) {
+        self.inner.present()
+    }
+
+    fn window(&self) -> &sdl2::video::Window {
+        self.inner.window()
+    }
+
+    fn window_mut(&mut self) -> &mut sdl2::video::Window {
+        self.inner.window_mut()
+    }
+
+    fn set_font(&mut self, font: &crate::font::Font) {
+        self.renderer.set_font(font);
+        self.inner.set_font(font)
+    }
+
+    fn set_strict(&mut self, strict: bool) {
+        self.renderer.set_strict(strict);
+        self.inner.set_strict(strict)
+    }
+
+    fn set_background_color(&mut self, color: &gfx::Color) {
+        self.inner.set_background_color(color)
+    }
+
+    fn handle_event(&mut self, event: &sdl2::event::Event) {
+        self.inner.handle_event(event)
+    }
+}
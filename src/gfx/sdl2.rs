@@ -2,21 +2,34 @@ pub mod canvas_gfx;
 #[cfg(feature = "gl3")]
 pub mod gl3_gfx;
 
+use std::io::Write;
 use std::ops::DerefMut;
 
+use anyhow::anyhow;
+
 use sdl2::event::Event;
 use sdl2::rect::Rect;
 use sdl2::video::Window;
 
+use super::Color;
 use super::Gfx;
+use crate::font::Font;
+use crate::font::CHAR_HEIGHT;
+use crate::font::CHAR_WIDTH;
 
 /// Initial size of the window when using this renderer.
 pub const WINDOW_RESOLUTION: [u32; 2] = [1280, 800];
+/// Spacing of the lines of the pixel grid that can be shown over the game, in game pixels. It
+/// matches the size of the font's characters.
+pub const PIXEL_GRID_SIZE: [usize; 2] = [CHAR_WIDTH, CHAR_HEIGHT];
 
 /// Trait for handling display for `Sdl2Sys`, while providing access to common graphics methods.
 pub trait Sdl2Gfx: Gfx {
-    /// Display the current framebuffer into the `dst` rectangle of the render buffer.
-    fn show_game_framebuffer(&mut self, dst: &Rect);
+    /// Display the current framebuffer into the `dst` rectangle of the render buffer, along with
+    /// the pixel grid if it is enabled, and the `overlay` lines of text on top of it.
+    ///
+    /// The overlay is only drawn into the render buffer, so it never ends up in the game's pages.
+    fn show_game_framebuffer(&mut self, dst: &Rect, overlay: &[String]);
 
     /// Present the render buffer on the screen.
     fn present(&mut self);
@@ -24,15 +37,35 @@ pub trait Sdl2Gfx: Gfx {
     /// Returns the window the renderer will render into.
     fn window(&self) -> &Window;
 
+    /// Returns the window the renderer will render into, for changing its size or mode.
+    fn window_mut(&mut self) -> &mut Window;
+
+    /// Use `font` to draw the game's text.
+    fn set_font(&mut self, font: &Font);
+
+    /// Panic when a polygon has an invalid color if `strict` is set, instead of logging a warning
+    /// and skipping it.
+    fn set_strict(&mut self, strict: bool);
+
+    /// Fill the parts of the window around the game with `color`.
+    fn set_background_color(&mut self, color: &Color);
+
     /// Gives the renderer a chance to handle its own input, to e.g. change rendering parameters.
     /// Also useful to catch window resize events.
     fn handle_event(&mut self, _event: &Event) {}
+
+    /// Write the list of draw commands making up the current frame into `w`, in JSON format.
+    ///
+    /// Fails if the renderer does not record draw commands.
+    fn write_drawlist(&self, _w: &mut dyn Write) -> anyhow::Result<()> {
+        Err(anyhow!("this renderer does not record draw commands"))
+    }
 }
 
 /// Proxy implementation for containers of `Sdl2Gfx`.
 impl<D: Sdl2Gfx + ?Sized + 'static, C: DerefMut<Target = D> + Gfx> Sdl2Gfx for C {
-    fn show_game_framebuffer(&mut self, dst: &Rect) {
-        self.deref_mut().show_game_framebuffer(dst)
+    fn show_game_framebuffer(&mut self, dst: &Rect, overlay: &[String]) {
+        self.deref_mut().show_game_framebuffer(dst, overlay)
     }
 
     fn present(&mut self) {
@@ -43,7 +76,27 @@ impl<D: Sdl2Gfx + ?Sized + 'static, C: DerefMut<Target = D> + Gfx> Sdl2Gfx for C
         self.deref().window()
     }
 
+    fn window_mut(&mut self) -> &mut Window {
+        self.deref_mut().window_mut()
+    }
+
+    fn set_font(&mut self, font: &Font) {
+        self.deref_mut().set_font(font)
+    }
+
+    fn set_strict(&mut self, strict: bool) {
+        self.deref_mut().set_strict(strict)
+    }
+
+    fn set_background_color(&mut self, color: &Color) {
+        self.deref_mut().set_background_color(color)
+    }
+
     fn handle_event(&mut self, event: &Event) {
         self.deref_mut().handle_event(event)
     }
+
+    fn write_drawlist(&self, w: &mut dyn Write) -> anyhow::Result<()> {
+        self.deref().write_drawlist(w)
+    }
 }
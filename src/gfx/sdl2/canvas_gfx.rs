@@ -6,8 +6,13 @@
 use std::any::Any;
 use std::convert::TryFrom;
 
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
 use sdl2::pixels::PixelFormat;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::rect::Point;
 use sdl2::rect::Rect;
+use sdl2::render::BlendMode;
 use sdl2::render::Canvas;
 use sdl2::render::Texture;
 use sdl2::video::Window;
@@ -17,16 +22,19 @@ use anyhow::anyhow;
 use anyhow::Result;
 use tracing::trace_span;
 
+use crate::font::Font;
 use crate::gfx;
 use crate::gfx::sdl2::Sdl2Gfx;
+use crate::gfx::sw::IndexedImage;
 use crate::gfx::sw::RasterGameRenderer;
 use crate::gfx::Color;
-use crate::gfx::Display;
 use crate::gfx::Gfx;
 use crate::gfx::Palette;
+use crate::gfx::Vsync;
 use crate::scenes::InitForScene;
 use crate::sys::Snapshotable;
 
+use super::PIXEL_GRID_SIZE;
 use super::WINDOW_RESOLUTION;
 
 /// Pure software renderer and display for SDL2. [`gfx::GameRenderer`] is just implemented by
@@ -43,17 +51,39 @@ pub struct Sdl2CanvasGfx {
     canvas: Canvas<Window>,
     /// Texture onto which the game buffer to be displayed is rendered.
     texture: Texture,
+    /// Texture containing the text shown on top of the game, with transparent pixels around it.
+    overlay_texture: Texture,
+    /// Lines of text currently in `overlay_texture`.
+    overlay_lines: Vec<String>,
     /// Native pixel format of the display.
     pixel_format: PixelFormat,
     /// Number of bytes per pixel, used when rendering the current buffer to the native pixel
     /// format.
     bytes_per_pixel: usize,
+
+    /// Whether to blend the previous frame over the current one, so elements that only appear
+    /// or disappear for one frame can be noticed.
+    onion_skin: bool,
+    /// Frame presented by the game before the current one and its palette, if `onion_skin` is
+    /// enabled.
+    previous_frame: Option<(Box<IndexedImage>, Palette)>,
+    /// Frame currently presented by the game and its palette, if `onion_skin` is enabled. It
+    /// becomes `previous_frame` when the game presents the next one.
+    current_frame: Option<(Box<IndexedImage>, Palette)>,
+
+    /// Whether to draw a grid over the game to make its pixels easier to locate.
+    pixel_grid: bool,
+    /// Color of the parts of the window around the game.
+    background_color: sdl2::pixels::Color,
 }
 
 impl Sdl2CanvasGfx {
     /// Create a new raster display, using the given SDL context. This takes
     /// care of creating the window, canvas, and everything we need to draw.
-    pub fn new(sdl_context: &Sdl) -> Result<Self> {
+    ///
+    /// If `parallel_raster` is `true`, large polygons are filled using several threads. Adaptive
+    /// `vsync` is not supported by SDL's canvas, which waits for every vertical sync instead.
+    pub fn new(sdl_context: &Sdl, parallel_raster: bool, vsync: Vsync) -> Result<Self> {
         let sdl_video = sdl_context.video().map_err(|s| anyhow!(s))?;
 
         let window = sdl_video
@@ -62,7 +92,11 @@ impl Sdl2CanvasGfx {
             .allow_highdpi()
             .build()?;
 
-        let canvas = window.into_canvas().build()?;
+        let canvas = match vsync {
+            Vsync::Off => window.into_canvas(),
+            Vsync::On | Vsync::Adaptive => window.into_canvas().present_vsync(),
+        }
+        .build()?;
 
         let texture_creator = canvas.texture_creator();
         let pixel_format_enum = texture_creator.default_pixel_format();
@@ -73,17 +107,119 @@ impl Sdl2CanvasGfx {
             gfx::SCREEN_RESOLUTION[0] as u32,
             gfx::SCREEN_RESOLUTION[1] as u32,
         )?;
+        let mut overlay_texture = texture_creator.create_texture_streaming(
+            PixelFormatEnum::RGBA32,
+            gfx::SCREEN_RESOLUTION[0] as u32,
+            gfx::SCREEN_RESOLUTION[1] as u32,
+        )?;
+        overlay_texture.set_blend_mode(BlendMode::Blend);
 
         Ok(Sdl2CanvasGfx {
             canvas,
             current_framebuffer: 0,
             current_palette: Default::default(),
             texture,
+            overlay_texture,
+            overlay_lines: Vec::new(),
             pixel_format,
             bytes_per_pixel,
-            raster: RasterGameRenderer::new(),
+            onion_skin: false,
+            previous_frame: None,
+            current_frame: None,
+            pixel_grid: false,
+            background_color: sdl2::pixels::Color::RGB(0, 0, 0),
+            raster: {
+                let mut raster = RasterGameRenderer::new();
+                raster.set_parallel_fill(parallel_raster);
+                raster
+            },
         })
     }
+
+    /// Enable or disable blending the previous frame at 50% over the one being displayed.
+    pub fn set_onion_skin(&mut self, onion_skin: bool) {
+        self.onion_skin = onion_skin;
+        self.previous_frame = None;
+        self.current_frame = None;
+    }
+
+    /// Enable or disable rounding the edges of polygons outwards, which closes the one-pixel gaps
+    /// that can appear between them.
+    pub fn set_conservative_raster(&mut self, conservative: bool) {
+        self.raster.set_conservative_fill(conservative);
+    }
+
+    /// Render the game `scale` times larger than the screen in each dimension, and average the
+    /// pixels back to the screen resolution when displaying it. This smooths the edges of
+    /// polygons.
+    pub fn set_raster_scale(&mut self, scale: usize) {
+        self.raster.set_scale(scale);
+        self.previous_frame = None;
+        self.current_frame = None;
+    }
+
+    /// Display `page_id` when it has been rendered at a higher resolution than the screen.
+    fn blit_downscaled(&mut self, page_id: usize, palette: &Palette) {
+        let buffer = self.raster.get_buffer(page_id);
+        let mut colors = downscale(&buffer, palette);
+        if let Some((previous, previous_palette)) = &self.previous_frame {
+            for (color, previous) in colors.iter_mut().zip(downscale(previous, previous_palette)) {
+                *color = color.blend(&previous);
+            }
+        }
+
+        // Avoid borrowing self in the closure
+        let bytes_per_pixel = self.bytes_per_pixel;
+        let pixel_format = &self.pixel_format;
+
+        let render_into_texture = |texture: &mut [u8], pitch: usize| {
+            for (src_line, dst_line) in colors
+                .chunks_exact(gfx::SCREEN_RESOLUTION[0])
+                .zip(texture.chunks_exact_mut(pitch))
+            {
+                for (&Color { r, g, b }, dst_pix) in src_line
+                    .iter()
+                    .zip(dst_line.chunks_exact_mut(bytes_per_pixel))
+                {
+                    let color = sdl2::pixels::Color::RGB(r, g, b).to_u32(pixel_format);
+                    dst_pix.copy_from_slice(&color.to_ne_bytes()[0..bytes_per_pixel]);
+                }
+            }
+        };
+
+        trace_span!("render_into_texture")
+            .in_scope(|| self.texture.with_lock(None, render_into_texture).unwrap());
+    }
+}
+
+/// Returns the colors of `image` at the resolution of the game screen, each pixel being the
+/// average of the square of pixels of `image` that it covers.
+fn downscale(image: &IndexedImage, palette: &Palette) -> Vec<Color> {
+    let scale = image.scale();
+    let width = image.dimensions().0;
+    let num_samples = (scale * scale) as u32;
+
+    let mut colors = Vec::with_capacity(gfx::SCREEN_RESOLUTION[0] * gfx::SCREEN_RESOLUTION[1]);
+    for lines in image.pixels().chunks_exact(width * scale) {
+        for x in 0..gfx::SCREEN_RESOLUTION[0] {
+            let mut sum = [0u32; 3];
+            for line in lines.chunks_exact(width) {
+                for &pix in &line[x * scale..(x + 1) * scale] {
+                    let color = palette.lookup(pix);
+                    sum[0] += color.r as u32;
+                    sum[1] += color.g as u32;
+                    sum[2] += color.b as u32;
+                }
+            }
+            colors.push(Color {
+                r: (sum[0] / num_samples) as u8,
+                g: (sum[1] / num_samples) as u8,
+                b: (sum[2] / num_samples) as u8,
+            });
+        }
+    }
+
+    colors
 }
 
 impl gfx::GameRenderer for Sdl2CanvasGfx {
@@ -117,13 +253,19 @@ impl gfx::GameRenderer for Sdl2CanvasGfx {
     }
 }
 
-impl gfx::Display for Sdl2CanvasGfx {
-    #[tracing::instrument(level = "trace", skip(self, palette))]
-    fn blitframebuffer(&mut self, page_id: usize, palette: &Palette) {
+impl Sdl2CanvasGfx {
+    /// Render `page_id` into the texture shown on the screen, blending `previous_frame` over it
+    /// if there is one.
+    fn show_page(&mut self, page_id: usize, palette: &Palette) {
         // Keep information useful for snapshotting...
         self.current_framebuffer = page_id;
         self.current_palette = palette.clone();
 
+        if self.raster.get_buffer(page_id).scale() > 1 {
+            self.blit_downscaled(page_id, palette);
+            return;
+        }
+
         // Maps each palette index to the native color of the current display.
         let palette_to_color = {
             let mut palette_to_color = [0u32; gfx::PALETTE_SIZE];
@@ -134,22 +276,46 @@ impl gfx::Display for Sdl2CanvasGfx {
             palette_to_color
         };
 
+        // With onion skinning, maps each pair of current and previous palette indices to the
+        // average of their colors.
+        let blended = self
+            .previous_frame
+            .as_ref()
+            .map(|(image, previous_palette)| {
+                let mut blend_to_color = [0u32; gfx::PALETTE_SIZE * gfx::PALETTE_SIZE];
+                for (i, color) in blend_to_color.iter_mut().enumerate() {
+                    let current = palette.lookup((i / gfx::PALETTE_SIZE) as u8);
+                    let previous = previous_palette.lookup((i % gfx::PALETTE_SIZE) as u8);
+                    let Color { r, g, b } = current.blend(previous);
+                    *color = sdl2::pixels::Color::RGB(r, g, b).to_u32(&self.pixel_format);
+                }
+                (image.pixels(), blend_to_color)
+            });
+
         // Avoid borrowing self in the closure
         let bytes_per_pixel = self.bytes_per_pixel;
+        let buffer = self.raster.get_buffer(page_id);
 
         let render_into_texture = |texture: &mut [u8], pitch: usize| {
-            for (src_line, dst_line) in self
-                .raster
-                .get_buffer(page_id)
+            for (y, (src_line, dst_line)) in buffer
                 .pixels()
                 .chunks_exact(gfx::SCREEN_RESOLUTION[0])
                 .zip(texture.chunks_exact_mut(pitch))
+                .enumerate()
             {
-                for (src_pix, dst_pix) in src_line
+                for (x, (src_pix, dst_pix)) in src_line
                     .iter()
                     .zip(dst_line.chunks_exact_mut(bytes_per_pixel))
+                    .enumerate()
                 {
-                    let color = palette_to_color[*src_pix as usize];
+                    let color = match &blended {
+                        None => palette_to_color[*src_pix as usize],
+                        Some((previous, blend_to_color)) => {
+                            let previous_pix = previous[y * gfx::SCREEN_RESOLUTION[0] + x];
+                            blend_to_color
+                                [*src_pix as usize * gfx::PALETTE_SIZE + previous_pix as usize]
+                        }
+                    };
                     dst_pix.copy_from_slice(&color.to_ne_bytes()[0..bytes_per_pixel]);
                 }
             }
@@ -160,11 +326,37 @@ impl gfx::Display for Sdl2CanvasGfx {
     }
 }
 
+impl gfx::Display for Sdl2CanvasGfx {
+    #[tracing::instrument(level = "trace", skip(self, palette))]
+    fn blitframebuffer(&mut self, page_id: usize, palette: &Palette) {
+        if self.onion_skin {
+            self.previous_frame = self.current_frame.take();
+        }
+
+        self.show_page(page_id, palette);
+
+        if self.onion_skin {
+            let buffer = self.raster.get_buffer(page_id);
+            self.current_frame = Some((Box::new(buffer.clone()), palette.clone()));
+        }
+    }
+
+    fn read_framebuffer_rgb(&self) -> Option<gfx::RgbFrame> {
+        Some(
+            self.raster
+                .get_buffer(self.current_framebuffer)
+                .to_rgb(&self.current_palette),
+        )
+    }
+}
+
 #[derive(Clone)]
 struct Sdl2CanvasGfxSnapshot {
     raster: RasterGameRenderer,
     current_framebuffer: usize,
     current_palette: Palette,
+    previous_frame: Option<(Box<IndexedImage>, Palette)>,
+    current_frame: Option<(Box<IndexedImage>, Palette)>,
 }
 
 impl Snapshotable for Sdl2CanvasGfx {
@@ -175,13 +367,17 @@ impl Snapshotable for Sdl2CanvasGfx {
             raster: self.raster.clone(),
             current_framebuffer: self.current_framebuffer,
             current_palette: self.current_palette.clone(),
+            previous_frame: self.previous_frame.clone(),
+            current_frame: self.current_frame.clone(),
         })
     }
 
     fn restore_snapshot(&mut self, snapshot: &Self::State) -> bool {
         if let Some(snapshot) = snapshot.downcast_ref::<Sdl2CanvasGfxSnapshot>() {
             self.raster = snapshot.raster.clone();
-            self.blitframebuffer(snapshot.current_framebuffer, &snapshot.current_palette);
+            self.previous_frame = snapshot.previous_frame.clone();
+            self.current_frame = snapshot.current_frame.clone();
+            self.show_page(snapshot.current_framebuffer, &snapshot.current_palette);
             true
         } else {
             false
@@ -204,13 +400,56 @@ impl Gfx for Sdl2CanvasGfx {}
 
 impl Sdl2Gfx for Sdl2CanvasGfx {
     #[tracing::instrument(skip(self))]
-    fn show_game_framebuffer(&mut self, dst: &Rect) {
+    fn show_game_framebuffer(&mut self, dst: &Rect, overlay: &[String]) {
         // Clear screen
-        self.canvas
-            .set_draw_color(sdl2::pixels::Color::RGB(0, 0, 0));
+        self.canvas.set_draw_color(self.background_color);
         self.canvas.clear();
         // Blit the game screen into the window viewport
         self.canvas.copy(&self.texture, None, Some(*dst)).unwrap();
+
+        if self.pixel_grid {
+            self.canvas.set_blend_mode(BlendMode::Blend);
+            self.canvas
+                .set_draw_color(sdl2::pixels::Color::RGBA(255, 255, 255, 64));
+            let lines = (0..gfx::SCREEN_RESOLUTION[0])
+                .step_by(PIXEL_GRID_SIZE[0])
+                .map(|x| {
+                    let x = dst.x()
+                        + (x as i32 * dst.width() as i32) / gfx::SCREEN_RESOLUTION[0] as i32;
+                    (Point::new(x, dst.top()), Point::new(x, dst.bottom() - 1))
+                })
+                .chain(
+                    (0..gfx::SCREEN_RESOLUTION[1])
+                        .step_by(PIXEL_GRID_SIZE[1])
+                        .map(|y| {
+                            let y = dst.y()
+                                + (y as i32 * dst.height() as i32)
+                                    / gfx::SCREEN_RESOLUTION[1] as i32;
+                            (Point::new(dst.left(), y), Point::new(dst.right() - 1, y))
+                        }),
+                );
+            for (start, end) in lines {
+                self.canvas.draw_line(start, end).unwrap();
+            }
+            self.canvas.set_blend_mode(BlendMode::None);
+        }
+
+        if !overlay.is_empty() {
+            if overlay != self.overlay_lines {
+                let lines = overlay.iter().map(String::as_str).collect::<Vec<_>>();
+                self.overlay_texture
+                    .update(
+                        None,
+                        &gfx::overlay_text_rgba(&lines),
+                        gfx::SCREEN_RESOLUTION[0] * 4,
+                    )
+                    .unwrap();
+                self.overlay_lines = overlay.to_vec();
+            }
+            self.canvas
+                .copy(&self.overlay_texture, None, Some(*dst))
+                .unwrap();
+        }
     }
 
     #[tracing::instrument(skip(self))]
@@ -221,4 +460,31 @@ impl Sdl2Gfx for Sdl2CanvasGfx {
     fn window(&self) -> &Window {
         self.canvas.window()
     }
+
+    fn window_mut(&mut self) -> &mut Window {
+        self.canvas.window_mut()
+    }
+
+    fn set_font(&mut self, font: &Font) {
+        self.raster.set_font(font);
+    }
+
+    fn set_strict(&mut self, strict: bool) {
+        self.raster.set_strict(strict);
+    }
+
+    fn set_background_color(&mut self, color: &Color) {
+        self.background_color = sdl2::pixels::Color::RGB(color.r, color.g, color.b);
+    }
+
+    fn handle_event(&mut self, event: &Event) {
+        if let Event::KeyDown {
+            keycode: Some(Keycode::F4),
+            repeat: false,
+            ..
+        } = event
+        {
+            self.pixel_grid ^= true;
+        }
+    }
 }
@@ -1,4 +1,5 @@
 use std::any::Any;
+use std::io::Write;
 
 use sdl2::event::Event;
 use sdl2::event::WindowEvent;
@@ -6,24 +7,31 @@ use sdl2::keyboard::Keycode;
 use sdl2::rect::Rect;
 use sdl2::video::GLContext;
 use sdl2::video::GLProfile;
+use sdl2::video::SwapInterval;
 use sdl2::video::Window;
 use sdl2::Sdl;
 
 use anyhow::anyhow;
 use anyhow::Result;
+use tracing::warn;
 
+use crate::font::Font;
 use crate::gfx;
 use crate::gfx::gl3::GlGameRenderer;
 use crate::gfx::gl3::GlRasterRenderer;
 use crate::gfx::gl3::GlRenderer;
 use crate::gfx::gl3::IndexedFrameRenderer;
+use crate::gfx::gl3::IndexedTexture;
 use crate::gfx::gl3::PolyRenderingMode;
 use crate::gfx::gl3::Viewport;
 use crate::gfx::sdl2::Sdl2Gfx;
+use crate::gfx::sdl2::PIXEL_GRID_SIZE;
 use crate::gfx::sdl2::WINDOW_RESOLUTION;
 use crate::gfx::sw::RasterGameRenderer;
+use crate::gfx::Color;
 use crate::gfx::Display;
 use crate::gfx::Palette;
+use crate::gfx::Vsync;
 use crate::scenes::InitForScene;
 use crate::sys::Snapshotable;
 
@@ -50,12 +58,30 @@ pub struct Sdl2GlGfx {
     poly_renderer: GlGameRenderer,
 
     framebuffer_renderer: IndexedFrameRenderer,
+    /// Texture containing the text shown on top of the game, with transparent pixels around it.
+    overlay_texture: IndexedTexture,
+    /// Lines of text currently in `overlay_texture`.
+    overlay_lines: Vec<String>,
     current_framebuffer: usize,
     palette: Palette,
+    /// Whether to draw a grid over the game to make its pixels easier to locate.
+    pixel_grid: bool,
+    /// Color of the parts of the window around the game.
+    background_color: Color,
 }
 
 impl Sdl2GlGfx {
-    pub fn new(sdl_context: &Sdl, rendering_mode: RenderingMode) -> Result<Self> {
+    /// Create a new GL display using `rendering_mode`. If `parallel_raster` is `true`, the CPU
+    /// renderer fills large polygons using several threads. If `truecolor` is `true`, the GPU
+    /// renderer renders actual colors instead of color indices. `vsync` sets the swap interval of
+    /// the window.
+    pub fn new(
+        sdl_context: &Sdl,
+        rendering_mode: RenderingMode,
+        parallel_raster: bool,
+        truecolor: bool,
+        vsync: Vsync,
+    ) -> Result<Self> {
         let sdl_video = sdl_context.video().map_err(|s| anyhow!(s))?;
 
         let gl_attr = sdl_video.gl_attr();
@@ -73,9 +99,21 @@ impl Sdl2GlGfx {
         let opengl_context = window.gl_create_context().map_err(|s| anyhow!(s))?;
         gl::load_with(|s| sdl_video.gl_get_proc_address(s) as _);
 
-        unsafe {
-            gl::LineWidth(5.0);
+        let swap_interval = match vsync {
+            Vsync::Off => SwapInterval::Immediate,
+            Vsync::On => SwapInterval::VSync,
+            Vsync::Adaptive => SwapInterval::LateSwapTearing,
+        };
+        if let Err(e) = sdl_video.gl_set_swap_interval(swap_interval) {
+            warn!("Failed to set swap interval: {}", e);
+            if vsync == Vsync::Adaptive {
+                sdl_video
+                    .gl_set_swap_interval(SwapInterval::VSync)
+                    .unwrap_or_else(|e| warn!("Failed to enable vsync: {}", e));
+            }
+        }
 
+        unsafe {
             gl::Disable(gl::DEPTH_TEST);
             gl::Disable(gl::STENCIL_TEST);
             gl::Enable(gl::PRIMITIVE_RESTART);
@@ -88,7 +126,7 @@ impl Sdl2GlGfx {
             window,
             _opengl_context: opengl_context,
 
-            raster_renderer: GlRasterRenderer::new()?,
+            raster_renderer: GlRasterRenderer::new(parallel_raster)?,
             poly_renderer: {
                 let rendering_mode = match rendering_mode {
                     RenderingMode::Raster | RenderingMode::Poly => PolyRenderingMode::Poly,
@@ -99,13 +137,27 @@ impl Sdl2GlGfx {
                     rendering_mode,
                     window_size.0 as usize,
                     window_size.1 as usize,
+                    truecolor,
                 )?
             },
             framebuffer_renderer: IndexedFrameRenderer::new()?,
+            overlay_texture: IndexedTexture::new_truecolor(
+                gfx::SCREEN_RESOLUTION[0],
+                gfx::SCREEN_RESOLUTION[1],
+            ),
+            overlay_lines: Vec::new(),
             current_framebuffer: 0,
             palette: Default::default(),
+            pixel_grid: false,
+            background_color: Default::default(),
         })
     }
+
+    /// Enable or disable rounding the edges of polygons outwards in the CPU renderer, which
+    /// closes the one-pixel gaps that can appear between them.
+    pub fn set_conservative_raster(&mut self, conservative: bool) {
+        self.raster_renderer.set_conservative_fill(conservative);
+    }
 }
 
 impl gfx::GameRenderer for Sdl2GlGfx {
@@ -154,8 +206,23 @@ impl gfx::Display for Sdl2GlGfx {
         self.palette = palette.clone();
         match self.rendering_mode {
             RenderingMode::Raster => self.raster_renderer.update_texture(page_id),
-            RenderingMode::Poly | RenderingMode::Line => self.poly_renderer.update_texture(page_id),
+            RenderingMode::Poly | RenderingMode::Line => {
+                self.poly_renderer.set_palette(palette);
+                self.poly_renderer.update_texture(page_id)
+            }
+        };
+    }
+
+    fn read_framebuffer_rgb(&self) -> Option<gfx::RgbFrame> {
+        let framebuffer_texture = match self.rendering_mode {
+            RenderingMode::Raster => self.raster_renderer.as_ref(),
+            RenderingMode::Poly | RenderingMode::Line => self.poly_renderer.as_ref(),
         };
+
+        Some(
+            self.framebuffer_renderer
+                .read_rgb(framebuffer_texture, &self.palette),
+        )
     }
 }
 
@@ -207,12 +274,13 @@ impl gfx::Gfx for Sdl2GlGfx {}
 
 impl Sdl2Gfx for Sdl2GlGfx {
     #[tracing::instrument(skip(self))]
-    fn show_game_framebuffer(&mut self, dst: &Rect) {
+    fn show_game_framebuffer(&mut self, dst: &Rect, overlay: &[String]) {
         // We do a full-screen rendering of the active buffer, but we may end up with rendering
         // artefacts if the buffer's ratio does not match the current screen resolution. Clearing
         // the screen prevents that from happening.
         unsafe {
-            gl::ClearColor(0.0, 0.0, 0.0, 1.0);
+            let Color { r, g, b } = self.background_color;
+            gl::ClearColor(r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, 1.0);
             gl::Clear(gl::COLOR_BUFFER_BIT);
         }
 
@@ -221,17 +289,34 @@ impl Sdl2Gfx for Sdl2GlGfx {
             RenderingMode::Poly | RenderingMode::Line => self.poly_renderer.as_ref(),
         };
 
+        let viewport = Viewport {
+            x: dst.x(),
+            y: dst.y(),
+            width: dst.width() as i32,
+            height: dst.height() as i32,
+        };
+
         self.framebuffer_renderer.render(
             framebuffer_texture,
             &self.palette,
+            self.pixel_grid.then_some(PIXEL_GRID_SIZE),
             0,
-            &Viewport {
-                x: dst.x(),
-                y: dst.y(),
-                width: dst.width() as i32,
-                height: dst.height() as i32,
-            },
+            &viewport,
         );
+
+        if !overlay.is_empty() {
+            if overlay != self.overlay_lines {
+                let lines = overlay.iter().map(String::as_str).collect::<Vec<_>>();
+                self.overlay_texture.set_rgba_data(
+                    &gfx::overlay_text_rgba(&lines),
+                    gfx::SCREEN_RESOLUTION[0],
+                    gfx::SCREEN_RESOLUTION[1],
+                );
+                self.overlay_lines = overlay.to_vec();
+            }
+            self.framebuffer_renderer
+                .render_overlay(&self.overlay_texture, 0, &viewport);
+        }
     }
 
     #[tracing::instrument(skip(self))]
@@ -243,11 +328,35 @@ impl Sdl2Gfx for Sdl2GlGfx {
         &self.window
     }
 
+    fn window_mut(&mut self) -> &mut Window {
+        &mut self.window
+    }
+
+    fn set_font(&mut self, font: &Font) {
+        self.raster_renderer.set_font(font);
+        self.poly_renderer.set_font(font);
+    }
+
+    fn set_strict(&mut self, strict: bool) {
+        self.raster_renderer.set_strict(strict);
+        self.poly_renderer.set_strict(strict);
+    }
+
+    fn set_background_color(&mut self, color: &Color) {
+        self.background_color = color.clone();
+    }
+
+    fn write_drawlist(&self, w: &mut dyn Write) -> Result<()> {
+        Ok(self.poly_renderer.write_drawlist(w)?)
+    }
+
     #[tracing::instrument(skip(self))]
     fn handle_event(&mut self, event: &Event) {
         match event {
+            // Unlike `Resized`, `SizeChanged` is also sent when the size changes because the window
+            // goes fullscreen or back.
             Event::Window {
-                win_event: WindowEvent::Resized(w, h),
+                win_event: WindowEvent::SizeChanged(w, h),
                 ..
             } => self
                 .poly_renderer
@@ -257,6 +366,7 @@ impl Sdl2Gfx for Sdl2GlGfx {
                 repeat: false,
                 ..
             } => match *key {
+                Keycode::F4 => self.pixel_grid ^= true,
                 Keycode::F1 => self.rendering_mode = RenderingMode::Raster,
                 Keycode::F2 => {
                     self.rendering_mode = RenderingMode::Poly;
@@ -270,6 +380,17 @@ impl Sdl2Gfx for Sdl2GlGfx {
                         .set_rendering_mode(PolyRenderingMode::Line);
                     self.poly_renderer.redraw();
                 }
+                Keycode::F5 if matches!(self.rendering_mode, RenderingMode::Line) => {
+                    let smooth = !self.poly_renderer.line_smooth();
+                    self.poly_renderer.set_line_smooth(smooth);
+                    self.poly_renderer.redraw();
+                }
+                Keycode::F6 | Keycode::F7 if matches!(self.rendering_mode, RenderingMode::Line) => {
+                    let delta = if *key == Keycode::F6 { -1.0 } else { 1.0 };
+                    let width = self.poly_renderer.line_width() + delta;
+                    self.poly_renderer.set_line_width(width);
+                    self.poly_renderer.redraw();
+                }
                 _ => {}
             },
             _ => {}
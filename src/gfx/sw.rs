@@ -8,6 +8,9 @@ use anyhow::Result;
 use crate::gfx::polygon::Polygon;
 use crate::gfx::polygon::Trapezoid;
 use crate::gfx::polygon::TrapezoidLine;
+use crate::gfx::Palette;
+use crate::gfx::PolygonOptions;
+use crate::gfx::RgbFrame;
 use crate::gfx::SCREEN_RESOLUTION;
 
 /// Apply the zoom function on a point's coordinate `p`: multiply it by `zoom`,
@@ -27,7 +30,24 @@ impl Trapezoid<i16> {
     ///
     /// Filling each returned line with the color of the polygon will result in it being properly
     /// rendered.
-    pub fn raster_iterator(&self) -> impl Iterator<Item = TrapezoidLine<i16>> {
+    ///
+    /// The edges are interpolated in 16.16 fixed-point and rounded to the nearest pixel. Ties are
+    /// resolved outwards on both edges, i.e. down on the left edge (`+ 0x7fff`) and up on the right
+    /// edge (`+ 0x8000`). Using `+ 0x18000` on the right edge, as some other implementations do,
+    /// makes every line one pixel wider than the original game.
+    ///
+    /// If `conservative` is `true`, the edges are instead always rounded outwards, i.e. down on the
+    /// left edge and up on the right one. Lines then cover every pixel the exact edges go through,
+    /// which closes the one-pixel gaps that can appear between adjacent polygons, at the cost of
+    /// making them slightly larger than in the original game.
+    pub fn raster_iterator(&self, conservative: bool) -> impl Iterator<Item = TrapezoidLine<i16>> {
+        // Rounding added to the left and right edges before scaling them back.
+        let (round_left, round_right) = if conservative {
+            (0, 0xffff)
+        } else {
+            (0x7fff, 0x8000)
+        };
+
         // Interestingly the `y` range does not seem to be inclusive?
         let v_range = self.top.y..self.bot.y;
         let dy = v_range.len() as i32;
@@ -44,9 +64,9 @@ impl Trapezoid<i16> {
 
         v_range.scan((x_top_start, x_top_end), move |(left, right), y| {
             // Center the leftmost pixel and scale back.
-            let start_x = ((*left + 0x7fff) >> 16) as i16;
+            let start_x = ((*left + round_left) >> 16) as i16;
             // Center the rightmost pixel and scale back.
-            let end_x = ((*right + 0x8000) >> 16) as i16;
+            let end_x = ((*right + round_right) >> 16) as i16;
             *left += slope_left;
             *right += slope_right;
             Some(TrapezoidLine {
@@ -57,16 +77,54 @@ impl Trapezoid<i16> {
     }
 }
 
+/// Minimum number of lines a polygon must cover for its filling to be split between threads. Below
+/// that, spawning the threads costs more than it saves.
+const PARALLEL_FILL_MIN_LINES: usize = 64;
+
+/// Indexed-color image the game draws into.
+///
+/// The image can be larger than the game screen by an integer factor, in which case everything
+/// drawn into it is scaled accordingly. This allows rendering at a higher internal resolution and
+/// downscaling the result for display.
 #[derive(Clone)]
-pub struct IndexedImage([u8; SCREEN_RESOLUTION[0] * SCREEN_RESOLUTION[1]]);
+pub struct IndexedImage {
+    width: usize,
+    height: usize,
+    data: Vec<u8>,
+}
 
 impl Default for IndexedImage {
     fn default() -> Self {
-        IndexedImage([0u8; SCREEN_RESOLUTION[0] * SCREEN_RESOLUTION[1]])
+        IndexedImage::new(1)
     }
 }
 
 impl IndexedImage {
+    /// Create an image `scale` times larger than the game screen in each dimension.
+    pub fn new(scale: usize) -> Self {
+        assert!(scale > 0, "invalid image scale");
+        let width = SCREEN_RESOLUTION[0] * scale;
+        let height = SCREEN_RESOLUTION[1] * scale;
+
+        IndexedImage {
+            width,
+            height,
+            data: vec![0u8; width * height],
+        }
+    }
+
+    /// Returns the width and height of the image, in pixels.
+    pub fn dimensions(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// Returns the factor by which this image is larger than the game screen.
+    pub fn scale(&self) -> usize {
+        self.width / SCREEN_RESOLUTION[0]
+    }
+
+    /// Set the content of the image from a 4-bit planar bitmap of the game's resolution, scaling
+    /// it up if needed.
     pub fn set_content(&mut self, buffer: &[u8]) -> Result<()> {
         const EXPECTED_LENGTH: usize = SCREEN_RESOLUTION[0] * SCREEN_RESOLUTION[1] / 2;
         if buffer.len() != EXPECTED_LENGTH {
@@ -78,24 +136,32 @@ impl IndexedImage {
         }
 
         let planes: Vec<&[u8]> = buffer.chunks(8000).collect();
+        let scale = self.scale();
 
-        for (i, pixel) in self.0.iter_mut().enumerate() {
+        for i in 0..SCREEN_RESOLUTION[0] * SCREEN_RESOLUTION[1] {
             let idx = i / 8;
             let bit = 7 - (i % 8);
-            *pixel = (planes[0][idx] >> bit) & 0b1
+            let pixel = (planes[0][idx] >> bit) & 0b1
                 | ((planes[1][idx] >> bit) & 0b1) << 1
                 | ((planes[2][idx] >> bit) & 0b1) << 2
                 | ((planes[3][idx] >> bit) & 0b1) << 3;
+
+            let x = (i % SCREEN_RESOLUTION[0]) * scale;
+            let y = (i / SCREEN_RESOLUTION[0]) * scale;
+            for line in y..y + scale {
+                let start = line * self.width + x;
+                self.data[start..start + scale].fill(pixel);
+            }
         }
 
         Ok(())
     }
 
-    fn offset(x: i16, y: i16) -> Result<usize, ()> {
-        if x < 0 || x >= SCREEN_RESOLUTION[0] as i16 || y < 0 || y >= SCREEN_RESOLUTION[1] as i16 {
+    fn offset(&self, x: i16, y: i16) -> Result<usize, ()> {
+        if x < 0 || x as usize >= self.width || y < 0 || y as usize >= self.height {
             Err(())
         } else {
-            Ok((y as usize * SCREEN_RESOLUTION[0]) + x as usize)
+            Ok((y as usize * self.width) + x as usize)
         }
     }
 
@@ -105,64 +171,104 @@ impl IndexedImage {
             return;
         }
 
-        if let Ok(offset) = IndexedImage::offset(x, y) {
-            self.0[offset] = color;
+        if let Ok(offset) = self.offset(x, y) {
+            self.data[offset] = color;
         }
     }
 
     #[allow(dead_code)]
     fn get_pixel(&mut self, x: i16, y: i16) -> Result<u8, ()> {
-        match IndexedImage::offset(x, y) {
-            Ok(offset) => Ok(self.0[offset]),
+        match self.offset(x, y) {
+            Ok(offset) => Ok(self.data[offset]),
             Err(_) => Err(()),
         }
     }
 
+    /// Limit the start and end of `x_range` to the width of an image of `width` pixels.
+    fn clip_x_range(x_range: &std::ops::RangeInclusive<i16>, width: usize) -> (usize, usize) {
+        let x_start = ((*x_range.start()).clamp(0, width as i16 - 1)) as usize;
+        let x_stop = ((*x_range.end()).clamp(0, width as i16 - 1)) as usize;
+
+        (x_start, x_stop)
+    }
+
     /// Draw a horizontal line at ordinate `y`, between `x_range`.
+    ///
+    /// The coordinates are those of the image, i.e. they are not scaled.
     fn draw_hline<F>(&mut self, x_range: std::ops::RangeInclusive<i16>, y: i16, draw_func: F)
     where
         F: Fn(&mut [u8], usize),
     {
-        let line_offset = match IndexedImage::offset(0, y) {
+        let line_offset = match self.offset(0, y) {
             Ok(offset) => offset,
             // Line is not on screen.
             Err(_) => return,
         };
 
-        // Limit x_start and x_stop to [0..SCREEN_RESOLUTION[0]].
-        let x_start = ((*x_range.start()).clamp(0, SCREEN_RESOLUTION[0] as i16 - 1)) as usize;
-        let x_stop = ((*x_range.end()).clamp(0, SCREEN_RESOLUTION[0] as i16 - 1)) as usize;
+        let (x_start, x_stop) = IndexedImage::clip_x_range(&x_range, self.width);
 
-        let slice = &mut self.0[line_offset + x_start..=line_offset + x_stop];
+        let slice = &mut self.data[line_offset + x_start..=line_offset + x_stop];
         draw_func(slice, line_offset + x_start);
     }
 
-    fn fill_polygon<F>(
+    /// Fill `poly`, calling `draw_func` on each of its lines with the absolute offset of the line
+    /// in the image.
+    ///
+    /// `pos`, `offset` and `zoom` are given in game screen coordinates, and scaled to the size of
+    /// the image.
+    ///
+    /// If `options.parallel` is `true` and the polygon is large enough, its lines are distributed
+    /// between several threads, each one of them filling a different range of rows of the image.
+    ///
+    /// If `options.conservative` is `true`, the edges of the polygon are rounded outwards (see
+    /// [`Trapezoid::raster_iterator`]).
+    pub fn fill_polygon<F>(
         &mut self,
         poly: &Polygon,
         pos: (i16, i16),
         offset: (i16, i16),
         zoom: u16,
+        options: &PolygonOptions,
         draw_func: F,
     ) where
-        F: Fn(&mut [u8], usize),
+        F: Fn(&mut [u8], usize) + Sync,
     {
+        let &PolygonOptions {
+            parallel,
+            conservative,
+            ..
+        } = options;
+
         let bb = poly.bb();
+        let image_scale = self.scale() as i16;
+        let pos = (pos.0 * image_scale, pos.1 * image_scale);
 
-        // Optimization for single-pixel polygons
+        // Optimization for single-pixel polygons, which cover a square of `image_scale` pixels.
         if bb == (0, 0) {
-            if let Ok(offset) = IndexedImage::offset(pos.0, pos.1) {
-                draw_func(&mut self.0[offset..offset + 1], offset);
+            for y in pos.1..pos.1 + image_scale {
+                self.draw_hline(pos.0..=pos.0 + image_scale - 1, y, &draw_func);
             }
             return;
         }
 
+        let zoom = zoom.saturating_mul(image_scale as u16);
+
         // Offset x and y by the polygon center.
         let bbox_offset = (scale(bb.0 as i16, zoom) / 2, scale(bb.1 as i16, zoom) / 2);
         let offset = (scale(offset.0, zoom), scale(offset.1, zoom));
         let tx = pos.0 + offset.0 - bbox_offset.0;
         let ty = pos.1 + offset.1 - bbox_offset.1;
 
+        // Skip polygons that lie entirely outside of the screen. The scaled bounding box is
+        // extended by one pixel on each side to account for the rounding of the edges.
+        let left = tx as i32 - 1;
+        let top = ty as i32 - 1;
+        let right = tx as i32 + scale(bb.0 as i16, zoom) as i32 + 1;
+        let bottom = ty as i32 + scale(bb.1 as i16, zoom) as i32 + 1;
+        if right < 0 || bottom < 0 || left >= self.width as i32 || top >= self.height as i32 {
+            return;
+        }
+
         let trapezoids = poly
             .trapezoid_iter()
             // Use `i16` as the scaling and translate operations might move our points out of the
@@ -171,19 +277,112 @@ impl IndexedImage {
             .map(|t| t.scale(zoom))
             .map(|t| t.translate((tx, ty)));
 
-        for trapezoid in trapezoids {
-            for line in trapezoid.raster_iterator() {
+        if !parallel {
+            for trapezoid in trapezoids {
+                for line in trapezoid.raster_iterator(conservative) {
+                    self.draw_hline(line.x_range, line.y, &draw_func);
+                }
+            }
+            return;
+        }
+
+        let lines = trapezoids
+            .flat_map(|t| t.raster_iterator(conservative))
+            .collect::<Vec<_>>();
+        if lines.len() < PARALLEL_FILL_MIN_LINES {
+            for line in lines {
                 self.draw_hline(line.x_range, line.y, &draw_func);
             }
+        } else {
+            self.fill_lines_parallel(&lines, &draw_func);
+        }
+    }
+
+    /// Split the image into bands of rows, and fill the `lines` falling into each band from a
+    /// different thread. Since every line only touches a single row, the bands can be filled
+    /// independently.
+    fn fill_lines_parallel<F>(&mut self, lines: &[TrapezoidLine<i16>], draw_func: &F)
+    where
+        F: Fn(&mut [u8], usize) + Sync,
+    {
+        let num_threads = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+        let width = self.width;
+        let rows_per_band = self.height.div_ceil(num_threads);
+
+        std::thread::scope(|s| {
+            for (i, band) in self.data.chunks_mut(rows_per_band * width).enumerate() {
+                let first_row = i * rows_per_band;
+                let rows = first_row..first_row + band.len() / width;
+
+                s.spawn(move || {
+                    for line in lines
+                        .iter()
+                        .filter(|line| line.y >= 0 && rows.contains(&(line.y as usize)))
+                    {
+                        let (x_start, x_stop) = IndexedImage::clip_x_range(&line.x_range, width);
+                        let line_offset = (line.y as usize - first_row) * width;
+                        draw_func(
+                            &mut band[line_offset + x_start..=line_offset + x_stop],
+                            line.y as usize * width + x_start,
+                        );
+                    }
+                });
+            }
+        });
+    }
+
+    /// Fill the whole image with `color`.
+    pub fn fill(&mut self, color: u8) {
+        self.data.fill(color);
+    }
+
+    /// Copy `src` into this image, moving it down by `vscroll` lines (or up if `vscroll` is
+    /// negative). Lines that are not covered by `src` after scrolling are left untouched, so
+    /// nothing is copied if `src` is scrolled entirely out of the image.
+    ///
+    /// `vscroll` is given in game screen lines. Both images must have the same dimensions.
+    pub fn copy_from_vscrolled(&mut self, src: &IndexedImage, vscroll: i16) {
+        let scroll_lines = vscroll.unsigned_abs() as usize * self.scale();
+        if scroll_lines >= self.height {
+            return;
+        }
+
+        let len = self.data.len();
+        let scroll_len = scroll_lines * self.width;
+        let (src_start, dst_start) = if vscroll < 0 {
+            (scroll_len, 0)
+        } else {
+            (0, scroll_len)
+        };
+
+        self.data[dst_start..len - src_start]
+            .copy_from_slice(&src.data[src_start..len - dst_start]);
+    }
+
+    /// Returns the pixels of the image with their colors looked up in `palette`.
+    pub fn to_rgb(&self, palette: &Palette) -> RgbFrame {
+        RgbFrame {
+            width: self.width,
+            height: self.height,
+            pixels: self
+                .data
+                .iter()
+                .flat_map(|&pix| {
+                    let color = palette.lookup(pix);
+                    [color.r, color.g, color.b]
+                })
+                .collect(),
         }
     }
 
-    pub fn pixels(&self) -> &[u8; SCREEN_RESOLUTION[0] * SCREEN_RESOLUTION[1]] {
-        &self.0
+    pub fn pixels(&self) -> &[u8] {
+        &self.data
     }
 
     #[allow(dead_code)]
     pub fn as_ptr(&self) -> *const u8 {
-        self.0.as_ptr()
+        self.data.as_ptr()
     }
 }
@@ -1,18 +1,21 @@
 use std::cell::Ref;
 use std::cell::RefCell;
 
+use crate::font::Font;
 use crate::gfx::polygon::Polygon;
 use crate::gfx::sw::IndexedImage;
 use crate::gfx::GameRenderer;
 use crate::gfx::PolySegment;
 use crate::gfx::PolygonFiller;
+use crate::gfx::PolygonOptions;
 use crate::gfx::SimplePolygonRenderer;
-use crate::gfx::SCREEN_RESOLUTION;
 use crate::scenes::InitForScene;
 use crate::sys::Snapshotable;
 
 #[derive(Clone)]
-struct RasterRendererBuffers(Box<[RefCell<IndexedImage>; 4]>);
+struct RasterRendererBuffers {
+    pages: Box<[RefCell<IndexedImage>; 4]>,
+}
 
 impl PolygonFiller for RasterRendererBuffers {
     #[tracing::instrument(level = "trace", skip(self))]
@@ -24,15 +27,19 @@ impl PolygonFiller for RasterRendererBuffers {
         pos: (i16, i16),
         offset: (i16, i16),
         zoom: u16,
+        options: &PolygonOptions,
     ) {
-        let mut dst = self.0[dst_page_id].borrow_mut();
+        let mut dst = self.pages[dst_page_id].borrow_mut();
 
         match color {
             // Direct indexed color - fill the buffer with that color.
-            0x0..=0xf => dst.fill_polygon(poly, pos, offset, zoom, |line, _off| line.fill(color)),
+            0x0..=0xf => dst.fill_polygon(poly, pos, offset, zoom, options, |line, _off| {
+                line.fill(color)
+            }),
             // 0x10 special color - set the MSB of the current color to create
-            // transparency effect.
-            0x10 => dst.fill_polygon(poly, pos, offset, zoom, |line, _off| {
+            // transparency effect. The result is still a valid 4-bit index, and
+            // applying transparency again on the same pixel has no effect.
+            0x10 => dst.fill_polygon(poly, pos, offset, zoom, options, |line, _off| {
                 for pixel in line {
                     *pixel |= 0x8
                 }
@@ -42,13 +49,15 @@ impl PolygonFiller for RasterRendererBuffers {
                 // Do not try to copy page 0 into itself - not only the page won't change,
                 // but this will actually panic as we try to double-borrow the page.
                 if dst_page_id != 0 {
-                    let src = self.0[0].borrow();
-                    dst.fill_polygon(poly, pos, offset, zoom, |line, off| {
-                        line.copy_from_slice(&src.0[off..off + line.len()]);
+                    let src = self.pages[0].borrow();
+                    let src = src.pixels();
+                    dst.fill_polygon(poly, pos, offset, zoom, options, |line, off| {
+                        line.copy_from_slice(&src[off..off + line.len()]);
                     });
                 }
             }
-            color => panic!("Unexpected color 0x{:x}", color),
+            // Polygons of other colors are skipped by `SimplePolygonRenderer`.
+            color => unreachable!("Unexpected color 0x{:x}", color),
         };
     }
 }
@@ -60,23 +69,57 @@ impl PolygonFiller for RasterRendererBuffers {
 pub struct RasterGameRenderer {
     renderer: SimplePolygonRenderer,
     buffers: RasterRendererBuffers,
+    font: Font,
 }
 
 impl RasterGameRenderer {
     pub fn new() -> RasterGameRenderer {
         RasterGameRenderer {
             renderer: Default::default(),
-            buffers: RasterRendererBuffers(Box::new([
-                RefCell::new(Default::default()),
-                RefCell::new(Default::default()),
-                RefCell::new(Default::default()),
-                RefCell::new(Default::default()),
-            ])),
+            buffers: RasterRendererBuffers {
+                pages: Box::new([
+                    RefCell::new(Default::default()),
+                    RefCell::new(Default::default()),
+                    RefCell::new(Default::default()),
+                    RefCell::new(Default::default()),
+                ]),
+            },
+            font: Default::default(),
+        }
+    }
+
+    /// Enable or disable filling large polygons using several threads.
+    pub fn set_parallel_fill(&mut self, parallel: bool) {
+        self.renderer.options_mut().parallel = parallel;
+    }
+
+    /// Enable or disable rounding the edges of polygons outwards, which closes the one-pixel gaps
+    /// that can appear between adjacent polygons.
+    pub fn set_conservative_fill(&mut self, conservative: bool) {
+        self.renderer.options_mut().conservative = conservative;
+    }
+
+    /// Panic when a polygon has an invalid color if `strict` is set, instead of logging a warning
+    /// and skipping it.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.renderer.options_mut().strict = strict;
+    }
+
+    /// Use `font` to draw characters.
+    pub fn set_font(&mut self, font: &Font) {
+        self.font = font.clone();
+    }
+
+    /// Render into buffers `scale` times larger than the game screen in each dimension. The
+    /// content of all the buffers is cleared.
+    pub fn set_scale(&mut self, scale: usize) {
+        for page in self.buffers.pages.iter() {
+            *page.borrow_mut() = IndexedImage::new(scale);
         }
     }
 
     pub fn get_buffer(&self, page_id: usize) -> Ref<'_, IndexedImage> {
-        self.buffers.0[page_id].borrow()
+        self.buffers.pages[page_id].borrow()
     }
 }
 
@@ -95,11 +138,7 @@ impl InitForScene for RasterGameRenderer {
 // only need the buffers.
 impl GameRenderer for RasterGameRenderer {
     fn fillvideopage(&mut self, dst_page_id: usize, color_idx: u8) {
-        let mut dst = self.buffers.0[dst_page_id].borrow_mut();
-
-        for pixel in dst.0.iter_mut() {
-            *pixel = color_idx;
-        }
+        self.buffers.pages[dst_page_id].borrow_mut().fill(color_idx);
     }
 
     fn copyvideopage(&mut self, src_page_id: usize, dst_page_id: usize, vscroll: i16) {
@@ -108,73 +147,45 @@ impl GameRenderer for RasterGameRenderer {
             return;
         }
 
-        if !(-199..=199).contains(&vscroll) {
-            tracing::warn!("out-of-range vscroll for copyvideopage: {}", vscroll);
-            return;
-        }
-
-        let src = &self.buffers.0[src_page_id].borrow_mut();
-        let src_len = src.0.len();
-        let dst = &mut self.buffers.0[dst_page_id].borrow_mut();
-        let dst_len = dst.0.len();
-
-        let src_start = if vscroll < 0 {
-            vscroll.unsigned_abs() as usize * SCREEN_RESOLUTION[0]
-        } else {
-            0
-        };
-        let dst_start = if vscroll > 0 {
-            vscroll.unsigned_abs() as usize * SCREEN_RESOLUTION[0]
-        } else {
-            0
-        };
-        let src_slice = &src.0[src_start..src_len - dst_start];
-        let dst_slice = &mut dst.0[dst_start..dst_len - src_start];
+        let src = &self.buffers.pages[src_page_id].borrow();
+        let dst = &mut self.buffers.pages[dst_page_id].borrow_mut();
 
-        dst_slice.copy_from_slice(src_slice);
+        dst.copy_from_vscrolled(src, vscroll);
     }
 
     fn draw_char(&mut self, dst_page_id: usize, pos: (i16, i16), color: u8, c: u8) {
-        use crate::font::*;
-
         // Only direct colors are valid for fonts.
         if color > 0xf {
             tracing::error!("Unexpected font color 0x{:x}", color);
             return;
         }
 
-        // Our font starts at the first supported character.
-        let font_offset = match c {
-            FONT_FIRST_CHAR..=FONT_LAST_CHAR => c - FONT_FIRST_CHAR,
-            c => {
-                tracing::error!(
-                    "Character '{}' (0x{:x}) is not covered by font!",
-                    c as char,
-                    c
-                );
-                return;
-            }
-        } as usize
-            * CHAR_HEIGHT;
-
         // Each character is encoded with 8 bytes, 1 byte per line.
-        let char_bitmap = &FONT[font_offset..font_offset + CHAR_HEIGHT];
+        let char_bitmap = self.font.glyph(c);
 
-        let mut dst = self.buffers.0[dst_page_id].borrow_mut();
+        let mut dst = self.buffers.pages[dst_page_id].borrow_mut();
+        // Each pixel of the font covers a square of `scale` pixels of the image.
+        let scale = dst.scale();
+        let width = dst.dimensions().0;
+        let x_range = (pos.0 * scale as i16)..=((pos.0 + 8) * scale as i16 - 1);
         for (i, char_line) in char_bitmap.iter().map(|b| b.reverse_bits()).enumerate() {
-            dst.draw_hline(pos.0..=(pos.0 + 7), pos.1 + i as i16, |slice, off| {
-                for (i, pixel) in slice.iter_mut().enumerate() {
-                    if (char_line >> ((off + i) & 0x7) & 0x1) == 1 {
-                        *pixel = color
+            let y = (pos.1 + i as i16) * scale as i16;
+            for y in y..y + scale as i16 {
+                dst.draw_hline(x_range.clone(), y, |slice, off| {
+                    for (i, pixel) in slice.iter_mut().enumerate() {
+                        let x = ((off + i) % width) / scale;
+                        if (char_line >> (x & 0x7) & 0x1) == 1 {
+                            *pixel = color
+                        }
                     }
-                }
-            })
+                })
+            }
         }
     }
 
     fn blit_buffer(&mut self, dst_page_id: usize, buffer: &[u8]) {
         assert_eq!(buffer.len(), 32000);
-        let mut dst = self.buffers.0[dst_page_id].borrow_mut();
+        let mut dst = self.buffers.pages[dst_page_id].borrow_mut();
         dst.set_content(buffer)
             .unwrap_or_else(|e| tracing::error!("blit_buffer failed: {}", e));
     }
@@ -222,7 +233,7 @@ mod test {
     fn test_new_image() {
         let image: IndexedImage = Default::default();
 
-        for pixel in image.0.iter() {
+        for pixel in image.pixels().iter() {
             assert_eq!(*pixel, 0);
         }
     }
@@ -240,4 +251,450 @@ mod test {
         image.set_pixel(1000, 1000, 0x1);
         assert_eq!(image.get_pixel(1000, 1000), Err(()));
     }
+
+    #[test]
+    fn test_copy_vscrolled() {
+        let mut src: IndexedImage = Default::default();
+        src.set_pixel(5, 0, 0x1);
+        src.set_pixel(5, 199, 0x2);
+
+        let mut dst: IndexedImage = Default::default();
+        dst.set_pixel(6, 0, 0x3);
+        dst.copy_from_vscrolled(&src, 10);
+        assert_eq!(dst.get_pixel(5, 10), Ok(0x1));
+        // Lines scrolled out of the screen are lost, uncovered ones are preserved.
+        assert_eq!(dst.get_pixel(5, 199), Ok(0x0));
+        assert_eq!(dst.get_pixel(6, 0), Ok(0x3));
+
+        let mut dst: IndexedImage = Default::default();
+        dst.copy_from_vscrolled(&src, -199);
+        assert_eq!(dst.get_pixel(5, 0), Ok(0x2));
+        assert_eq!(dst.get_pixel(5, 1), Ok(0x0));
+    }
+
+    #[test]
+    fn test_copy_vscrolled_bounds() {
+        let mut src: IndexedImage = Default::default();
+        src.fill(0x1);
+        src.set_pixel(5, 0, 0x2);
+        src.set_pixel(5, 199, 0x3);
+        let copy = |vscroll| {
+            let mut dst: IndexedImage = Default::default();
+            dst.copy_from_vscrolled(&src, vscroll);
+            dst
+        };
+
+        assert_eq!(copy(0).pixels(), src.pixels());
+
+        // A single line remains on screen.
+        let mut dst = copy(199);
+        assert_eq!(dst.get_pixel(5, 199), Ok(0x2));
+        assert_eq!(dst.get_pixel(4, 199), Ok(0x1));
+        assert_eq!(dst.get_pixel(4, 198), Ok(0x0));
+        let mut dst = copy(-199);
+        assert_eq!(dst.get_pixel(5, 0), Ok(0x3));
+        assert_eq!(dst.get_pixel(4, 0), Ok(0x1));
+        assert_eq!(dst.get_pixel(4, 1), Ok(0x0));
+
+        // Nothing remains on screen, so the destination is left untouched.
+        for vscroll in [200, -200, i16::MAX, i16::MIN] {
+            assert!(copy(vscroll).pixels().iter().all(|&p| p == 0x0));
+        }
+    }
+
+    #[test]
+    /// Check how the edges of a trapezoid are rounded to pixels, since one-pixel differences are
+    /// enough to make thin polygons disappear.
+    fn test_raster_iterator_rounding() {
+        use crate::gfx::polygon::Trapezoid;
+        use crate::gfx::polygon::TrapezoidLine;
+
+        let lines = |trapezoid: Trapezoid<i16>| {
+            trapezoid
+                .raster_iterator(false)
+                .map(|l| (*l.x_range.start(), *l.x_range.end(), l.y))
+                .collect::<Vec<_>>()
+        };
+
+        // Vertical edges: lines cover both ends, the bottom line is excluded.
+        assert_eq!(
+            lines(Trapezoid {
+                top: TrapezoidLine {
+                    x_range: 10..=20,
+                    y: 0
+                },
+                bot: TrapezoidLine {
+                    x_range: 10..=20,
+                    y: 3
+                },
+            }),
+            vec![(10, 20, 0), (10, 20, 1), (10, 20, 2)]
+        );
+
+        // Integer slope: no rounding involved.
+        assert_eq!(
+            lines(Trapezoid {
+                top: TrapezoidLine {
+                    x_range: 0..=0,
+                    y: 0
+                },
+                bot: TrapezoidLine {
+                    x_range: 0..=8,
+                    y: 4
+                },
+            }),
+            vec![(0, 0, 0), (0, 2, 1), (0, 4, 2), (0, 6, 3)]
+        );
+
+        // Half-pixel slope: ties are rounded outwards on both edges.
+        assert_eq!(
+            lines(Trapezoid {
+                top: TrapezoidLine {
+                    x_range: 0..=0,
+                    y: 0
+                },
+                bot: TrapezoidLine {
+                    x_range: 6..=6,
+                    y: 4
+                },
+            }),
+            vec![(0, 0, 0), (1, 2, 1), (3, 3, 2), (4, 5, 3)]
+        );
+    }
+
+    #[test]
+    /// Check that filling a polygon using several threads gives the same result as doing it on a
+    /// single one, including when it goes beyond the screen borders.
+    fn test_parallel_fill() {
+        use crate::gfx::polygon::OwnedPolygon;
+        use crate::gfx::polygon::Point;
+
+        let poly = OwnedPolygon::new(
+            (200, 180),
+            vec![
+                Point::new(120, 0),
+                Point::new(200, 90),
+                Point::new(150, 180),
+                Point::new(20, 180),
+                Point::new(0, 90),
+                Point::new(40, 0),
+            ],
+        );
+
+        let parallel_options = PolygonOptions {
+            parallel: true,
+            ..Default::default()
+        };
+        for (pos, zoom) in [((160, 100), 64), ((10, 190), 64), ((160, 100), 128)] {
+            let mut sequential: IndexedImage = Default::default();
+            sequential.fill_polygon(&poly, pos, (0, 0), zoom, &Default::default(), |line, _| {
+                line.fill(0x5)
+            });
+            let mut parallel: IndexedImage = Default::default();
+            parallel.fill_polygon(&poly, pos, (0, 0), zoom, &parallel_options, |line, _| {
+                line.fill(0x5)
+            });
+
+            assert!(sequential.pixels().contains(&0x5));
+            assert_eq!(sequential.pixels(), parallel.pixels());
+        }
+    }
+
+    #[test]
+    /// Check that with conservative filling, the two halves of a hexagon cut along a slanted line
+    /// cover the whole hexagon, without any gap along the cut.
+    fn test_conservative_fill_no_gaps() {
+        use crate::gfx::polygon::OwnedPolygon;
+        use crate::gfx::polygon::Point;
+
+        let hexagon = OwnedPolygon::new(
+            (100, 100),
+            vec![
+                Point::new(70, 0),
+                Point::new(100, 50),
+                Point::new(70, 100),
+                Point::new(30, 100),
+                Point::new(0, 50),
+                Point::new(30, 0),
+            ],
+        );
+        let right_half = OwnedPolygon::new(
+            (100, 100),
+            vec![
+                Point::new(70, 0),
+                Point::new(100, 50),
+                Point::new(70, 100),
+                Point::new(40, 100),
+                Point::new(50, 50),
+                Point::new(60, 0),
+            ],
+        );
+        let left_half = OwnedPolygon::new(
+            (100, 100),
+            vec![
+                Point::new(60, 0),
+                Point::new(50, 50),
+                Point::new(40, 100),
+                Point::new(30, 100),
+                Point::new(0, 50),
+                Point::new(30, 0),
+            ],
+        );
+
+        for zoom in [32, 50, 64, 100, 128] {
+            let fill = |image: &mut IndexedImage, poly: &OwnedPolygon, conservative, color| {
+                image.fill_polygon(
+                    poly,
+                    (160, 100),
+                    (0, 0),
+                    zoom,
+                    &PolygonOptions {
+                        conservative,
+                        ..Default::default()
+                    },
+                    |line, _| line.fill(color),
+                )
+            };
+            let mut whole: IndexedImage = Default::default();
+            fill(&mut whole, &hexagon, false, 0x1);
+            let mut halves: IndexedImage = Default::default();
+            fill(&mut halves, &left_half, true, 0x2);
+            fill(&mut halves, &right_half, true, 0x3);
+
+            for (i, (&w, &h)) in whole.pixels().iter().zip(halves.pixels()).enumerate() {
+                assert!(w == 0 || h != 0, "gap at offset {} with zoom {}", i, zoom);
+            }
+        }
+    }
+
+    #[test]
+    /// Check that polygons lying entirely outside of the screen do not change any pixel, and that
+    /// polygons partially on the screen are not culled.
+    fn test_offscreen_polygon_culled() {
+        use crate::gfx::polygon::OwnedPolygon;
+        use crate::gfx::polygon::Point;
+
+        let poly = OwnedPolygon::new(
+            (40, 40),
+            vec![
+                Point::new(40, 0),
+                Point::new(40, 40),
+                Point::new(0, 40),
+                Point::new(0, 0),
+            ],
+        );
+
+        for pos in [
+            (-100, 100),
+            (500, 100),
+            (160, -100),
+            (160, 400),
+            (-1000, -1000),
+        ] {
+            let mut image: IndexedImage = Default::default();
+            image.fill_polygon(&poly, pos, (0, 0), 64, &Default::default(), |line, _| {
+                line.fill(0x5)
+            });
+            assert!(!image.pixels().contains(&0x5), "{:?}", pos);
+        }
+
+        // Partially visible polygons are still drawn.
+        for pos in [(-10, 100), (330, 100), (160, -10), (160, 210)] {
+            let mut image: IndexedImage = Default::default();
+            image.fill_polygon(&poly, pos, (0, 0), 64, &Default::default(), |line, _| {
+                line.fill(0x5)
+            });
+            assert!(image.pixels().contains(&0x5), "{:?}", pos);
+        }
+    }
+
+    #[test]
+    /// Check that rendering at a higher internal resolution scales everything drawn accordingly.
+    fn test_scaled_rendering() {
+        use crate::gfx::polygon::OwnedPolygon;
+        use crate::gfx::polygon::Point;
+        use crate::gfx::GameRenderer;
+        use crate::gfx::SCREEN_RESOLUTION;
+
+        let mut reference = RasterGameRenderer::new();
+        let mut scaled = RasterGameRenderer::new();
+        scaled.set_scale(2);
+
+        let mut bitmap = [0u8; 32000];
+        bitmap[100] = 0b1010_0000;
+        for renderer in [&mut reference, &mut scaled] {
+            renderer.blit_buffer(0, &bitmap);
+            renderer.draw_char(0, (16, 100), 0x3, b'A');
+        }
+
+        // Text and bitmaps are upscaled without any change.
+        let reference_pixels = reference.get_buffer(0).pixels().to_vec();
+        let scaled_buffer = scaled.get_buffer(0);
+        assert_eq!(scaled_buffer.dimensions(), (640, 400));
+        for (i, pixel) in scaled_buffer.pixels().iter().enumerate() {
+            let (x, y) = ((i % 640) / 2, (i / 640) / 2);
+            assert_eq!(*pixel, reference_pixels[y * SCREEN_RESOLUTION[0] + x]);
+        }
+        drop(scaled_buffer);
+
+        let poly = OwnedPolygon::new(
+            (10, 10),
+            vec![
+                Point::new(10, 0),
+                Point::new(10, 10),
+                Point::new(0, 10),
+                Point::new(0, 0),
+            ],
+        );
+        let mut image = IndexedImage::new(2);
+        image.fill_polygon(
+            &poly,
+            (160, 50),
+            (0, 0),
+            128,
+            &Default::default(),
+            |line, _| line.fill(0x5),
+        );
+        // Zoomed twice, the polygon spans 20 game pixels, i.e. 40 pixels of the image.
+        let count = image.pixels().iter().filter(|&&p| p == 0x5).count();
+        assert!((39 * 39..=41 * 41).contains(&count), "{}", count);
+        assert_eq!(image.get_pixel(320, 100), Ok(0x5));
+        assert_eq!(image.get_pixel(280, 100), Ok(0x0));
+    }
+
+    #[test]
+    fn test_render_scene_to_image() {
+        use crate::gfx::SCREEN_RESOLUTION;
+        use crate::testing::*;
+
+        let image = render_scene_to_image(test_scene_resources(), TEST_SCENE, 2);
+        let pixel = |x: usize, y: usize| image.pixels()[y * SCREEN_RESOLUTION[0] + x];
+
+        // Background.
+        assert_eq!(pixel(0, 0), 0x2);
+        // Square, with the transparent one on top of it.
+        assert_eq!(pixel(145, 85), 0x5);
+        assert_eq!(pixel(160, 100), 0xd);
+        // Hierarchy: recolored square, and transparent one over the background.
+        assert_eq!(pixel(50, 40), 0x7);
+        assert_eq!(pixel(80, 70), 0xa);
+    }
+
+    #[test]
+    /// Check that pixels made transparent by color 0x10 keep their color when their page is
+    /// copied, and can be looked up in the palette.
+    fn test_transparency_across_copy() {
+        use crate::gfx::SCREEN_RESOLUTION;
+        use crate::testing::*;
+
+        let (vm, mut renderer) = run_scene(
+            RasterGameRenderer::new(),
+            test_scene_resources(),
+            TEST_SCENE,
+            2,
+        );
+        let pixel =
+            |image: &IndexedImage, x: usize, y: usize| image.pixels()[y * SCREEN_RESOLUTION[0] + x];
+        assert_eq!(pixel(&renderer.get_buffer(1), 160, 100), 0xd);
+        // The scene copied page 1 into page 2 without scrolling.
+        assert_eq!(
+            renderer.get_buffer(1).pixels(),
+            renderer.get_buffer(2).pixels()
+        );
+
+        renderer.fillvideopage(3, 0x0);
+        renderer.copyvideopage(1, 3, 10);
+        let copy = renderer.get_buffer(3);
+        assert_eq!(pixel(&copy, 160, 110), 0xd);
+        assert!(copy.pixels().iter().all(|&p| p <= 0xf));
+
+        let palette = vm.palette();
+        let (dark, bright) = (palette.lookup(0x5), palette.lookup(0xd));
+        assert_ne!((dark.r, dark.g, dark.b), (bright.r, bright.g, bright.b));
+    }
+
+    /// Measures the throughput of `IndexedImage::fill_polygon` for a few representative polygons
+    /// at several zoom levels, with and without parallel filling.
+    ///
+    /// Run with `cargo test --release -- --ignored --nocapture bench_`.
+    #[test]
+    #[ignore = "benchmark"]
+    fn bench_fill_polygon() {
+        use crate::gfx::polygon::OwnedPolygon;
+        use crate::gfx::polygon::Point;
+
+        const ITERATIONS: u32 = 1000;
+
+        let polygons = [
+            (
+                "triangle",
+                OwnedPolygon::new(
+                    (100, 100),
+                    vec![
+                        Point::new(50, 0),
+                        Point::new(100, 100),
+                        Point::new(0, 100),
+                        Point::new(50, 0),
+                    ],
+                ),
+            ),
+            (
+                "hexagon",
+                OwnedPolygon::new(
+                    (100, 100),
+                    vec![
+                        Point::new(70, 0),
+                        Point::new(100, 50),
+                        Point::new(70, 100),
+                        Point::new(30, 100),
+                        Point::new(0, 50),
+                        Point::new(30, 0),
+                    ],
+                ),
+            ),
+            (
+                "fullscreen quad",
+                OwnedPolygon::new(
+                    (255, 199),
+                    vec![
+                        Point::new(255, 0),
+                        Point::new(255, 199),
+                        Point::new(0, 199),
+                        Point::new(0, 0),
+                    ],
+                ),
+            ),
+        ];
+
+        let mut image: IndexedImage = Default::default();
+        for (name, poly) in &polygons {
+            for zoom in [32, 64, 128, 256] {
+                for parallel in [false, true] {
+                    let start = std::time::Instant::now();
+                    for _ in 0..ITERATIONS {
+                        image.fill_polygon(
+                            poly,
+                            (160, 100),
+                            (0, 0),
+                            zoom,
+                            &PolygonOptions {
+                                parallel,
+                                ..Default::default()
+                            },
+                            |line, _| line.fill(0x5),
+                        );
+                    }
+                    let elapsed = start.elapsed();
+
+                    println!(
+                        "fill_polygon {} zoom {} parallel {}: {:?} per polygon",
+                        name,
+                        zoom,
+                        parallel,
+                        elapsed / ITERATIONS
+                    );
+                }
+            }
+        }
+    }
 }
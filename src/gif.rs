@@ -0,0 +1,259 @@
+//! Minimal animated GIF encoder.
+//!
+//! Only supports 16-color frames covering the whole image, each with its own color table. This is
+//! exactly what the game produces, and spares us an image library for the sole purpose of
+//! recording clips.
+
+use std::collections::HashMap;
+use std::io;
+use std::io::Write;
+
+/// Number of colors of every frame.
+const NUM_COLORS: usize = 16;
+/// Minimum LZW code size, i.e. number of bits required to encode a color index.
+const LZW_MIN_CODE_SIZE: u32 = 4;
+/// Maximum size of LZW codes, as defined by the GIF specification.
+const LZW_MAX_CODE_SIZE: u32 = 12;
+/// Maximum length of a data sub-block.
+const MAX_SUB_BLOCK_LEN: usize = 0xff;
+
+/// Packs variable-width codes into bytes, least significant bit first.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u32,
+    nbits: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, width: u32) {
+        self.acc |= (code as u32) << self.nbits;
+        self.nbits += width;
+        while self.nbits >= 8 {
+            self.bytes.push(self.acc as u8);
+            self.acc >>= 8;
+            self.nbits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.nbits > 0 {
+            self.bytes.push(self.acc as u8);
+        }
+        self.bytes
+    }
+}
+
+/// Compress `pixels` (color indices all lower than `NUM_COLORS`) using GIF's variant of LZW.
+fn lzw_compress(pixels: &[u8]) -> Vec<u8> {
+    let clear_code = 1u16 << LZW_MIN_CODE_SIZE;
+    let eoi_code = clear_code + 1;
+
+    let mut out = BitWriter::default();
+    let mut dict = HashMap::<(u16, u8), u16>::new();
+    let mut next_code = eoi_code + 1;
+    let mut width = LZW_MIN_CODE_SIZE + 1;
+
+    // The code width increases as soon as the next code to be assigned does not fit anymore.
+    // Decoders assign codes one step later than us, hence the check being done right after
+    // emitting a code but before assigning the next one.
+    fn emit(out: &mut BitWriter, code: u16, next_code: u16, width: &mut u32) {
+        out.write(code, *width);
+        if next_code >= (1 << *width) && *width < LZW_MAX_CODE_SIZE {
+            *width += 1;
+        }
+    }
+
+    out.write(clear_code, width);
+    let mut pixels = pixels.iter().copied();
+    if let Some(first) = pixels.next() {
+        let mut prefix = first as u16;
+        for pixel in pixels {
+            if let Some(&code) = dict.get(&(prefix, pixel)) {
+                prefix = code;
+                continue;
+            }
+
+            emit(&mut out, prefix, next_code, &mut width);
+            if next_code as usize >= (1 << LZW_MAX_CODE_SIZE) - 1 {
+                // Table is full, start over.
+                out.write(clear_code, width);
+                dict.clear();
+                next_code = eoi_code + 1;
+                width = LZW_MIN_CODE_SIZE + 1;
+            } else {
+                dict.insert((prefix, pixel), next_code);
+                next_code += 1;
+            }
+            prefix = pixel as u16;
+        }
+        emit(&mut out, prefix, next_code, &mut width);
+    }
+    out.write(eoi_code, width);
+
+    out.finish()
+}
+
+/// Writes frames into an animated GIF.
+pub struct GifWriter<W: Write> {
+    w: W,
+    width: usize,
+    height: usize,
+}
+
+impl<W: Write> GifWriter<W> {
+    /// Start a new looping animation of `width`x`height` pixels into `w`.
+    pub fn new(mut w: W, width: usize, height: usize) -> io::Result<Self> {
+        w.write_all(b"GIF89a")?;
+        // Logical screen descriptor: no global color table.
+        w.write_all(&(width as u16).to_le_bytes())?;
+        w.write_all(&(height as u16).to_le_bytes())?;
+        w.write_all(&[0x00, 0x00, 0x00])?;
+        // Netscape application extension, making the animation loop forever.
+        w.write_all(&[0x21, 0xff, 0x0b])?;
+        w.write_all(b"NETSCAPE2.0")?;
+        w.write_all(&[0x03, 0x01, 0x00, 0x00, 0x00])?;
+
+        Ok(Self { w, width, height })
+    }
+
+    /// Add a frame made of the color indices in `pixels` (line by line), which actual colors are
+    /// given by `palette`. The frame stays on screen for `delay` hundredths of a second.
+    pub fn write_frame(
+        &mut self,
+        pixels: &[u8],
+        palette: &[[u8; 3]; NUM_COLORS],
+        delay: u16,
+    ) -> io::Result<()> {
+        if pixels.len() != self.width * self.height
+            || pixels.iter().any(|&p| p as usize >= NUM_COLORS)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "frame data does not match dimensions or palette",
+            ));
+        }
+
+        let w = &mut self.w;
+        // Graphic control extension: leave the frame in place, no transparency.
+        w.write_all(&[0x21, 0xf9, 0x04, 0x04])?;
+        w.write_all(&delay.to_le_bytes())?;
+        w.write_all(&[0x00, 0x00])?;
+
+        // Image descriptor, followed by a local color table of 2^(3 + 1) entries.
+        w.write_all(&[0x2c, 0x00, 0x00, 0x00, 0x00])?;
+        w.write_all(&(self.width as u16).to_le_bytes())?;
+        w.write_all(&(self.height as u16).to_le_bytes())?;
+        w.write_all(&[0x80 | (LZW_MIN_CODE_SIZE as u8 - 1)])?;
+        for color in palette {
+            w.write_all(color)?;
+        }
+
+        w.write_all(&[LZW_MIN_CODE_SIZE as u8])?;
+        for block in lzw_compress(pixels).chunks(MAX_SUB_BLOCK_LEN) {
+            w.write_all(&[block.len() as u8])?;
+            w.write_all(block)?;
+        }
+        w.write_all(&[0x00])
+    }
+
+    /// Terminate the animation and return the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.w.write_all(&[0x3b])?;
+        self.w.flush()?;
+        Ok(self.w)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Straightforward GIF LZW decoder, used to check that our encoder's output can be read back.
+    fn lzw_decompress(data: &[u8]) -> Vec<u8> {
+        let clear_code = 1usize << LZW_MIN_CODE_SIZE;
+        let eoi_code = clear_code + 1;
+
+        let mut bits = data
+            .iter()
+            .flat_map(|&byte| (0..8).map(move |i| (byte >> i) & 1));
+        let mut read = |width: u32| {
+            (0..width).fold(0usize, |code, i| {
+                code | ((bits.next().expect("truncated data") as usize) << i)
+            })
+        };
+
+        let mut out = Vec::new();
+        let mut dict: Vec<Vec<u8>> = Vec::new();
+        let mut width = LZW_MIN_CODE_SIZE + 1;
+        let mut prev: Option<Vec<u8>> = None;
+        loop {
+            let code = read(width);
+            if code == clear_code {
+                dict = (0..clear_code as u8).map(|c| vec![c]).collect();
+                dict.extend([vec![], vec![]]);
+                width = LZW_MIN_CODE_SIZE + 1;
+                prev = None;
+                continue;
+            } else if code == eoi_code {
+                break;
+            }
+
+            let entry = match (&prev, dict.get(code)) {
+                (_, Some(entry)) => entry.clone(),
+                // Code being defined by this very step.
+                (Some(prev), None) => [prev.as_slice(), &prev[0..1]].concat(),
+                (None, None) => panic!("invalid code {}", code),
+            };
+            if let Some(prev) = prev {
+                if dict.len() < 1 << LZW_MAX_CODE_SIZE {
+                    dict.push([prev.as_slice(), &entry[0..1]].concat());
+                    if dict.len() == 1 << width && width < LZW_MAX_CODE_SIZE {
+                        width += 1;
+                    }
+                }
+            }
+            out.extend_from_slice(&entry);
+            prev = Some(entry);
+        }
+
+        out
+    }
+
+    #[test]
+    fn test_lzw_roundtrip() {
+        let inputs: [Vec<u8>; 4] = [
+            vec![],
+            vec![0x3],
+            vec![0x5; 64000],
+            // Pseudo-random data, to fill the dictionary and make it reset several times.
+            (0..64000u32)
+                .map(|i| (i.wrapping_mul(2_654_435_761) >> 28) as u8)
+                .collect(),
+        ];
+
+        for input in inputs {
+            assert_eq!(lzw_decompress(&lzw_compress(&input)), input);
+        }
+    }
+
+    #[test]
+    fn test_write_gif() {
+        let palette = [[0u8; 3]; NUM_COLORS];
+        let mut gif = GifWriter::new(Vec::new(), 2, 2).unwrap();
+        gif.write_frame(&[0, 1, 2, 3], &palette, 4).unwrap();
+        assert!(gif.write_frame(&[0, 1, 2], &palette, 4).is_err());
+        assert!(gif.write_frame(&[0, 1, 2, 16], &palette, 4).is_err());
+        let out = gif.finish().unwrap();
+
+        assert_eq!(&out[0..6], b"GIF89a");
+        assert_eq!(&out[6..10], &[2, 0, 2, 0]);
+        // Graphic control extension with our delay.
+        let gce = 6 + 7 + 19;
+        assert_eq!(&out[gce..gce + 8], &[0x21, 0xf9, 0x04, 0x04, 4, 0, 0, 0]);
+        // Image descriptor with a 16 colors local table.
+        assert_eq!(out[gce + 8], 0x2c);
+        assert_eq!(out[gce + 17], 0x83);
+        assert_eq!(out.last(), Some(&0x3b));
+    }
+}
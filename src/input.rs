@@ -1,20 +1,26 @@
+use std::collections::VecDeque;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum LeftRightDir {
     Neutral,
     Left,
     Right,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum UpDownDir {
     Neutral,
     Up,
     Down,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ButtonState {
     Released,
     Pushed,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct InputState {
     pub horizontal: LeftRightDir,
     pub vertical: UpDownDir,
@@ -30,3 +36,102 @@ impl InputState {
         }
     }
 }
+
+/// Change of one of the controls of an `InputState`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputEvent {
+    Horizontal(LeftRightDir),
+    Vertical(UpDownDir),
+    Button(ButtonState),
+}
+
+impl InputEvent {
+    /// Index of the control changed by this event.
+    fn control(&self) -> usize {
+        match self {
+            InputEvent::Horizontal(_) => 0,
+            InputEvent::Vertical(_) => 1,
+            InputEvent::Button(_) => 2,
+        }
+    }
+
+    fn apply(&self, state: &mut InputState) {
+        match *self {
+            InputEvent::Horizontal(dir) => state.horizontal = dir,
+            InputEvent::Vertical(dir) => state.vertical = dir,
+            InputEvent::Button(button) => state.button = button,
+        }
+    }
+}
+
+/// Queue of the input events received between VM rounds.
+///
+/// Events are keyed by the tick they happened before, and applied when the VM plays a round at
+/// or after that tick. A control changes at most once per round, so a key pressed and released
+/// before the VM had a chance to see it is still pressed for one round, even if no round was
+/// played during the frame it happened in.
+#[derive(Debug)]
+pub struct InputQueue {
+    state: InputState,
+    events: VecDeque<(u64, InputEvent)>,
+}
+
+impl InputQueue {
+    pub fn new() -> Self {
+        InputQueue {
+            state: InputState::new(),
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Queue `event`, to be applied to the first round played at or after `tick`.
+    pub fn push(&mut self, tick: u64, event: InputEvent) {
+        self.events.push_back((tick, event));
+    }
+
+    /// Returns the input state the VM must see for the round it plays at `tick`.
+    ///
+    /// Events are applied in the order they have been received, up to the first one changing a
+    /// control that has already been changed for this round. That event and the following ones
+    /// are kept for the next rounds.
+    pub fn state_for_round(&mut self, tick: u64) -> &InputState {
+        let mut changed = [false; 3];
+
+        while let Some(&(event_tick, event)) = self.events.front() {
+            if event_tick > tick || changed[event.control()] {
+                break;
+            }
+            changed[event.control()] = true;
+            event.apply(&mut self.state);
+            self.events.pop_front();
+        }
+
+        &self.state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_press_and_release() {
+        let mut queue = InputQueue::new();
+
+        // Pressed and released before the same round: the press is seen for one round.
+        queue.push(0, InputEvent::Button(ButtonState::Pushed));
+        queue.push(0, InputEvent::Horizontal(LeftRightDir::Left));
+        queue.push(0, InputEvent::Button(ButtonState::Released));
+        let state = *queue.state_for_round(3);
+        assert_eq!(state.button, ButtonState::Pushed);
+        assert_eq!(state.horizontal, LeftRightDir::Left);
+        let state = *queue.state_for_round(3);
+        assert_eq!(state.button, ButtonState::Released);
+        assert_eq!(state.horizontal, LeftRightDir::Left);
+
+        // Events are not applied before their tick.
+        queue.push(5, InputEvent::Vertical(UpDownDir::Up));
+        assert_eq!(queue.state_for_round(4).vertical, UpDownDir::Neutral);
+        assert_eq!(queue.state_for_round(5).vertical, UpDownDir::Up);
+    }
+}
@@ -1,36 +1,441 @@
 mod audio;
 mod font;
 mod gfx;
+mod gif;
 mod input;
+mod png;
 mod res;
 mod scenes;
 mod strings;
 mod sys;
+#[cfg(test)]
+mod testing;
 mod vm;
 
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+use std::time::Instant;
+
 use clap::Parser;
+use gfx::Display;
 use scenes::SCENES;
+use tracing::info;
+use tracing::warn;
 use tracing_subscriber::prelude::*;
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 struct Cli {
+    /// Directory containing the game data files
+    #[arg(long, value_name = "DIR", default_value = ".")]
+    data_dir: std::path::PathBuf,
+    /// Resources list to use instead of the memlist.bin file of the game data, for editions of
+    /// the game that differ only by their resources list. Relative to the data directory
+    #[arg(long, value_name = "FILE")]
+    memlist: Option<std::path::PathBuf>,
     /// The scene to start from (0..9)
     #[arg(short, long, value_name = "SCENE")]
     scene: Option<u8>,
-    /// How to render the game (raster, gl_raster, gl_poly, gl_line)
+    /// Seed of the game's random number generator (default: 48879)
+    #[arg(long, value_name = "SEED")]
+    seed: Option<u16>,
+    /// Number of instructions a thread of the game can run in a single round before it is
+    /// considered stuck and stopped until the next round
+    #[arg(long, value_name = "N", default_value_t = 1_000_000, value_parser = clap::value_parser!(u64).range(1..))]
+    instruction_budget: u64,
+    /// Log every instruction run by thread ID of the game (0..63), with the registers it changes
+    #[arg(long, value_name = "ID", value_parser = clap::value_parser!(u8).range(0..=63))]
+    trace_thread: Option<u8>,
+    /// Write the log of --trace-thread into FILE instead of the standard error
+    #[arg(long, value_name = "FILE", requires = "trace_thread")]
+    trace_thread_file: Option<std::path::PathBuf>,
+    /// Load all the resources of rank RANK when the game starts and keep them in memory. Can be
+    /// given several times
+    #[arg(long, value_name = "RANK")]
+    preload_rank: Vec<u8>,
+    /// Directory containing unpacked resources replacing the ones of the game data, named after
+    /// their number like 0042.bin for resource 0x42
+    #[arg(long, value_name = "DIR")]
+    patch_dir: Option<std::path::PathBuf>,
+    /// Restart the current scene when a file of the patch directory changes
+    #[arg(long, requires = "patch_dir")]
+    watch_patches: bool,
+    /// Level of graphics detail requested to the game: 0 for maximum details, 1 to remove
+    /// reflections
+    #[arg(long, value_name = "LEVEL", default_value_t = 0, value_parser = clap::value_parser!(u8).range(0..=1))]
+    gfx_detail: u8,
+    /// Continue the game from the start of the last scene reached
+    #[arg(long = "continue", conflicts_with = "scene")]
+    continue_game: bool,
+    /// How to render the game (raster, gl_raster, gl_poly, gl_line, gl_truecolor)
     #[arg(short, long, value_name = "RENDERER")]
     renderer: Option<String>,
     /// List all the available resources with their properties and exit
     #[arg(short, long)]
     list_resources: bool,
+    /// List all the scenes with the resources they use and exit
+    #[arg(long)]
+    list_scenes: bool,
+    /// Print all the strings of the game with their id, in the format of strings.txt, and exit
+    #[arg(long)]
+    dump_strings: bool,
+    /// Language of the game's text, e.g. "en" or "fr". Strings are loaded from strings-LANG.txt
+    #[arg(long, value_name = "LANG", default_value = strings::DEFAULT_LANG)]
+    lang: String,
+    /// Check that the resources used by every scene exist and are of the expected type before
+    /// starting the game
+    #[arg(long)]
+    check: bool,
     /// Dump all resources into the \"resources\" folder and exit
     #[arg(short, long)]
     dump_resources: bool,
+    /// Format of dumped bitmaps: raw 8bpp grayscale or PNG
+    #[arg(long, value_enum, default_value_t = DumpFormatArg::Raw)]
+    dump_format: DumpFormatArg,
+    /// Palette resource used to color bitmaps dumped as PNG (default: palette of the intro)
+    #[arg(long, value_name = "RES_ID")]
+    dump_palette: Option<usize>,
+    /// Dump resource INDEX into the \"resources\" folder and exit
+    #[arg(long, value_name = "INDEX")]
+    dump_resource: Option<usize>,
+    /// Print a hexdump and a summary of the content of resource INDEX and exit
+    #[arg(long, value_name = "INDEX")]
+    inspect: Option<usize>,
+    /// Export music resource INDEX and the sounds it uses into a ProTracker module at FILE and exit
+    #[arg(long, num_args = 2, value_names = ["INDEX", "FILE"])]
+    export_music: Option<Vec<String>>,
+    /// Read and unpack every resource to check the integrity of the game data and exit
+    #[arg(long)]
+    self_test: bool,
+    /// Print the differences between two VM states saved with Shift+D and exit
+    #[arg(long, num_args = 2, value_names = ["A", "B"])]
+    diff_states: Option<Vec<std::path::PathBuf>>,
     /// Record a trace in the Chrome format into trace_file instead of printing events on the
     /// standard output
     #[arg(short, long, value_name = "TRACE_FILE")]
     trace_file: Option<String>,
+    /// Output audio in stereo, with channels panned like the Amiga version
+    #[arg(long)]
+    stereo: bool,
+    /// Audio output frequency in Hz (8000..96000)
+    #[arg(
+        long,
+        value_name = "HZ",
+        default_value_t = 22050,
+        value_parser = clap::value_parser!(u32).range(8000..=96000)
+    )]
+    audio_freq: u32,
+    /// Size of the audio buffer in samples, a power of two (64..8192). Increase it if the sound
+    /// crackles
+    #[arg(long, value_name = "SAMPLES", value_parser = parse_audio_buffer)]
+    audio_buffer: Option<u16>,
+    /// How to interpolate sound samples when resampling them to the output frequency
+    #[arg(long, value_enum, value_name = "MODE", default_value_t = AudioInterpArg::Linear)]
+    audio_interp: AudioInterpArg,
+    /// Run without sound, e.g. on machines without an audio device
+    #[arg(long)]
+    no_audio: bool,
+    /// Start with all audio output stopped. Press `0` to toggle it
+    #[arg(long)]
+    mute: bool,
+    /// Fill large polygons using several threads with the raster renderers
+    #[arg(long)]
+    parallel_raster: bool,
+    /// Round the edges of polygons outwards to close the gaps between them, at the cost of
+    /// fidelity (raster renderers only)
+    #[arg(long)]
+    conservative_raster: bool,
+    /// Render the game this many times larger than its original resolution and downscale it for
+    /// display, to smooth the edges of polygons (1..4, raster renderer only)
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u8).range(1..=4)
+    )]
+    raster_scale: u8,
+    /// Draw the game's text with the font in FILE, made of 256 8x8 glyphs of 8 bytes each
+    #[arg(long, value_name = "FILE")]
+    font: Option<std::path::PathBuf>,
+    /// Color of the bars around the game, as a RRGGBB hexadecimal value
+    #[arg(long, value_name = "RRGGBB", default_value = "000000", value_parser = parse_color)]
+    bg_color: gfx::Color,
+    /// Record the game into an animated GIF, written when the game exits
+    #[arg(long, value_name = "FILE")]
+    record_gif: Option<std::path::PathBuf>,
+    /// Maximum number of frames per second of the GIF recording (1..50)
+    #[arg(long, value_name = "FPS", default_value_t = 25)]
+    gif_fps: u32,
+    /// Record the input given to the game into a demo, written when the game exits
+    #[arg(long, value_name = "FILE")]
+    record_demo: Option<std::path::PathBuf>,
+    /// Play the demo in FILE without display nor sound as fast as possible, print how long it
+    /// took and exit
+    #[arg(long, value_name = "FILE", conflicts_with = "record_demo")]
+    benchmark: Option<std::path::PathBuf>,
+    /// Take the input of the game from the demo in FILE instead of the keyboard
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["record_demo", "benchmark"])]
+    play_demo: Option<std::path::PathBuf>,
+    /// Run the game without a window nor sound, and print its frames on the terminal
+    #[arg(long, conflicts_with_all = ["record_demo", "benchmark"])]
+    ascii_preview: bool,
+    /// Show the current scene and round on top of the game while paused
+    #[arg(long)]
+    show_overlay: bool,
+    /// Enable debugging features, like the scene selection menu (F9)
+    #[arg(long)]
+    debug: bool,
+    /// Stop with an error when a polygon has an invalid color, instead of skipping it with a
+    /// warning
+    #[arg(long)]
+    strict: bool,
+    /// Print the VM registers and threads when the game exits
+    #[arg(long)]
+    dump_regs_on_exit: bool,
+    /// Print how many times each kind of instruction has been run when the game exits
+    #[arg(long)]
+    profile_opcodes: bool,
+    /// Blend the previous frame at 50% over the current one, to spot elements missing for a single
+    /// frame (raster renderer only)
+    #[arg(long)]
+    onion_skin: bool,
+    /// Write the draw commands of the current frame into a JSON file when F10 is pressed (GL
+    /// renderers only)
+    #[arg(long)]
+    dump_drawlist: bool,
+    /// Wait for the vertical sync of the screen before presenting frames
+    #[arg(long, value_enum, default_value_t = VsyncArg::On)]
+    vsync: VsyncArg,
+    /// Present at most N frames per second, to save power. The game runs at the same speed
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..=1000))]
+    max_fps: Option<u32>,
+    /// Number of game ticks per second: `pal` (50), `ntsc` (60), or a value between 10 and 200
+    #[arg(long, value_name = "HZ", default_value = "pal", value_parser = parse_tick_rate)]
+    tick_rate: u32,
+    /// Run exactly one game tick per frame instead of following the clock, for reproducible runs
+    #[arg(long)]
+    deterministic: bool,
+    /// Number of snapshots kept to rewind the game with B
+    #[arg(long, value_name = "N", default_value_t = sys::DEFAULT_REWIND_DEPTH, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(sys::MIN_REWIND_DEPTH as u64..))]
+    rewind_depth: usize,
+    /// Number of game ticks between two rewind snapshots
+    #[arg(long, value_name = "TICKS", default_value_t = sys::DEFAULT_REWIND_INTERVAL, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(sys::MIN_REWIND_INTERVAL as u64..))]
+    rewind_interval: usize,
+    /// Pause the sound while the window does not have the focus
+    #[arg(long, value_name = "BOOL", default_value_t = true, action = clap::ArgAction::Set)]
+    mute_on_focus_loss: bool,
+    /// Pause the game when the window loses the focus
+    #[arg(long)]
+    pause_on_focus_loss: bool,
+    /// Start in fullscreen mode. F11 switches between fullscreen and windowed modes
+    #[arg(long)]
+    fullscreen: bool,
+    /// Shape of the game's image: 4:3 like the screens of the time, 8:5 for square pixels, or
+    /// stretch to fill the window
+    #[arg(long, value_enum, default_value_t = AspectArg::SquarePixels)]
+    aspect: AspectArg,
+}
+
+/// Parse and validate the value of `--audio-buffer`.
+fn parse_audio_buffer(s: &str) -> Result<u16, String> {
+    let samples = s.parse::<u16>().map_err(|e| e.to_string())?;
+    if !(64..=8192).contains(&samples) || !samples.is_power_of_two() {
+        return Err("must be a power of two between 64 and 8192".into());
+    }
+
+    Ok(samples)
+}
+
+/// Parse a color given as a `RRGGBB` hexadecimal value, with an optional leading `#`.
+fn parse_color(s: &str) -> Result<gfx::Color, String> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err("must be 6 hexadecimal digits, like 202020".into());
+    }
+    let rgb = u32::from_str_radix(hex, 16).unwrap();
+
+    Ok(gfx::Color {
+        r: (rgb >> 16) as u8,
+        g: (rgb >> 8) as u8,
+        b: rgb as u8,
+    })
+}
+
+/// Parse and validate the value of `--tick-rate`.
+fn parse_tick_rate(s: &str) -> Result<u32, String> {
+    let rate = match s {
+        "pal" => sys::PAL_TICK_RATE,
+        "ntsc" => sys::NTSC_TICK_RATE,
+        s => s.parse::<u32>().map_err(|e| e.to_string())?,
+    };
+    if !(10..=200).contains(&rate) {
+        return Err("must be `pal`, `ntsc`, or between 10 and 200".into());
+    }
+
+    Ok(rate)
+}
+
+/// Open the resources of the game data in `data_dir`, described by the resources list `memlist`
+/// if given, or exit if they cannot be loaded.
+fn open_resources(data_dir: &Path, memlist: Option<&Path>) -> res::ResourceManager {
+    let resman = match memlist {
+        Some(memlist) => res::ResourceManager::new_with_memlist(data_dir, memlist, true),
+        None => res::ResourceManager::new_in_dir(data_dir, true),
+    };
+
+    resman.unwrap_or_else(|e| {
+        eprintln!("Cannot load the game data: {}", e);
+        std::process::exit(1);
+    })
+}
+
+/// Play the demo at `path` headlessly with the raster renderer, and print how long it took.
+fn run_benchmark(
+    path: &Path,
+    resman: res::ResourceManager,
+    parallel_raster: bool,
+    conservative_raster: bool,
+    strict: bool,
+) -> std::io::Result<()> {
+    let demo = vm::Demo::load(path)?;
+    let mut vm = Box::new(vm::VmBuilder::new().resources(resman).build()?);
+    let mut renderer = gfx::sw::RasterGameRenderer::new();
+    renderer.set_parallel_fill(parallel_raster);
+    renderer.set_conservative_fill(conservative_raster);
+    renderer.set_strict(strict);
+    let mut gfx = gfx::headless::HeadlessGfx::new(renderer);
+
+    let start = Instant::now();
+    let rounds = demo.play(&mut vm, &mut gfx, &mut audio::NullAudio)?;
+    let elapsed = start.elapsed();
+
+    if rounds < demo.num_rounds() {
+        warn!(
+            "The game stopped after {} of the {} rounds of the demo",
+            rounds,
+            demo.num_rounds()
+        );
+    }
+    println!(
+        "{} rounds played in {:.3}s, {:.1}us per round",
+        rounds,
+        elapsed.as_secs_f64(),
+        elapsed.as_secs_f64() * 1_000_000.0 / rounds.max(1) as f64
+    );
+
+    Ok(())
+}
+
+/// Returns the width of the terminal on standard output in characters, or `None` if it is not a
+/// terminal.
+#[cfg(unix)]
+fn terminal_columns() -> Option<usize> {
+    let mut size = libc::winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // SAFETY: TIOCGWINSZ only writes into `size`, which lives until the end of the call.
+    let ret = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+    match (ret, size.ws_col) {
+        (0, columns) if columns > 0 => Some(columns as usize),
+        _ => None,
+    }
+}
+
+#[cfg(not(unix))]
+fn terminal_columns() -> Option<usize> {
+    None
+}
+
+/// Run `vm` with the raster renderer at `tick_rate` ticks per second, without a window nor sound,
+/// and print each frame it presents on the terminal. The input is taken from the demo at `demo`,
+/// if any.
+fn run_ascii_preview(
+    vm: &mut vm::Vm,
+    demo: Option<&Path>,
+    tick_rate: u32,
+    strict: bool,
+) -> std::io::Result<()> {
+    let demo = demo.map(vm::Demo::load).transpose()?;
+    if let Some(demo) = &demo {
+        demo.start(vm);
+    }
+    let mut renderer = gfx::sw::RasterGameRenderer::new();
+    renderer.set_strict(strict);
+    let mut gfx = gfx::headless::HeadlessGfx::new(renderer);
+    // COLUMNS takes precedence over the size of the terminal, so previews can be made narrower.
+    let columns = std::env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+        .or_else(terminal_columns)
+        .unwrap_or(80);
+    let duration_per_tick = Duration::from_micros(1_000_000 / tick_rate as u64);
+    let mut out = std::io::stdout().lock();
+
+    for round in 0.. {
+        if let Some(demo) = &demo {
+            if !demo.apply_round(round, vm) {
+                break;
+            }
+        }
+        let start = Instant::now();
+        if !vm.process_round(&mut gfx, &mut audio::NullAudio)? {
+            break;
+        }
+
+        if vm.frame_presented() {
+            if let Some(frame) = gfx.read_framebuffer_rgb() {
+                // Draw from the top-left corner of the screen, and clear what is below the frame.
+                write!(
+                    out,
+                    "\x1b[H{}\x1b[J",
+                    gfx::ansi::frame_to_ansi(&frame, columns)
+                )?;
+                out.flush()?;
+            }
+        }
+
+        // Wait as long as the game asks to, like the game loop does.
+        let wait = duration_per_tick * vm.get_frames_to_wait().max(1) as u32;
+        if let Some(remaining) = wait.checked_sub(start.elapsed()) {
+            std::thread::sleep(remaining);
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum DumpFormatArg {
+    Raw,
+    Png,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum AudioInterpArg {
+    None,
+    Linear,
+    Cubic,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum VsyncArg {
+    On,
+    Off,
+    Adaptive,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum AspectArg {
+    #[value(name = "4:3")]
+    Classic,
+    #[value(name = "8:5")]
+    SquarePixels,
+    Stretch,
 }
 
 fn main() {
@@ -43,16 +448,105 @@ fn main() {
 
     let mut must_exit = false;
 
+    let dump_format = match cli.dump_format {
+        DumpFormatArg::Raw => res::DumpFormat::Raw,
+        DumpFormatArg::Png => res::DumpFormat::Png {
+            palette_res: cli.dump_palette.unwrap_or(SCENES[1].palette),
+        },
+    };
+
     if cli.list_resources {
-        let resman = res::ResourceManager::new().unwrap();
+        let resman = open_resources(&cli.data_dir, cli.memlist.as_deref());
         resman.list_resources();
         must_exit = true;
     }
 
+    if cli.list_scenes {
+        scenes::list_scenes();
+        must_exit = true;
+    }
+
+    if cli.dump_strings {
+        match strings::load_strings(&cli.lang) {
+            Ok(strings) => {
+                for (id, string) in strings.get_all() {
+                    println!("0x{:03X}, {}", id, string.replace('\n', "\\n"));
+                }
+            }
+            Err(e) => eprintln!("Failed to load strings: {}", e),
+        }
+        must_exit = true;
+    }
+
     if cli.dump_resources {
         println!("Dumping all resources...");
-        let mut resman = res::ResourceManager::new().unwrap();
-        resman.dump_resources().unwrap();
+        let resman = open_resources(&cli.data_dir, cli.memlist.as_deref());
+        resman.dump_resources(dump_format).unwrap();
+        must_exit = true;
+    }
+
+    if let Some(index) = cli.dump_resource {
+        println!("Dumping resource 0x{:02x}...", index);
+        let resman = open_resources(&cli.data_dir, cli.memlist.as_deref());
+        resman.dump_resource(index, dump_format).unwrap();
+        must_exit = true;
+    }
+
+    if let Some(index) = cli.inspect {
+        let resman = open_resources(&cli.data_dir, cli.memlist.as_deref());
+        if let Err(e) = resman.inspect_resource(index) {
+            eprintln!("Failed to inspect resource 0x{:02x}: {}", index, e);
+        }
+        must_exit = true;
+    }
+
+    if let Some([index, path]) = cli.export_music.as_deref() {
+        let resman = open_resources(&cli.data_dir, cli.memlist.as_deref());
+        match index
+            .parse::<usize>()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))
+            .and_then(|index| resman.export_music(index, Path::new(path)))
+        {
+            Ok(()) => println!("Music exported into {}", path),
+            Err(e) => eprintln!("Failed to export music {}: {}", index, e),
+        }
+        must_exit = true;
+    }
+
+    if cli.self_test {
+        let resman = open_resources(&cli.data_dir, cli.memlist.as_deref());
+        let report = resman.self_test();
+        for (i, status) in report.failures() {
+            let info = resman.resource_info(i).unwrap();
+            eprintln!(
+                "resource 0x{:02x} (bank {:02x}, offset 0x{:08x}): {}",
+                i, info.bank_id, info.bank_offset, status
+            );
+        }
+        println!(
+            "{} resources checked, {} failed",
+            report.statuses.len(),
+            report.failures().count()
+        );
+        if !report.passed() {
+            std::process::exit(1);
+        }
+        must_exit = true;
+    }
+
+    if let Some(paths) = &cli.diff_states {
+        let (a, b) = match (vm::VmState::load(&paths[0]), vm::VmState::load(&paths[1])) {
+            (Ok(a), Ok(b)) => (a, b),
+            (Err(e), _) | (_, Err(e)) => {
+                eprintln!("Cannot load VM state: {}", e);
+                std::process::exit(1);
+            }
+        };
+        let diff = a.diff(&b);
+        match diff.is_empty() {
+            true => println!("The states are identical"),
+            false => print!("{}", diff),
+        }
         must_exit = true;
     }
 
@@ -75,12 +569,187 @@ fn main() {
         return;
     }
 
-    let Some(mut sys) = sys::sdl2::sdl2_simple::new_with_renderer(&cli.renderer) else {
-        panic!("failed to create system component");
+    if cli.check {
+        let resman = open_resources(&cli.data_dir, cli.memlist.as_deref());
+        if let Err(errors) = scenes::validate(&resman) {
+            for e in &errors {
+                eprintln!("{}", e);
+            }
+            eprintln!("{} invalid scene resources, not starting", errors.len());
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(demo) = &cli.benchmark {
+        if let Err(e) = run_benchmark(
+            demo,
+            open_resources(&cli.data_dir, cli.memlist.as_deref()),
+            cli.parallel_raster,
+            cli.conservative_raster,
+            cli.strict,
+        ) {
+            eprintln!("Benchmark failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let sys_options = sys::SysOptions {
+        stereo: cli.stereo,
+        audio_freq: cli.audio_freq,
+        audio_buffer: cli.audio_buffer,
+        audio_interp: match cli.audio_interp {
+            AudioInterpArg::None => audio::InterpolationMode::None,
+            AudioInterpArg::Linear => audio::InterpolationMode::Linear,
+            AudioInterpArg::Cubic => audio::InterpolationMode::Cubic,
+        },
+        no_audio: cli.no_audio,
+        mute: cli.mute,
+        parallel_raster: cli.parallel_raster,
+        conservative_raster: cli.conservative_raster,
+        raster_scale: cli.raster_scale as usize,
+        font: cli.font,
+        bg_color: cli.bg_color,
+        record_gif: cli.record_gif,
+        gif_fps: cli.gif_fps,
+        record_demo: cli.record_demo,
+        play_demo: cli.play_demo.clone(),
+        show_overlay: cli.show_overlay,
+        debug: cli.debug,
+        strict: cli.strict,
+        onion_skin: cli.onion_skin,
+        dump_drawlist: cli.dump_drawlist,
+        vsync: match cli.vsync {
+            VsyncArg::On => gfx::Vsync::On,
+            VsyncArg::Off => gfx::Vsync::Off,
+            VsyncArg::Adaptive => gfx::Vsync::Adaptive,
+        },
+        max_fps: cli.max_fps,
+        tick_rate: cli.tick_rate,
+        deterministic: cli.deterministic,
+        mute_on_focus_loss: cli.mute_on_focus_loss,
+        pause_on_focus_loss: cli.pause_on_focus_loss,
+        fullscreen: cli.fullscreen,
+        aspect: match cli.aspect {
+            AspectArg::Classic => gfx::AspectRatio::Classic,
+            AspectArg::SquarePixels => gfx::AspectRatio::SquarePixels,
+            AspectArg::Stretch => gfx::AspectRatio::Stretch,
+        },
+        watch_patches: cli.patch_dir.clone().filter(|_| cli.watch_patches),
+        rewind_depth: cli.rewind_depth,
+        rewind_interval: cli.rewind_interval,
+    };
+    let sys = match cli.ascii_preview {
+        false => match sys::sdl2::sdl2_simple::new_with_renderer(&cli.renderer, &sys_options) {
+            Some(sys) => Some(sys),
+            None => panic!("failed to create system component"),
+        },
+        true => None,
     };
 
-    let mut vm = Box::new(vm::Vm::new().unwrap());
-    vm.request_scene(start_scene);
+    let mut vm_builder = vm::VmBuilder::new()
+        .data_dir(&cli.data_dir)
+        .instruction_budget(cli.instruction_budget as usize);
+    if !cli.preload_rank.is_empty() || cli.patch_dir.is_some() || cli.memlist.is_some() {
+        let mut resman = open_resources(&cli.data_dir, cli.memlist.as_deref());
+        if let Some(patch_dir) = &cli.patch_dir {
+            resman.set_patch_dir(patch_dir);
+        }
+        for &rank in &cli.preload_rank {
+            match resman.preload_rank(rank) {
+                Ok(count) => info!("Preloaded {} resources of rank {}", count, rank),
+                Err(e) => warn!("Failed to preload resources of rank {}: {}", rank, e),
+            }
+        }
+        vm_builder = vm_builder.resources(resman);
+    }
+    if let Some(seed) = cli.seed {
+        vm_builder = vm_builder.seed(seed);
+    }
+    if let Some(thread) = cli.trace_thread {
+        let out: Box<dyn std::io::Write> = match &cli.trace_thread_file {
+            None => Box::new(std::io::stderr()),
+            Some(path) => match std::fs::File::create(path) {
+                Ok(file) => Box::new(std::io::BufWriter::new(file)),
+                Err(e) => {
+                    eprintln!("Cannot create {}: {}", path.display(), e);
+                    std::process::exit(1);
+                }
+            },
+        };
+        vm_builder = vm_builder.trace_thread(thread as usize, out);
+    }
+    if cli.lang != strings::DEFAULT_LANG {
+        match strings::load_strings(&cli.lang) {
+            Ok(strings) => vm_builder = vm_builder.strings(strings),
+            Err(e) => warn!(
+                "Failed to load the {} strings, using English: {}",
+                cli.lang, e
+            ),
+        }
+    }
+    let mut vm = Box::new(vm_builder.build().unwrap());
+    match cli.continue_game {
+        false => vm.request_scene(start_scene),
+        true => match vm::Checkpoint::load_latest(Path::new(".")) {
+            Ok(Some(checkpoint)) => {
+                info!("Continuing from scene {}", checkpoint.scene());
+                vm.restore_checkpoint(&checkpoint);
+            }
+            Ok(None) => {
+                warn!("No checkpoint to continue from, starting a new game");
+                vm.request_scene(start_scene);
+            }
+            Err(e) => {
+                eprintln!("Failed to load checkpoint: {}", e);
+                std::process::exit(1);
+            }
+        },
+    }
+    // Set after restoring a checkpoint, which would otherwise bring back the level it was saved
+    // with.
+    vm.set_gfx_detail(cli.gfx_detail);
+
+    match sys {
+        Some(mut sys) => sys.game_loop(&mut vm),
+        None => {
+            if let Err(e) =
+                run_ascii_preview(&mut vm, cli.play_demo.as_deref(), cli.tick_rate, cli.strict)
+            {
+                eprintln!("Preview failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
 
-    sys.game_loop(&mut vm);
+    if cli.dump_regs_on_exit {
+        println!("VM state at round {}:\n{:?}", vm.round(), vm);
+    }
+    if cli.profile_opcodes {
+        print_opcode_profile(&vm);
+    }
+}
+
+/// Print how many times `vm` has run each kind of instruction, most frequent first.
+fn print_opcode_profile(vm: &vm::Vm) {
+    // The sprite instructions use many opcodes each.
+    let mut counts = BTreeMap::<&str, u64>::new();
+    for (opcode, &count) in vm.opcode_histogram().iter().enumerate() {
+        if count > 0 {
+            *counts.entry(vm::opcode_name(opcode as u8)).or_default() += count;
+        }
+    }
+    let mut counts = counts.into_iter().collect::<Vec<_>>();
+    counts.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+
+    let total = counts.iter().map(|(_, count)| count).sum::<u64>();
+    println!("{} instructions run in {} rounds:", total, vm.round());
+    for (name, count) in counts {
+        println!(
+            "{:<16} {:>12} {:>6.2}%",
+            name,
+            count,
+            count as f64 * 100.0 / total as f64
+        );
+    }
 }
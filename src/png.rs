@@ -0,0 +1,142 @@
+//! Minimal PNG encoder.
+//!
+//! Only supports writing 8-bit RGB images, with the image data stored uncompressed. This is enough
+//! for dumping the game's graphics into a format any image viewer can open, without pulling an
+//! image library for that sole purpose.
+
+use std::io;
+use std::io::Write;
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+/// Maximum length of a non-compressed deflate block.
+const MAX_STORED_BLOCK_LEN: usize = 0xffff;
+
+/// CRC-32 of `data`, as used in PNG chunks.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Adler-32 checksum of `data`, as used at the end of zlib streams.
+fn adler32(data: &[u8]) -> u32 {
+    let (a, b) = data.iter().fold((1u32, 0u32), |(a, b), &byte| {
+        let a = (a + byte as u32) % 65521;
+        (a, (b + a) % 65521)
+    });
+    (b << 16) | a
+}
+
+fn write_chunk<W: Write>(w: &mut W, chunk_type: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    w.write_all(&(data.len() as u32).to_be_bytes())?;
+    let mut crc_data = Vec::with_capacity(chunk_type.len() + data.len());
+    crc_data.extend_from_slice(chunk_type);
+    crc_data.extend_from_slice(data);
+    w.write_all(&crc_data)?;
+    w.write_all(&crc32(&crc_data).to_be_bytes())
+}
+
+/// Wrap `data` into a zlib stream made of non-compressed deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    // CMF/FLG: deflate with 32K window, no preset dictionary, check bits valid.
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_STORED_BLOCK_LEN).peekable();
+    // An empty input still needs one (final) block.
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[0x01, 0x00, 0x00, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+/// Write a `width`x`height` image into `w` in PNG format. `rgb` contains the pixels, line by line,
+/// with 3 bytes per pixel.
+pub fn write_rgb<W: Write>(w: &mut W, width: usize, height: usize, rgb: &[u8]) -> io::Result<()> {
+    if rgb.len() != width * height * 3 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "image data does not match dimensions",
+        ));
+    }
+
+    w.write_all(&PNG_SIGNATURE)?;
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&(width as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bits per component, RGB, deflate compression, standard filtering, no interlacing.
+    ihdr.extend_from_slice(&[8, 2, 0, 0, 0]);
+    write_chunk(w, b"IHDR", &ihdr)?;
+
+    // Each line is preceded by its filter type, which is always "none" for us.
+    let mut raw = Vec::with_capacity(height * (width * 3 + 1));
+    for line in rgb.chunks(width * 3) {
+        raw.push(0);
+        raw.extend_from_slice(line);
+    }
+    write_chunk(w, b"IDAT", &zlib_stored(&raw))?;
+
+    write_chunk(w, b"IEND", &[])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksums() {
+        assert_eq!(crc32(b"IEND"), 0xae42_6082);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    }
+
+    #[test]
+    fn test_zlib_stored_blocks() {
+        let data = vec![0xaau8; MAX_STORED_BLOCK_LEN + 10];
+        let zlib = zlib_stored(&data);
+
+        // Header + 2 block headers + data + adler32.
+        assert_eq!(zlib.len(), 2 + 5 * 2 + data.len() + 4);
+        // First block is not final and full.
+        assert_eq!(&zlib[2..7], &[0x00, 0xff, 0xff, 0x00, 0x00]);
+        // Second block is final and contains the remainder.
+        let second = 7 + MAX_STORED_BLOCK_LEN;
+        assert_eq!(&zlib[second..second + 5], &[0x01, 10, 0x00, 0xf5, 0xff]);
+    }
+
+    #[test]
+    fn test_write_rgb() {
+        let mut out = Vec::new();
+        write_rgb(&mut out, 2, 1, &[0xff, 0, 0, 0, 0xff, 0]).unwrap();
+
+        assert_eq!(&out[0..8], &PNG_SIGNATURE);
+        // IHDR chunk.
+        assert_eq!(&out[8..16], &[0, 0, 0, 13, b'I', b'H', b'D', b'R']);
+        assert_eq!(&out[16..24], &[0, 0, 0, 2, 0, 0, 0, 1]);
+        // IEND chunk with its well-known CRC.
+        assert_eq!(
+            &out[out.len() - 12..],
+            &[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82]
+        );
+
+        assert!(write_rgb(&mut Vec::new(), 2, 2, &[0; 6]).is_err());
+    }
+}
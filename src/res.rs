@@ -1,18 +1,34 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
 use std::fmt;
 use std::fs::File;
 use std::io;
+use std::io::BufWriter;
 use std::io::Read;
 use std::io::Seek;
 use std::io::SeekFrom;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::SystemTime;
 
 use enumn::N;
 use tracing::debug;
+use tracing::info;
+use tracing::warn;
 use zerocopy::big_endian::U16;
 use zerocopy::big_endian::U32;
 use zerocopy::FromBytes;
 
+use crate::audio::write_protracker;
 use crate::audio::MusicModule;
+use crate::audio::MusicModuleHeader;
 use crate::audio::SoundSample;
+use crate::gfx::Palette;
+use crate::gfx::PALETTE_SIZE;
+use crate::png;
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug, N)]
 pub enum ResType {
@@ -75,11 +91,19 @@ impl<'a> UnpackContext<'a> {
     // data up to packed_len. The data will then be uncompressed in-place.
     fn new(data: &'a mut [u8], packed_len: usize) -> io::Result<UnpackContext<'a>> {
         assert!(data.len() >= packed_len);
-        let footer_start = packed_len - std::mem::size_of::<UnpackFooter>();
-        assert_eq!(footer_start % 4, 0);
+        let footer_start = Self::footer_start(packed_len)?;
         let footer = UnpackFooter::read_from_bytes(&data[footer_start..packed_len]).unwrap();
         let data_size = footer.data_size.get() as usize;
-        assert_eq!(data_size, data.len());
+        if data_size != data.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "unpacked size {} does not match the expected {}",
+                    data_size,
+                    data.len()
+                ),
+            ));
+        }
         let crc = footer.crc.get() ^ footer.chk.get();
 
         Ok(UnpackContext {
@@ -91,6 +115,27 @@ impl<'a> UnpackContext<'a> {
         })
     }
 
+    // Returns the offset of the footer in packed data of `packed_len` bytes, which is made of
+    // 32-bit words.
+    fn footer_start(packed_len: usize) -> io::Result<usize> {
+        packed_len
+            .checked_sub(std::mem::size_of::<UnpackFooter>())
+            .filter(|start| start % 4 == 0)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid packed data length {}", packed_len),
+                )
+            })
+    }
+
+    // Returns the size of the unpacked data, as recorded in the footer of `packed`.
+    fn unpacked_size(packed: &[u8]) -> io::Result<usize> {
+        let footer =
+            UnpackFooter::read_from_bytes(&packed[Self::footer_start(packed.len())?..]).unwrap();
+        Ok(footer.data_size.get() as usize)
+    }
+
     fn rcr(&mut self) -> bool {
         let rcf = (self.chk & 1) == 1;
         self.chk >>= 1;
@@ -176,6 +221,88 @@ impl<'a> UnpackContext<'a> {
     }
 }
 
+/// Width of bitmap resources, in pixels.
+const BITMAP_WIDTH: usize = 320;
+/// Height of bitmap resources, in pixels.
+const BITMAP_HEIGHT: usize = 200;
+
+/// How resources should be written when dumped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DumpFormat {
+    /// Write the unpacked data as-is. Bitmaps are converted to 8bpp grayscale.
+    Raw,
+    /// Convert bitmaps to PNG files, using the first palette of palette resource `palette_res`.
+    /// Other resources are written as-is.
+    Png { palette_res: usize },
+}
+
+/// Name of the file listing all the resources in the DOS version.
+const MEMLIST_FILE: &str = "memlist.bin";
+
+/// Edition of the game the data files come from.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GameVariant {
+    /// DOS version, with a `memlist.bin` file and `bankXX` files.
+    Dos,
+    /// Amiga version. The resources list is embedded into the executable and the `bankXX` files
+    /// have different sizes.
+    Amiga,
+    /// Atari ST version. Similar to the Amiga one.
+    AtariSt,
+}
+
+impl fmt::Display for GameVariant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GameVariant::Dos => write!(f, "DOS"),
+            GameVariant::Amiga => write!(f, "Amiga"),
+            GameVariant::AtariSt => write!(f, "Atari ST"),
+        }
+    }
+}
+
+impl GameVariant {
+    /// Returns the variant corresponding to a `bank01` file of `size` bytes, for versions that do
+    /// not come with a `memlist.bin`.
+    fn from_bank01_size(size: u64) -> Option<GameVariant> {
+        match size {
+            // French and English Amiga versions.
+            244674 | 244868 => Some(GameVariant::Amiga),
+            227142 => Some(GameVariant::AtariSt),
+            _ => None,
+        }
+    }
+
+    /// Detect the variant of the game data present in `data_dir`.
+    fn detect(data_dir: &Path) -> io::Result<GameVariant> {
+        if data_dir.join(MEMLIST_FILE).exists() {
+            return Ok(GameVariant::Dos);
+        }
+
+        let bank01_size = std::fs::metadata(data_dir.join("bank01"))
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!(
+                        "cannot find {} or bank01, is the game data present?",
+                        MEMLIST_FILE
+                    ),
+                )
+            })?
+            .len();
+
+        GameVariant::from_bank01_size(bank01_size).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} is missing and bank01 ({} bytes) does not match any known game version",
+                    MEMLIST_FILE, bank01_size
+                ),
+            )
+        })
+    }
+}
+
 /// An entry in the `memlist.bin` file.
 #[derive(FromBytes)]
 #[repr(C, packed)]
@@ -200,7 +327,7 @@ struct MemlistEntry {
 #[allow(dead_code)]
 struct MemEntry {
     res_type: ResType,
-    // not sure what this is?
+    /// Loading priority of the resource. See [`ResourceInfo::rank_num`].
     rank_num: u8,
     bank_id: u8,
     bank_offset: u32,
@@ -225,44 +352,225 @@ impl TryFrom<&MemlistEntry> for MemEntry {
     }
 }
 
+/// Public information about a resource, as described by the `memlist.bin` file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResourceInfo {
+    pub res_type: ResType,
+    /// Loading priority of the resource. When asked to load several resources, the original
+    /// game loads them in decreasing order of rank, so that e.g. the bytecode and polygons a
+    /// scene starts with come before its sounds. Ranks do not identify the scene or part of the
+    /// game a resource belongs to: resources of very different scenes share the same rank, so
+    /// [`ResourceManager::preload_rank`] is a way to load a whole class of resources ahead of
+    /// time, not a scene.
+    pub rank_num: u8,
+    pub bank_id: u8,
+    pub bank_offset: u32,
+    pub packed_size: usize,
+    pub size: usize,
+}
+
+impl From<&MemEntry> for ResourceInfo {
+    fn from(entry: &MemEntry) -> Self {
+        Self {
+            res_type: entry.res_type,
+            rank_num: entry.rank_num,
+            bank_id: entry.bank_id,
+            bank_offset: entry.bank_offset,
+            packed_size: entry.packed_size,
+            size: entry.size,
+        }
+    }
+}
+
+/// State of a resource, as checked by [`ResourceManager::self_test`].
+#[derive(Debug)]
+pub enum ResourceStatus {
+    /// The resource could be read, and unpacked if needed.
+    Ok,
+    /// The resource could not be read from its bank file.
+    ReadError(io::Error),
+    /// The size of the unpacked data recorded along the packed data differs from the one declared
+    /// in the resources list.
+    SizeMismatch { declared: usize, unpacked: usize },
+    /// The resource has been unpacked, but the CRC of the unpacked data is invalid.
+    BadCrc,
+}
+
+impl fmt::Display for ResourceStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResourceStatus::Ok => write!(f, "ok"),
+            ResourceStatus::ReadError(e) => write!(f, "read error: {}", e),
+            ResourceStatus::SizeMismatch { declared, unpacked } => write!(
+                f,
+                "unpacks to {} bytes instead of the declared {}",
+                unpacked, declared
+            ),
+            ResourceStatus::BadCrc => write!(f, "invalid CRC"),
+        }
+    }
+}
+
+/// Result of [`ResourceManager::self_test`]: the number and status of every resource.
+#[derive(Debug)]
+pub struct SelfTestReport {
+    pub statuses: Vec<(usize, ResourceStatus)>,
+}
+
+impl SelfTestReport {
+    /// Returns the number and status of the resources that failed the test.
+    pub fn failures(&self) -> impl Iterator<Item = (usize, &ResourceStatus)> {
+        self.statuses
+            .iter()
+            .map(|(i, status)| (*i, status))
+            .filter(|(_, status)| !matches!(status, ResourceStatus::Ok))
+    }
+
+    /// Returns whether every resource passed the test.
+    pub fn passed(&self) -> bool {
+        self.failures().next().is_none()
+    }
+}
+
+/// Returns the number of the resource replaced by a file of a patch directory named `name`.
+fn patch_file_index(name: &str) -> Option<usize> {
+    name.strip_suffix(".bin")
+        .filter(|stem| stem.len() == 4)
+        .and_then(|stem| usize::from_str_radix(stem, 16).ok())
+}
+
+/// Detects changes in a patch directory (see [`ResourceManager::set_patch_dir`]) by comparing the
+/// modification times of its files between calls to [`PatchWatcher::poll`].
+pub struct PatchWatcher {
+    patch_dir: PathBuf,
+    mtimes: HashMap<usize, SystemTime>,
+}
+
+impl PatchWatcher {
+    pub fn new(patch_dir: &Path) -> Self {
+        let mut watcher = PatchWatcher {
+            patch_dir: patch_dir.to_path_buf(),
+            mtimes: HashMap::new(),
+        };
+        watcher.mtimes = watcher.scan();
+        watcher
+    }
+
+    fn scan(&self) -> HashMap<usize, SystemTime> {
+        let entries = match std::fs::read_dir(&self.patch_dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!("Cannot read {}: {}", self.patch_dir.display(), e);
+                return HashMap::new();
+            }
+        };
+
+        entries
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let index = patch_file_index(entry.file_name().to_str()?)?;
+                Some((index, entry.metadata().ok()?.modified().ok()?))
+            })
+            .collect()
+    }
+
+    /// Returns the numbers of the resources which file has been added, modified or removed
+    /// since the last call, in increasing order.
+    pub fn poll(&mut self) -> Vec<usize> {
+        let mtimes = self.scan();
+        let mut changed = mtimes
+            .iter()
+            .filter(|(index, mtime)| self.mtimes.get(index) != Some(mtime))
+            .map(|(&index, _)| index)
+            .chain(
+                self.mtimes
+                    .keys()
+                    .filter(|index| !mtimes.contains_key(index))
+                    .copied(),
+            )
+            .collect::<Vec<_>>();
+        changed.sort_unstable();
+        self.mtimes = mtimes;
+
+        changed
+    }
+}
+
 pub struct LoadedResource {
     pub res_type: ResType,
-    pub data: Vec<u8>,
+    /// Unpacked data of the resource, possibly shared with the resource cache.
+    pub data: Rc<Vec<u8>>,
 }
 
 impl LoadedResource {
+    /// Returns the data of the resource as an owned vector, copying it only if it is also held by
+    /// the resource cache.
+    pub fn into_data(self) -> Vec<u8> {
+        Rc::try_unwrap(self.data).unwrap_or_else(|data| (*data).clone())
+    }
+
     pub fn into_sound(self) -> Option<Box<SoundSample>> {
         match self.res_type {
-            ResType::Sound => Some(unsafe { SoundSample::from_raw_resource(self.data) }),
+            ResType::Sound => Some(unsafe { SoundSample::from_raw_resource(self.into_data()) }),
             _ => None,
         }
     }
 
     pub fn into_music(self) -> Option<Box<MusicModule>> {
         match self.res_type {
-            ResType::Music => Some(unsafe { MusicModule::from_raw_resource(self.data) }),
+            ResType::Music => Some(unsafe { MusicModule::from_raw_resource(self.into_data()) }),
             _ => None,
         }
     }
 }
 
 impl MemEntry {
-    fn load(&self) -> io::Result<Vec<u8>> {
-        // Some resources happen to be empty but are still referenced during the game...
+    /// Load and unpack the resource from its bank file in `data_dir`.
+    fn load(&self, data_dir: &Path) -> io::Result<Vec<u8>> {
+        let mut data = self.read_packed(data_dir)?;
+
+        if self.is_packed() {
+            let unpack_ctx = UnpackContext::new(&mut data[..], self.packed_size)?;
+            unpack_ctx.unpack()?;
+        }
+
+        Ok(data)
+    }
+
+    /// Returns whether the resource is stored packed in its bank file.
+    fn is_packed(&self) -> bool {
+        self.size > self.packed_size
+    }
+
+    /// Read the resource from its bank file in `data_dir` without unpacking it.
+    ///
+    /// The returned buffer is of the unpacked size of the resource, with the packed data at its
+    /// beginning.
+    fn read_packed(&self, data_dir: &Path) -> io::Result<Vec<u8>> {
+        // Some resources happen to be empty but are still referenced during the game. They don't
+        // need their bank file to be present.
         if self.size == 0 {
             return Ok(Vec::new());
         }
 
-        let mut file = File::open(format!("bank{:02x}", self.bank_id))?;
+        let bank = format!("bank{:02x}", self.bank_id);
+        let mut file = File::open(data_dir.join(&bank)).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => io::Error::new(
+                e.kind(),
+                format!("{} not found in {}", bank, data_dir.display()),
+            ),
+            _ => io::Error::new(e.kind(), format!("cannot open {}: {}", bank, e)),
+        })?;
         file.seek(SeekFrom::Start(self.bank_offset as u64))?;
 
         let mut data = vec![0u8; self.size];
-        file.read_exact(&mut data[..self.packed_size])?;
-
-        if self.size > self.packed_size {
-            let unpack_ctx = UnpackContext::new(&mut data[..], self.packed_size)?;
-            unpack_ctx.unpack()?;
-        }
+        file.read_exact(&mut data[..self.packed_size])
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::UnexpectedEof => {
+                    io::Error::new(e.kind(), format!("{} is truncated", bank))
+                }
+                _ => e,
+            })?;
 
         Ok(data)
     }
@@ -297,22 +605,145 @@ impl MemEntry {
 
 #[allow(dead_code)]
 pub struct ResourceManager {
+    variant: GameVariant,
+    /// Directory containing the game data files.
+    data_dir: PathBuf,
     resources: Vec<MemEntry>,
+    /// Already unpacked resources, indexed by resource number. `None` if caching is disabled.
+    cache: Option<RefCell<HashMap<usize, Rc<Vec<u8>>>>>,
+    /// Ranks of the resources that have been preloaded, and must stay in the cache.
+    preloaded_ranks: RefCell<Vec<u8>>,
+    /// Directory containing unpacked resources to use instead of the ones of the game data.
+    patch_dir: Option<PathBuf>,
 }
 
 impl ResourceManager {
-    // TODO: change constructor to take a path to data, and return an error if the memlist cannot
-    // be built
-    pub fn new() -> io::Result<ResourceManager> {
+    /// Create a new resource manager for the game data in `data_dir`. If `use_cache` is `true`,
+    /// unpacked resources are kept in memory and shared between subsequent loads of the same
+    /// resource.
+    pub fn new_in_dir(data_dir: &Path, use_cache: bool) -> io::Result<ResourceManager> {
         let mut ret = ResourceManager {
+            variant: GameVariant::detect(data_dir)?,
+            data_dir: data_dir.to_path_buf(),
             resources: Vec::new(),
+            cache: use_cache.then(Default::default),
+            preloaded_ranks: Default::default(),
+            patch_dir: None,
         };
         ret.load_mementries()?;
         Ok(ret)
     }
 
+    /// Create a new resource manager for the game data in `data_dir`, using the resources list
+    /// `memlist` instead of the `memlist.bin` file of the data. A relative `memlist` is looked
+    /// for in `data_dir`. This allows running editions of the game whose resources list differs
+    /// while sharing the same banks.
+    ///
+    /// The resources list is assumed to be in the DOS format, and must describe at least one
+    /// resource.
+    pub fn new_with_memlist(
+        data_dir: &Path,
+        memlist: &Path,
+        use_cache: bool,
+    ) -> io::Result<ResourceManager> {
+        let memlist = data_dir.join(memlist);
+        let mut ret = ResourceManager {
+            variant: GameVariant::Dos,
+            data_dir: data_dir.to_path_buf(),
+            resources: Vec::new(),
+            cache: use_cache.then(Default::default),
+            preloaded_ranks: Default::default(),
+            patch_dir: None,
+        };
+        ret.load_dos_mementries(&memlist).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("invalid resources list {}: {}", memlist.display(), e),
+            )
+        })?;
+        if ret.resources.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "resources list {} does not describe any resource",
+                    memlist.display()
+                ),
+            ));
+        }
+
+        Ok(ret)
+    }
+
+    /// Create a resource manager serving the resources in `resources` from memory, without any
+    /// game data on disk. Each resource is given as its number, type, and unpacked data.
+    #[cfg(test)]
+    pub fn new_in_memory(resources: Vec<(usize, ResType, Vec<u8>)>) -> ResourceManager {
+        let num_resources = resources.iter().map(|(i, _, _)| i + 1).max().unwrap_or(0);
+        let mut entries = (0..num_resources)
+            .map(|_| MemEntry {
+                res_type: ResType::Sound,
+                rank_num: 0,
+                bank_id: 0,
+                bank_offset: 0,
+                packed_size: 0,
+                size: 0,
+            })
+            .collect::<Vec<_>>();
+        let mut cache = HashMap::new();
+        for (i, res_type, data) in resources {
+            entries[i].res_type = res_type;
+            entries[i].size = data.len();
+            entries[i].packed_size = data.len();
+            cache.insert(i, Rc::new(data));
+        }
+
+        ResourceManager {
+            variant: GameVariant::Dos,
+            data_dir: PathBuf::new(),
+            resources: entries,
+            cache: Some(RefCell::new(cache)),
+            preloaded_ranks: Default::default(),
+            patch_dir: None,
+        }
+    }
+
+    /// Look for resources in `patch_dir` before loading them from the game data.
+    ///
+    /// A file named after the number of a resource, like `0042.bin` for resource 0x42, replaces
+    /// that resource. Its content is used as-is, so it must not be packed. The type of the
+    /// resource stays the one given in the resources list.
+    pub fn set_patch_dir(&mut self, patch_dir: &Path) {
+        self.patch_dir = Some(patch_dir.to_path_buf());
+    }
+
+    /// Drop resource `index` from the cache, even if it has been preloaded, so it is loaded again
+    /// the next time it is needed.
+    pub fn invalidate(&self, index: usize) {
+        if let Some(cache) = &self.cache {
+            cache.borrow_mut().remove(&index);
+        }
+    }
+
+    /// Returns the variant of the game the data comes from.
+    pub fn variant(&self) -> GameVariant {
+        self.variant
+    }
+
     fn load_mementries(&mut self) -> io::Result<()> {
-        let mut file = File::open("memlist.bin").expect("Cannot open memlist.bin!");
+        match self.variant {
+            GameVariant::Dos => self.load_dos_mementries(&self.data_dir.join(MEMLIST_FILE)),
+            variant => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "{} game data detected, but only the DOS version is supported for now",
+                    variant
+                ),
+            )),
+        }
+    }
+
+    fn load_dos_mementries(&mut self, memlist: &Path) -> io::Result<()> {
+        let mut file = File::open(memlist)?;
 
         loop {
             let entry = MemlistEntry::read_from_io(&mut file)?;
@@ -337,6 +768,16 @@ impl ResourceManager {
         Ok(())
     }
 
+    /// Returns the number of resources described by the memlist.
+    pub fn resource_count(&self) -> usize {
+        self.resources.len()
+    }
+
+    /// Returns the information about resource entry `index`, or `None` if it does not exist.
+    pub fn resource_info(&self, index: usize) -> Option<ResourceInfo> {
+        self.resources.get(index).map(ResourceInfo::from)
+    }
+
     /// Returns the resource type and data of resource entry `index`, loading it if necessary.
     pub fn load_resource(&self, index: usize) -> io::Result<LoadedResource> {
         let res = self
@@ -344,12 +785,163 @@ impl ResourceManager {
             .get(index)
             .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Resource does not exist!"))?;
 
+        let data = match &self.cache {
+            None => Rc::new(self.load_entry(index, res)?),
+            Some(cache) => {
+                if let Some(data) = cache.borrow().get(&index) {
+                    return Ok(LoadedResource {
+                        res_type: res.res_type,
+                        data: Rc::clone(data),
+                    });
+                }
+
+                let data = Rc::new(self.load_entry(index, res)?);
+                cache.borrow_mut().insert(index, Rc::clone(&data));
+                data
+            }
+        };
+
         Ok(LoadedResource {
             res_type: res.res_type,
-            data: res.load()?,
+            data,
         })
     }
 
+    /// Load and unpack `entry` from disk, adding the resource number and its location to the
+    /// error message if that fails.
+    fn load_entry(&self, index: usize, entry: &MemEntry) -> io::Result<Vec<u8>> {
+        if let Some(data) = self.load_patch(index, entry)? {
+            return Ok(data);
+        }
+
+        entry.load(&self.data_dir).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!(
+                    "failed to load resource 0x{:02x} (bank {:02x}, offset 0x{:08x}): {}",
+                    index, entry.bank_id, entry.bank_offset, e
+                ),
+            )
+        })
+    }
+
+    /// Load the replacement of `entry` from the patch directory, if there is one.
+    fn load_patch(&self, index: usize, entry: &MemEntry) -> io::Result<Option<Vec<u8>>> {
+        let Some(patch_dir) = &self.patch_dir else {
+            return Ok(None);
+        };
+        let path = patch_dir.join(format!("{:04x}.bin", index));
+        let data = match std::fs::read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(io::Error::new(
+                    e.kind(),
+                    format!("failed to read {}: {}", path.display(), e),
+                ))
+            }
+        };
+
+        // Bitmaps are copied into a page without any further check.
+        let expected_size = match entry.res_type {
+            ResType::Bitmap => Some(BITMAP_WIDTH * BITMAP_HEIGHT / 2),
+            _ => None,
+        };
+        if let Some(expected_size) = expected_size.filter(|&size| size != data.len()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} is {} bytes long, but {} resources must be {} bytes long",
+                    path.display(),
+                    data.len(),
+                    entry.res_type,
+                    expected_size
+                ),
+            ));
+        }
+
+        info!("Resource 0x{:02x} replaced by {}", index, path.display());
+        Ok(Some(data))
+    }
+
+    /// Read and unpack every resource from disk, and check that the unpacked data has a valid CRC
+    /// and the size declared in the resources list.
+    ///
+    /// The cache is bypassed, so every resource is actually read and unpacked.
+    pub fn self_test(&self) -> SelfTestReport {
+        SelfTestReport {
+            statuses: self
+                .resources
+                .iter()
+                .enumerate()
+                // Entry 0 is not a resource.
+                .skip(1)
+                .map(|(i, entry)| (i, self.test_entry(entry)))
+                .collect(),
+        }
+    }
+
+    fn test_entry(&self, entry: &MemEntry) -> ResourceStatus {
+        let mut data = match entry.read_packed(&self.data_dir) {
+            Ok(data) => data,
+            Err(e) => return ResourceStatus::ReadError(e),
+        };
+        if !entry.is_packed() {
+            return ResourceStatus::Ok;
+        }
+
+        match UnpackContext::unpacked_size(&data[..entry.packed_size]) {
+            Ok(unpacked) if unpacked != entry.size => {
+                return ResourceStatus::SizeMismatch {
+                    declared: entry.size,
+                    unpacked,
+                }
+            }
+            Ok(_) => (),
+            Err(e) => return ResourceStatus::ReadError(e),
+        }
+
+        // The size has been checked, so the only error unpacking can return is a bad CRC.
+        match UnpackContext::new(&mut data[..], entry.packed_size).and_then(|ctx| ctx.unpack()) {
+            Ok(()) => ResourceStatus::Ok,
+            Err(_) => ResourceStatus::BadCrc,
+        }
+    }
+
+    /// Drop all the resources kept in the cache, if any, except the preloaded ones.
+    pub fn clear_cache(&self) {
+        if let Some(cache) = &self.cache {
+            let preloaded_ranks = self.preloaded_ranks.borrow();
+            cache
+                .borrow_mut()
+                .retain(|&i, _| preloaded_ranks.contains(&self.resources[i].rank_num));
+        }
+    }
+
+    /// Load all the resources of rank `rank` into the cache, so the game does not have to read
+    /// them from disk when it needs them. They are kept in the cache until the resource manager
+    /// is dropped.
+    ///
+    /// Returns the number of resources loaded. Nothing is loaded if the cache is disabled.
+    pub fn preload_rank(&self, rank: u8) -> io::Result<usize> {
+        if self.cache.is_none() {
+            return Ok(0);
+        }
+
+        let mut count = 0;
+        for (i, entry) in self.resources.iter().enumerate() {
+            // Entry 0 is not a resource.
+            if i == 0 || entry.rank_num != rank {
+                continue;
+            }
+            self.load_resource(i)?;
+            count += 1;
+        }
+        self.preloaded_ranks.borrow_mut().push(rank);
+
+        Ok(count)
+    }
+
     fn show_stats_for(&self, res_type: ResType) {
         let (nb_res, psize, size) = self
             .resources
@@ -395,101 +987,689 @@ impl ResourceManager {
     }
 
     pub fn list_resources(&self) {
-        for (i, resource_info) in self.resources.iter().enumerate() {
-            println!("Entry 0x{:02x}: {:?}", i, resource_info);
+        println!("Game variant: {}", self.variant());
+
+        for i in 0..self.resource_count() {
+            if let Some(resource_info) = self.resource_info(i) {
+                println!("Entry 0x{:02x}: {:?}", i, resource_info);
+            }
         }
 
         println!("Entries stats by type:");
         self.show_stats();
+
+        println!("Entries stats by rank:");
+        let mut ranks = BTreeMap::<u8, (usize, usize)>::new();
+        for res in self.resources.iter().skip(1) {
+            let stat = ranks.entry(res.rank_num).or_default();
+            stat.0 += 1;
+            stat.1 += res.size;
+        }
+        for (rank, (nb_res, size)) in ranks {
+            println!("rank {}: {} entries, size {}", rank, nb_res, size);
+        }
     }
 
-    pub fn dump_resources(&mut self) -> io::Result<()> {
+    /// Dump all the resources into the `resources` directory.
+    pub fn dump_resources(&self, format: DumpFormat) -> io::Result<()> {
         for i in 1..self.resources.len() {
-            let resource = &self.resources[i];
-            let data = self.load_resource(i)?.data;
+            self.dump_resource(i, format)?;
+        }
 
-            debug!(
-                "Entry 0x{:x} of type {} loaded: {} ({}) bytes @{:1x},0x{:08x}",
-                i,
-                resource.res_type,
-                resource.size,
-                resource.packed_size,
-                resource.bank_id,
-                resource.bank_offset
-            );
+        Ok(())
+    }
 
-            const DUMPED_RESOURCES_DIR: &str = "resources";
-            match std::fs::create_dir(DUMPED_RESOURCES_DIR) {
-                Ok(()) => (),
-                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => (),
-                Err(e) => return Err(e),
+    /// Dump resource `index` into the `resources` directory. Bitmaps are converted according to
+    /// `format`, other resources are written as-is.
+    pub fn dump_resource(&self, index: usize, format: DumpFormat) -> io::Result<()> {
+        let resource = self
+            .resources
+            .get(index)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Resource does not exist!"))?;
+        let data = self.load_resource(index)?.data;
+
+        debug!(
+            "Entry 0x{:x} of type {} loaded: {} ({}) bytes @{:1x},0x{:08x}",
+            index,
+            resource.res_type,
+            resource.size,
+            resource.packed_size,
+            resource.bank_id,
+            resource.bank_offset
+        );
+
+        const DUMPED_RESOURCES_DIR: &str = "resources";
+        match std::fs::create_dir(DUMPED_RESOURCES_DIR) {
+            Ok(()) => (),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => (),
+            Err(e) => return Err(e),
+        }
+
+        let prefix = match resource.res_type {
+            ResType::Bitmap => "img",
+            ResType::Bytecode => "code",
+            ResType::Cinematic => "cine",
+            ResType::Sound => "sound",
+            ResType::Music => "music",
+            ResType::Palette => "palette",
+            ResType::Poly => "poly",
+        };
+        let extension = match (resource.res_type, format) {
+            (ResType::Bitmap, DumpFormat::Png { .. }) => "png",
+            _ => "dat",
+        };
+        let mut file = File::create(format!(
+            "{}/{}_{:02x}.{}",
+            DUMPED_RESOURCES_DIR, prefix, index, extension
+        ))?;
+
+        match (resource.res_type, format) {
+            // for f in (ls img_*.dat); convert -size 320x200+0 -depth 8 gray:$f $f.png; end
+            (ResType::Bitmap, DumpFormat::Raw) => file.write_all(
+                &MemEntry::fixup_bitmap(&data)
+                    .iter()
+                    .map(|x| x << 4)
+                    .collect::<Vec<u8>>(),
+            ),
+            (ResType::Bitmap, DumpFormat::Png { palette_res }) => {
+                let palette = self.load_dump_palette(palette_res)?;
+                let rgb = MemEntry::fixup_bitmap(&data)
+                    .iter()
+                    .flat_map(|&c| {
+                        let color = palette.lookup(c);
+                        [color.r, color.g, color.b]
+                    })
+                    .collect::<Vec<u8>>();
+                png::write_rgb(&mut file, BITMAP_WIDTH, BITMAP_HEIGHT, &rgb)
             }
+            _ => file.write_all(&data),
+        }
+    }
 
-            use std::io::Write;
-
-            match resource.res_type {
-                // for f in (ls img_*.dat); convert -size 320x200+0 -depth 8 gray:$f $f.png; end
-                ResType::Bitmap => {
-                    let mut file =
-                        File::create(format!("{}/img_{:02x}.dat", DUMPED_RESOURCES_DIR, i))?;
-                    file.write_all(
-                        &MemEntry::fixup_bitmap(&data)
-                            .iter()
-                            .map(|x| x << 4)
-                            .collect::<Vec<u8>>(),
-                    )?;
-                }
-                ResType::Bytecode => {
-                    let mut file =
-                        File::create(format!("{}/code_{:02x}.dat", DUMPED_RESOURCES_DIR, i))?;
-                    file.write_all(&data)?;
-                }
-                ResType::Cinematic => {
-                    let mut file =
-                        File::create(format!("{}/cine_{:02x}.dat", DUMPED_RESOURCES_DIR, i))?;
-                    file.write_all(&data)?;
-                }
-                ResType::Sound => {
-                    let mut file =
-                        File::create(format!("{}/sound_{:02x}.dat", DUMPED_RESOURCES_DIR, i))?;
-                    file.write_all(&data)?;
+    /// Returns the first palette of palette resource `index`, to be used for dumping bitmaps.
+    fn load_dump_palette(&self, index: usize) -> io::Result<Palette> {
+        let res = self.load_resource(index)?;
+        if res.res_type != ResType::Palette || res.data.len() < 32 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("resource 0x{:02x} is not a palette", index),
+            ));
+        }
+
+        let mut palette = Palette::default();
+        palette.set(res.data[0..32].try_into().unwrap());
+        Ok(palette)
+    }
+
+    /// Export music resource `index` into a ProTracker module at `path`, along with the sounds it
+    /// uses as instruments.
+    pub fn export_music(&self, index: usize, path: &Path) -> io::Result<()> {
+        let resource = self.load_resource(index)?;
+        if resource.res_type != ResType::Music
+            || resource.data.len() < size_of::<MusicModuleHeader>()
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("resource 0x{:02x} is not a music", index),
+            ));
+        }
+        let music = resource.into_music().unwrap();
+
+        let samples = music
+            .header
+            .instruments
+            .iter()
+            .map(|instrument| match instrument.res_num as usize {
+                0 => None,
+                res_num => {
+                    let sample = self
+                        .load_resource(res_num)
+                        .ok()
+                        .and_then(LoadedResource::into_sound);
+                    if sample.is_none() {
+                        warn!("instrument resource 0x{:02x} is not a sound", res_num);
+                    }
+                    sample
                 }
-                ResType::Music => {
-                    let mut file =
-                        File::create(format!("{}/music_{:02x}.dat", DUMPED_RESOURCES_DIR, i))?;
-                    file.write_all(&data)?;
+            })
+            .collect::<Vec<_>>();
+
+        let mut file = BufWriter::new(File::create(path)?);
+        write_protracker(
+            &mut file,
+            &format!("awer music 0x{:02x}", index),
+            &music,
+            &samples,
+        )?;
+        file.flush()
+    }
+
+    /// Print the unpacked data of resource `index` as a hexdump, followed by a summary of its
+    /// content for sounds, music and palettes.
+    pub fn inspect_resource(&self, index: usize) -> io::Result<()> {
+        let info = self
+            .resource_info(index)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Resource does not exist!"))?;
+        let resource = self.load_resource(index)?;
+
+        println!("Entry 0x{:02x}: {:?}", index, info);
+        print!("{}", hexdump(&resource.data));
+
+        match resource.res_type {
+            ResType::Sound if resource.data.len() >= SoundSample::HEADER_LEN => {
+                let sample = resource.into_sound().unwrap();
+                match sample.loop_pos() {
+                    Some(pos) => println!(
+                        "Sound: {} bytes, loops over the last {} bytes",
+                        sample.len(),
+                        sample.len() - pos
+                    ),
+                    None => println!("Sound: {} bytes, no loop", sample.len()),
                 }
-                ResType::Palette => {
-                    let mut file =
-                        File::create(format!("{}/palette_{:02x}.dat", DUMPED_RESOURCES_DIR, i))?;
-                    file.write_all(&data)?;
+            }
+            ResType::Music if resource.data.len() >= size_of::<MusicModuleHeader>() => {
+                let music = resource.into_music().unwrap();
+                let header = &music.header;
+                println!(
+                    "Music: delay {}, {} patterns, {} orders: {:02x?}",
+                    header.delay,
+                    music.patterns.len(),
+                    header.num_order,
+                    &header.order_table
+                        [..(header.num_order as usize).min(header.order_table.len())]
+                );
+                for (i, instrument) in header.instruments.iter().enumerate() {
+                    if instrument.res_num != 0 {
+                        println!(
+                            "Instrument {:2}: resource 0x{:02x}, volume {}",
+                            i + 1,
+                            instrument.res_num,
+                            instrument.volume
+                        );
+                    }
                 }
-                ResType::Poly => {
-                    let mut file =
-                        File::create(format!("{}/poly_{:02x}.dat", DUMPED_RESOURCES_DIR, i))?;
-                    file.write_all(&data)?;
+            }
+            ResType::Palette => {
+                for (i, chunk) in resource.data.chunks_exact(32).enumerate() {
+                    let mut palette = Palette::default();
+                    palette.set(chunk.try_into().unwrap());
+                    let colors = (0..PALETTE_SIZE as u8)
+                        .map(|c| {
+                            let color = palette.lookup(c);
+                            format!("#{:02x}{:02x}{:02x}", color.r, color.g, color.b)
+                        })
+                        .collect::<Vec<_>>();
+                    println!("Palette {:2}: {}", i, colors.join(" "));
                 }
-            };
+            }
+            _ => (),
         }
 
         Ok(())
     }
 }
 
+/// Format `data` as lines of 16 bytes, each starting with its offset and ending with the
+/// printable ASCII characters of the line.
+fn hexdump(data: &[u8]) -> String {
+    let mut out = String::new();
+
+    for (i, line) in data.chunks(16).enumerate() {
+        let hex = line
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let ascii = line
+            .iter()
+            .map(|&b| match b {
+                0x20..=0x7e => b as char,
+                _ => '.',
+            })
+            .collect::<String>();
+        out.push_str(&format!("{:08x}  {:<47}  |{}|\n", i * 16, hex, ascii));
+    }
+
+    out
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// Pack `data` into the format expected by `UnpackContext`.
+    ///
+    /// This is a very naive packer that only uses literal runs and long back-references, but it
+    /// produces valid input for the unpacker, which lets us test it without the game data.
+    fn pack(data: &[u8]) -> Vec<u8> {
+        // Bits in the order they will be consumed by the unpacker.
+        let mut bits = Vec::new();
+        let push_code = |bits: &mut Vec<bool>, value: usize, num_bits: u8| {
+            for i in (0..num_bits).rev() {
+                bits.push((value >> i) & 0x1 == 1);
+            }
+        };
+        let flush_literals = |bits: &mut Vec<bool>, literals: &mut Vec<u8>| {
+            for run in literals.chunks(8) {
+                bits.extend([false, false]);
+                push_code(bits, run.len() - 1, 3);
+                for &byte in run {
+                    push_code(bits, byte as usize, 8);
+                }
+            }
+            literals.clear();
+        };
+
+        // Data is unpacked starting from its end.
+        let mut literals = Vec::new();
+        let mut o = data.len();
+        while o > 0 {
+            let (len, offset) = (1..=std::cmp::min(64, data.len() - o))
+                .map(|offset| {
+                    let len = (0..std::cmp::min(256, o))
+                        .take_while(|j| data[o - 1 - j] == data[o - 1 - j + offset])
+                        .count();
+                    (len, offset)
+                })
+                .max()
+                .unwrap_or((0, 0));
+
+            if len >= 3 {
+                flush_literals(&mut bits, &mut literals);
+                bits.push(true);
+                push_code(&mut bits, 2, 2);
+                push_code(&mut bits, len - 1, 8);
+                push_code(&mut bits, offset, 12);
+                o -= len;
+            } else {
+                literals.push(data[o - 1]);
+                o -= 1;
+            }
+        }
+        flush_literals(&mut bits, &mut literals);
+
+        // Store the bits into 32-bit words, the first one read being the last in the buffer.
+        let words = bits
+            .chunks(32)
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .enumerate()
+                    .fold(0u32, |word, (i, &bit)| word | ((bit as u32) << i))
+            })
+            .collect::<Vec<_>>();
+
+        // The footer's chunk only contains its end marker, so the first word is read right away.
+        let chk = 1u32;
+        let crc = words.iter().fold(chk, |crc, word| crc ^ word);
+        let mut packed = words
+            .iter()
+            .rev()
+            .flat_map(|word| word.to_be_bytes())
+            .collect::<Vec<_>>();
+        packed.extend(chk.to_be_bytes());
+        packed.extend(crc.to_be_bytes());
+        packed.extend((data.len() as u32).to_be_bytes());
+        packed
+    }
+
+    /// Unpack `packed` into a buffer of `size` bytes.
+    fn unpack(packed: &[u8], size: usize) -> io::Result<Vec<u8>> {
+        let mut data = vec![0u8; size];
+        data[..packed.len()].copy_from_slice(packed);
+        UnpackContext::new(&mut data, packed.len())?.unpack()?;
+        Ok(data)
+    }
+
+    /// Synthetic data looking vaguely like a bitmap: runs of colors with a bit of noise.
+    fn synthetic_data(len: usize) -> Vec<u8> {
+        let mut seed = 0x1234_5678u32;
+        (0..len)
+            .map(|i| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                if seed >> 28 == 0 {
+                    (seed >> 24) as u8
+                } else {
+                    ((i / 37) % 16) as u8
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_unpack() {
+        let data = synthetic_data(32000);
+        let packed = pack(&data);
+        assert!(packed.len() < data.len());
+        assert_eq!(unpack(&packed, data.len()).unwrap(), data);
+
+        // Corrupt the packed data and check that the CRC catches it.
+        let mut corrupted = packed.clone();
+        corrupted[0] ^= 0x1;
+        assert!(unpack(&corrupted, data.len()).is_err());
+    }
+
+    #[test]
+    fn test_patch_dir() {
+        let dir = std::env::temp_dir().join(format!("awer-patch-dir-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("0001.bin"), [1, 2, 3]).unwrap();
+        std::fs::write(dir.join("0002.bin"), [0; 100]).unwrap();
+
+        let mut resman = ResourceManager::new_in_memory(vec![]);
+        resman.cache = None;
+        resman.resources = vec![
+            MemEntry {
+                res_type: ResType::Sound,
+                rank_num: 0,
+                bank_id: 0,
+                bank_offset: 0,
+                packed_size: 0,
+                size: 0,
+            },
+            MemEntry {
+                res_type: ResType::Bytecode,
+                rank_num: 0,
+                bank_id: 0,
+                bank_offset: 0,
+                packed_size: 0,
+                size: 0,
+            },
+            MemEntry {
+                res_type: ResType::Bitmap,
+                rank_num: 0,
+                bank_id: 0,
+                bank_offset: 0,
+                packed_size: 0,
+                size: 0,
+            },
+        ];
+        resman.set_patch_dir(&dir);
+        let loaded = (0..3).map(|i| resman.load_resource(i)).collect::<Vec<_>>();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // Resources without a replacement come from the game data.
+        assert_eq!(*loaded[0].as_ref().unwrap().data, Vec::<u8>::new());
+        let patched = loaded[1].as_ref().unwrap();
+        assert_eq!(patched.res_type, ResType::Bytecode);
+        assert_eq!(*patched.data, vec![1, 2, 3]);
+        // A bitmap that is not 32000 bytes long would not fit into a page.
+        let err = loaded[2].as_ref().err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_patch_watcher() {
+        let dir = std::env::temp_dir().join(format!("awer-patch-watcher-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("0001.bin"), [1]).unwrap();
+        std::fs::write(dir.join("0002.bin"), [2]).unwrap();
+        std::fs::write(dir.join("notes.txt"), "").unwrap();
+
+        let mut watcher = PatchWatcher::new(&dir);
+        let unchanged = watcher.poll();
+
+        std::fs::write(dir.join("0010.bin"), [3]).unwrap();
+        std::fs::remove_file(dir.join("0001.bin")).unwrap();
+        File::options()
+            .write(true)
+            .open(dir.join("0002.bin"))
+            .unwrap()
+            .set_modified(SystemTime::UNIX_EPOCH)
+            .unwrap();
+        let changed = watcher.poll();
+        let changed_again = watcher.poll();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(unchanged, Vec::<usize>::new());
+        assert_eq!(changed, vec![1, 2, 0x10]);
+        assert_eq!(changed_again, Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_self_test() {
+        let data = synthetic_data(4000);
+        let packed = pack(&data);
+        let mut corrupted = packed.clone();
+        corrupted[0] ^= 0x1;
+
+        let dir = std::env::temp_dir().join(format!("awer-self-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut bank = data.clone();
+        bank.extend(&packed);
+        bank.extend(&corrupted);
+        std::fs::write(dir.join("bank01"), &bank).unwrap();
+
+        let entry = |bank_id, bank_offset: usize, packed_size, size| MemEntry {
+            res_type: ResType::Bitmap,
+            rank_num: 0,
+            bank_id,
+            bank_offset: bank_offset as u32,
+            packed_size,
+            size,
+        };
+        let mut resman = ResourceManager::new_in_memory(vec![]);
+        resman.data_dir = dir.clone();
+        resman.resources = vec![
+            // Entry 0 is not a resource, and is not checked even though its bank is missing.
+            entry(2, 0, 4, 4),
+            // Stored as-is.
+            entry(1, 0, data.len(), data.len()),
+            // Packed.
+            entry(1, data.len(), packed.len(), data.len()),
+            // Declared size differs from the one of the packed data.
+            entry(1, data.len(), packed.len(), data.len() + 4),
+            entry(1, data.len() + packed.len(), packed.len(), data.len()),
+            // Missing bank.
+            entry(2, 0, 4, 4),
+        ];
+        let report = resman.self_test();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(!report.passed());
+        assert_eq!(report.statuses.len(), 5);
+        assert!(matches!(report.statuses[0], (1, ResourceStatus::Ok)));
+        assert!(matches!(report.statuses[1], (2, ResourceStatus::Ok)));
+        assert!(matches!(
+            report.statuses[2],
+            (
+                3,
+                ResourceStatus::SizeMismatch {
+                    declared: 4004,
+                    unpacked: 4000
+                }
+            )
+        ));
+        assert!(matches!(report.statuses[3], (4, ResourceStatus::BadCrc)));
+        assert!(matches!(
+            report.statuses[4],
+            (5, ResourceStatus::ReadError(_))
+        ));
+        assert_eq!(
+            report.failures().map(|(i, _)| i).collect::<Vec<_>>(),
+            vec![3, 4, 5]
+        );
+    }
+
+    /// Measures the throughput of `UnpackContext::unpack`.
+    ///
+    /// Run with `cargo test --release -- --ignored --nocapture bench_`.
+    #[test]
+    #[ignore = "benchmark"]
+    fn bench_unpack() {
+        const ITERATIONS: u32 = 200;
+
+        let data = synthetic_data(32000);
+        let packed = pack(&data);
+
+        let start = std::time::Instant::now();
+        for _ in 0..ITERATIONS {
+            std::hint::black_box(unpack(&packed, data.len()).unwrap());
+        }
+        let elapsed = start.elapsed();
+
+        println!(
+            "unpack: {:?} per {} bytes buffer, {:.1} MB/s",
+            elapsed / ITERATIONS,
+            data.len(),
+            (data.len() as f64 * ITERATIONS as f64) / elapsed.as_secs_f64() / 1_000_000.0
+        );
+    }
+
+    #[test]
+    fn test_new_with_memlist() {
+        let dir = std::env::temp_dir().join(format!("awer-memlist-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // One bitmap of rank 2 in bank 1, followed by the end of the list.
+        let mut entry = [0u8; 20];
+        entry[1] = ResType::Bitmap as u8;
+        entry[6] = 2;
+        entry[7] = 1;
+        entry[19] = 100;
+        let mut end = [0u8; 20];
+        end[0] = 0xff;
+        std::fs::write(dir.join("valid.bin"), [entry, end].concat()).unwrap();
+        std::fs::write(dir.join("empty.bin"), end).unwrap();
+        std::fs::write(dir.join("truncated.bin"), &entry[..10]).unwrap();
+
+        let valid = ResourceManager::new_with_memlist(&dir, Path::new("valid.bin"), false);
+        let empty = ResourceManager::new_with_memlist(&dir, Path::new("empty.bin"), false);
+        let truncated = ResourceManager::new_with_memlist(&dir, &dir.join("truncated.bin"), false);
+        let missing = ResourceManager::new_with_memlist(&dir, Path::new("missing.bin"), false);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let valid = valid.unwrap();
+        assert_eq!(valid.resource_count(), 1);
+        let info = valid.resource_info(0).unwrap();
+        assert_eq!(info.rank_num, 2);
+        assert_eq!(info.size, 100);
+        assert!(empty
+            .err()
+            .unwrap()
+            .to_string()
+            .contains("does not describe any resource"));
+        assert!(truncated
+            .err()
+            .unwrap()
+            .to_string()
+            .contains("invalid resources list"));
+        assert_eq!(missing.err().unwrap().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_variant_from_bank01_size() {
+        assert_eq!(
+            GameVariant::from_bank01_size(244674),
+            Some(GameVariant::Amiga)
+        );
+        assert_eq!(
+            GameVariant::from_bank01_size(244868),
+            Some(GameVariant::Amiga)
+        );
+        assert_eq!(
+            GameVariant::from_bank01_size(227142),
+            Some(GameVariant::AtariSt)
+        );
+        assert_eq!(GameVariant::from_bank01_size(0), None);
+    }
+
+    #[test]
+    fn test_load_error_context() {
+        let entry = MemEntry {
+            res_type: ResType::Sound,
+            rank_num: 0,
+            bank_id: 0xfe,
+            bank_offset: 0x1234,
+            packed_size: 0x10,
+            size: 0x10,
+        };
+
+        let err = ResourceManager::new_in_memory(vec![])
+            .load_entry(0x2a, &entry)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        let msg = err.to_string();
+        assert!(msg.contains("resource 0x2a"), "{}", msg);
+        assert!(msg.contains("bank fe"), "{}", msg);
+        assert!(msg.contains("offset 0x00001234"), "{}", msg);
+        assert!(msg.contains("bankfe not found"), "{}", msg);
+
+        // Empty resources load without their bank file.
+        let entry = MemEntry {
+            packed_size: 0,
+            size: 0,
+            ..entry
+        };
+        assert_eq!(
+            ResourceManager::new_in_memory(vec![])
+                .load_entry(0x2a, &entry)
+                .unwrap(),
+            Vec::<u8>::new()
+        );
+    }
+
+    #[test]
+    fn test_hexdump() {
+        let data = (0x30..0x52).collect::<Vec<u8>>();
+        assert_eq!(
+            hexdump(&data),
+            "00000000  30 31 32 33 34 35 36 37 38 39 3a 3b 3c 3d 3e 3f  |0123456789:;<=>?|\n\
+             00000010  40 41 42 43 44 45 46 47 48 49 4a 4b 4c 4d 4e 4f  |@ABCDEFGHIJKLMNO|\n\
+             00000020  50 51                                            |PQ|\n"
+        );
+        assert_eq!(
+            hexdump(&[0x00, 0x7f, 0x41]).trim_end(),
+            "00000000  00 7f 41                                         |..A|"
+        );
+        assert_eq!(hexdump(&[]), "");
+    }
+
+    #[test]
+    fn test_preload_rank() {
+        let mut resman = ResourceManager::new_in_memory(vec![
+            (1, ResType::Sound, vec![1]),
+            (2, ResType::Sound, vec![2]),
+            (3, ResType::Music, vec![3]),
+        ]);
+        resman.resources[1].rank_num = 3;
+        resman.resources[3].rank_num = 3;
+
+        assert_eq!(resman.preload_rank(3).unwrap(), 2);
+        assert_eq!(resman.preload_rank(5).unwrap(), 0);
+
+        // Preloaded resources survive the cache being cleared, others are read from disk again.
+        resman.clear_cache();
+        assert_eq!(*resman.load_resource(1).unwrap().data, vec![1]);
+        assert_eq!(*resman.load_resource(3).unwrap().data, vec![3]);
+        assert!(resman.load_resource(2).is_err());
+    }
+
     #[test]
     fn test_load_res() -> io::Result<()> {
-        let resman = ResourceManager::new()?;
-        assert_ne!(resman.resources.len(), 0);
+        let resman = ResourceManager::new_in_dir(Path::new("."), false)?;
+        assert_ne!(resman.resource_count(), 0);
 
-        for i in 1..resman.resources.len() {
-            let expected_size = resman.resources[i].size;
+        for i in 1..resman.resource_count() {
+            let expected_size = resman.resource_info(i).unwrap().size;
             let resource = resman.load_resource(i)?;
             assert_eq!(expected_size, resource.data.len());
         }
+        let report = resman.self_test();
+        assert!(
+            report.passed(),
+            "{:?}",
+            report.failures().collect::<Vec<_>>()
+        );
+
+        // Repeated loads must return the same data when the cache is enabled, and reload it
+        // after the cache has been cleared.
+        let resman = ResourceManager::new_in_dir(Path::new("."), true)?;
+        let first = resman.load_resource(1)?.data;
+        assert!(Rc::ptr_eq(&first, &resman.load_resource(1)?.data));
+        resman.clear_cache();
+        let reloaded = resman.load_resource(1)?.data;
+        assert!(!Rc::ptr_eq(&first, &reloaded));
+        assert_eq!(first, reloaded);
 
         Ok(())
     }
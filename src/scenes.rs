@@ -1,15 +1,93 @@
+use std::fmt;
 use std::ops::DerefMut;
 
+use crate::res::ResType;
 use crate::res::ResourceManager;
 
 #[derive(Debug)]
 pub struct Scene {
+    /// Name of the scene, for display purposes.
+    pub name: &'static str,
     pub palette: usize,
     pub code: usize,
     pub video1: usize,
     pub video2: usize,
 }
 
+/// Print every scene with the resources it uses.
+pub fn list_scenes() {
+    for (i, scene) in SCENES.iter().enumerate() {
+        println!(
+            "Scene {}: {:<16} palette 0x{:02x}, code 0x{:02x}, video1 0x{:02x}, video2 0x{:02x}",
+            i, scene.name, scene.palette, scene.code, scene.video1, scene.video2
+        );
+    }
+}
+
+/// Problem with a resource referenced by a scene, as found by [`validate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SceneError {
+    /// Index of the scene in `SCENES`.
+    pub scene: usize,
+    /// Name of the `Scene` field referencing the resource.
+    pub field: &'static str,
+    /// Index of the resource.
+    pub res_id: usize,
+    /// Actual type of the resource, or `None` if it does not exist.
+    pub res_type: Option<ResType>,
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "scene {} ({}): {} resource 0x{:02x} ",
+            self.scene, SCENES[self.scene].name, self.field, self.res_id
+        )?;
+        match self.res_type {
+            None => write!(f, "does not exist"),
+            Some(res_type) => write!(f, "has unexpected type {}", res_type),
+        }
+    }
+}
+
+/// Check that the resources referenced by every scene exist in `resman` and are of the expected
+/// type, so bad game data can be reported before the game crashes while loading a scene.
+///
+/// Returns all the problems found.
+pub fn validate(resman: &ResourceManager) -> Result<(), Vec<SceneError>> {
+    let mut errors = Vec::new();
+
+    for (i, scene) in SCENES.iter().enumerate() {
+        let mut check = |field, res_id, expected: &[ResType]| {
+            let res_type = resman.resource_info(res_id).map(|info| info.res_type);
+            if !res_type.is_some_and(|t| expected.contains(&t)) {
+                errors.push(SceneError {
+                    scene: i,
+                    field,
+                    res_id,
+                    res_type,
+                });
+            }
+        };
+
+        const VIDEO_TYPES: &[ResType] = &[ResType::Cinematic, ResType::Poly];
+        check("palette", scene.palette, &[ResType::Palette]);
+        check("code", scene.code, &[ResType::Bytecode]);
+        check("video1", scene.video1, VIDEO_TYPES);
+        // 0 means the scene has no second video segment.
+        if scene.video2 != 0 {
+            check("video2", scene.video2, VIDEO_TYPES);
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
 /// Trait for types that need to be initialized every time a scene is loaded.
 pub trait InitForScene {
     /// Reinitialize the object to be able to process `scene` using `resman`. This usually means
@@ -29,6 +107,7 @@ impl<I: InitForScene + ?Sized, C: DerefMut<Target = I>> InitForScene for C {
 pub const SCENES: [Scene; 9] = [
     // Copy protection (0)
     Scene {
+        name: "Copy protection",
         palette: 0x14,
         code: 0x15,
         video1: 0x16,
@@ -36,6 +115,7 @@ pub const SCENES: [Scene; 9] = [
     },
     // Intro (1)
     Scene {
+        name: "Intro",
         palette: 0x17,
         code: 0x18,
         video1: 0x19,
@@ -43,6 +123,7 @@ pub const SCENES: [Scene; 9] = [
     },
     // Game begins (2)
     Scene {
+        name: "Game begins",
         palette: 0x1a,
         code: 0x1b,
         video1: 0x1c,
@@ -50,12 +131,15 @@ pub const SCENES: [Scene; 9] = [
     },
     // Jail (3)
     Scene {
+        name: "Jail",
         palette: 0x1d,
         code: 0x1e,
         video1: 0x1f,
         video2: 0x11,
     },
+    // City (4)
     Scene {
+        name: "City",
         palette: 0x20,
         code: 0x21,
         video1: 0x22,
@@ -63,6 +147,7 @@ pub const SCENES: [Scene; 9] = [
     },
     // Tank (5)
     Scene {
+        name: "Tank",
         palette: 0x23,
         code: 0x24,
         video1: 0x25,
@@ -70,6 +155,7 @@ pub const SCENES: [Scene; 9] = [
     },
     // Bath (6)
     Scene {
+        name: "Bath",
         palette: 0x26,
         code: 0x27,
         video1: 0x28,
@@ -77,6 +163,7 @@ pub const SCENES: [Scene; 9] = [
     },
     // End sequence (7)
     Scene {
+        name: "End sequence",
         palette: 0x29,
         code: 0x2a,
         video1: 0x2b,
@@ -84,9 +171,43 @@ pub const SCENES: [Scene; 9] = [
     },
     // Password (8)
     Scene {
+        name: "Password",
         palette: 0x7d,
         code: 0x7e,
         video1: 0x7f,
         video2: 0x00,
     },
 ];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn test_validate() {
+        // Only the test scene is valid.
+        let errors = validate(&testing::test_scene_resources()).unwrap_err();
+        assert!(!errors.is_empty());
+        assert!(errors.iter().all(|e| e.scene != testing::TEST_SCENE));
+
+        // Resources of the wrong type are reported.
+        let scene = &SCENES[testing::TEST_SCENE];
+        let resman = ResourceManager::new_in_memory(vec![
+            (scene.palette, ResType::Palette, vec![]),
+            (scene.code, ResType::Poly, vec![]),
+            (scene.video1, ResType::Cinematic, vec![]),
+        ]);
+        let errors = validate(&resman).unwrap_err();
+        assert!(errors.contains(&SceneError {
+            scene: testing::TEST_SCENE,
+            field: "code",
+            res_id: scene.code,
+            res_type: Some(ResType::Poly),
+        }));
+        assert!(errors
+            .iter()
+            .filter(|e| e.scene == testing::TEST_SCENE)
+            .all(|e| e.field == "code"));
+    }
+}
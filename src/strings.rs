@@ -4,23 +4,157 @@ use std::io::BufRead;
 use std::io::BufReader;
 use std::io::{self};
 
-pub type GameStrings = BTreeMap<usize, String>;
+/// Language of the strings in `strings.txt`, which the other languages are merged over.
+pub const DEFAULT_LANG: &str = "en";
 
-pub fn load_strings() -> io::Result<GameStrings> {
-    let file = File::open("strings.txt")?;
-    let lines = BufReader::new(file).lines();
+/// Strings displayed by the game, indexed by their id.
+#[derive(Default)]
+pub struct GameStrings(BTreeMap<usize, String>);
 
-    let mut strings = GameStrings::new();
+impl GameStrings {
+    /// Returns the string with `id`, if there is one.
+    pub fn get(&self, id: usize) -> Option<&str> {
+        self.0.get(&id).map(String::as_str)
+    }
+
+    /// Returns all the strings along with their id, in increasing id order.
+    pub fn get_all(&self) -> impl Iterator<Item = (usize, &str)> {
+        self.0.iter().map(|(&id, string)| (id, string.as_str()))
+    }
+}
+
+/// Read a string table from `reader`, in which each line contains the id of a string in
+/// hexadecimal (`0xNNN`), a comma and a space, and the string with line breaks escaped as `\n`.
+/// Empty lines are skipped.
+///
+/// Returns an error of kind [`io::ErrorKind::InvalidData`] giving the line number if a line does
+/// not follow that format.
+fn read_table<R: BufRead>(reader: R) -> io::Result<BTreeMap<usize, String>> {
+    let mut strings = BTreeMap::new();
 
-    for line in lines {
+    for (i, line) in reader.lines().enumerate() {
         let line = line?;
-        let parts = line.split_at(5);
+        // Some editors start files with a byte order mark.
+        let line = match i {
+            0 => line.strip_prefix('\u{feff}').unwrap_or(&line),
+            _ => &line,
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
 
-        let index = usize::from_str_radix(&parts.0[2..], 16).map_err(std::io::Error::other)?;
-        let string = parts.1[2..].replace("\\n", "\n");
+        let invalid = |what: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line {}: {}", i + 1, what),
+            )
+        };
+        let (index, string) = line
+            .split_once(", ")
+            .ok_or_else(|| invalid("expected an id and a string separated by \", \""))?;
+        let index = index
+            .strip_prefix("0x")
+            .and_then(|index| usize::from_str_radix(index, 16).ok())
+            .ok_or_else(|| invalid("invalid string id"))?;
 
-        strings.insert(index, string);
+        strings.insert(index, string.replace("\\n", "\n"));
     }
 
     Ok(strings)
 }
+
+/// Read the string table of the file at `path`.
+fn read_table_file(path: &str) -> io::Result<BTreeMap<usize, String>> {
+    File::open(path)
+        .and_then(|file| read_table(BufReader::new(file)))
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))
+}
+
+/// Load the game's strings in language `lang`.
+///
+/// The strings of [`DEFAULT_LANG`] are read from `strings.txt`. For other languages, the strings
+/// of `strings-<lang>.txt` replace the ones with the same id, so translations only need to list
+/// the strings that differ.
+pub fn load_strings(lang: &str) -> io::Result<GameStrings> {
+    let mut strings = read_table_file("strings.txt")?;
+
+    if lang != DEFAULT_LANG {
+        strings.extend(read_table_file(&format!("strings-{}.txt", lang))?);
+    }
+
+    Ok(GameStrings(strings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_table() {
+        let mut strings = read_table(&b"0x001, Hello\n0x01A, Two\\nlines\n0x002, Bye"[..]).unwrap();
+        strings.extend(read_table(&b"0x002, Au revoir"[..]).unwrap());
+        let strings = GameStrings(strings);
+
+        assert_eq!(
+            strings.get_all().collect::<Vec<_>>(),
+            vec![(0x1, "Hello"), (0x2, "Au revoir"), (0x1a, "Two\nlines")]
+        );
+        assert_eq!(strings.get(0x3), None);
+    }
+
+    #[test]
+    fn test_read_table_lenient() {
+        // Byte order mark, empty and blank lines, Windows line endings and multi-byte characters.
+        let strings = read_table(
+            "\u{feff}0x001, Un\n\n   \n0x002, Deux\r\n0x0e9, \u{e9}t\u{e9}\n".as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(
+            strings.into_iter().collect::<Vec<_>>(),
+            vec![
+                (0x1, "Un".to_string()),
+                (0x2, "Deux".to_string()),
+                (0xe9, "\u{e9}t\u{e9}".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_table_invalid() {
+        for (table, line) in [
+            ("0x001, Un\n0x2", 2),
+            ("0x001, Un\n\n0x002,Deux", 3),
+            ("\u{e9}\u{e9}\u{e9}, Trois", 1),
+            ("001, Un", 1),
+            ("0x, Un", 1),
+            ("0xzz, Un", 1),
+            ("0x\u{e9}1, Un", 1),
+        ] {
+            let err = read_table(table.as_bytes()).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{:?}", table);
+            assert!(
+                err.to_string().starts_with(&format!("line {}:", line)),
+                "{:?}: {}",
+                table,
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn test_load_strings_fr() {
+        let en = load_strings("en").unwrap();
+        let fr = load_strings("fr").unwrap();
+
+        // The French table replaces the strings it defines...
+        assert_eq!(en.get(0x190), Some("Good evening professor."));
+        assert_eq!(fr.get(0x190), Some("Bonsoir professeur."));
+        assert_eq!(fr.get(0x143), Some("CHARGEMENT..."));
+        // ... and keeps the English ones for the others.
+        assert_eq!(fr.get(0x049), Some("Delphine Software"));
+        assert_eq!(
+            en.get_all().map(|(id, _)| id).collect::<Vec<_>>(),
+            fr.get_all().map(|(id, _)| id).collect::<Vec<_>>()
+        );
+    }
+}
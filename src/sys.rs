@@ -2,9 +2,101 @@
 pub mod sdl2;
 
 use std::ops::DerefMut;
+use std::path::PathBuf;
 
+use crate::audio::InterpolationMode;
+use crate::gfx::AspectRatio;
+use crate::gfx::Color;
+use crate::gfx::Vsync;
 use crate::vm::Vm;
 
+/// Number of game ticks per second on PAL systems, the default.
+pub const PAL_TICK_RATE: u32 = 50;
+/// Number of game ticks per second on NTSC systems.
+pub const NTSC_TICK_RATE: u32 = 60;
+
+/// Number of rewind snapshots kept by default.
+pub const DEFAULT_REWIND_DEPTH: usize = 50;
+/// Smallest number of rewind snapshots that can be kept.
+pub const MIN_REWIND_DEPTH: usize = 2;
+/// Number of game ticks between two rewind snapshots by default.
+pub const DEFAULT_REWIND_INTERVAL: usize = 200;
+/// Smallest number of game ticks between two rewind snapshots. Going back to a snapshot restored
+/// less than a few ticks ago goes to the one before it, so snapshots must be further apart.
+pub const MIN_REWIND_INTERVAL: usize = 20;
+
+/// Options controlling how a `Sys` runs the game.
+#[derive(Debug, Default, Clone)]
+pub struct SysOptions {
+    /// Output audio in stereo, with channels panned like the Amiga version.
+    pub stereo: bool,
+    /// Requested audio output frequency, in Hz.
+    pub audio_freq: u32,
+    /// Requested size of the audio buffer, in samples. Derived from the frequency if `None`.
+    pub audio_buffer: Option<u16>,
+    /// How sound samples are interpolated when resampled to the output frequency.
+    pub audio_interp: InterpolationMode,
+    /// Run without opening an audio device. The game plays no sound at all.
+    pub no_audio: bool,
+    /// Start with all audio output stopped, until it is unmuted by the player.
+    pub mute: bool,
+    /// Fill large polygons using several threads when rendering on the CPU.
+    pub parallel_raster: bool,
+    /// Round the edges of polygons outwards when rendering on the CPU, to close the gaps between
+    /// adjacent polygons.
+    pub conservative_raster: bool,
+    /// Factor by which the CPU renderer's internal resolution is larger than the game's. The
+    /// rendered frames are downscaled for display.
+    pub raster_scale: usize,
+    /// File to load the font used to draw the game's text from, instead of the built-in one.
+    pub font: Option<PathBuf>,
+    /// Color of the parts of the window around the game.
+    pub bg_color: Color,
+    /// Record the displayed frames into an animated GIF at this path.
+    pub record_gif: Option<PathBuf>,
+    /// Maximum number of frames per second of the GIF recording.
+    pub gif_fps: u32,
+    /// Record the input given to the game into a demo at this path.
+    pub record_demo: Option<PathBuf>,
+    /// Take the input of the game from the demo at this path instead of the keyboard.
+    pub play_demo: Option<PathBuf>,
+    /// Show information about the game on top of it while paused.
+    pub show_overlay: bool,
+    /// Enable debugging features, like the scene selection menu.
+    pub debug: bool,
+    /// Panic on polygons of invalid colors instead of skipping them.
+    pub strict: bool,
+    /// Blend the previous frame over the current one, to spot elements missing for one frame.
+    pub onion_skin: bool,
+    /// Allow writing the draw commands of the current frame into a JSON file.
+    pub dump_drawlist: bool,
+    /// Whether to wait for the vertical sync when presenting frames. Frames are presented as fast
+    /// as possible if it is off.
+    pub vsync: Vsync,
+    /// Maximum number of frames presented per second, to save power.
+    pub max_fps: Option<u32>,
+    /// Number of game ticks per second. The VM counts the time it waits between frames in ticks,
+    /// so this controls the speed of the game, but not the tempo of the music.
+    pub tick_rate: u32,
+    /// Run exactly one tick per frame, without looking at the clock, so that the game runs the
+    /// same way every time given the same input.
+    pub deterministic: bool,
+    /// Pause the sound while the window does not have the focus.
+    pub mute_on_focus_loss: bool,
+    /// Pause the game when the window loses the focus.
+    pub pause_on_focus_loss: bool,
+    /// Start with the window covering the whole screen.
+    pub fullscreen: bool,
+    /// Shape of the game's image in the window.
+    pub aspect: AspectRatio,
+    /// Patch directory to watch, restarting the current scene when one of its files changes.
+    pub watch_patches: Option<PathBuf>,
+    /// Number of snapshots kept for rewinding the game.
+    pub rewind_depth: usize,
+    /// Number of game ticks between two rewind snapshots.
+    pub rewind_interval: usize,
+}
+
 pub trait Sys {
     fn game_loop(&mut self, vm: &mut Vm);
 }
@@ -1 +1,2 @@
+mod rewind;
 pub mod sdl2_simple;
@@ -0,0 +1,285 @@
+//! History of snapshots of the game, to rewind it.
+//!
+//! The history is a single timeline: restoring a snapshot drops everything that happened after
+//! it, so playing on from there replaces the rounds that had been played the first time instead
+//! of branching off them. The snapshots are thus always ordered from the most recent to the
+//! oldest position in the game.
+
+use std::collections::VecDeque;
+
+use crate::audio::NullAudio;
+use crate::gfx;
+use crate::sys::Snapshotable;
+use crate::vm::Vm;
+use crate::vm::VmSnapshot;
+use crate::vm::VmState;
+
+/// Maximum number of rounds recorded after a snapshot. A new snapshot is taken once it is
+/// reached, which bounds the number of rounds to replay when rewinding one round.
+const MAX_ROUNDS_PER_SNAPSHOT: usize = 120;
+
+/// Number of ticks after restoring a snapshot during which restoring again goes to the snapshot
+/// before it, instead of the same one again.
+const SNAPSHOT_REMOVAL_COOLDOWN: usize = 10;
+
+struct Snapshot {
+    // Full snapshot of the VM state.
+    snapshot: VmSnapshot,
+    // Number of rounds played on the current timeline when the snapshot was taken.
+    position: u64,
+    // States of the VM before each round played since the snapshot, used to rewind one round at
+    // a time by replaying them. `None` if some rounds have been played without being recorded.
+    rounds: Option<Vec<VmState>>,
+}
+
+/// Snapshots taken at regular intervals while the game runs, most recent first.
+pub struct RewindHistory {
+    snapshots: VecDeque<Snapshot>,
+    /// Maximum number of snapshots kept.
+    depth: usize,
+    /// Number of ticks between two snapshots.
+    interval: usize,
+    /// Ticks run since the last snapshot was taken or restored.
+    ticks_since_snapshot: usize,
+    /// Whether the most recent snapshot has been restored less than `SNAPSHOT_REMOVAL_COOLDOWN`
+    /// ticks ago.
+    just_restored: bool,
+    /// Value subtracted from the round of the VM to get its position on the current timeline,
+    /// i.e. the number of rounds that have been undone by restoring snapshots.
+    rounds_undone: u64,
+}
+
+impl RewindHistory {
+    /// Create an empty history keeping up to `depth` snapshots, taken every `interval` ticks.
+    pub fn new(depth: usize, interval: usize) -> Self {
+        Self {
+            snapshots: VecDeque::new(),
+            depth,
+            interval,
+            ticks_since_snapshot: 0,
+            just_restored: false,
+            rounds_undone: 0,
+        }
+    }
+
+    /// Returns the position of `vm` on the current timeline.
+    fn position(&self, vm: &Vm) -> u64 {
+        vm.round() - self.rounds_undone
+    }
+
+    /// Make the current position of `vm` on the timeline be `position`.
+    fn set_position(&mut self, vm: &Vm, position: u64) {
+        self.rounds_undone = vm.round() - position;
+    }
+
+    /// Drop all the snapshots, e.g. because they expect the code of another scene to be loaded.
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.ticks_since_snapshot = 0;
+        self.just_restored = false;
+    }
+
+    /// Add a snapshot of `vm` and `gfx`, dropping the oldest ones to keep at most `depth` of them.
+    pub fn take_snapshot<G: gfx::Gfx + ?Sized>(&mut self, vm: &Vm, gfx: &G) {
+        self.snapshots.push_front(Snapshot {
+            snapshot: VmSnapshot::new(vm, gfx),
+            position: self.position(vm),
+            rounds: Some(Vec::new()),
+        });
+        self.snapshots.truncate(self.depth);
+        self.ticks_since_snapshot = 0;
+        self.just_restored = false;
+    }
+
+    /// Account for a game tick, taking a snapshot of `vm` and `gfx` if it is time to.
+    pub fn tick<G: gfx::Gfx + ?Sized>(&mut self, vm: &Vm, gfx: &G) {
+        self.ticks_since_snapshot += 1;
+        if self.ticks_since_snapshot == SNAPSHOT_REMOVAL_COOLDOWN {
+            self.just_restored = false;
+        }
+
+        let rounds_full = self.snapshots.front().is_some_and(|snapshot| {
+            snapshot
+                .rounds
+                .as_ref()
+                .is_some_and(|rounds| rounds.len() >= MAX_ROUNDS_PER_SNAPSHOT)
+        });
+        if self.ticks_since_snapshot >= self.interval || rounds_full {
+            self.take_snapshot(vm, gfx);
+        }
+    }
+
+    /// Record the state of `vm` before it plays a round, so it can be rewound to it later.
+    pub fn record_round(&mut self, vm: &Vm) {
+        if let Some(Snapshot {
+            rounds: Some(rounds),
+            ..
+        }) = self.snapshots.front_mut()
+        {
+            rounds.push(vm.take_snapshot());
+        }
+    }
+
+    /// Note that rounds have been played without being recorded, so the game cannot be rewound
+    /// by a single round until the next snapshot.
+    pub fn forget_rounds(&mut self) {
+        if let Some(snapshot) = self.snapshots.front_mut() {
+            snapshot.rounds = None;
+        }
+    }
+
+    /// Restore the most recent snapshot into `vm` and `gfx`, or the one before it if the most
+    /// recent one has just been restored. The snapshots taken after the restored one are dropped.
+    ///
+    /// Returns `false` if there is no snapshot to restore.
+    pub fn restore<G: gfx::Gfx + ?Sized>(&mut self, vm: &mut Vm, gfx: &mut G) -> bool {
+        // Keep at least one snapshot to restore.
+        if self.just_restored && self.snapshots.len() >= 2 {
+            self.snapshots.pop_front();
+        }
+
+        let Some(snapshot) = self.snapshots.front_mut() else {
+            return false;
+        };
+        snapshot.snapshot.restore(vm, gfx);
+        // The game restarts from the snapshot.
+        snapshot.rounds = Some(Vec::new());
+        let position = snapshot.position;
+        self.set_position(vm, position);
+        self.ticks_since_snapshot = 0;
+        self.just_restored = true;
+
+        true
+    }
+
+    /// Rewind `vm` and `gfx` by one round, using the rounds recorded since the last snapshot.
+    ///
+    /// Returns `false` if this is not possible because the rounds to go back to have not been
+    /// recorded.
+    pub fn rewind_one_round<G: gfx::Gfx + ?Sized>(
+        &mut self,
+        vm: &mut Vm,
+        gfx: &mut G,
+    ) -> std::io::Result<bool> {
+        // If no round has been played since the last snapshot, the previous round is the last one
+        // recorded by the snapshot before it.
+        let nothing_recorded =
+            |snapshot: &Snapshot| matches!(&snapshot.rounds, Some(r) if r.is_empty());
+        if self.snapshots.len() >= 2 && self.snapshots.front().is_some_and(nothing_recorded) {
+            self.snapshots.pop_front();
+        }
+
+        let Some(Snapshot {
+            snapshot,
+            position,
+            rounds: Some(rounds),
+        }) = self.snapshots.front_mut()
+        else {
+            return Ok(false);
+        };
+        let Some(target) = rounds.pop() else {
+            return Ok(false);
+        };
+
+        snapshot.restore_and_replay(rounds, vm, gfx, &mut NullAudio)?;
+        vm.restore_snapshot(&target);
+        let position = *position + rounds.len() as u64;
+        self.set_position(vm, position);
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gfx::headless::HeadlessGfx;
+    use crate::gfx::sw::RasterGameRenderer;
+    use crate::res::ResType;
+    use crate::res::ResourceManager;
+    use crate::scenes::SCENES;
+
+    /// Checks that the snapshots go from the most recent position in the game to the oldest, and
+    /// that none is ahead of `vm`.
+    fn assert_coherent(history: &RewindHistory, vm: &Vm) {
+        let positions = history
+            .snapshots
+            .iter()
+            .map(|s| s.position)
+            .collect::<Vec<_>>();
+        assert!(positions.windows(2).all(|w| w[0] > w[1]), "{:?}", positions);
+        assert!(positions
+            .first()
+            .is_some_and(|&p| p <= history.position(vm)));
+        // Register 0 counts the rounds played since the scene started, which gives the actual
+        // position of the VM.
+        assert_eq!(vm.get_reg(0) as u64, history.position(vm));
+    }
+
+    #[test]
+    fn test_rewind_then_advance() {
+        let scene = &SCENES[1];
+        #[rustfmt::skip]
+        let code = vec![
+            // addi v0, 1
+            0x03, 0x00, 0x00, 0x01,
+            // break
+            0x06,
+            // jmp 0x0000
+            0x07, 0x00, 0x00,
+        ];
+        let mut vm = Vm::new_with_resman(ResourceManager::new_in_memory(vec![
+            (scene.palette, ResType::Palette, vec![0; 2048]),
+            (scene.code, ResType::Bytecode, code),
+            (scene.video1, ResType::Cinematic, vec![]),
+        ]));
+        let mut gfx = HeadlessGfx::new(RasterGameRenderer::new());
+        vm.request_scene(1);
+        // Load the scene, which resets the registers.
+        vm.process_round(&mut gfx, &mut NullAudio).unwrap();
+        vm.set_reg(0, 0);
+        let mut history = RewindHistory::new(4, SNAPSHOT_REMOVAL_COOLDOWN + 5);
+        history.set_position(&vm, 0);
+        history.take_snapshot(&vm, &gfx);
+
+        let mut play = |history: &mut RewindHistory, vm: &mut Vm, rounds: usize| {
+            for _ in 0..rounds {
+                history.tick(vm, &gfx);
+                history.record_round(vm);
+                vm.process_round(&mut gfx, &mut NullAudio).unwrap();
+            }
+        };
+
+        play(&mut history, &mut vm, 50);
+        assert_coherent(&history, &vm);
+        // Only the last snapshots are kept.
+        assert_eq!(history.snapshots.len(), 4);
+        assert_eq!(history.snapshots[0].position, 44);
+
+        // Restoring twice in a row goes back two snapshots, and drops the most recent one.
+        assert!(history.restore(&mut vm, &mut HeadlessGfx::new(RasterGameRenderer::new())));
+        assert!(history.restore(&mut vm, &mut HeadlessGfx::new(RasterGameRenderer::new())));
+        assert_eq!(history.snapshots.len(), 3);
+        assert_eq!(history.position(&vm), 29);
+        assert_coherent(&history, &vm);
+
+        // Playing on replaces the rounds played the first time.
+        play(&mut history, &mut vm, 20);
+        assert_coherent(&history, &vm);
+        assert_eq!(
+            history
+                .snapshots
+                .iter()
+                .map(|s| s.position)
+                .collect::<Vec<_>>(),
+            vec![43, 29, 14, 0]
+        );
+
+        assert!(history
+            .rewind_one_round(&mut vm, &mut HeadlessGfx::new(RasterGameRenderer::new()))
+            .unwrap());
+        assert_eq!(history.position(&vm), 48);
+        play(&mut history, &mut vm, 3);
+        assert_coherent(&history, &vm);
+    }
+}
@@ -4,117 +4,652 @@
 use sdl2::event::Event;
 use sdl2::event::WindowEvent;
 use sdl2::keyboard::Keycode;
+use sdl2::keyboard::Mod;
 use sdl2::rect::Rect;
+use sdl2::video::FullscreenType;
 use sdl2::Sdl;
 use tracing::error;
+use tracing::info;
+use tracing::warn;
 
 use crate::audio::sdl2::Sdl2Audio;
+use crate::audio::ChannelInfo;
+use crate::audio::Mixer;
 use crate::audio::MusicPlayer;
+use crate::audio::NullAudio;
+use crate::audio::LINES_PER_PATTERN;
+use crate::audio::NUM_AUDIO_CHANNELS;
+use crate::font::Font;
+use crate::gfx::recorder::GifRecorder;
 use crate::gfx::sdl2::canvas_gfx::Sdl2CanvasGfx;
 #[cfg(feature = "gl3")]
 use crate::gfx::sdl2::gl3_gfx::RenderingMode;
 #[cfg(feature = "gl3")]
 use crate::gfx::sdl2::gl3_gfx::Sdl2GlGfx;
 use crate::gfx::sdl2::Sdl2Gfx;
+use crate::gfx::Vsync;
 use crate::gfx::{self};
 use crate::input::ButtonState;
-use crate::input::InputState;
+use crate::input::InputEvent;
+use crate::input::InputQueue;
 use crate::input::LeftRightDir;
 use crate::input::UpDownDir;
+use crate::res::PatchWatcher;
+use crate::scenes::SCENES;
+use crate::sys::sdl2::rewind::RewindHistory;
+use crate::sys::Snapshotable;
 use crate::sys::Sys;
+use crate::sys::SysOptions;
+use crate::vm::Checkpoint;
+use crate::vm::Demo;
 use crate::vm::Vm;
-use crate::vm::VmSnapshot;
 
-use std::collections::VecDeque;
+use std::fs::File;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
 use std::thread;
 use std::time::Duration;
 use std::time::Instant;
 
-const TICKS_PER_SECOND: u64 = 50;
-const DURATION_PER_TICK: Duration =
-    // Use microseconds to add precision.
-    Duration::from_micros(1_000_000 / TICKS_PER_SECOND);
+/// Upper bound to the number of frames presented per second when vsync is enabled, so we don't
+/// spin needlessly if the driver does not actually wait for the vertical sync.
+const MAX_FRAMES_PER_SECOND: u64 = 250;
+const MIN_DURATION_PER_FRAME: Duration = Duration::from_micros(1_000_000 / MAX_FRAMES_PER_SECOND);
+/// Maximum amount of time we try to catch up with, e.g. after the window has been dragged around.
+/// Anything beyond is dropped instead of making the game run in fast-forward.
+const MAX_TICKS_BEHIND: u32 = 10;
+/// How much faster the game runs in fast-forward mode.
+const FAST_MODE_SPEED: u32 = 8;
+
+/// Audio output of the system, which may be a SDL audio device or nothing at all.
+trait AudioDevice: Mixer + MusicPlayer {
+    /// Mute or unmute the sound effects played by the game, without affecting the music.
+    fn set_sfx_muted(&mut self, muted: bool);
+    fn sfx_muted(&self) -> bool;
+    /// Stop or restart all audio output, independently of whether it is paused.
+    fn set_muted(&mut self, muted: bool);
+    fn muted(&self) -> bool;
+    /// Returns what each channel of the mixer is currently playing.
+    fn channel_snapshot(&self) -> [Option<ChannelInfo>; NUM_AUDIO_CHANNELS];
+    /// Stop all audio output before the rest of the system is torn down.
+    fn shutdown(&mut self);
+}
+
+impl AudioDevice for Sdl2Audio {
+    fn set_sfx_muted(&mut self, muted: bool) {
+        Sdl2Audio::set_sfx_muted(self, muted)
+    }
+
+    fn sfx_muted(&self) -> bool {
+        Sdl2Audio::sfx_muted(self)
+    }
+
+    fn set_muted(&mut self, muted: bool) {
+        Sdl2Audio::set_muted(self, muted)
+    }
+
+    fn muted(&self) -> bool {
+        Sdl2Audio::muted(self)
+    }
+
+    fn channel_snapshot(&self) -> [Option<ChannelInfo>; NUM_AUDIO_CHANNELS] {
+        Sdl2Audio::channel_snapshot(self)
+    }
+
+    fn shutdown(&mut self) {
+        Sdl2Audio::shutdown(self)
+    }
+}
+
+impl AudioDevice for NullAudio {
+    fn set_sfx_muted(&mut self, _muted: bool) {}
+
+    fn sfx_muted(&self) -> bool {
+        true
+    }
+
+    fn set_muted(&mut self, _muted: bool) {}
+
+    fn muted(&self) -> bool {
+        true
+    }
+
+    fn channel_snapshot(&self) -> [Option<ChannelInfo>; NUM_AUDIO_CHANNELS] {
+        [None; NUM_AUDIO_CHANNELS]
+    }
+
+    fn shutdown(&mut self) {}
+}
 
 pub struct Sdl2Sys<D: Sdl2Gfx> {
     sdl_context: Sdl,
     display: D,
-    audio_device: Sdl2Audio,
+    audio_device: Box<dyn AudioDevice>,
+    options: SysOptions,
+}
+
+/// Creates a dynamic SDL Sys instance using `display`, recording its frames if `options` asks
+/// for it.
+fn new_sys<D: Sdl2Gfx + 'static>(
+    mut display: D,
+    sdl_context: Sdl,
+    audio_device: Box<dyn AudioDevice>,
+    options: &SysOptions,
+) -> Option<Box<dyn Sys>> {
+    let font = match &options.font {
+        None => Font::default(),
+        Some(path) => Font::load(path).unwrap_or_else(|e| {
+            error!(
+                "Failed to load font {}: {}, using the built-in one",
+                path.display(),
+                e
+            );
+            Font::default()
+        }),
+    };
+    display.set_background_color(&options.bg_color);
+
+    match &options.record_gif {
+        None => {
+            display.set_font(&font);
+            display.set_strict(options.strict);
+            Some(Box::new(Sdl2Sys {
+                sdl_context,
+                display,
+                audio_device,
+                options: options.clone(),
+            }))
+        }
+        Some(path) => {
+            let mut display = GifRecorder::new(display, path, options.gif_fps)
+                .map_err(|e| {
+                    error!("Failed to create GIF file {}: {}", path.display(), e);
+                })
+                .ok()?;
+            display.set_font(&font);
+            display.set_strict(options.strict);
+            Some(Box::new(Sdl2Sys {
+                sdl_context,
+                display,
+                audio_device,
+                options: options.clone(),
+            }))
+        }
+    }
 }
 
-/// Creates a dynamic SDL Sys instance with a given renderer.
-pub fn new_with_renderer(renderer: &Option<String>) -> Option<Box<dyn Sys>> {
+/// Creates a dynamic SDL Sys instance with a given renderer, configured according to `options`.
+pub fn new_with_renderer(renderer: &Option<String>, options: &SysOptions) -> Option<Box<dyn Sys>> {
     let sdl_context = sdl2::init()
         .map_err(|e| {
             error!("Failed to initialize SDL: {}", e);
         })
         .ok()?;
 
-    let audio_device = Sdl2Audio::new(&sdl_context, 22050)
-        .map_err(|e| {
-            error!("Failed to initialize SDL audio device: {}", e);
-        })
-        .ok()?;
+    let mut audio_device: Box<dyn AudioDevice> = if options.no_audio {
+        Box::new(NullAudio)
+    } else {
+        Box::new(
+            Sdl2Audio::new(
+                &sdl_context,
+                options.audio_freq as usize,
+                options.audio_buffer,
+                options.stereo,
+                options.audio_interp,
+            )
+            .map_err(|e| {
+                error!("Failed to initialize SDL audio device: {}", e);
+            })
+            .ok()?,
+        )
+    };
+    audio_device.set_muted(options.mute);
 
     let backend = renderer.as_ref().map(String::as_str).unwrap_or("raster");
+    if options.onion_skin && backend != "raster" {
+        warn!("onion skinning is only supported by the raster renderer");
+    }
+    if options.conservative_raster && !matches!(backend, "raster" | "gl_raster") {
+        warn!("conservative rasterization is only supported by the raster renderers");
+    }
+    if options.raster_scale > 1 && backend != "raster" {
+        warn!("raster scaling is only supported by the raster renderer");
+    }
     match backend {
-        "raster" => Some(Box::new(Sdl2Sys {
-            display: Sdl2CanvasGfx::new(&sdl_context).ok()?,
+        "raster" => new_sys(
+            {
+                let mut gfx =
+                    Sdl2CanvasGfx::new(&sdl_context, options.parallel_raster, options.vsync)
+                        .ok()?;
+                gfx.set_onion_skin(options.onion_skin);
+                gfx.set_conservative_raster(options.conservative_raster);
+                gfx.set_raster_scale(options.raster_scale);
+                gfx
+            },
+            sdl_context,
+            audio_device,
+            options,
+        ),
+        #[cfg(feature = "gl3")]
+        "gl_raster" => new_sys(
+            {
+                let mut gfx = Sdl2GlGfx::new(
+                    &sdl_context,
+                    RenderingMode::Raster,
+                    options.parallel_raster,
+                    false,
+                    options.vsync,
+                )
+                .ok()?;
+                gfx.set_conservative_raster(options.conservative_raster);
+                gfx
+            },
             sdl_context,
             audio_device,
-        }) as Box<dyn Sys>),
+            options,
+        ),
         #[cfg(feature = "gl3")]
-        "gl_raster" => Some(Box::new(Sdl2Sys {
-            display: Sdl2GlGfx::new(&sdl_context, RenderingMode::Raster).ok()?,
+        "gl_poly" => new_sys(
+            Sdl2GlGfx::new(
+                &sdl_context,
+                RenderingMode::Poly,
+                options.parallel_raster,
+                false,
+                options.vsync,
+            )
+            .ok()?,
             sdl_context,
             audio_device,
-        }) as Box<dyn Sys>),
+            options,
+        ),
         #[cfg(feature = "gl3")]
-        "gl_poly" => Some(Box::new(Sdl2Sys {
-            display: Sdl2GlGfx::new(&sdl_context, RenderingMode::Poly).ok()?,
+        "gl_line" => new_sys(
+            Sdl2GlGfx::new(
+                &sdl_context,
+                RenderingMode::Line,
+                options.parallel_raster,
+                false,
+                options.vsync,
+            )
+            .ok()?,
             sdl_context,
             audio_device,
-        }) as Box<dyn Sys>),
+            options,
+        ),
         #[cfg(feature = "gl3")]
-        "gl_line" => Some(Box::new(Sdl2Sys {
-            display: Sdl2GlGfx::new(&sdl_context, RenderingMode::Line).ok()?,
+        "gl_truecolor" => new_sys(
+            Sdl2GlGfx::new(
+                &sdl_context,
+                RenderingMode::Poly,
+                options.parallel_raster,
+                true,
+                options.vsync,
+            )
+            .ok()?,
             sdl_context,
             audio_device,
-        }) as Box<dyn Sys>),
+            options,
+        ),
         // Just a test for Sdl2Gfx trait object.
         #[cfg(feature = "gl3")]
-        "gl_raster_boxed" => Some(Box::new(Sdl2Sys {
-            display: Box::new(Sdl2GlGfx::new(&sdl_context, RenderingMode::Raster).ok()?)
-                as Box<dyn Sdl2Gfx>,
+        "gl_raster_boxed" => new_sys(
+            Box::new(
+                Sdl2GlGfx::new(
+                    &sdl_context,
+                    RenderingMode::Raster,
+                    options.parallel_raster,
+                    false,
+                    options.vsync,
+                )
+                .ok()?,
+            ) as Box<dyn Sdl2Gfx>,
             sdl_context,
             audio_device,
-        }) as Box<dyn Sys>),
+            options,
+        ),
         _ => None,
     }
 }
 
-struct Snapshot {
-    // Full snapshot of the VM state.
-    snapshot: VmSnapshot,
-    // Whether the snapshot has just been restored and we should skip it if 'B' is pressed.
-    just_restored: bool,
+/// Lines of the overlay shown while the game is paused.
+fn pause_lines<M: MusicPlayer + ?Sized>(vm: &Vm, music: &M) -> Vec<String> {
+    vec![
+        "PAUSED".into(),
+        match vm.scene() {
+            Some(scene) => format!("SCENE {}", scene),
+            None => "NO SCENE".into(),
+        },
+        format!("ROUND {}", vm.round()),
+        match (music.playback_position(), music.num_orders()) {
+            (Some((order, line)), Some(num_orders)) => format!(
+                "MUSIC {}/{} LINE {}/{}",
+                order, num_orders, line, LINES_PER_PATTERN
+            ),
+            _ => "NO MUSIC".into(),
+        },
+        {
+            let hero = vm.hero_state();
+            format!(
+                "HERO {} {} {} MASK {:02X}",
+                match hero.horizontal {
+                    LeftRightDir::Left => "LEFT",
+                    LeftRightDir::Neutral => "-",
+                    LeftRightDir::Right => "RIGHT",
+                },
+                match hero.vertical {
+                    UpDownDir::Up => "UP",
+                    UpDownDir::Neutral => "-",
+                    UpDownDir::Down => "DOWN",
+                },
+                match hero.action {
+                    ButtonState::Released => "-",
+                    ButtonState::Pushed => "ACTION",
+                },
+                hero.mask
+            )
+        },
+    ]
+}
+
+/// Lines of the overlay showing what each channel of the mixer is playing.
+fn channel_lines(channels: &[Option<ChannelInfo>]) -> Vec<String> {
+    channels
+        .iter()
+        .enumerate()
+        .map(|(i, channel)| match channel {
+            None => format!("CH{} -", i),
+            Some(info) => format!(
+                "CH{} SND {:02X} VOL {:02X} {}{}",
+                i,
+                info.sample_id,
+                info.volume,
+                match info.len {
+                    Some(len) => format!("POS {}/{}", info.position, len),
+                    None => "NOT LOADED".into(),
+                },
+                if info.stopping { " STOP" } else { "" }
+            ),
+        })
+        .collect()
+}
+
+/// Debug menu allowing to jump to any scene of the game.
+struct SceneMenu {
+    /// Index of the currently selected scene in `SCENES`.
+    selected: usize,
+}
+
+impl SceneMenu {
+    fn new(vm: &Vm) -> Self {
+        Self {
+            selected: vm.scene().unwrap_or(0),
+        }
+    }
+
+    fn select_previous(&mut self) {
+        self.selected = self.selected.checked_sub(1).unwrap_or(SCENES.len() - 1);
+    }
+
+    fn select_next(&mut self) {
+        self.selected = (self.selected + 1) % SCENES.len();
+    }
+
+    /// Lines of the menu overlay.
+    fn lines(&self) -> Vec<String> {
+        std::iter::once("SELECT SCENE (ENTER/ESC)".into())
+            .chain(SCENES.iter().enumerate().map(|(i, scene)| {
+                let marker = if i == self.selected { '>' } else { ' ' };
+                format!("{} {} {}", marker, i, scene.name.to_uppercase())
+            }))
+            .collect()
+    }
+}
+
+/// Debug menu allowing to pause and resume the threads of the VM.
+struct ThreadMenu {
+    /// Position of the currently selected thread in the list returned by `Vm::threads`.
+    selected: usize,
 }
 
-impl From<VmSnapshot> for Snapshot {
-    fn from(snapshot: VmSnapshot) -> Self {
+impl ThreadMenu {
+    /// Maximum number of threads listed at once, so the menu fits on the screen.
+    const MAX_LINES: usize = 20;
+
+    fn new() -> Self {
+        Self { selected: 0 }
+    }
+
+    fn select_previous(&mut self, vm: &Vm) {
+        let num_threads = vm.threads().len().max(1);
+        self.selected = (self.selected + num_threads - 1) % num_threads;
+    }
+
+    fn select_next(&mut self, vm: &Vm) {
+        self.selected = (self.selected + 1) % vm.threads().len().max(1);
+    }
+
+    /// Pause the selected thread if it runs, or resume it if it is paused.
+    fn toggle(&self, vm: &mut Vm) {
+        if let Some(thread) = vm.threads().get(self.selected) {
+            vm.set_thread_enabled(thread.id, !thread.enabled);
+            info!(
+                "Thread {} {}",
+                thread.id,
+                if thread.enabled { "paused" } else { "resumed" }
+            );
+        }
+    }
+
+    /// Lines of the menu overlay.
+    fn lines(&self, vm: &Vm) -> Vec<String> {
+        let threads = vm.threads();
+        let first = self.selected.saturating_sub(Self::MAX_LINES - 1);
+        std::iter::once("THREADS (SPACE/ESC)".into())
+            .chain(
+                threads
+                    .iter()
+                    .enumerate()
+                    .skip(first)
+                    .take(Self::MAX_LINES)
+                    .map(|(i, thread)| {
+                        let marker = if i == self.selected { '>' } else { ' ' };
+                        format!(
+                            "{} {:02} {:04X} {}",
+                            marker,
+                            thread.id,
+                            thread.pc,
+                            if thread.enabled { "RUN" } else { "PAUSED" }
+                        )
+                    }),
+            )
+            .collect()
+    }
+}
+
+/// Measures how many frames are presented and how many VM rounds are processed per second.
+struct PerfCounter {
+    since: Instant,
+    frames: u32,
+    first_round: u64,
+    fps: f64,
+    rounds_per_second: f64,
+}
+
+impl PerfCounter {
+    /// How often the measurements are updated.
+    const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+    fn new(vm: &Vm) -> Self {
         Self {
-            snapshot,
-            just_restored: false,
+            since: Instant::now(),
+            frames: 0,
+            first_round: vm.round(),
+            fps: 0.0,
+            rounds_per_second: 0.0,
+        }
+    }
+
+    /// Account for a newly presented frame.
+    fn frame_presented(&mut self, vm: &Vm) {
+        self.frames += 1;
+
+        let elapsed = self.since.elapsed();
+        if elapsed >= Self::UPDATE_INTERVAL {
+            let secs = elapsed.as_secs_f64();
+            self.fps = self.frames as f64 / secs;
+            self.rounds_per_second = (vm.round() - self.first_round) as f64 / secs;
+            self.since = Instant::now();
+            self.frames = 0;
+            self.first_round = vm.round();
+        }
+    }
+
+    /// Lines of the performance HUD.
+    fn lines(&self) -> Vec<String> {
+        vec![
+            format!("FPS {:.1}", self.fps),
+            format!("RPS {:.1}", self.rounds_per_second),
+        ]
+    }
+}
+
+/// Switch the window of `display` to fullscreen if `windowed_size` is `None`, or back to a window
+/// of `windowed_size` otherwise.
+///
+/// Returns the size to give back to the window when leaving fullscreen, or `None` if the window is
+/// not fullscreen anymore.
+fn toggle_fullscreen<D: Sdl2Gfx>(
+    display: &mut D,
+    windowed_size: Option<(u32, u32)>,
+) -> Option<(u32, u32)> {
+    let window = display.window_mut();
+    match windowed_size {
+        None => {
+            let size = window.size();
+            match window.set_fullscreen(FullscreenType::Desktop) {
+                Ok(()) => Some(size),
+                Err(e) => {
+                    error!("Failed to switch to fullscreen: {}", e);
+                    None
+                }
+            }
+        }
+        Some((w, h)) => {
+            if let Err(e) = window.set_fullscreen(FullscreenType::Off) {
+                error!("Failed to leave fullscreen: {}", e);
+                return windowed_size;
+            }
+            if let Err(e) = window.set_size(w, h) {
+                warn!("Failed to restore the window size: {}", e);
+            }
+            None
         }
     }
 }
 
-fn take_snapshot<G: gfx::Gfx + ?Sized>(history: &mut VecDeque<Snapshot>, vm: &Vm, gfx: &G) {
-    const MAX_GAME_SNAPSHOTS: usize = 50;
+/// Make `vm` jump to `scene`.
+///
+/// The rewind `history` is cleared, since its snapshots expect the code of the current scene to be
+/// loaded.
+fn jump_to_scene(vm: &mut Vm, history: &mut RewindHistory, scene: usize) {
+    vm.request_scene(scene);
+    history.clear();
+}
+
+/// Returns the number of game ticks to run after `elapsed` real time.
+///
+/// The game logic runs at a fixed rate, independently of how often frames are presented: as many
+/// ticks as fit into the time elapsed, sped up by `speed`, are run, and the remainder is kept in
+/// `accumulator` for the next frames. The game does not try to catch up with more than
+/// `max_time_behind`.
+///
+/// If `deterministic` is set, exactly one tick is run whatever the time elapsed.
+fn ticks_to_run(
+    accumulator: &mut Duration,
+    elapsed: Duration,
+    duration_per_tick: Duration,
+    max_time_behind: Duration,
+    speed: u32,
+    deterministic: bool,
+) -> u32 {
+    if deterministic {
+        return 1;
+    }
+
+    *accumulator = (*accumulator + elapsed * speed).min(max_time_behind * speed);
+    let ticks_to_run = (accumulator.as_micros() / duration_per_tick.as_micros()) as u32;
+    *accumulator -= duration_per_tick * ticks_to_run;
+    ticks_to_run
+}
+
+/// Save `checkpoint` with a thumbnail of what `display` currently shows.
+fn save_checkpoint<D: gfx::Display>(mut checkpoint: Checkpoint, display: &D) {
+    if let Some(frame) = display.read_framebuffer_rgb() {
+        checkpoint.set_thumbnail(&frame);
+    }
+    match checkpoint.save(Path::new(".")) {
+        Ok(path) => info!("Checkpoint saved into {}", path.display()),
+        Err(e) => error!("Failed to save checkpoint: {}", e),
+    }
+}
+
+/// Stop recording `demo` and save it into `path`.
+fn save_demo(demo: &mut Option<Demo>, path: Option<&Path>) {
+    let (Some(demo), Some(path)) = (demo.take(), path) else {
+        return;
+    };
+    match demo.save(path) {
+        Ok(()) => info!(
+            "Demo of {} rounds saved into {}",
+            demo.num_rounds(),
+            path.display()
+        ),
+        Err(e) => error!("Failed to save demo: {}", e),
+    }
+}
+
+/// Stop recording `demo` and playing `playback` because the game state has been changed in a way
+/// they cannot follow.
+fn interrupt_demo(
+    demo: &mut Option<Demo>,
+    playback: &mut Option<DemoPlayback>,
+    path: Option<&Path>,
+) {
+    if demo.is_some() {
+        warn!("Game state changed outside of the game, stopping the demo recording");
+    }
+    if playback.take().is_some() {
+        warn!("Game state changed outside of the game, stopping the demo playback");
+    }
+    save_demo(demo, path);
+}
+
+/// Demo being played, and the next round to play from it.
+struct DemoPlayback {
+    demo: Demo,
+    round: usize,
+}
 
-    history.push_front(VmSnapshot::new(vm, gfx).into());
+/// Give `vm` the input of the next round of `playback`, if any. Playback stops once the demo has
+/// no more rounds to play.
+fn play_demo_round(playback: &mut Option<DemoPlayback>, vm: &mut Vm) {
+    let Some(DemoPlayback { demo, round }) = playback else {
+        return;
+    };
+    if demo.apply_round(*round, vm) {
+        *round += 1;
+    } else {
+        info!("End of the demo, back to the keyboard");
+        *playback = None;
+    }
+}
 
-    while history.len() > MAX_GAME_SNAPSHOTS {
-        history.pop_back();
+impl<D: Sdl2Gfx> Drop for Sdl2Sys<D> {
+    fn drop(&mut self) {
+        // Stop the audio before the display and SDL context go away.
+        self.audio_device.shutdown();
     }
 }
 
@@ -122,19 +657,77 @@ impl<D: Sdl2Gfx> Sys for Sdl2Sys<D> {
     fn game_loop(&mut self, vm: &mut Vm) {
         // Events, time and input
         let mut sdl_events = self.sdl_context.event_pump().unwrap();
-        let mut next_tick_time = Instant::now();
+        let mut last_frame_time = Instant::now();
+        // Real time elapsed but not consumed by game ticks yet.
+        let mut tick_accumulator = Duration::ZERO;
+        // Use microseconds to add precision.
+        let duration_per_tick = Duration::from_micros(1_000_000 / self.options.tick_rate as u64);
         let mut ticks_to_wait = 0;
-        let mut input = InputState::new();
+        // Minimum time between two presented frames, if their rate is limited.
+        let min_frame_duration = {
+            let vsync_limit = (!self.options.deterministic && self.options.vsync != Vsync::Off)
+                .then_some(MIN_DURATION_PER_FRAME);
+            let max_fps_limit = self
+                .options
+                .max_fps
+                .map(|fps| Duration::from_micros(1_000_000 / fps as u64));
+            vsync_limit.max(max_fps_limit)
+        };
+        // Longest time the game can be late and catch up with. A frame limit lower than the tick
+        // rate makes it regularly late by a whole frame, which must not slow it down.
+        let max_time_behind =
+            duration_per_tick * MAX_TICKS_BEHIND + min_frame_duration.unwrap_or_default();
+        let mut input = InputQueue::new();
+        // Number of ticks run so far, used to order the input events with the VM rounds.
+        let mut tick = 0u64;
 
         // Modes
         let mut fast_mode = false;
         let mut pause = false;
+        let mut show_perf_hud = false;
+        let mut show_channels = false;
+        let mut perf_counter = PerfCounter::new(vm);
+        let mut scene_menu: Option<SceneMenu> = None;
+        let mut thread_menu: Option<ThreadMenu> = None;
+
+        // Demo playback
+        let mut playback =
+            self.options
+                .play_demo
+                .as_deref()
+                .and_then(|path| match Demo::load(path) {
+                    Ok(demo) => {
+                        demo.start(vm);
+                        info!("Playing demo of {} rounds", demo.num_rounds());
+                        Some(DemoPlayback { demo, round: 0 })
+                    }
+                    Err(e) => {
+                        error!("Failed to load demo {}: {}", path.display(), e);
+                        None
+                    }
+                });
 
         // State rewind
-        const TICKS_PER_SNAPSHOT: usize = 200;
-        let mut history: VecDeque<Snapshot> = VecDeque::new();
-        let mut snapshot_cpt = 0;
-        take_snapshot(&mut history, vm, &self.display);
+        let mut history =
+            RewindHistory::new(self.options.rewind_depth, self.options.rewind_interval);
+        history.take_snapshot(vm, &self.display);
+
+        // Demo recording
+        let demo_path = self.options.record_demo.clone();
+        let mut demo = match &demo_path {
+            Some(_) => {
+                let demo = Demo::new(vm);
+                if demo.is_none() {
+                    warn!("Demos can only be recorded from the start of a scene, not recording");
+                }
+                demo
+            }
+            None => None,
+        };
+
+        // Checkpoint taken when the game moved to a new scene, saved once the scene presents its
+        // first frame so its thumbnail shows the new scene.
+        let mut pending_checkpoint = None;
 
         // Ignore keys presses from being handled right after window has gained
         // focus to avoid e.g escape being considered if esc was part of the
@@ -142,29 +735,179 @@ impl<D: Sdl2Gfx> Sys for Sdl2Sys<D> {
         const KEYPRESS_COOLDOWN_TICKS: usize = 1;
         let mut keypress_cooldown = KEYPRESS_COOLDOWN_TICKS;
 
-        let mut released_keys = Vec::new();
+        // Patched resources
+        const PATCH_POLL_PERIOD: Duration = Duration::from_secs(1);
+        let mut patch_watcher = self.options.watch_patches.as_deref().map(PatchWatcher::new);
+        let mut last_patch_poll = Instant::now();
+
+        // Size of the window before it went fullscreen, if it is.
+        let mut windowed_size = None;
+        if self.options.fullscreen {
+            windowed_size = toggle_fullscreen(&mut self.display, windowed_size);
+        }
+
         'run: loop {
             // Update input
-            released_keys.clear();
             for event in sdl_events.poll_iter() {
                 match event {
                     Event::Quit { .. } => break 'run,
                     Event::Window {
                         win_event: WindowEvent::FocusGained,
                         ..
-                    } => keypress_cooldown = KEYPRESS_COOLDOWN_TICKS,
+                    } => {
+                        keypress_cooldown = KEYPRESS_COOLDOWN_TICKS;
+                        if self.options.mute_on_focus_loss
+                            && !pause
+                            && scene_menu.is_none()
+                            && thread_menu.is_none()
+                        {
+                            self.audio_device.resume();
+                        }
+                    }
+                    Event::Window {
+                        win_event: WindowEvent::FocusLost,
+                        ..
+                    } => {
+                        // Pausing this way is the same as pressing the pause key, so the game
+                        // stays paused until the player resumes it.
+                        if self.options.pause_on_focus_loss && !pause {
+                            pause = true;
+                        }
+                        if self.options.mute_on_focus_loss || pause {
+                            self.audio_device.pause();
+                        }
+                    }
+                    // The scene menu takes all key presses while it is open.
+                    Event::KeyDown {
+                        keycode: Some(key),
+                        repeat: false,
+                        ..
+                    } if scene_menu.is_some() => {
+                        let menu = scene_menu.as_mut().unwrap();
+                        match key {
+                            Keycode::Up => menu.select_previous(),
+                            Keycode::Down => menu.select_next(),
+                            Keycode::Return => {
+                                interrupt_demo(&mut demo, &mut playback, demo_path.as_deref());
+                                jump_to_scene(vm, &mut history, menu.selected);
+                                scene_menu = None;
+                                pause = false;
+                                self.audio_device.resume();
+                            }
+                            Keycode::Escape | Keycode::F9 => {
+                                scene_menu = None;
+                                if !pause {
+                                    self.audio_device.resume();
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                    // So does the thread menu.
+                    Event::KeyDown {
+                        keycode: Some(key),
+                        repeat: false,
+                        ..
+                    } if thread_menu.is_some() => {
+                        let menu = thread_menu.as_mut().unwrap();
+                        match key {
+                            Keycode::Up => menu.select_previous(vm),
+                            Keycode::Down => menu.select_next(vm),
+                            Keycode::Space | Keycode::Return => {
+                                interrupt_demo(&mut demo, &mut playback, demo_path.as_deref());
+                                menu.toggle(vm);
+                            }
+                            Keycode::Escape | Keycode::T => {
+                                thread_menu = None;
+                                if !pause {
+                                    self.audio_device.resume();
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
                     Event::KeyDown {
                         keycode: Some(key),
+                        keymod,
                         repeat: false,
                         ..
                     } if keypress_cooldown == 0 => match key {
                         Keycode::Escape => break 'run,
-                        Keycode::Left => input.horizontal = LeftRightDir::Left,
-                        Keycode::Right => input.horizontal = LeftRightDir::Right,
-                        Keycode::Up => input.vertical = UpDownDir::Up,
-                        Keycode::Down => input.vertical = UpDownDir::Down,
-                        Keycode::Space => input.button = ButtonState::Pushed,
+                        Keycode::Left => {
+                            input.push(tick, InputEvent::Horizontal(LeftRightDir::Left))
+                        }
+                        Keycode::Right => {
+                            input.push(tick, InputEvent::Horizontal(LeftRightDir::Right))
+                        }
+                        Keycode::Up => input.push(tick, InputEvent::Vertical(UpDownDir::Up)),
+                        Keycode::Down => input.push(tick, InputEvent::Vertical(UpDownDir::Down)),
+                        Keycode::Space => input.push(tick, InputEvent::Button(ButtonState::Pushed)),
                         Keycode::F => fast_mode = true,
+                        Keycode::F8 => show_perf_hud ^= true,
+                        Keycode::F11 => {
+                            windowed_size = toggle_fullscreen(&mut self.display, windowed_size)
+                        }
+                        Keycode::F12 => show_channels ^= true,
+                        Keycode::D if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) => {
+                            let path = PathBuf::from(format!("state-{:08}.bin", vm.round()));
+                            match vm.take_snapshot().save(&path) {
+                                Ok(()) => info!("VM state saved into {}", path.display()),
+                                Err(e) => error!("Failed to save VM state: {}", e),
+                            }
+                        }
+                        Keycode::D => info!("VM state at round {}:\n{:?}", vm.round(), vm),
+                        Keycode::F10 if self.options.dump_drawlist => {
+                            let path = format!("drawlist-{:08}.json", vm.round());
+                            match File::create(&path).map_err(anyhow::Error::from).and_then(
+                                |file| {
+                                    let mut w = BufWriter::new(file);
+                                    self.display.write_drawlist(&mut w)?;
+                                    Ok(w.flush()?)
+                                },
+                            ) {
+                                Ok(()) => info!("Draw list written to {}", path),
+                                Err(e) => error!("Failed to write draw list: {}", e),
+                            }
+                        }
+                        Keycode::M => {
+                            let muted = !self.audio_device.music_muted();
+                            self.audio_device.set_music_muted(muted);
+                            info!("Music {}", if muted { "muted" } else { "unmuted" });
+                        }
+                        Keycode::S => {
+                            let muted = !self.audio_device.sfx_muted();
+                            self.audio_device.set_sfx_muted(muted);
+                            info!("Sound effects {}", if muted { "muted" } else { "unmuted" });
+                        }
+                        Keycode::Num0 => {
+                            let muted = !self.audio_device.muted();
+                            self.audio_device.set_muted(muted);
+                            info!("Audio {}", if muted { "muted" } else { "unmuted" });
+                        }
+                        Keycode::LeftBracket | Keycode::RightBracket if self.options.debug => {
+                            let current = vm.scene().unwrap_or(0);
+                            let scene = if key == Keycode::LeftBracket {
+                                current.checked_sub(1).unwrap_or(SCENES.len() - 1)
+                            } else {
+                                (current + 1) % SCENES.len()
+                            };
+                            interrupt_demo(&mut demo, &mut playback, demo_path.as_deref());
+                            jump_to_scene(vm, &mut history, scene);
+                        }
+                        Keycode::R if self.options.debug => {
+                            interrupt_demo(&mut demo, &mut playback, demo_path.as_deref());
+                            if vm.restart_scene() {
+                                history.clear();
+                            }
+                        }
+                        Keycode::F9 if self.options.debug => {
+                            scene_menu = Some(SceneMenu::new(vm));
+                            self.audio_device.pause();
+                        }
+                        Keycode::T if self.options.debug => {
+                            thread_menu = Some(ThreadMenu::new());
+                            self.audio_device.pause();
+                        }
                         Keycode::P => {
                             pause ^= true;
                             if pause {
@@ -173,145 +916,237 @@ impl<D: Sdl2Gfx> Sys for Sdl2Sys<D> {
                                 self.audio_device.resume();
                             }
                         }
-                        Keycode::B => {
-                            if let Some(state) = history.front() {
-                                // If the state has just been restored, remove it unless that would
-                                // mean we are left with just one state.
-                                if state.just_restored && history.len() >= 2 {
-                                    history.pop_front();
+                        Keycode::B if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) => {
+                            interrupt_demo(&mut demo, &mut playback, demo_path.as_deref());
+                            match history.rewind_one_round(vm, &mut self.display) {
+                                Ok(true) => ticks_to_wait = vm.get_frames_to_wait(),
+                                Ok(false) => info!("Cannot rewind by a single round from here"),
+                                Err(e) => {
+                                    error!("{}, exiting.", e);
+                                    break 'run;
                                 }
                             }
-
-                            if let Some(state) = history.front_mut() {
-                                state.snapshot.restore(vm, &mut self.display);
-                                snapshot_cpt = 0;
-                                state.just_restored = true;
+                        }
+                        Keycode::B => {
+                            interrupt_demo(&mut demo, &mut playback, demo_path.as_deref());
+                            history.restore(vm, &mut self.display);
+                        }
+                        Keycode::N
+                            if pause && keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) =>
+                        {
+                            interrupt_demo(&mut demo, &mut playback, demo_path.as_deref());
+                            history.take_snapshot(vm, &self.display);
+                            vm.update_input(input.state_for_round(tick));
+                            if let Err(e) =
+                                vm.run_until_next_frame(&mut self.display, &mut *self.audio_device)
+                            {
+                                error!("{}, exiting.", e);
+                                break 'run;
                             }
+                            // The rounds played to reach the next frame have not been recorded.
+                            history.forget_rounds();
+                            ticks_to_wait = vm.get_frames_to_wait();
                         }
                         Keycode::N if pause => {
-                            take_snapshot(&mut history, vm, &self.display);
-                            vm.update_input(&input);
+                            history.take_snapshot(vm, &self.display);
+                            vm.update_input(input.state_for_round(tick));
                             if let Some(value_of_0xf4) = self.audio_device.take_value_of_0xf4() {
                                 vm.set_reg(0xf4, value_of_0xf4);
                             }
-                            vm.process_round(&mut self.display, &mut self.audio_device);
+                            play_demo_round(&mut playback, vm);
+                            history.record_round(vm);
+                            if let Some(demo) = &mut demo {
+                                demo.record_round(vm);
+                            }
+                            if let Err(e) =
+                                vm.process_round(&mut self.display, &mut *self.audio_device)
+                            {
+                                error!("{}, exiting.", e);
+                                break 'run;
+                            }
                             ticks_to_wait = vm.get_frames_to_wait();
                         }
                         _ => {}
                     },
-                    // Store key released events so they can be processed later after the VM update.
-                    // This gives the game a chance to proceed keys that have been both pressed and
-                    // released within the same cycle.
+                    // Releases go through the input queue like presses do, so a key pressed and
+                    // released before the VM could play a round is still seen by the next one.
                     Event::KeyUp {
                         keycode: Some(key),
                         repeat: false,
                         ..
-                    } => released_keys.push(key),
+                    } => match key {
+                        Keycode::Left | Keycode::Right => {
+                            input.push(tick, InputEvent::Horizontal(LeftRightDir::Neutral))
+                        }
+                        Keycode::Up | Keycode::Down => {
+                            input.push(tick, InputEvent::Vertical(UpDownDir::Neutral))
+                        }
+                        Keycode::Space => {
+                            input.push(tick, InputEvent::Button(ButtonState::Released))
+                        }
+                        Keycode::F => fast_mode = false,
+                        _ => {}
+                    },
                     _ => {}
                 }
 
                 // Give the display subsystem a chance to manage its own input (hack!)
                 self.display.handle_event(&event);
             }
-            vm.update_input(&input);
-
-            // Now update the state of all the released keys.
-            for &key in &released_keys {
-                match key {
-                    Keycode::Left | Keycode::Right => input.horizontal = LeftRightDir::Neutral,
-                    Keycode::Up | Keycode::Down => input.vertical = UpDownDir::Neutral,
-                    Keycode::Space => input.button = ButtonState::Released,
-                    Keycode::F => fast_mode = false,
-                    _ => {}
-                }
-            }
 
             // Decrease keypress cooldown if we just gained focus.
             keypress_cooldown = keypress_cooldown.saturating_sub(1);
 
-            // Wait until the time slice for the current game tick is elapsed.
-            let now = Instant::now();
-            match now - next_tick_time {
-                d if d < DURATION_PER_TICK => {
-                    thread::sleep(DURATION_PER_TICK - d);
-                }
-                _ => (),
+            // Don't present frames faster than we need to, unless asked to go as fast as possible.
+            // The time slept is accounted for by the game ticks below.
+            let elapsed = last_frame_time.elapsed();
+            if let Some(min_frame_duration) = min_frame_duration.filter(|&d| elapsed < d) {
+                thread::sleep(min_frame_duration - elapsed);
             }
             let now = Instant::now();
+            let elapsed = now - last_frame_time;
+            last_frame_time = now;
 
-            // Get how many ticks we need to run and set next_tick_time to the next tick.
-            let ticks_to_run = if pause {
-                next_tick_time = Instant::now();
+            let ticks_to_run = if pause || scene_menu.is_some() || thread_menu.is_some() {
+                tick_accumulator = Duration::ZERO;
                 0
-            } else if fast_mode {
-                next_tick_time = Instant::now();
-                8
             } else {
-                let mut ticks_to_run = 1;
-                next_tick_time += DURATION_PER_TICK;
-                while now + (DURATION_PER_TICK * ticks_to_run) < next_tick_time {
-                    ticks_to_run += 1;
-                }
-                ticks_to_run
+                ticks_to_run(
+                    &mut tick_accumulator,
+                    elapsed,
+                    duration_per_tick,
+                    max_time_behind,
+                    if fast_mode { FAST_MODE_SPEED } else { 1 },
+                    self.options.deterministic,
+                )
             };
 
-            // If we try to restore a state twice within that cooldown, we will restore the state
-            // before that one instead.
-            const SNAPSHOT_REMOVAL_COOLDOWN: usize = 10;
-            // Update VM state
-            for _ in 0..ticks_to_run {
-                snapshot_cpt += 1;
-
-                if snapshot_cpt == SNAPSHOT_REMOVAL_COOLDOWN {
-                    if let Some(snapshot) = history.front_mut() {
-                        snapshot.just_restored = false;
+            if let Some(watcher) = patch_watcher
+                .as_mut()
+                .filter(|_| last_patch_poll.elapsed() >= PATCH_POLL_PERIOD)
+            {
+                last_patch_poll = Instant::now();
+                let changed = watcher.poll();
+                if !changed.is_empty() {
+                    info!(
+                        "Patched resources {:02x?} changed, restarting scene",
+                        changed
+                    );
+                    interrupt_demo(&mut demo, &mut playback, demo_path.as_deref());
+                    if vm.reload_resources(&changed) {
+                        history.clear();
                     }
                 }
+            }
 
-                if snapshot_cpt == TICKS_PER_SNAPSHOT {
-                    take_snapshot(&mut history, vm, &self.display);
-                    snapshot_cpt = 0;
-                }
+            // Update VM state
+            for _ in 0..ticks_to_run {
+                history.tick(vm, &self.display);
 
                 if ticks_to_wait == 0 {
+                    vm.update_input(input.state_for_round(tick));
                     if let Some(value_of_0xf4) = self.audio_device.take_value_of_0xf4() {
                         vm.set_reg(0xf4, value_of_0xf4);
                     }
-                    if !vm.process_round(&mut self.display, &mut self.audio_device) {
-                        error!("0 threads to run, exiting.");
-                        break 'run;
+                    play_demo_round(&mut playback, vm);
+                    history.record_round(vm);
+                    if let Some(demo) = &mut demo {
+                        demo.record_round(vm);
+                    }
+                    match vm.process_round(&mut self.display, &mut *self.audio_device) {
+                        Ok(true) => (),
+                        Ok(false) => {
+                            error!("0 threads to run, exiting.");
+                            break 'run;
+                        }
+                        Err(e) => {
+                            error!("{}, exiting.", e);
+                            break 'run;
+                        }
+                    }
+
+                    if let Some(checkpoint) = vm.take_checkpoint() {
+                        pending_checkpoint = Some(checkpoint);
+                    }
+                    if vm.frame_presented() {
+                        if let Some(checkpoint) = pending_checkpoint.take() {
+                            save_checkpoint(checkpoint, &self.display);
+                        }
                     }
 
                     ticks_to_wait = vm.get_frames_to_wait();
                 }
                 ticks_to_wait -= 1;
-            }
-
-            fn div_by_screen_ratio(x: u32) -> u32 {
-                x * 5 / 8
-            }
-
-            fn mul_by_screen_ratio(x: u32) -> u32 {
-                x * 8 / 5
+                tick += 1;
             }
 
             // Compute destination rectangle of game screen
-            let viewport = {
+            let viewport_dst = {
                 let (w, h) = self.display.window().drawable_size();
-                Rect::new(0, 0, w, h)
-            };
-            let viewport_dst = if div_by_screen_ratio(viewport.width()) < viewport.height() {
-                let w = viewport.width();
-                let h = div_by_screen_ratio(viewport.width());
-                sdl2::rect::Rect::new(0, (viewport.height() - h) as i32 / 2, w, h)
-            } else {
-                let w = mul_by_screen_ratio(viewport.height());
-                let h = viewport.height();
-                sdl2::rect::Rect::new((viewport.width() - w) as i32 / 2, 0, w, h)
+                let (x, y, w, h) = self.options.aspect.fit(w, h);
+                Rect::new(x, y, w, h)
             };
 
-            self.display.show_game_framebuffer(&viewport_dst);
+            let mut overlay_lines = Vec::new();
+            if self.options.show_overlay && pause {
+                overlay_lines.extend(pause_lines(vm, &*self.audio_device));
+            }
+            if show_perf_hud {
+                overlay_lines.extend(perf_counter.lines());
+            }
+            if show_channels {
+                overlay_lines.extend(channel_lines(&self.audio_device.channel_snapshot()));
+            }
+            if let Some(menu) = &scene_menu {
+                overlay_lines.extend(menu.lines());
+            }
+            if let Some(menu) = &thread_menu {
+                overlay_lines.extend(menu.lines(vm));
+            }
+
+            // Always present, even if no tick has been run, so the display is refreshed as often as
+            // it can.
+            self.display
+                .show_game_framebuffer(&viewport_dst, &overlay_lines);
             self.display.present();
+            perf_counter.frame_presented(vm);
+        }
+
+        save_demo(&mut demo, demo_path.as_deref());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ticks_to_run() {
+        let tick = Duration::from_millis(20);
+        let max_behind = tick * 10;
+        let run = |accumulator: &mut Duration, elapsed, speed, deterministic| {
+            ticks_to_run(accumulator, elapsed, tick, max_behind, speed, deterministic)
+        };
+
+        // The time left after running whole ticks is kept for the next frames.
+        let mut accumulator = Duration::ZERO;
+        assert_eq!(run(&mut accumulator, tick * 5 / 2, 1, false), 2);
+        assert_eq!(accumulator, tick / 2);
+        assert_eq!(run(&mut accumulator, tick / 2, 1, false), 1);
+        assert_eq!(accumulator, Duration::ZERO);
+        assert_eq!(run(&mut accumulator, tick / 2, 1, false), 0);
+
+        // Fast mode runs more ticks, and the game does not catch up with too long delays.
+        let mut accumulator = Duration::ZERO;
+        assert_eq!(run(&mut accumulator, tick, 8, false), 8);
+        assert_eq!(run(&mut accumulator, tick * 100, 1, false), 10);
+
+        // Deterministic runs always run a single tick per frame.
+        let mut accumulator = Duration::ZERO;
+        for elapsed in [Duration::ZERO, tick / 2, tick, tick * 100] {
+            assert_eq!(run(&mut accumulator, elapsed, 1, true), 1);
+            assert_eq!(run(&mut accumulator, elapsed, 8, true), 1);
         }
+        assert_eq!(accumulator, Duration::ZERO);
     }
 }
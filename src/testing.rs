@@ -0,0 +1,126 @@
+//! Helpers for running the VM headlessly in tests.
+//!
+//! [`RasterGameRenderer`] is the reference renderer: it is the closest to the original game, so
+//! the output of other renderers is checked against it.
+
+use crate::audio::NullAudio;
+use crate::gfx::headless::HeadlessGfx;
+use crate::gfx::headless::PageReader;
+use crate::gfx::sw::IndexedImage;
+use crate::gfx::sw::RasterGameRenderer;
+use crate::gfx::{self};
+use crate::res::ResType;
+use crate::res::ResourceManager;
+use crate::scenes;
+use crate::scenes::InitForScene;
+use crate::vm::Vm;
+
+/// Scene the resources of [`test_scene_resources`] are given to.
+pub const TEST_SCENE: usize = 1;
+
+/// Returns a resource manager containing a small handcrafted scene exercising the renderers, so
+/// they can be tested without the game data.
+///
+/// It fills page 1, draws a few polygons into it (including a hierarchy and a transparent one),
+/// copies it to page 2 and displays the latter.
+pub fn test_scene_resources() -> ResourceManager {
+    let scene = &scenes::SCENES[TEST_SCENE];
+
+    let palette = (0..32).map(|i| (i * 8) as u8).collect();
+
+    #[rustfmt::skip]
+    let code = vec![
+        // setpalette 0
+        0x0b, 0x00, 0xff,
+        // selectvideopage 1
+        0x0d, 0x01,
+        // fillvideopage 1, color 2
+        0x0e, 0x01, 0x02,
+        // sprs: square at (160, 100)
+        0x80, 0x00, 0xa0, 0x64,
+        // sprs: hierarchy at (64, 48)
+        0x80, 0x0c, 0x40, 0x30,
+        // sprs: transparent square at (160, 100)
+        0x80, 0x06, 0xa0, 0x64,
+        // copyvideopage 1 -> 2
+        0x0f, 0x01, 0x02,
+        // blitframebuffer 2
+        0x10, 0x02,
+        // killthread
+        0x11,
+    ];
+
+    #[rustfmt::skip]
+    let polygons = vec![
+        // 0x00: 40x40 square of color 5.
+        0xc5, 40, 40, 4, 40, 0, 40, 40, 0, 40, 0, 0,
+        // 0x0c: 20x20 transparent square.
+        0xd0, 20, 20, 4, 20, 0, 20, 20, 0, 20, 0, 0,
+        // 0x18: hierarchy of the first square recolored to 7, and the transparent one.
+        0x02, 10, 10, 1,
+        0x80, 0x00, 0, 0, 0x07, 0x00,
+        0x00, 0x06, 30, 30,
+    ];
+
+    ResourceManager::new_in_memory(vec![
+        (scene.palette, ResType::Palette, palette),
+        (scene.code, ResType::Bytecode, code),
+        (scene.video1, ResType::Cinematic, polygons),
+    ])
+}
+
+/// Checks performed on the VM after each round run by [`run_scene_with_probe`].
+///
+/// This allows tests to assert e.g. that a register has a given value at a given round, without
+/// having to drive the VM themselves.
+pub trait VmProbe {
+    /// Called after the VM has completed its `round`-th round, counting from 1.
+    fn on_round(&mut self, vm: &Vm, round: u64);
+}
+
+impl<F: FnMut(&Vm, u64)> VmProbe for F {
+    fn on_round(&mut self, vm: &Vm, round: u64) {
+        self(vm, round)
+    }
+}
+
+/// Run `scene` from `resman` for `rounds` rounds, rendering with `renderer`.
+///
+/// Returns the VM and renderer in their final state.
+pub fn run_scene<R: gfx::GameRenderer + PageReader + InitForScene + Clone + 'static>(
+    renderer: R,
+    resman: ResourceManager,
+    scene: usize,
+    rounds: usize,
+) -> (Vm, R) {
+    run_scene_with_probe(renderer, resman, scene, rounds, &mut |_: &Vm, _| {})
+}
+
+/// Same as [`run_scene`], but calls `probe` after each round.
+pub fn run_scene_with_probe<R: gfx::GameRenderer + PageReader + InitForScene + Clone + 'static>(
+    renderer: R,
+    resman: ResourceManager,
+    scene: usize,
+    rounds: usize,
+    probe: &mut dyn VmProbe,
+) -> (Vm, R) {
+    let mut vm = Vm::new_with_resman(resman);
+    let mut gfx = HeadlessGfx::new(renderer);
+
+    vm.request_scene(scene);
+    for round in 1..=rounds as u64 {
+        vm.process_round(&mut gfx, &mut NullAudio).unwrap();
+        probe.on_round(&vm, round);
+    }
+
+    (vm, gfx.renderer)
+}
+
+/// Run `scene` from `resman` for `rounds` rounds using the reference renderer, and return the
+/// page being displayed at the end.
+pub fn render_scene_to_image(resman: ResourceManager, scene: usize, rounds: usize) -> IndexedImage {
+    let (vm, renderer) = run_scene(RasterGameRenderer::new(), resman, scene, rounds);
+    let image = renderer.get_buffer(vm.front_buffer()).clone();
+
+    image
+}
@@ -1,6 +1,12 @@
 #![allow(dead_code)]
 
+mod builder;
+mod checkpoint;
+mod demo;
+mod disasm;
 mod ops;
+mod state_file;
+mod trace;
 
 use std::any::Any;
 use std::fmt;
@@ -8,12 +14,16 @@ use std::io::Cursor;
 use std::io::Result;
 use std::io::Seek;
 use std::io::SeekFrom;
-use std::mem::transmute;
-use std::mem::MaybeUninit;
 
+use tracing::error;
 use tracing::info;
+use tracing::warn;
 
+pub use self::builder::VmBuilder;
+pub use self::checkpoint::Checkpoint;
+pub use self::demo::Demo;
 use self::ops::*;
+pub use self::trace::ThreadTrace;
 use crate::audio;
 use crate::gfx;
 use crate::gfx::Palette;
@@ -29,8 +39,15 @@ use byteorder::ReadBytesExt;
 
 const VM_NUM_THREADS: usize = 64;
 const VM_NUM_VARIABLES: usize = 256;
+/// Number of rounds after which `Vm::run_until_next_frame` gives up waiting for a frame.
+const MAX_ROUNDS_PER_FRAME: usize = 1000;
 
 const VM_VARIABLE_RANDOM_SEED: u8 = 0x3c; // 60
+/// Value of the random seed register when the game starts, unless another one is given.
+const DEFAULT_RANDOM_SEED: u16 = 0xbeef;
+/// Number of instructions a thread can run in a single round before it is considered stuck,
+/// unless another budget is given. This is far more than any thread of the game needs.
+const DEFAULT_INSTRUCTION_BUDGET: usize = 1_000_000;
 const VM_VARIABLE_LAST_KEYCHAR: u8 = 0xda; // 218
 const VM_VARIABLE_HERO_POS_UPDOWN: u8 = 0xe5; // 229
 const VM_VARIABLE_SND_SYNC: u8 = 0xf4; // 244
@@ -49,22 +66,53 @@ const VM_VARIABLE_HERO_POS_MASK: u8 = 0xfd; // 253
 const VM_VARIABLE_HERO_ACTION_POS_MASK: u8 = 0xfe; // 254
 const VM_VARIABLE_PAUSE_SLICES: u8 = 0xff; // 255
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 enum ThreadState {
     Inactive,
     Active(u64),
     Paused(u64),
 }
 
-#[derive(Clone)]
+/// A thread of the VM that is not inactive, as returned by [`Vm::threads`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ThreadInfo {
+    pub id: usize,
+    /// Position in the scene's code the thread runs from.
+    pub pc: u64,
+    /// Whether the thread runs, as opposed to being paused.
+    pub enabled: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Thread {
     state: ThreadState,
-    // State to set this thread into for the next cycle.
+    // Position to move this thread to for the next cycle, as `Active(pc)`, or `Inactive` to stop
+    // it. Set by setvec and resetthread.
     requested_state: Option<ThreadState>,
+    // Whether to pause (`true`) or resume this thread for the next cycle. This is independent from
+    // the position of the thread: pausing a thread that has not run yet during this cycle keeps the
+    // position it stops at, and a thread moved by setvec keeps being paused or not. An inactive
+    // thread has no position to pause at, so a pause stays requested until setvec activates it.
+    requested_pause: Option<bool>,
     // Return address for jsr/return ops
     call_stack: Vec<u64>,
 }
 
+impl Thread {
+    /// Returns the state this thread will be in during the next cycle, once the requested changes
+    /// are applied.
+    fn next_state(&self) -> ThreadState {
+        let paused = self
+            .requested_pause
+            .unwrap_or(matches!(self.state, ThreadState::Paused(_)));
+        match self.requested_state.unwrap_or(self.state) {
+            ThreadState::Inactive => ThreadState::Inactive,
+            ThreadState::Active(pc) | ThreadState::Paused(pc) if paused => ThreadState::Paused(pc),
+            ThreadState::Active(pc) | ThreadState::Paused(pc) => ThreadState::Active(pc),
+        }
+    }
+}
+
 // TODO: move into own module?
 // We should be able to replace this state with an earlier state (from the same
 // scene) and have the game catch up painlessly.
@@ -76,6 +124,8 @@ pub struct VmState {
     threads: [Thread; VM_NUM_THREADS],
     // Whether we need to load a new scene during the next cycle.
     requested_scene: Option<usize>,
+    /// Scene which code and resources are currently loaded, if any.
+    scene: Option<usize>,
 
     /// Current target of draw operations.
     render_buffer: usize,
@@ -85,6 +135,43 @@ pub struct VmState {
     front_buffer: usize,
     /// Palette currently in use.
     palette: Palette,
+    /// Whether a frame has been presented during the current round.
+    frame_presented: bool,
+}
+
+impl VmState {
+    /// Create the state of a VM which has not loaded any scene yet, with registers `regs`.
+    fn new(regs: [i16; VM_NUM_VARIABLES]) -> Self {
+        VmState {
+            regs,
+            threads: Vm::init_threads(),
+            requested_scene: None,
+            scene: None,
+            render_buffer: 0,
+            back_buffer: 0,
+            front_buffer: 0,
+            palette: Default::default(),
+            frame_presented: false,
+        }
+    }
+
+    /// Create a state with all registers set to zero and all threads inactive, for testing
+    /// operations in isolation.
+    #[cfg(test)]
+    pub fn test_default() -> Self {
+        Self::new([0; VM_NUM_VARIABLES])
+    }
+}
+
+/// State of the hero's controls as seen by the game, decoded from the VM registers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeroState {
+    pub horizontal: LeftRightDir,
+    pub vertical: UpDownDir,
+    pub action: ButtonState,
+    /// Directions and action combined into the mask used by the game's scripts: right (0x1),
+    /// left (0x2), down (0x4), up (0x8) and action (0x80).
+    pub mask: u8,
 }
 
 pub struct VmSys {
@@ -99,7 +186,7 @@ impl InitForScene for VmSys {
         resman: &ResourceManager,
         scene: &scenes::Scene,
     ) -> std::io::Result<()> {
-        self.palette = resman.load_resource(scene.palette)?.data;
+        self.palette = resman.load_resource(scene.palette)?.into_data();
 
         Ok(())
     }
@@ -116,7 +203,7 @@ impl InitForScene for VmCode {
         resman: &ResourceManager,
         scene: &scenes::Scene,
     ) -> std::io::Result<()> {
-        self.code = resman.load_resource(scene.code)?.data;
+        self.code = resman.load_resource(scene.code)?.into_data();
 
         Ok(())
     }
@@ -141,12 +228,80 @@ impl VmCode {
     }
 }
 
+/// Defines [`opcode_name`] and [`opcode_span`] from a single table of opcode patterns and
+/// instruction names, so the two cannot disagree.
+///
+/// Span names must be static, hence the need for one invocation of `trace_span!` per opcode.
+macro_rules! opcodes {
+    ($($pattern:pat => $name:literal,)*) => {
+        /// Returns the name of the instruction `opcode` encodes.
+        pub fn opcode_name(opcode: u8) -> &'static str {
+            match opcode {
+                $($pattern => $name,)*
+                _ => "unknown",
+            }
+        }
+
+        /// Returns a trace-level span named after `opcode`, so profiling traces show how much
+        /// time is spent in each kind of instruction.
+        fn opcode_span(opcode: u8) -> tracing::Span {
+            match opcode {
+                $($pattern => tracing::trace_span!(concat!("op_", $name)),)*
+                _ => tracing::trace_span!("op_unknown", opcode),
+            }
+        }
+    };
+}
+
+opcodes! {
+    0x80..=0xff => "sprs",
+    0x40..=0x7f => "sprl",
+    0x00 => "seti",
+    0x01 => "set",
+    0x02 => "add",
+    0x03 => "addi",
+    0x04 => "jsr",
+    0x05 => "return",
+    0x06 => "break",
+    0x07 => "jmp",
+    0x08 => "setvec",
+    0x09 => "jnz",
+    0x0a => "condjmp",
+    0x0b => "setpalette",
+    0x0c => "resetthread",
+    0x0d => "selectvideopage",
+    0x0e => "fillvideopage",
+    0x0f => "copyvideopage",
+    0x10 => "blitframebuffer",
+    0x11 => "killthread",
+    0x12 => "drawstring",
+    0x13 => "sub",
+    0x14 => "and",
+    0x15 => "or",
+    0x16 => "shl",
+    0x17 => "shr",
+    0x18 => "playsound",
+    0x19 => "loadresource",
+    0x1a => "playmusic",
+}
+
 pub struct Vm {
     state: VmState,
     code: VmCode,
     sys: VmSys,
     resman: ResourceManager,
     round: u64,
+    /// Checkpoint taken when the game last moved to a new scene, until it is retrieved.
+    checkpoint: Option<Checkpoint>,
+    /// Whether the requested scene has been asked for from outside the game, like when starting
+    /// it or jumping to another scene. No checkpoint is taken when loading such a scene.
+    scene_requested_by_host: bool,
+    /// Number of instructions after which a thread that did not yield is stopped for the round.
+    instruction_budget: usize,
+    /// Number of times each opcode has been run since the VM started.
+    opcode_counts: Box<[u64; 256]>,
+    /// Log of the instructions run by a thread, if one is being traced.
+    thread_trace: Option<ThreadTrace>,
 }
 
 pub struct VmSnapshot {
@@ -164,10 +319,46 @@ impl VmSnapshot {
     }
 
     /// Restore a previously captured snapshot into `vm` and `gfx`.
+    ///
+    /// If the snapshot has been taken during another scene than the current one, the code and
+    /// resources of that scene are reloaded first, as the restored state would be meaningless
+    /// without them.
     pub fn restore<G: gfx::Gfx + ?Sized>(&self, vm: &mut Vm, gfx: &mut G) {
+        if let Some(scene) = self.vm_state.scene.filter(|&s| vm.state.scene != Some(s)) {
+            info!("Snapshot is from scene {}, reloading it", scene);
+            if let Err(e) = vm.load_scene(scene, gfx) {
+                error!("Failed to reload scene {}: {}", scene, e);
+            }
+        }
+
         vm.restore_snapshot(&self.vm_state);
         gfx.restore_snapshot(&self.gfx_state);
     }
+
+    /// Restore this snapshot, then run one round from each of `rounds`, the states of the VM
+    /// before each round played since the snapshot was taken.
+    ///
+    /// This brings `gfx` back to the state it had after the last of `rounds`, which is cheaper
+    /// than snapshotting it every round. The VM is forced into each recorded state, so the
+    /// rounds play exactly like they originally did whatever the input.
+    pub fn restore_and_replay<
+        G: gfx::Gfx + ?Sized,
+        A: audio::Mixer + audio::MusicPlayer + ?Sized,
+    >(
+        &self,
+        rounds: &[VmState],
+        vm: &mut Vm,
+        gfx: &mut G,
+        audio: &mut A,
+    ) -> Result<()> {
+        self.restore(vm, gfx);
+        for state in rounds {
+            vm.restore_snapshot(state);
+            vm.process_round(gfx, audio)?;
+        }
+
+        Ok(())
+    }
 }
 
 impl fmt::Debug for Vm {
@@ -178,50 +369,73 @@ impl fmt::Debug for Vm {
             }
             write!(f, "{:04x?}, ", r)?;
         }
+        writeln!(f)?;
+
+        // Threads that are not inactive, with their program counter.
+        for (i, thread) in self.state.threads.iter().enumerate() {
+            match thread.state {
+                ThreadState::Inactive => (),
+                ThreadState::Active(pc) => writeln!(f, "thread {:02}: active at 0x{:04x}", i, pc)?,
+                ThreadState::Paused(pc) => writeln!(f, "thread {:02}: paused at 0x{:04x}", i, pc)?,
+            }
+        }
         Ok(())
     }
 }
 
 impl Vm {
     fn init_threads() -> [Thread; VM_NUM_THREADS] {
-        // Really surprised that there is no better way of doing this. Since
-        // Vec is not copyable, we cannot initialize the threads array using
-        // the [Thread {...}; VM_NUM_THREADS] syntax. So we leave that area
-        // uninitialized and iterate over it to initialize the threads.
-        let mut threads: [MaybeUninit<Thread>; VM_NUM_THREADS] =
-            unsafe { MaybeUninit::uninit().assume_init() };
-        for thread in threads.iter_mut() {
-            *thread = MaybeUninit::new(Thread {
-                state: ThreadState::Inactive,
-                requested_state: None,
-                call_stack: Vec::new(),
-            });
-        }
-        unsafe { transmute::<_, [Thread; VM_NUM_THREADS]>(threads) }
+        // Since Vec is not copyable, we cannot initialize the threads array using the
+        // [Thread {...}; VM_NUM_THREADS] syntax. This used to be worked around with an array of
+        // `MaybeUninit` transmuted once filled, but `from_fn` builds each thread in place without
+        // any unsafe code.
+        std::array::from_fn(|_| Thread {
+            state: ThreadState::Inactive,
+            requested_state: None,
+            requested_pause: None,
+            call_stack: Vec::new(),
+        })
     }
 
+    /// Create a new VM with the game data and strings of the current directory.
     pub fn new() -> Result<Vm> {
+        VmBuilder::new().build()
+    }
+
+    /// Create a new VM which loads its resources from `resman`.
+    pub fn new_with_resman(resman: ResourceManager) -> Vm {
+        Self::from_parts(
+            resman,
+            strings::load_strings(strings::DEFAULT_LANG).unwrap_or_default(),
+            DEFAULT_RANDOM_SEED,
+            DEFAULT_INSTRUCTION_BUDGET,
+        )
+    }
+
+    fn from_parts(
+        resman: ResourceManager,
+        strings: GameStrings,
+        seed: u16,
+        instruction_budget: usize,
+    ) -> Vm {
         let mut regs = [0; VM_NUM_VARIABLES];
-        Self::set_regs_initial_values(&mut regs);
-
-        Ok(Vm {
-            state: VmState {
-                regs,
-                threads: Vm::init_threads(),
-                requested_scene: None,
-                render_buffer: 0,
-                back_buffer: 0,
-                front_buffer: 0,
-                palette: Default::default(),
-            },
+        Self::set_regs_initial_values(&mut regs, seed);
+
+        Vm {
+            state: VmState::new(regs),
             code: VmCode::new(Vec::new()),
             sys: VmSys {
                 palette: Vec::new(),
-                strings: strings::load_strings().unwrap_or_default(),
+                strings,
             },
-            resman: ResourceManager::new()?,
+            resman,
             round: 0,
-        })
+            checkpoint: None,
+            scene_requested_by_host: false,
+            instruction_budget,
+            opcode_counts: Box::new([0; 256]),
+            thread_trace: None,
+        }
     }
 
     pub fn get_reg(&self, i: u8) -> i16 {
@@ -241,30 +455,45 @@ impl Vm {
         audio: &mut A,
     ) {
         let mut cursor = self.code.get_cursor(pc);
+        let mut budget = self.instruction_budget;
+        let mut trace = self
+            .thread_trace
+            .as_mut()
+            .filter(|trace| trace.traces(cur_thread));
 
         loop {
+            if let Some(trace) = trace.as_mut() {
+                trace.log_changes(&self.state.regs);
+            }
+
+            // A thread that never yields would hang the game: stop it where it is, and let it
+            // continue from there next round.
+            if budget == 0 {
+                warn!(
+                    "thread {} ran {} instructions without yielding, breaking it at 0x{:04x}",
+                    cur_thread,
+                    self.instruction_budget,
+                    cursor.position()
+                );
+                op_break(&mut self.state.threads[cur_thread], &mut cursor);
+                break;
+            }
+            budget -= 1;
+
+            if let Some(trace) = trace.as_mut() {
+                trace.log_instruction(
+                    self.round,
+                    cursor.get_ref(),
+                    cursor.position(),
+                    &self.state.regs,
+                );
+            }
             let opcode = cursor.read_u8().unwrap();
+            let _span = opcode_span(opcode).entered();
+            self.opcode_counts[opcode as usize] += 1;
 
             // State op - change the current state.
-            type StateOp = fn(u8, &mut Cursor<&[u8]>, &mut VmState) -> bool;
-            let op: Option<StateOp> = match opcode {
-                0x00 => Some(op_seti),
-                0x01 => Some(op_set),
-                0x02 => Some(op_add),
-                0x03 => Some(op_addi),
-                0x07 => Some(op_jmp),
-                0x08 => Some(op_setvec),
-                0x09 => Some(op_jnz),
-                0x0a => Some(op_condjmp),
-                0x0c => Some(op_resetthread),
-                0x13 => Some(op_sub),
-                0x14 => Some(op_and),
-                0x15 => Some(op_or),
-                0x16 => Some(op_shl),
-                0x17 => Some(op_shr),
-                _ => None,
-            };
-            if let Some(op) = op {
+            if let Some(op) = state_op(opcode) {
                 if op(opcode, &mut cursor, &mut self.state) {
                     break;
                 } else {
@@ -344,6 +573,10 @@ impl Vm {
 
             panic!("Unknown opcode {:02x}!", opcode);
         }
+
+        if let Some(trace) = trace {
+            trace.log_changes(&self.state.regs);
+        }
     }
 
     pub fn update_input(&mut self, input: &InputState) {
@@ -400,35 +633,75 @@ impl Vm {
         self.set_reg(VM_VARIABLE_HERO_ACTION_POS_MASK, mask);
     }
 
+    /// Returns the state of the hero's controls, as last set by [`Vm::update_input`] or the
+    /// game itself.
+    pub fn hero_state(&self) -> HeroState {
+        HeroState {
+            horizontal: match self.get_reg(VM_VARIABLE_HERO_POS_LEFT_RIGHT) {
+                v if v < 0 => LeftRightDir::Left,
+                0 => LeftRightDir::Neutral,
+                _ => LeftRightDir::Right,
+            },
+            vertical: match self.get_reg(VM_VARIABLE_HERO_POS_UPDOWN) {
+                v if v < 0 => UpDownDir::Up,
+                0 => UpDownDir::Neutral,
+                _ => UpDownDir::Down,
+            },
+            action: match self.get_reg(VM_VARIABLE_HERO_ACTION) {
+                0 => ButtonState::Released,
+                _ => ButtonState::Pushed,
+            },
+            mask: self.get_reg(VM_VARIABLE_HERO_ACTION_POS_MASK) as u8,
+        }
+    }
+
     fn process_step<G: gfx::Gfx + ?Sized, A: audio::Mixer + audio::MusicPlayer + ?Sized>(
         &mut self,
         gfx: &mut G,
         audio: &mut A,
-    ) -> usize {
+    ) -> Result<usize> {
         // Check if we need to switch to a new part of the game.
         if let Some(requested_scene) = self.state.requested_scene.take() {
             info!("Loading scene {}", requested_scene);
-            let scene = &scenes::SCENES[requested_scene];
-            self.code.init_from_scene(&self.resman, scene).unwrap();
-            self.sys.init_from_scene(&self.resman, scene).unwrap();
-            gfx.init_from_scene(&self.resman, scene).unwrap();
+            self.load_scene(requested_scene, gfx).map_err(|e| {
+                std::io::Error::new(
+                    e.kind(),
+                    format!("failed to load scene {}: {}", requested_scene, e),
+                )
+            })?;
             audio.reset();
+            if !std::mem::take(&mut self.scene_requested_by_host) {
+                self.checkpoint = Some(Checkpoint {
+                    scene: requested_scene,
+                    regs: self.state.regs,
+                    thumbnail: None,
+                });
+            }
 
             // Reset all threads
             self.state.threads = Vm::init_threads();
             self.state.threads[0].state = ThreadState::Active(0);
         }
 
+        self.state.frame_presented = false;
+
         let mut actionable_threads = Vec::<(usize, u64)>::new();
         // Build the list of actionable threads for this round
         for i in 0..VM_NUM_THREADS {
             let thread = &mut self.state.threads[i];
 
-            // First move the requested state (if any) to be current.
-            if let Some(requested_state) = thread.requested_state {
-                thread.state = requested_state;
-                thread.requested_state = None;
-            }
+            // First apply the requested changes (if any).
+            let paused = thread
+                .requested_pause
+                .unwrap_or(matches!(thread.state, ThreadState::Paused(_)));
+            thread.state = thread.next_state();
+            thread.requested_state = None;
+            // Like the original game, keep the pause of threads that are not active yet so they
+            // start paused.
+            thread.requested_pause = match thread.state {
+                ThreadState::Inactive => paused.then_some(true),
+                _ => None,
+            };
 
             if let ThreadState::Active(pc) = thread.state {
                 actionable_threads.push((i, pc));
@@ -441,26 +714,85 @@ impl Vm {
             self.process_thread(thread_id, pc, gfx, audio);
         }
 
-        nb_threads
+        Ok(nb_threads)
     }
 
+    /// Run all the active threads once, loading the requested scene first if there is one.
+    ///
+    /// Returns whether there was any thread to run, or an error if the requested scene could not
+    /// be loaded.
     #[tracing::instrument(level="debug", skip(self, gfx, audio), fields(round = self.round, nb_threads))]
     pub fn process_round<G: gfx::Gfx + ?Sized, A: audio::Mixer + audio::MusicPlayer + ?Sized>(
         &mut self,
         gfx: &mut G,
         audio: &mut A,
-    ) -> bool {
-        let nb_threads = self.process_step(gfx, audio);
+    ) -> Result<bool> {
+        let nb_threads = self.process_step(gfx, audio)?;
         tracing::Span::current().record("nb_threads", nb_threads);
 
         self.round += 1;
-        nb_threads != 0
+        Ok(nb_threads != 0)
     }
 
-    fn set_regs_initial_values(regs: &mut [i16; VM_NUM_VARIABLES]) {
-        // Random seed
-        // TODO: make actually random...
-        regs[VM_VARIABLE_RANDOM_SEED as usize] = 0xbeefu16 as i16;
+    /// Run rounds until one presents a frame, so the game can be stepped through one displayed
+    /// frame at a time. Gives up after `MAX_ROUNDS_PER_FRAME` rounds.
+    ///
+    /// Returns whether there are still threads to run, or an error if a scene could not be
+    /// loaded.
+    pub fn run_until_next_frame<
+        G: gfx::Gfx + ?Sized,
+        A: audio::Mixer + audio::MusicPlayer + ?Sized,
+    >(
+        &mut self,
+        gfx: &mut G,
+        audio: &mut A,
+    ) -> Result<bool> {
+        for _ in 0..MAX_ROUNDS_PER_FRAME {
+            if let Some(value_of_0xf4) = audio.take_value_of_0xf4() {
+                self.set_reg(VM_VARIABLE_SND_SYNC, value_of_0xf4);
+            }
+            if !self.process_round(gfx, audio)? {
+                return Ok(false);
+            }
+            if self.frame_presented() {
+                break;
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Load the code and resources of `scene`, and pass them to `gfx`.
+    #[tracing::instrument(skip(self, gfx))]
+    fn load_scene<G: gfx::Gfx + ?Sized>(&mut self, scene_id: usize, gfx: &mut G) -> Result<()> {
+        let scene = &scenes::SCENES[scene_id];
+        self.code.init_from_scene(&self.resman, scene)?;
+        self.sys.init_from_scene(&self.resman, scene)?;
+        gfx.init_from_scene(&self.resman, scene)?;
+        self.state.scene = Some(scene_id);
+
+        // Unpacked size of the resources, as declared by the resources list.
+        let size = |index: usize| self.resman.resource_info(index).map(|info| info.size);
+        let video2 = (scene.video2 != 0).then_some(scene.video2);
+        info!(
+            scene = scene_id,
+            name = scene.name,
+            palette = scene.palette,
+            palette_size = size(scene.palette),
+            code = scene.code,
+            code_size = size(scene.code),
+            video1 = scene.video1,
+            video1_size = size(scene.video1),
+            video2,
+            video2_size = video2.and_then(size),
+            "Scene loaded"
+        );
+
+        Ok(())
+    }
+
+    fn set_regs_initial_values(regs: &mut [i16; VM_NUM_VARIABLES], seed: u16) {
+        regs[VM_VARIABLE_RANDOM_SEED as usize] = seed as i16;
 
         // Seems to be necessary for scene 2.
         regs[0xbc] = 0x10;
@@ -479,11 +811,129 @@ impl Vm {
         self.set_reg(0xe4, 0x14);
 
         self.state.requested_scene = Some(scene);
+        self.scene_requested_by_host = true;
+    }
+
+    /// Load the current scene again from scratch during the next round, resetting its threads.
+    ///
+    /// Returns `false` if no scene is loaded yet.
+    pub fn restart_scene(&mut self) -> bool {
+        match self.state.scene {
+            Some(scene) => {
+                self.request_scene(scene);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the checkpoint taken when the game moved to the current scene, if it has not been
+    /// retrieved yet. Scenes loaded at the request of the host (see [`Vm::request_scene`]) do not
+    /// take checkpoints.
+    pub fn take_checkpoint(&mut self) -> Option<Checkpoint> {
+        self.checkpoint.take()
+    }
+
+    /// Resume the game from `checkpoint`. Its scene is loaded during the next round.
+    pub fn restore_checkpoint(&mut self, checkpoint: &Checkpoint) {
+        self.state.regs = checkpoint.regs;
+        self.state.requested_scene = Some(checkpoint.scene);
+        self.scene_requested_by_host = true;
+    }
+
+    /// Make the next loads of resources `indices` read them again, and restart the current scene so
+    /// the new versions are used.
+    ///
+    /// Returns `false` if no scene is loaded yet.
+    pub fn reload_resources(&mut self, indices: &[usize]) -> bool {
+        for &index in indices {
+            self.resman.invalidate(index);
+        }
+        self.restart_scene()
+    }
+
+    /// Set the level of graphics detail the game's scripts are asked to draw: 0 for maximum
+    /// details, 1 to remove reflections.
+    ///
+    /// This is only a hint given to the scripts through a register, which is saved in snapshots
+    /// along with the others.
+    pub fn set_gfx_detail(&mut self, level: u8) {
+        self.set_reg(VM_VARIABLE_GFX_DETAIL, level as i16);
     }
 
     pub fn get_frames_to_wait(&self) -> usize {
         self.get_reg(VM_VARIABLE_PAUSE_SLICES) as usize
     }
+
+    /// Returns the scene currently loaded, if any.
+    pub fn scene(&self) -> Option<usize> {
+        self.state.scene
+    }
+
+    /// Returns the number of rounds processed since the VM started.
+    pub fn round(&self) -> u64 {
+        self.round
+    }
+
+    /// Returns whether the last round presented a frame.
+    pub fn frame_presented(&self) -> bool {
+        self.state.frame_presented
+    }
+
+    /// Returns the threads that are not inactive, in the state they will have during the next
+    /// round.
+    pub fn threads(&self) -> Vec<ThreadInfo> {
+        self.state
+            .threads
+            .iter()
+            .enumerate()
+            .filter_map(|(id, thread)| match thread.next_state() {
+                ThreadState::Inactive => None,
+                ThreadState::Active(pc) => Some(ThreadInfo {
+                    id,
+                    pc,
+                    enabled: true,
+                }),
+                ThreadState::Paused(pc) => Some(ThreadInfo {
+                    id,
+                    pc,
+                    enabled: false,
+                }),
+            })
+            .collect()
+    }
+
+    /// Pause or resume thread `id` from the next round, as the `resetthread` instruction does.
+    /// The game's code can still resume or pause it afterwards.
+    ///
+    /// Returns `false` if the thread does not exist or is inactive, in which case it cannot be
+    /// resumed as it has nothing to run.
+    pub fn set_thread_enabled(&mut self, id: usize, enabled: bool) -> bool {
+        let Some(thread) = self.state.threads.get_mut(id) else {
+            return false;
+        };
+        if thread.next_state() == ThreadState::Inactive {
+            return false;
+        }
+        thread.requested_pause = Some(!enabled);
+
+        true
+    }
+
+    /// Returns how many times each opcode has been run since the VM started, indexed by opcode.
+    pub fn opcode_histogram(&self) -> [u64; 256] {
+        *self.opcode_counts
+    }
+
+    /// Returns the page currently on display.
+    pub fn front_buffer(&self) -> usize {
+        self.state.front_buffer
+    }
+
+    /// Returns the palette currently in use.
+    pub fn palette(&self) -> &Palette {
+        &self.state.palette
+    }
 }
 
 impl Snapshotable for Vm {
@@ -498,3 +948,633 @@ impl Snapshotable for Vm {
         true
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gfx::headless::PageReader;
+    use crate::gfx::PolySegment;
+    use crate::res::ResType;
+    use crate::testing;
+
+    /// A `Gfx` that draws nothing and only remembers the color of the last page fill, for tests
+    /// which only care about the VM.
+    #[derive(Default, Clone)]
+    struct NullGfx {
+        last_fill: Option<u8>,
+    }
+
+    impl gfx::GameRenderer for NullGfx {
+        fn fillvideopage(&mut self, _page_id: usize, color_idx: u8) {
+            self.last_fill = Some(color_idx);
+        }
+        fn copyvideopage(&mut self, _src_page_id: usize, _dst_page_id: usize, _vscroll: i16) {}
+        fn draw_polygons(
+            &mut self,
+            _segment: PolySegment,
+            _start_offset: u16,
+            _dst_page_id: usize,
+            _pos: (i16, i16),
+            _offset: (i16, i16),
+            _zoom: u16,
+        ) {
+        }
+        fn draw_char(&mut self, _dst_page_id: usize, _pos: (i16, i16), _color_idx: u8, _c: u8) {}
+        fn blit_buffer(&mut self, _dst_page_id: usize, _buffer: &[u8]) {}
+    }
+
+    impl PageReader for NullGfx {}
+
+    impl gfx::Display for NullGfx {
+        fn blitframebuffer(&mut self, _page_id: usize, _palette: &Palette) {}
+    }
+
+    impl Snapshotable for NullGfx {
+        type State = Box<dyn Any>;
+
+        fn take_snapshot(&self) -> Self::State {
+            Box::new(self.clone())
+        }
+
+        fn restore_snapshot(&mut self, snapshot: &Self::State) -> bool {
+            match snapshot.downcast_ref::<NullGfx>() {
+                Some(state) => {
+                    *self = state.clone();
+                    true
+                }
+                None => false,
+            }
+        }
+    }
+
+    impl InitForScene for NullGfx {
+        fn init_from_scene(
+            &mut self,
+            _resman: &ResourceManager,
+            _scene: &scenes::Scene,
+        ) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl gfx::Gfx for NullGfx {}
+
+    #[test]
+    fn test_init_threads() {
+        let threads = Vm::init_threads();
+        assert_eq!(threads.len(), VM_NUM_THREADS);
+        assert!(threads
+            .iter()
+            .all(|t| matches!(t.state, ThreadState::Inactive)
+                && t.requested_state.is_none()
+                && t.requested_pause.is_none()
+                && t.call_stack.is_empty()));
+    }
+
+    #[test]
+    fn test_restore_snapshot_from_other_scene() {
+        let resources = [1, 2]
+            .into_iter()
+            .flat_map(|i| {
+                let scene = &scenes::SCENES[i];
+                [
+                    (scene.palette, ResType::Palette, vec![i as u8; 2048]),
+                    (scene.code, ResType::Bytecode, vec![i as u8; 16]),
+                ]
+            })
+            .collect();
+        let mut vm = Vm::new_with_resman(ResourceManager::new_in_memory(resources));
+        let mut gfx = NullGfx::default();
+
+        vm.load_scene(1, &mut gfx).unwrap();
+        let snapshot = VmSnapshot::new(&vm, &gfx);
+        vm.load_scene(2, &mut gfx).unwrap();
+        assert_eq!(vm.scene(), Some(2));
+        assert_eq!(vm.code.code, vec![2; 16]);
+
+        // Restoring the snapshot must bring back the code of the scene it was taken in.
+        snapshot.restore(&mut vm, &mut gfx);
+        assert_eq!(vm.scene(), Some(1));
+        assert_eq!(vm.code.code, vec![1; 16]);
+        assert_eq!(vm.sys.palette, vec![1; 2048]);
+    }
+
+    #[test]
+    fn test_process_round_missing_scene() {
+        let mut vm = Vm::new_with_resman(ResourceManager::new_in_memory(vec![]));
+        vm.request_scene(1);
+
+        let err = vm
+            .process_round(&mut NullGfx::default(), &mut audio::NullAudio)
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("failed to load scene 1"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_run_until_next_frame() {
+        let scene = &scenes::SCENES[1];
+        #[rustfmt::skip]
+        let code = vec![
+            // break, break
+            0x06, 0x06,
+            // blitframebuffer 0
+            0x10, 0x00,
+            // killthread
+            0x11,
+        ];
+        let mut vm = Vm::new_with_resman(ResourceManager::new_in_memory(vec![
+            (scene.palette, ResType::Palette, vec![0; 2048]),
+            (scene.code, ResType::Bytecode, code),
+        ]));
+        vm.request_scene(1);
+
+        assert!(vm
+            .run_until_next_frame(&mut NullGfx::default(), &mut audio::NullAudio)
+            .unwrap());
+        assert!(vm.frame_presented());
+        assert_eq!(vm.round(), 3);
+
+        // No thread left to present another frame.
+        assert!(!vm
+            .run_until_next_frame(&mut NullGfx::default(), &mut audio::NullAudio)
+            .unwrap());
+        assert!(!vm.frame_presented());
+    }
+
+    #[test]
+    fn test_pause_inactive_thread() {
+        let scene = &scenes::SCENES[1];
+        #[rustfmt::skip]
+        let code = vec![
+            // resetthread 1..=1, pause
+            0x0c, 0x01, 0x01, 0x01,
+            // break
+            0x06,
+            // setvec 1, 0x000b
+            0x08, 0x01, 0x00, 0x0b,
+            // break
+            0x06,
+            // killthread
+            0x11,
+            // Code of thread 1: killthread
+            0x11,
+        ];
+        let mut vm = Vm::new_with_resman(ResourceManager::new_in_memory(vec![
+            (scene.palette, ResType::Palette, vec![0; 2048]),
+            (scene.code, ResType::Bytecode, code),
+        ]));
+        vm.request_scene(1);
+
+        for _ in 0..3 {
+            vm.process_round(&mut NullGfx::default(), &mut audio::NullAudio)
+                .unwrap();
+        }
+
+        // Thread 1 was paused before being moved, so it does not run once activated.
+        assert_eq!(vm.state.threads[1].state, ThreadState::Paused(0x0b));
+    }
+
+    #[test]
+    fn test_opcode_histogram() {
+        let scene = &scenes::SCENES[1];
+        #[rustfmt::skip]
+        let code = vec![
+            // break, break
+            0x06, 0x06,
+            // blitframebuffer 0
+            0x10, 0x00,
+            // killthread
+            0x11,
+        ];
+        let mut vm = Vm::new_with_resman(ResourceManager::new_in_memory(vec![
+            (scene.palette, ResType::Palette, vec![0; 2048]),
+            (scene.code, ResType::Bytecode, code),
+        ]));
+        vm.request_scene(1);
+
+        for _ in 0..3 {
+            vm.process_round(&mut NullGfx::default(), &mut audio::NullAudio)
+                .unwrap();
+        }
+
+        let histogram = vm.opcode_histogram();
+        assert_eq!(histogram[0x06], 2);
+        assert_eq!(histogram[0x10], 1);
+        assert_eq!(histogram[0x11], 1);
+        assert_eq!(histogram.iter().sum::<u64>(), 4);
+    }
+
+    #[test]
+    fn test_opcode_name() {
+        assert_eq!(opcode_name(0x00), "seti");
+        assert_eq!(opcode_name(0x10), "blitframebuffer");
+        assert_eq!(opcode_name(0x1a), "playmusic");
+        assert_eq!(opcode_name(0x1b), "unknown");
+        assert_eq!(opcode_name(0x3f), "unknown");
+        assert_eq!(opcode_name(0x40), "sprl");
+        assert_eq!(opcode_name(0x7f), "sprl");
+        assert_eq!(opcode_name(0x80), "sprs");
+        assert_eq!(opcode_name(0xff), "sprs");
+    }
+
+    #[test]
+    fn test_fillvideopage_color_range() {
+        let scene = &scenes::SCENES[1];
+        #[rustfmt::skip]
+        let code = vec![
+            // fillvideopage 1, color 0x1c
+            0x0e, 0x01, 0x1c,
+            // killthread
+            0x11,
+        ];
+        let mut vm = Vm::new_with_resman(ResourceManager::new_in_memory(vec![
+            (scene.palette, ResType::Palette, vec![0; 2048]),
+            (scene.code, ResType::Bytecode, code),
+        ]));
+        vm.request_scene(1);
+
+        let mut gfx = NullGfx::default();
+        vm.process_round(&mut gfx, &mut audio::NullAudio).unwrap();
+        assert_eq!(gfx.last_fill, Some(0xc));
+    }
+
+    #[test]
+    fn test_instruction_budget() {
+        let scene = &scenes::SCENES[1];
+        #[rustfmt::skip]
+        let code = vec![
+            // jmp 0x0000
+            0x07, 0x00, 0x00,
+        ];
+        let mut vm = VmBuilder::new()
+            .resources(ResourceManager::new_in_memory(vec![
+                (scene.palette, ResType::Palette, vec![0; 2048]),
+                (scene.code, ResType::Bytecode, code),
+            ]))
+            .instruction_budget(100)
+            .build()
+            .unwrap();
+        vm.request_scene(1);
+
+        // The thread is stopped instead of hanging, and resumes from where it was next round.
+        for _ in 0..2 {
+            assert!(vm
+                .process_round(&mut NullGfx::default(), &mut audio::NullAudio)
+                .unwrap());
+            assert_eq!(vm.state.threads[0].state, ThreadState::Active(0));
+        }
+    }
+
+    #[test]
+    fn test_hero_state() {
+        let mut vm = Vm::new_with_resman(ResourceManager::new_in_memory(vec![]));
+        vm.update_input(&InputState {
+            horizontal: LeftRightDir::Left,
+            vertical: UpDownDir::Down,
+            button: ButtonState::Pushed,
+        });
+
+        assert_eq!(
+            vm.hero_state(),
+            HeroState {
+                horizontal: LeftRightDir::Left,
+                vertical: UpDownDir::Down,
+                action: ButtonState::Pushed,
+                mask: 0x86,
+            }
+        );
+
+        vm.update_input(&InputState::new());
+        assert_eq!(
+            vm.hero_state(),
+            HeroState {
+                horizontal: LeftRightDir::Neutral,
+                vertical: UpDownDir::Neutral,
+                action: ButtonState::Released,
+                mask: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_debug_dump() {
+        let mut vm = Vm::new_with_resman(ResourceManager::new_in_memory(vec![]));
+        vm.set_reg(0x10, 0x1234);
+        vm.state.threads[0].state = ThreadState::Active(0x42);
+        vm.state.threads[3].state = ThreadState::Paused(0x100);
+
+        let dump = format!("{:?}", vm);
+        let lines = dump.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), VM_NUM_VARIABLES / 16 + 2);
+        assert!(lines[1].starts_with("1234, "));
+        assert_eq!(lines[16], "thread 00: active at 0x0042");
+        assert_eq!(lines[17], "thread 03: paused at 0x0100");
+    }
+
+    #[test]
+    fn test_gfx_detail() {
+        let mut vm = Vm::new_with_resman(ResourceManager::new_in_memory(vec![]));
+        assert_eq!(vm.get_reg(VM_VARIABLE_GFX_DETAIL), 0);
+
+        vm.set_gfx_detail(1);
+        let snapshot = vm.take_snapshot();
+        vm.set_gfx_detail(0);
+        vm.restore_snapshot(&snapshot);
+        assert_eq!(vm.get_reg(VM_VARIABLE_GFX_DETAIL), 1);
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let mut vm = Vm::new_with_resman(testing::test_scene_resources());
+        vm.set_reg(0x10, 1234);
+        // Scenes requested from outside the game do not take checkpoints.
+        vm.request_scene(testing::TEST_SCENE);
+        vm.process_round(&mut NullGfx::default(), &mut audio::NullAudio)
+            .unwrap();
+        assert_eq!(vm.take_checkpoint(), None);
+
+        // Like the `loadresource` instruction does when the game moves to another scene.
+        vm.state.requested_scene = Some(testing::TEST_SCENE);
+        vm.process_round(&mut NullGfx::default(), &mut audio::NullAudio)
+            .unwrap();
+        let checkpoint = vm.take_checkpoint().unwrap();
+        assert_eq!(checkpoint.scene(), testing::TEST_SCENE);
+        assert_eq!(vm.take_checkpoint(), None);
+
+        // Continuing from the checkpoint restarts its scene with the registers it had.
+        let mut vm = Vm::new_with_resman(testing::test_scene_resources());
+        vm.restore_checkpoint(&checkpoint);
+        vm.process_round(&mut NullGfx::default(), &mut audio::NullAudio)
+            .unwrap();
+        assert_eq!(vm.scene(), Some(testing::TEST_SCENE));
+        assert_eq!(vm.get_reg(0x10), 1234);
+    }
+
+    #[test]
+    fn test_restart_scene() {
+        let mut vm = Vm::new_with_resman(testing::test_scene_resources());
+        assert!(!vm.restart_scene());
+
+        vm.request_scene(testing::TEST_SCENE);
+        for _ in 0..3 {
+            vm.process_round(&mut NullGfx::default(), &mut audio::NullAudio)
+                .unwrap();
+        }
+
+        assert!(vm.restart_scene());
+        vm.process_round(&mut NullGfx::default(), &mut audio::NullAudio)
+            .unwrap();
+        assert_eq!(vm.scene(), Some(testing::TEST_SCENE));
+        // The game did not move to the scene by itself, so no checkpoint is taken.
+        assert_eq!(vm.take_checkpoint(), None);
+    }
+
+    #[test]
+    fn test_set_thread_enabled() {
+        let scene = &scenes::SCENES[1];
+        #[rustfmt::skip]
+        let code = vec![
+            // setvec thread 1, 0x0005
+            0x08, 0x01, 0x00, 0x05,
+            // killthread
+            0x11,
+            // add v0, 1
+            0x03, 0x00, 0x00, 0x01,
+            // break
+            0x06,
+            // jmp 0x0005
+            0x07, 0x00, 0x05,
+        ];
+        let mut vm = Vm::new_with_resman(ResourceManager::new_in_memory(vec![
+            (scene.palette, ResType::Palette, vec![0; 2048]),
+            (scene.code, ResType::Bytecode, code),
+        ]));
+        vm.request_scene(1);
+        let round = |vm: &mut Vm| {
+            vm.process_round(&mut NullGfx::default(), &mut audio::NullAudio)
+                .unwrap();
+            vm.get_reg(0)
+        };
+
+        assert!(!vm.set_thread_enabled(1, false));
+        assert_eq!(round(&mut vm), 0);
+        assert_eq!(round(&mut vm), 1);
+
+        assert!(vm.set_thread_enabled(1, false));
+        assert_eq!(
+            vm.threads(),
+            vec![ThreadInfo {
+                id: 1,
+                pc: 10,
+                enabled: false
+            }]
+        );
+        assert_eq!(round(&mut vm), 1);
+        assert_eq!(round(&mut vm), 1);
+
+        assert!(vm.set_thread_enabled(1, true));
+        assert_eq!(round(&mut vm), 2);
+        assert!(!vm.set_thread_enabled(2, true));
+        assert!(!vm.set_thread_enabled(VM_NUM_THREADS, true));
+    }
+
+    /// Pausing a thread must not capture its position: the thread may still run during the
+    /// current round, or be moved by `setvec`, and must resume from where it is at the end of the
+    /// round. A thread that stops during the round it is paused must not come back either.
+    #[test]
+    fn test_pause_keeps_position() {
+        let scene = &scenes::SCENES[1];
+        #[rustfmt::skip]
+        let mut code = vec![
+            // Round 1: setvec thread 1, 0x0040, break
+            0x08, 0x01, 0x00, 0x40, 0x06,
+            // Round 2: setvec thread 1, 0x0048, pause thread 1, break
+            0x08, 0x01, 0x00, 0x48, 0x0c, 0x01, 0x01, 0x01, 0x06,
+            // Round 3: resume thread 1, break
+            0x0c, 0x01, 0x01, 0x00, 0x06,
+            // Round 4: setvec thread 1, 0x0050, break
+            0x08, 0x01, 0x00, 0x50, 0x06,
+            // Round 5: pause thread 1, break
+            0x0c, 0x01, 0x01, 0x01, 0x06,
+            // Round 6: resume thread 1, break
+            0x0c, 0x01, 0x01, 0x00, 0x06,
+            // Then: break, jmp 0x0022
+            0x06, 0x07, 0x00, 0x22,
+        ];
+        code.resize(0x40, 0);
+        #[rustfmt::skip]
+        code.extend([
+            // 0x40: add v1, 1, break, jmp 0x0040
+            0x03, 0x01, 0x00, 0x01, 0x06, 0x07, 0x00, 0x40,
+            // 0x48: add v2, 1, break, jmp 0x0048
+            0x03, 0x02, 0x00, 0x01, 0x06, 0x07, 0x00, 0x48,
+            // 0x50: killthread
+            0x11,
+        ]);
+        let mut vm = Vm::new_with_resman(ResourceManager::new_in_memory(vec![
+            (scene.palette, ResType::Palette, vec![0; 2048]),
+            (scene.code, ResType::Bytecode, code),
+        ]));
+        vm.request_scene(1);
+        let round = |vm: &mut Vm| {
+            vm.process_round(&mut NullGfx::default(), &mut audio::NullAudio)
+                .unwrap();
+            vm.threads().into_iter().find(|t| t.id == 1)
+        };
+
+        assert_eq!(
+            round(&mut vm),
+            Some(ThreadInfo {
+                id: 1,
+                pc: 0x40,
+                enabled: true
+            })
+        );
+        // Thread 1 runs once more at 0x40 after being paused, but is then paused where setvec
+        // moved it.
+        assert_eq!(
+            round(&mut vm),
+            Some(ThreadInfo {
+                id: 1,
+                pc: 0x48,
+                enabled: false
+            })
+        );
+        assert_eq!(vm.get_reg(1), 1);
+        assert_eq!(
+            round(&mut vm),
+            Some(ThreadInfo {
+                id: 1,
+                pc: 0x48,
+                enabled: true
+            })
+        );
+        assert_eq!(vm.get_reg(2), 0);
+        round(&mut vm);
+        assert_eq!(vm.get_reg(2), 1);
+        // Thread 1 is paused while it kills itself: it stays inactive.
+        assert_eq!(round(&mut vm), None);
+        assert_eq!(round(&mut vm), None);
+        assert_eq!(vm.get_reg(1), 1);
+    }
+
+    /// Output shared with the test, as the VM takes ownership of its trace.
+    #[derive(Clone, Default)]
+    struct SharedOutput(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+    impl std::io::Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_trace_thread() {
+        let scene = &scenes::SCENES[1];
+        #[rustfmt::skip]
+        let mut code = vec![
+            // setvec thread 1, 0x0010
+            0x08, 0x01, 0x00, 0x10,
+            // 0x04: break, jmp 0x0004
+            0x06, 0x07, 0x00, 0x04,
+        ];
+        code.resize(0x10, 0);
+        #[rustfmt::skip]
+        code.extend([
+            // 0x10: addi v1, 2, break, killthread
+            0x03, 0x01, 0x00, 0x02, 0x06, 0x11,
+        ]);
+        let out = SharedOutput::default();
+        let mut vm = VmBuilder::new()
+            .resources(ResourceManager::new_in_memory(vec![
+                (scene.palette, ResType::Palette, vec![0; 2048]),
+                (scene.code, ResType::Bytecode, code),
+            ]))
+            .trace_thread(1, Box::new(out.clone()))
+            .build()
+            .unwrap();
+        vm.request_scene(1);
+        for _ in 0..4 {
+            vm.process_round(&mut NullGfx::default(), &mut audio::NullAudio)
+                .unwrap();
+        }
+
+        // Only the instructions of thread 1 are logged.
+        assert_eq!(
+            String::from_utf8(out.0.borrow().clone()).unwrap(),
+            "round 1 thread 01 0x0010: addi v0x01, 2\n\
+             \x20   v0x01: 0 -> 2\n\
+             round 1 thread 01 0x0014: break\n\
+             round 2 thread 01 0x0015: killthread\n"
+        );
+    }
+
+    #[test]
+    fn test_probe() {
+        let mut presented = Vec::new();
+        let (vm, _) = testing::run_scene_with_probe(
+            NullGfx::default(),
+            testing::test_scene_resources(),
+            testing::TEST_SCENE,
+            3,
+            &mut |vm: &Vm, round| {
+                assert_eq!(vm.round(), round);
+                presented.push(vm.frame_presented());
+            },
+        );
+
+        // The test scene displays its only frame and then kills its thread.
+        assert_eq!(presented, vec![true, false, false]);
+        assert_eq!(vm.front_buffer(), 2);
+    }
+
+    #[test]
+    fn test_restore_and_replay() {
+        let scene = &scenes::SCENES[1];
+        #[rustfmt::skip]
+        let code = vec![
+            // fillvideopage 1, color 2, then break
+            0x0e, 0x01, 0x02, 0x06,
+            // fillvideopage 1, color 3, then break
+            0x0e, 0x01, 0x03, 0x06,
+            // fillvideopage 1, color 4, then break
+            0x0e, 0x01, 0x04, 0x06,
+            // killthread
+            0x11,
+        ];
+        let mut vm = Vm::new_with_resman(ResourceManager::new_in_memory(vec![
+            (scene.palette, ResType::Palette, vec![0; 2048]),
+            (scene.code, ResType::Bytecode, code),
+        ]));
+        let mut gfx = NullGfx::default();
+        vm.request_scene(1);
+        vm.process_round(&mut gfx, &mut audio::NullAudio).unwrap();
+
+        let snapshot = VmSnapshot::new(&vm, &gfx);
+        let mut rounds = Vec::new();
+        for _ in 0..2 {
+            rounds.push(vm.take_snapshot());
+            vm.process_round(&mut gfx, &mut audio::NullAudio).unwrap();
+        }
+        assert_eq!(gfx.last_fill, Some(4));
+
+        // Go back to the state before the last round.
+        let target = rounds.pop().unwrap();
+        snapshot
+            .restore_and_replay(&rounds, &mut vm, &mut gfx, &mut audio::NullAudio)
+            .unwrap();
+        vm.restore_snapshot(&target);
+        assert_eq!(gfx.last_fill, Some(3));
+
+        // Running the last round again gives the same result as originally.
+        vm.process_round(&mut gfx, &mut audio::NullAudio).unwrap();
+        assert_eq!(gfx.last_fill, Some(4));
+    }
+}
@@ -0,0 +1,127 @@
+//! Construction of a [`Vm`] with some of its dependencies given instead of loaded from the
+//! current directory.
+
+use std::io::Result;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use super::ThreadTrace;
+use super::Vm;
+use super::DEFAULT_INSTRUCTION_BUDGET;
+use super::DEFAULT_RANDOM_SEED;
+use crate::res::ResourceManager;
+use crate::strings;
+use crate::strings::GameStrings;
+
+/// Builder for a [`Vm`]. Everything that is not given is loaded or set the same way as
+/// [`Vm::new`] does.
+#[derive(Default)]
+pub struct VmBuilder {
+    data_dir: Option<PathBuf>,
+    resman: Option<ResourceManager>,
+    strings: Option<GameStrings>,
+    seed: Option<u16>,
+    instruction_budget: Option<usize>,
+    thread_trace: Option<ThreadTrace>,
+}
+
+impl VmBuilder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Load the game data from `data_dir` instead of the current directory.
+    pub fn data_dir(mut self, data_dir: impl Into<PathBuf>) -> Self {
+        self.data_dir = Some(data_dir.into());
+        self
+    }
+
+    /// Load the resources from `resman`. This takes precedence over [`VmBuilder::data_dir`].
+    pub fn resources(mut self, resman: ResourceManager) -> Self {
+        self.resman = Some(resman);
+        self
+    }
+
+    /// Use `strings` as the game's text instead of the English strings.
+    pub fn strings(mut self, strings: GameStrings) -> Self {
+        self.strings = Some(strings);
+        self
+    }
+
+    /// Seed the game's random number generator with `seed`.
+    pub fn seed(mut self, seed: u16) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Stop threads that run more than `budget` instructions in a single round, as they are
+    /// likely stuck in a loop.
+    pub fn instruction_budget(mut self, budget: usize) -> Self {
+        self.instruction_budget = Some(budget);
+        self
+    }
+
+    /// Log every instruction run by `thread`, along with the registers it changes, into `out`.
+    pub fn trace_thread(mut self, thread: usize, out: Box<dyn Write>) -> Self {
+        self.thread_trace = Some(ThreadTrace::new(thread, out));
+        self
+    }
+
+    /// Build the VM, or return an error if the game data could not be found.
+    pub fn build(self) -> Result<Vm> {
+        let resman = match self.resman {
+            Some(resman) => resman,
+            None => ResourceManager::new_in_dir(
+                self.data_dir.as_deref().unwrap_or(Path::new(".")),
+                true,
+            )?,
+        };
+        let strings = self
+            .strings
+            .unwrap_or_else(|| strings::load_strings(strings::DEFAULT_LANG).unwrap_or_default());
+
+        let mut vm = Vm::from_parts(
+            resman,
+            strings,
+            self.seed.unwrap_or(DEFAULT_RANDOM_SEED),
+            self.instruction_budget
+                .unwrap_or(DEFAULT_INSTRUCTION_BUDGET),
+        );
+        vm.thread_trace = self.thread_trace;
+
+        Ok(vm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::VM_VARIABLE_RANDOM_SEED;
+
+    #[test]
+    fn test_build() {
+        let vm = VmBuilder::new()
+            .resources(ResourceManager::new_in_memory(vec![]))
+            .strings(GameStrings::default())
+            .seed(0x1234)
+            .build()
+            .unwrap();
+        assert_eq!(vm.get_reg(VM_VARIABLE_RANDOM_SEED), 0x1234);
+        assert_eq!(vm.sys.strings.get_all().count(), 0);
+
+        let vm = VmBuilder::new()
+            .resources(ResourceManager::new_in_memory(vec![]))
+            .build()
+            .unwrap();
+        assert_eq!(
+            vm.get_reg(VM_VARIABLE_RANDOM_SEED),
+            DEFAULT_RANDOM_SEED as i16
+        );
+    }
+
+    #[test]
+    fn test_missing_data_dir() {
+        assert!(VmBuilder::new().data_dir("/nonexistent").build().is_err());
+    }
+}
@@ -0,0 +1,317 @@
+//! Checkpoints saved when the game moves to a new scene, so it can be continued from there later.
+//!
+//! Scene transitions are natural restart points: all the threads are reset and the scene's code
+//! and resources reloaded, so the scene number and the registers are all we need to save. A small
+//! picture of the screen can be saved along with them, to preview the checkpoint.
+
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use byteorder::ReadBytesExt;
+use byteorder::WriteBytesExt;
+use byteorder::BE;
+use tracing::warn;
+
+use super::VM_NUM_VARIABLES;
+use crate::gfx::RgbFrame;
+use crate::gfx::SCREEN_RESOLUTION;
+use crate::scenes::SCENES;
+
+const MAGIC: &[u8; 4] = b"AWCP";
+/// Version 2 adds the thumbnail. Version 1 files are still read.
+const VERSION: u8 = 2;
+/// Dimensions of the thumbnails, a quarter of the game screen's.
+const THUMBNAIL_SIZE: [usize; 2] = [SCREEN_RESOLUTION[0] / 4, SCREEN_RESOLUTION[1] / 4];
+const THUMBNAIL_LEN: usize = THUMBNAIL_SIZE[0] * THUMBNAIL_SIZE[1] * 3;
+/// Number of checkpoint files kept. The oldest one is replaced when a new checkpoint is saved.
+const NUM_CHECKPOINT_FILES: usize = 3;
+
+/// State of the VM when it starts a scene.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub(super) scene: usize,
+    pub(super) regs: [i16; VM_NUM_VARIABLES],
+    /// RGB picture of the screen at `THUMBNAIL_SIZE`, if one has been set.
+    pub(super) thumbnail: Option<Vec<u8>>,
+}
+
+fn checkpoint_path(dir: &Path, slot: usize) -> PathBuf {
+    dir.join(format!("checkpoint-{}.sav", slot))
+}
+
+impl Checkpoint {
+    /// Returns the scene this checkpoint starts.
+    pub fn scene(&self) -> usize {
+        self.scene
+    }
+
+    /// Set the thumbnail of the checkpoint from `frame`, the screen as displayed. Each pixel of
+    /// the thumbnail is the average of the pixels of `frame` it covers.
+    pub fn set_thumbnail(&mut self, frame: &RgbFrame) {
+        let [width, height] = THUMBNAIL_SIZE;
+        if frame.width < width
+            || frame.height < height
+            || frame.pixels.len() != frame.width * frame.height * 3
+        {
+            warn!(
+                "Unexpected frame of {}x{} pixels, not creating a thumbnail",
+                frame.width, frame.height
+            );
+            return;
+        }
+
+        let mut thumbnail = Vec::with_capacity(THUMBNAIL_LEN);
+        for y in 0..height {
+            let lines = y * frame.height / height..(y + 1) * frame.height / height;
+            for x in 0..width {
+                let columns = x * frame.width / width..(x + 1) * frame.width / width;
+                let mut sum = [0usize; 3];
+                for line in frame.pixels[lines.start * frame.width * 3..lines.end * frame.width * 3]
+                    .chunks_exact(frame.width * 3)
+                {
+                    for pixel in line[columns.start * 3..columns.end * 3].chunks_exact(3) {
+                        for (sum, &c) in sum.iter_mut().zip(pixel) {
+                            *sum += c as usize;
+                        }
+                    }
+                }
+                let num_pixels = lines.len() * columns.len();
+                thumbnail.extend(sum.map(|sum| (sum / num_pixels) as u8));
+            }
+        }
+
+        self.thumbnail = Some(thumbnail);
+    }
+
+    /// Write the checkpoint, numbered `sequence`, into `w`.
+    fn write<W: Write>(&self, sequence: u32, w: &mut W) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_u8(VERSION)?;
+        w.write_u32::<BE>(sequence)?;
+        w.write_u8(self.scene as u8)?;
+        for reg in self.regs {
+            w.write_i16::<BE>(reg)?;
+        }
+        match &self.thumbnail {
+            None => w.write_u8(0)?,
+            Some(thumbnail) => {
+                w.write_u8(1)?;
+                w.write_all(thumbnail)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read a checkpoint written by `write`, and return it along with its sequence number.
+    fn read<R: Read>(r: &mut R) -> io::Result<(u32, Self)> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a checkpoint file"));
+        }
+        let version = r.read_u8()?;
+        if !(1..=VERSION).contains(&version) {
+            return Err(invalid("unsupported checkpoint version"));
+        }
+        let sequence = r.read_u32::<BE>()?;
+        let scene = r.read_u8()? as usize;
+        if scene >= SCENES.len() {
+            return Err(invalid("invalid scene number"));
+        }
+        let mut regs = [0; VM_NUM_VARIABLES];
+        r.read_i16_into::<BE>(&mut regs)?;
+        let thumbnail = match version {
+            1 => None,
+            _ => match r.read_u8()? {
+                0 => None,
+                _ => {
+                    let mut thumbnail = vec![0u8; THUMBNAIL_LEN];
+                    r.read_exact(&mut thumbnail)?;
+                    Some(thumbnail)
+                }
+            },
+        };
+
+        Ok((
+            sequence,
+            Self {
+                scene,
+                regs,
+                thumbnail,
+            },
+        ))
+    }
+
+    /// Read all the checkpoint files present in `dir`, with their sequence numbers. Corrupt files
+    /// are skipped.
+    fn read_all(dir: &Path) -> io::Result<Vec<(u32, Self)>> {
+        let mut checkpoints = Vec::new();
+        for slot in 0..NUM_CHECKPOINT_FILES {
+            let path = checkpoint_path(dir, slot);
+            let file = match File::open(&path) {
+                Ok(file) => file,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            match Self::read(&mut BufReader::new(file)) {
+                Ok(checkpoint) => checkpoints.push(checkpoint),
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof
+                    ) =>
+                {
+                    warn!("Ignoring corrupt checkpoint {}: {}", path.display(), e)
+                }
+                Err(e) => {
+                    return Err(io::Error::new(
+                        e.kind(),
+                        format!("{}: {}", path.display(), e),
+                    ))
+                }
+            }
+        }
+
+        Ok(checkpoints)
+    }
+
+    /// Save the checkpoint into `dir`, replacing the oldest checkpoint file if they are all used.
+    ///
+    /// Returns the path of the file written.
+    pub fn save(&self, dir: &Path) -> io::Result<PathBuf> {
+        let sequence = Self::read_all(dir)?
+            .iter()
+            .map(|(sequence, _)| sequence + 1)
+            .max()
+            .unwrap_or(0);
+        let path = checkpoint_path(dir, sequence as usize % NUM_CHECKPOINT_FILES);
+
+        let mut w = BufWriter::new(File::create(&path)?);
+        self.write(sequence, &mut w)?;
+        w.flush()?;
+
+        Ok(path)
+    }
+
+    /// Load the most recent checkpoint saved in `dir`, if there is any.
+    pub fn load_latest(dir: &Path) -> io::Result<Option<Self>> {
+        Ok(Self::read_all(dir)?
+            .into_iter()
+            .max_by_key(|(sequence, _)| *sequence)
+            .map(|(_, checkpoint)| checkpoint))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checkpoint(scene: usize) -> Checkpoint {
+        let mut regs = [0; VM_NUM_VARIABLES];
+        for (i, reg) in regs.iter_mut().enumerate() {
+            *reg = (i as i16) * -3;
+        }
+        Checkpoint {
+            scene,
+            regs,
+            thumbnail: None,
+        }
+    }
+
+    #[test]
+    fn test_read_write() {
+        let mut data = Vec::new();
+        checkpoint(4).write(12, &mut data).unwrap();
+        assert_eq!(data.len(), 11 + VM_NUM_VARIABLES * 2);
+
+        let (sequence, read) = Checkpoint::read(&mut &data[..]).unwrap();
+        assert_eq!(sequence, 12);
+        assert_eq!(read, checkpoint(4));
+
+        // Version 1 files have no thumbnail.
+        data[4] = 1;
+        let (_, read) = Checkpoint::read(&mut &data[..data.len() - 1]).unwrap();
+        assert_eq!(read, checkpoint(4));
+
+        data[0] = b'X';
+        assert!(Checkpoint::read(&mut &data[..]).is_err());
+    }
+
+    #[test]
+    fn test_thumbnail() {
+        // Left half black, right half with a different color on even and odd lines.
+        let [width, height] = SCREEN_RESOLUTION;
+        let frame = RgbFrame {
+            width,
+            height,
+            pixels: (0..height)
+                .flat_map(|y| {
+                    (0..width).flat_map(move |x| match (x < width / 2, y % 2) {
+                        (true, _) => [0, 0, 0],
+                        (false, 0) => [100, 200, 0],
+                        (false, _) => [200, 100, 10],
+                    })
+                })
+                .collect(),
+        };
+
+        let mut checkpoint = checkpoint(2);
+        checkpoint.set_thumbnail(&frame);
+        let thumbnail = checkpoint.thumbnail.as_ref().unwrap();
+        assert_eq!(thumbnail.len(), THUMBNAIL_LEN);
+        assert_eq!(&thumbnail[0..3], &[0, 0, 0]);
+        assert_eq!(&thumbnail[THUMBNAIL_LEN - 3..], &[150, 150, 5]);
+
+        let mut data = Vec::new();
+        checkpoint.write(0, &mut data).unwrap();
+        assert_eq!(Checkpoint::read(&mut &data[..]).unwrap().1, checkpoint);
+
+        // Inconsistent frames are ignored.
+        let mut other = self::checkpoint(2);
+        other.set_thumbnail(&RgbFrame {
+            width: width * 2,
+            ..frame
+        });
+        assert_eq!(other.thumbnail, None);
+    }
+
+    #[test]
+    fn test_rotation() {
+        let dir = std::env::temp_dir().join(format!("awer-checkpoints-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        assert_eq!(Checkpoint::load_latest(&dir).unwrap(), None);
+        for scene in 0..5 {
+            checkpoint(scene).save(&dir).unwrap();
+            assert_eq!(
+                Checkpoint::load_latest(&dir).unwrap(),
+                Some(checkpoint(scene))
+            );
+        }
+        // Only the last checkpoints are kept.
+        let mut scenes = Checkpoint::read_all(&dir)
+            .unwrap()
+            .into_iter()
+            .map(|(_, c)| c.scene)
+            .collect::<Vec<_>>();
+        scenes.sort();
+        assert_eq!(scenes, vec![2, 3, 4]);
+
+        // Corrupt files are skipped, and replaced in turn.
+        std::fs::write(checkpoint_path(&dir, 1), b"AWCP\x02").unwrap();
+        assert_eq!(Checkpoint::load_latest(&dir).unwrap(), Some(checkpoint(3)));
+        checkpoint(5).save(&dir).unwrap();
+        assert_eq!(Checkpoint::load_latest(&dir).unwrap(), Some(checkpoint(5)));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
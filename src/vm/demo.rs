@@ -0,0 +1,337 @@
+//! Demos: recordings of the input given to the game, which can be played back to reproduce a
+//! playthrough.
+//!
+//! The VM is deterministic, so its state when the recording started and the input it received
+//! at every round are enough to play the game again exactly the same way. The sound
+//! synchronization register, which the music player sets from outside the VM, is recorded along
+//! with the input.
+
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+
+use byteorder::ReadBytesExt;
+use byteorder::WriteBytesExt;
+use byteorder::BE;
+
+use super::Checkpoint;
+use super::Vm;
+use super::VM_NUM_VARIABLES;
+use super::VM_VARIABLE_SND_SYNC;
+use crate::audio;
+use crate::gfx;
+use crate::input::ButtonState;
+use crate::input::InputState;
+use crate::input::LeftRightDir;
+use crate::input::UpDownDir;
+use crate::scenes::SCENES;
+
+const MAGIC: &[u8; 4] = b"AWDM";
+const VERSION: u8 = 1;
+
+const INPUT_RIGHT: u8 = 0x1;
+const INPUT_LEFT: u8 = 0x2;
+const INPUT_DOWN: u8 = 0x4;
+const INPUT_UP: u8 = 0x8;
+const INPUT_ACTION: u8 = 0x80;
+
+/// External input received by the VM before playing a round.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct DemoRound {
+    /// Controls of the hero, as a combination of the `INPUT_*` bits.
+    input: u8,
+    /// Value of the sound synchronization register.
+    sound_sync: i16,
+}
+
+impl DemoRound {
+    fn input_state(&self) -> InputState {
+        InputState {
+            horizontal: match self.input & (INPUT_LEFT | INPUT_RIGHT) {
+                INPUT_LEFT => LeftRightDir::Left,
+                INPUT_RIGHT => LeftRightDir::Right,
+                _ => LeftRightDir::Neutral,
+            },
+            vertical: match self.input & (INPUT_UP | INPUT_DOWN) {
+                INPUT_UP => UpDownDir::Up,
+                INPUT_DOWN => UpDownDir::Down,
+                _ => UpDownDir::Neutral,
+            },
+            button: match self.input & INPUT_ACTION {
+                0 => ButtonState::Released,
+                _ => ButtonState::Pushed,
+            },
+        }
+    }
+}
+
+/// Recording of a game, starting when the VM is about to load a scene.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Demo {
+    start: Checkpoint,
+    rounds: Vec<DemoRound>,
+}
+
+impl Demo {
+    /// Start recording a demo from the current state of `vm`.
+    ///
+    /// Returns `None` if `vm` is not about to load a scene, as the demo could not be played back
+    /// from an arbitrary point of a scene.
+    pub fn new(vm: &Vm) -> Option<Self> {
+        Some(Demo {
+            start: Checkpoint {
+                scene: vm.state.requested_scene?,
+                regs: vm.state.regs,
+                thumbnail: None,
+            },
+            rounds: Vec::new(),
+        })
+    }
+
+    /// Record the input of the round `vm` is about to play.
+    pub fn record_round(&mut self, vm: &Vm) {
+        self.rounds.push(DemoRound {
+            input: vm.hero_state().mask,
+            sound_sync: vm.get_reg(VM_VARIABLE_SND_SYNC),
+        });
+    }
+
+    /// Returns the number of rounds recorded.
+    pub fn num_rounds(&self) -> usize {
+        self.rounds.len()
+    }
+
+    /// Bring `vm` to the state the demo starts from.
+    pub fn start(&self, vm: &mut Vm) {
+        vm.restore_checkpoint(&self.start);
+    }
+
+    /// Give `vm` the input recorded for its `round`-th round since the start of the demo.
+    ///
+    /// Returns `false` if the demo has no such round.
+    pub fn apply_round(&self, round: usize, vm: &mut Vm) -> bool {
+        let Some(round) = self.rounds.get(round) else {
+            return false;
+        };
+        vm.update_input(&round.input_state());
+        vm.set_reg(VM_VARIABLE_SND_SYNC, round.sound_sync);
+
+        true
+    }
+
+    /// Play the demo from its start with `vm`, as fast as possible.
+    ///
+    /// Returns the number of rounds played, which is smaller than the number of rounds recorded
+    /// if the game ran out of threads to run.
+    pub fn play<G: gfx::Gfx + ?Sized, A: audio::Mixer + audio::MusicPlayer + ?Sized>(
+        &self,
+        vm: &mut Vm,
+        gfx: &mut G,
+        audio: &mut A,
+    ) -> io::Result<usize> {
+        self.start(vm);
+
+        for i in 0..self.rounds.len() {
+            self.apply_round(i, vm);
+            if !vm.process_round(gfx, audio)? {
+                return Ok(i + 1);
+            }
+        }
+
+        Ok(self.rounds.len())
+    }
+
+    fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_u8(VERSION)?;
+        w.write_u8(self.start.scene as u8)?;
+        for reg in self.start.regs {
+            w.write_i16::<BE>(reg)?;
+        }
+        w.write_u32::<BE>(self.rounds.len() as u32)?;
+        for round in &self.rounds {
+            w.write_u8(round.input)?;
+            w.write_i16::<BE>(round.sound_sync)?;
+        }
+
+        Ok(())
+    }
+
+    fn read<R: Read>(r: &mut R) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a demo file"));
+        }
+        if r.read_u8()? != VERSION {
+            return Err(invalid("unsupported demo version"));
+        }
+        let scene = r.read_u8()? as usize;
+        if scene >= SCENES.len() {
+            return Err(invalid("invalid scene number"));
+        }
+        let mut regs = [0; VM_NUM_VARIABLES];
+        r.read_i16_into::<BE>(&mut regs)?;
+
+        let num_rounds = r.read_u32::<BE>()?;
+        let rounds = (0..num_rounds)
+            .map(|_| {
+                Ok(DemoRound {
+                    input: r.read_u8()?,
+                    sound_sync: r.read_i16::<BE>()?,
+                })
+            })
+            .collect::<io::Result<_>>()?;
+
+        Ok(Demo {
+            start: Checkpoint {
+                scene,
+                regs,
+                thumbnail: None,
+            },
+            rounds,
+        })
+    }
+
+    /// Write the demo into the file at `path`.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write(&mut w)?;
+        w.flush()
+    }
+
+    /// Load the demo saved in the file at `path`.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Self::read(&mut BufReader::new(File::open(path)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::NullAudio;
+    use crate::gfx::headless::HeadlessGfx;
+    use crate::gfx::sw::RasterGameRenderer;
+    use crate::gfx::Display;
+    use crate::res::ResType;
+    use crate::res::ResourceManager;
+    use crate::vm::VmBuilder;
+
+    #[test]
+    fn test_record_and_read() {
+        let mut vm = Vm::new_with_resman(ResourceManager::new_in_memory(vec![]));
+        assert_eq!(Demo::new(&vm), None);
+
+        vm.request_scene(2);
+        let mut demo = Demo::new(&vm).unwrap();
+        for (input, sound_sync) in [(INPUT_LEFT | INPUT_ACTION, 0), (INPUT_DOWN, 4), (0, -1)] {
+            let round = DemoRound { input, sound_sync };
+            vm.update_input(&round.input_state());
+            vm.set_reg(VM_VARIABLE_SND_SYNC, sound_sync);
+            demo.record_round(&vm);
+            assert_eq!(demo.rounds.last(), Some(&round));
+        }
+
+        let mut data = Vec::new();
+        demo.write(&mut data).unwrap();
+        assert_eq!(Demo::read(&mut &data[..]).unwrap(), demo);
+
+        data.truncate(data.len() - 1);
+        assert!(Demo::read(&mut &data[..]).is_err());
+    }
+
+    /// Check that playing a demo twice from the same seed renders exactly the same frames.
+    #[test]
+    fn test_play_is_deterministic() {
+        let resources = || {
+            let scene = &SCENES[1];
+            #[rustfmt::skip]
+            let code = vec![
+                // setpalette 0
+                0x0b, 0x00, 0xff,
+                // set v0, v60 (random seed)
+                0x01, 0x00, 0x3c,
+                // selectvideopage 1
+                0x0d, 0x01,
+                // fillvideopage 1, color 2
+                0x0e, 0x01, 0x02,
+                // add v0, v252 (hero left/right)
+                0x02, 0x00, 0xfc,
+                // sprl: square at (v0, 80)
+                0x58, 0x00, 0x00, 0x00, 0x50,
+                // blitframebuffer 1
+                0x10, 0x01,
+                // break
+                0x06,
+                // jmp 0x0006
+                0x07, 0x00, 0x06,
+            ];
+            #[rustfmt::skip]
+            let polygons = vec![
+                // 40x40 square of color 5.
+                0xc5, 40, 40, 4, 40, 0, 40, 40, 0, 40, 0, 0,
+            ];
+            ResourceManager::new_in_memory(vec![
+                (
+                    scene.palette,
+                    ResType::Palette,
+                    (0..32).map(|i| i * 8).collect(),
+                ),
+                (scene.code, ResType::Bytecode, code),
+                (scene.video1, ResType::Cinematic, polygons),
+            ])
+        };
+        let new_vm = || {
+            VmBuilder::new()
+                .resources(resources())
+                .seed(100)
+                .build()
+                .unwrap()
+        };
+
+        let mut vm = new_vm();
+        vm.request_scene(1);
+        let mut demo = Demo::new(&vm).unwrap();
+        for input in [
+            INPUT_RIGHT,
+            INPUT_RIGHT,
+            0,
+            INPUT_LEFT | INPUT_ACTION,
+            INPUT_LEFT,
+        ] {
+            let round = DemoRound {
+                input,
+                sound_sync: 0,
+            };
+            vm.update_input(&round.input_state());
+            demo.record_round(&vm);
+        }
+
+        let play = || {
+            let mut vm = new_vm();
+            let mut gfx = HeadlessGfx::new(RasterGameRenderer::new());
+            demo.start(&mut vm);
+            let mut frames = Vec::new();
+            for round in 0.. {
+                if !demo.apply_round(round, &mut vm) {
+                    break;
+                }
+                assert!(vm.process_round(&mut gfx, &mut NullAudio).unwrap());
+                frames.push(gfx.read_framebuffer_rgb().unwrap());
+            }
+            frames
+        };
+
+        let frames = play();
+        assert_eq!(frames.len(), demo.num_rounds());
+        // The input moves the square around.
+        assert_ne!(frames[0], frames[1]);
+        assert_eq!(frames, play());
+    }
+}
@@ -0,0 +1,157 @@
+//! Decoding of the game's bytecode into readable instructions, for tracing what a thread runs.
+
+use std::io::Cursor;
+
+use byteorder::ReadBytesExt;
+use byteorder::BE;
+
+use super::opcode_name;
+
+/// Returns the text of register `id`.
+fn reg(id: u8) -> String {
+    format!("v0x{:02x}", id)
+}
+
+/// Decodes the instruction of `code` at `pc`.
+///
+/// Returns the text of the instruction and its length in bytes, or `None` if `code` ends before
+/// the instruction does.
+pub fn disassemble(code: &[u8], pc: usize) -> Option<(String, usize)> {
+    let mut cursor = Cursor::new(code.get(pc..)?);
+    let c = &mut cursor;
+    let u8_ = |c: &mut Cursor<&[u8]>| c.read_u8().ok();
+    let u16_ = |c: &mut Cursor<&[u8]>| c.read_u16::<BE>().ok();
+    let i16_ = |c: &mut Cursor<&[u8]>| c.read_i16::<BE>().ok();
+
+    let opcode = u8_(c)?;
+    let name = opcode_name(opcode);
+    let operands = match opcode {
+        op if op & 0x80 == 0x80 => {
+            let offset = ((((op & 0x7f) as u16) << 8) | u8_(c)? as u16) * 2;
+            format!("0x{:04x}, {}, {}", offset, u8_(c)?, u8_(c)?)
+        }
+        op if op & 0xc0 == 0x40 => {
+            let offset = u16_(c)? * 2;
+            let x = match op & 0x30 {
+                0x00 => i16_(c)?.to_string(),
+                0x10 => reg(u8_(c)?),
+                0x30 => (u8_(c)? as u16 + 0x100).to_string(),
+                _ => u8_(c)?.to_string(),
+            };
+            let y = match op & 0xc {
+                0x00 => i16_(c)?.to_string(),
+                0x04 => reg(u8_(c)?),
+                _ => u8_(c)?.to_string(),
+            };
+            match op & 0x3 {
+                0x1 => format!("0x{:04x}, {}, {}, zoom {}", offset, x, y, reg(u8_(c)?)),
+                0x2 => format!("0x{:04x}, {}, {}, zoom {}", offset, x, y, u8_(c)?),
+                0x3 => format!("0x{:04x}, {}, {}, video2", offset, x, y),
+                _ => format!("0x{:04x}, {}, {}", offset, x, y),
+            }
+        }
+        0x00 | 0x03 => format!("{}, {}", reg(u8_(c)?), i16_(c)?),
+        0x01 | 0x02 | 0x13 => format!("{}, {}", reg(u8_(c)?), reg(u8_(c)?)),
+        0x04 | 0x07 => format!("0x{:04x}", u16_(c)?),
+        0x05 | 0x06 | 0x11 => String::new(),
+        0x08 => format!("{}, 0x{:04x}", u8_(c)?, u16_(c)?),
+        0x09 => format!("{}, 0x{:04x}", reg(u8_(c)?), u16_(c)?),
+        0x0a => {
+            let op = u8_(c)?;
+            let b = reg(u8_(c)?);
+            let a = match op {
+                op if op & 0x80 != 0 => reg(u8_(c)?),
+                op if op & 0x40 != 0 => i16_(c)?.to_string(),
+                _ => u8_(c)?.to_string(),
+            };
+            let cmp = match op & 0x7 {
+                0 => "==",
+                1 => "!=",
+                2 => ">",
+                3 => ">=",
+                4 => "<",
+                5 => "<=",
+                _ => "??",
+            };
+            format!("{} {} {}, 0x{:04x}", b, cmp, a, u16_(c)?)
+        }
+        0x0b => {
+            let palette = u8_(c)?;
+            // Fade speed, unused.
+            u8_(c)?;
+            palette.to_string()
+        }
+        0x0c => {
+            let (first, last) = (u8_(c)?, u8_(c)?);
+            let op = match u8_(c)? {
+                0 => "activate",
+                1 => "pause",
+                2 => "reset",
+                _ => "??",
+            };
+            format!("{}..={}, {}", first, last, op)
+        }
+        0x0d | 0x10 => format!("0x{:02x}", u8_(c)?),
+        0x0e => format!("0x{:02x}, {}", u8_(c)?, u8_(c)?),
+        0x0f => format!("0x{:02x}, 0x{:02x}", u8_(c)?, u8_(c)?),
+        0x12 => format!("0x{:03x}, {}, {}, {}", u16_(c)?, u8_(c)?, u8_(c)?, u8_(c)?),
+        0x14 | 0x15 => format!("{}, 0x{:04x}", reg(u8_(c)?), u16_(c)?),
+        0x16 | 0x17 => format!("{}, {}", reg(u8_(c)?), u16_(c)?),
+        0x18 => format!("0x{:02x}, {}, {}, {}", u16_(c)?, u8_(c)?, u8_(c)?, u8_(c)?),
+        0x19 => format!("0x{:02x}", u16_(c)?),
+        0x1a => format!("0x{:02x}, {}, {}", u16_(c)?, u16_(c)?, u8_(c)?),
+        _ => format!("0x{:02x}", opcode),
+    };
+
+    let text = match operands.is_empty() {
+        true => name.to_string(),
+        false => format!("{} {}", name, operands),
+    };
+
+    Some((text, cursor.position() as usize))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disassemble() {
+        #[rustfmt::skip]
+        let code = [
+            // seti v0x3c, -2
+            0x00, 0x3c, 0xff, 0xfe,
+            // condjmp v0x01 >= v0x02, 0x0010
+            0x0a, 0x83, 0x01, 0x02, 0x00, 0x10,
+            // condjmp v0x01 < 300, 0x0010
+            0x0a, 0x44, 0x01, 0x01, 0x2c, 0x00, 0x10,
+            // resetthread 1..=3, pause
+            0x0c, 0x01, 0x03, 0x01,
+            // sprl 0x0020, v0x10, 80, zoom 64
+            0x5a, 0x00, 0x10, 0x10, 0x50, 0x40,
+            // break
+            0x06,
+            // Truncated jmp.
+            0x07, 0x00,
+        ];
+
+        let mut pc = 0;
+        let mut lines = Vec::new();
+        while let Some((text, len)) = disassemble(&code, pc) {
+            lines.push(text);
+            pc += len;
+        }
+        assert_eq!(
+            lines,
+            vec![
+                "seti v0x3c, -2",
+                "condjmp v0x01 >= v0x02, 0x0010",
+                "condjmp v0x01 < 300, 0x0010",
+                "resetthread 1..=3, pause",
+                "sprl 0x0020, v0x10, 80, zoom 64",
+                "break",
+            ]
+        );
+        assert_eq!(pc, code.len() - 2);
+    }
+}
@@ -10,6 +10,30 @@ use super::*;
 use crate::gfx::PolySegment;
 use crate::res;
 
+/// Operation only changing the state of the VM. Returns `true` if the current thread must yield.
+pub type StateOp = fn(u8, &mut Cursor<&[u8]>, &mut VmState) -> bool;
+
+/// Returns the state operation run for `opcode`, or `None` if `opcode` is not one.
+pub fn state_op(opcode: u8) -> Option<StateOp> {
+    match opcode {
+        0x00 => Some(op_seti),
+        0x01 => Some(op_set),
+        0x02 => Some(op_add),
+        0x03 => Some(op_addi),
+        0x07 => Some(op_jmp),
+        0x08 => Some(op_setvec),
+        0x09 => Some(op_jnz),
+        0x0a => Some(op_condjmp),
+        0x0c => Some(op_resetthread),
+        0x13 => Some(op_sub),
+        0x14 => Some(op_and),
+        0x15 => Some(op_or),
+        0x16 => Some(op_shl),
+        0x17 => Some(op_shr),
+        _ => None,
+    }
+}
+
 pub fn op_seti(_op: u8, cursor: &mut Cursor<&[u8]>, state: &mut VmState) -> bool {
     let var_id = cursor.read_u8().unwrap();
     let value = cursor.read_i16::<BE>().unwrap();
@@ -114,14 +138,8 @@ pub fn op_break(thread: &mut Thread, cursor: &mut Cursor<&[u8]>) -> bool {
 
 #[tracing::instrument(level = "trace", skip(thread))]
 fn r#break(thread: &mut Thread, pc: u64) {
+    // A pause requested by resetthread applies to the position set here.
     thread.state = ThreadState::Active(pc);
-    // Kind of a hack. We may have met a resetthread op that has set this
-    // thread to the Paused state, with its PC of that time. But now the thread
-    // has run some more and the PC is different - unless we update the PC of
-    // the requested state, we will play the same state again and again.
-    if let Some(ThreadState::Paused(_)) = thread.requested_state {
-        thread.requested_state = Some(ThreadState::Paused(pc));
-    }
 }
 
 pub fn op_jmp(_op: u8, cursor: &mut Cursor<&[u8]>, _state: &mut VmState) -> bool {
@@ -263,26 +281,14 @@ fn resetthread(state: &mut VmState, first_thread: u8, last_thread: u8, op: Reset
     }
 
     for thread in &mut state.threads[first_thread..=last_thread] {
-        let pc = match thread.state {
-            // Do not activate already active threads or the PC will be invalid.
-            ThreadState::Active(pc) if op != ResetThreadOp::Activate => pc,
-            // BUG: if a thread is active but has not run yet, and we pause
-            // it here, wouldn't the PC be the one pre-run?
-            ThreadState::Paused(pc) => pc,
-            _ => {
-                // We must switch the thread to inactive regardless of whether
-                // we have a PC or not!
-                if let ResetThreadOp::Reset = op {
-                    thread.requested_state = Some(ThreadState::Inactive);
-                }
-                continue;
-            }
-        };
-        thread.requested_state = Some(match op {
-            ResetThreadOp::Activate => ThreadState::Active(pc),
-            ResetThreadOp::Pause => ThreadState::Paused(pc),
-            ResetThreadOp::Reset => ThreadState::Inactive,
-        });
+        // Pausing and resuming do not capture the position of the thread: it may not have run yet
+        // during this cycle, or be moved by setvec, and must resume from where it is when the
+        // cycle ends.
+        match op {
+            ResetThreadOp::Activate => thread.requested_pause = Some(false),
+            ResetThreadOp::Pause => thread.requested_pause = Some(true),
+            ResetThreadOp::Reset => thread.requested_state = Some(ThreadState::Inactive),
+        }
     }
 }
 
@@ -329,11 +335,14 @@ pub fn op_shl(_op: u8, cursor: &mut Cursor<&[u8]>, state: &mut VmState) -> bool
     false
 }
 
+/// Shifts are logical, like in the original engine: registers are shifted as unsigned 16-bit
+/// values, so shifting right does not extend their sign, and shifting by 16 bits or more clears
+/// them.
 #[tracing::instrument(level = "trace", skip(state), fields(res))]
 fn shl(state: &mut VmState, var_id: u8, val: u16) {
     let dst_val = &mut state.regs[var_id as usize];
 
-    *dst_val <<= val;
+    *dst_val = (*dst_val as u16).checked_shl(val as u32).unwrap_or(0) as i16;
     tracing::span::Span::current().record("res", *dst_val);
 }
 
@@ -350,7 +359,8 @@ pub fn op_shr(_op: u8, cursor: &mut Cursor<&[u8]>, state: &mut VmState) -> bool
 fn shr(state: &mut VmState, var_id: u8, val: u16) {
     let dst_val = &mut state.regs[var_id as usize];
 
-    *dst_val >>= val;
+    // See `shl` for why the register is shifted as unsigned.
+    *dst_val = (*dst_val as u16).checked_shr(val as u32).unwrap_or(0) as i16;
     tracing::span::Span::current().record("res", *dst_val);
 }
 
@@ -473,7 +483,12 @@ fn fillvideopage<G: gfx::Gfx + ?Sized>(state: &mut VmState, gfx: &mut G, page_id
     let resolved_page_id = lookup_buffer(state, page_id);
     tracing::Span::current().record("resolved_page_id", resolved_page_id);
 
-    gfx.fillvideopage(resolved_page_id, color);
+    // Pages are made of 4-bit pixels, so the original game only keeps the lower nibble of the
+    // color.
+    if color > 0xf {
+        tracing::warn!("fillvideopage: color 0x{:x} is out of range", color);
+    }
+    gfx.fillvideopage(resolved_page_id, color & 0xf);
 }
 
 pub fn op_copyvideopage<G: gfx::Gfx + ?Sized>(
@@ -484,7 +499,11 @@ pub fn op_copyvideopage<G: gfx::Gfx + ?Sized>(
     gfx: &mut G,
 ) -> bool {
     // TODO: source buffer sometimes have bit 0x40 set. Why?
-    let src_page_id = cursor.read_u8().unwrap();
+    // Whatever its meaning, we need to discard it so the 0x80 vscroll form is recognized.
+    let src_page_id = match cursor.read_u8().unwrap() {
+        page_id @ 0xfe..=0xff => page_id,
+        page_id => page_id & !0x40,
+    };
     let dst_page_id = cursor.read_u8().unwrap();
     // Bit 0x80 indicates that we are interested in vscroll, only if we are
     // copying from a regular page.
@@ -552,6 +571,7 @@ fn blitframebuffer<G: gfx::Gfx + ?Sized>(state: &mut VmState, gfx: &mut G, page_
     state.front_buffer = resolved_page_id;
 
     gfx.blitframebuffer(state.front_buffer, &state.palette);
+    state.frame_presented = true;
 
     // TODO: this doesn't seem to ever be used?
     state.regs[VM_VARIABLE_SLICES_USED as usize] = 1;
@@ -571,7 +591,7 @@ pub fn op_drawstring<G: gfx::Gfx + ?Sized>(
     let y = cursor.read_u8().unwrap() as i16;
     let color = cursor.read_u8().unwrap();
 
-    let string = match sys.strings.get(&(string_id as usize)) {
+    let string = match sys.strings.get(string_id as usize) {
         None => {
             error!("cannot find string 0x{:04x}", string_id);
             return false;
@@ -592,23 +612,54 @@ fn drawstring<G: gfx::Gfx + ?Sized>(
     dst_page_id: usize,
     gfx: &mut G,
 ) {
+    for (pos, c) in layout_string(pos, string) {
+        gfx.draw_char(dst_page_id, pos, color, c);
+    }
+}
+
+/// Returns the position and character code of each character of `string` drawn from `pos`.
+///
+/// Characters are encoded in Latin-1 so extended fonts can cover them. The ones that have no
+/// Latin-1 code are replaced by [`FALLBACK_CHAR`](crate::font::FALLBACK_CHAR). Lines that do not
+/// fit on the screen are wrapped, and the ones below its bottom edge are dropped.
+fn layout_string(pos: (i16, i16), string: &str) -> Vec<((i16, i16), u8)> {
     use crate::font::CHAR_HEIGHT;
     use crate::font::CHAR_WIDTH;
+    use crate::font::FALLBACK_CHAR;
+    use crate::gfx::SCREEN_RESOLUTION;
+
     let (mut x, mut y) = pos;
+    let mut chars = Vec::with_capacity(string.len());
 
     for c in string.chars() {
-        match c {
-            '\n' => {
-                y += CHAR_HEIGHT as i16;
-                x = pos.0;
-            }
-            c if c.is_ascii() => {
-                gfx.draw_char(dst_page_id, (x, y), color, c as u8);
-                x += CHAR_WIDTH as i16;
-            }
-            c => error!("invalid non-ASCII character '{}' in string {}", c, string),
+        if c == '\n' {
+            y += CHAR_HEIGHT as i16;
+            x = pos.0;
+            continue;
+        }
+
+        // Wrap lines that would go past the right edge of the screen, unless they only have a
+        // single character.
+        if x > pos.0 && x + CHAR_WIDTH as i16 > SCREEN_RESOLUTION[0] as i16 {
+            y += CHAR_HEIGHT as i16;
+            x = pos.0;
+        }
+        if y >= SCREEN_RESOLUTION[1] as i16 {
+            break;
         }
+
+        let c = u8::try_from(c).unwrap_or_else(|_| {
+            warn!(
+                "character '{}' in string {:?} has no Latin-1 code",
+                c, string
+            );
+            FALLBACK_CHAR
+        });
+        chars.push(((x, y), c));
+        x += CHAR_WIDTH as i16;
     }
+
+    chars
 }
 
 const DEFAULT_ZOOM: u16 = 0x40;
@@ -625,8 +676,8 @@ pub fn op_sprs<G: gfx::Gfx + ?Sized>(
     let mut y = cursor.read_u8().unwrap() as i16;
 
     if y > 199 {
-        y = 199;
         x += y - 199;
+        y = 199;
     }
 
     gfx.draw_polygons(
@@ -707,10 +758,6 @@ fn playsound<A: audio::Mixer + ?Sized>(
     };
 }
 
-fn delay_to_tempo(delay: u16) -> usize {
-    delay as usize * 60 / 7050
-}
-
 pub fn op_playmusic<A: audio::Mixer + audio::MusicPlayer + ?Sized>(
     _op: u8,
     cursor: &mut Cursor<&[u8]>,
@@ -740,13 +787,11 @@ fn playmusic<A: audio::Mixer + audio::MusicPlayer + ?Sized>(
         (0, 0) => audio.stop_music(),
         // Update the playback speed.
         (0, new_delay) => {
-            let new_tempo = delay_to_tempo(new_delay);
+            let new_tempo = audio::delay_to_tempo(new_delay);
             audio.update_tempo(new_tempo);
         }
         // Load new music module and start playback.
         (res_id, delay) => match resman
-            // TODO: mmm we are probably preloading the music, right? In that case this should just
-            // retrieve it, or probably a Rc to it...
             .load_resource(res_id as usize)
             .ok()
             .and_then(|r| r.into_music())
@@ -755,19 +800,24 @@ fn playmusic<A: audio::Mixer + audio::MusicPlayer + ?Sized>(
                 error!("failed to obtain music resource 0x{:02x}", res_id);
             }
             Some(music) => {
-                // Take the default delay of the music if none is specified.
-                let delay = if delay == 0 {
-                    music.header.delay
-                } else {
-                    delay
-                };
-                let tempo = delay_to_tempo(delay);
+                let tempo = music_tempo(delay, &music);
                 audio.play_music(music, tempo, pos as u16)
             }
         },
     };
 }
 
+/// Returns the tempo to play `music` at when the game asks for `delay`.
+fn music_tempo(delay: u16, music: &audio::MusicModule) -> usize {
+    // Take the default delay of the music if none is specified.
+    let delay = if delay == 0 {
+        music.header.delay
+    } else {
+        delay
+    };
+    audio::delay_to_tempo(delay)
+}
+
 /// Asks the resource manager to load a resource from disk.
 ///
 /// This is apparently used to trigger the loading of sounds and musics at the beginning of a scene.
@@ -808,9 +858,10 @@ fn loadresource<G: gfx::Gfx + ?Sized, A: audio::Mixer + ?Sized>(
     let res_id = res_id as usize;
 
     // In the original game, this meant "free all memory". Since we don't have
-    // to manage memory ourselves, we don't need to do that - just stopping
-    // any activity is enough.
+    // to manage memory ourselves, we just drop the cached resources - stopping
+    // any activity should also be enough.
     if res_id == 0 {
+        resman.clear_cache();
         // TODO: just stop sound and music?
         warn!("op_loadresource(0) - not yet implemented!");
         return;
@@ -853,3 +904,308 @@ fn loadresource<G: gfx::Gfx + ?Sized, A: audio::Mixer + ?Sized>(
         _ => (),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::mem::size_of;
+
+    use super::*;
+    use crate::audio::MusicModule;
+    use crate::audio::MusicModuleHeader;
+    use crate::audio::MusicPattern;
+    use crate::font::FALLBACK_CHAR;
+
+    /// Run the state operations of `bytes` on `state`, dispatching them like the VM does, until
+    /// one makes the thread yield, an opcode that is not a state operation is met, or the end of
+    /// `bytes` is reached.
+    ///
+    /// Returns the position the code stopped at, which is `bytes.len()` if it ran to its end.
+    fn run_ops(bytes: &[u8], state: &mut VmState) -> u64 {
+        run_ops_with(bytes, state, |_, _, _| None)
+    }
+
+    /// Same as [`run_ops`], but also runs the polygon drawing operations, drawing into `gfx`.
+    fn run_draw_ops<G: gfx::Gfx>(bytes: &[u8], state: &mut VmState, gfx: &mut G) -> u64 {
+        let sys = VmSys {
+            palette: Vec::new(),
+            strings: Default::default(),
+        };
+        run_ops_with(bytes, state, |opcode, cursor, state| match opcode {
+            op if op & 0x80 == 0x80 => Some(op_sprs(op, cursor, state, &sys, gfx)),
+            op if op & 0xc0 == 0x40 => Some(op_sprl(op, cursor, state, &sys, gfx)),
+            _ => None,
+        })
+    }
+
+    /// Run the state operations of `bytes` like [`run_ops`] does, as well as the ones `other_op`
+    /// runs. `other_op` returns whether the thread must yield, or `None` if it does not handle the
+    /// opcode.
+    fn run_ops_with<F>(bytes: &[u8], state: &mut VmState, mut other_op: F) -> u64
+    where
+        F: FnMut(u8, &mut Cursor<&[u8]>, &mut VmState) -> Option<bool>,
+    {
+        let mut cursor = Cursor::new(bytes);
+        // Reading past the end moves the cursor back to the end, so check before reading.
+        while cursor.position() < bytes.len() as u64 {
+            let opcode = cursor.read_u8().unwrap();
+            let yields = match state_op(opcode) {
+                Some(op) => op(opcode, &mut cursor, state),
+                None => match other_op(opcode, &mut cursor, state) {
+                    Some(yields) => yields,
+                    None => return cursor.position() - 1,
+                },
+            };
+            if yields {
+                break;
+            }
+        }
+
+        cursor.position()
+    }
+
+    #[test]
+    fn test_condjmp() {
+        // Jump target, past the end of the code so we can tell whether the jump happened.
+        const TARGET: u64 = 0x100;
+        // Expected results of comparing 5 with 3, 5 and 7, for each comparison.
+        let comparisons = [
+            (0, [false, true, false]),
+            (1, [true, false, true]),
+            (2, [true, false, false]),
+            (3, [true, true, false]),
+            (4, [false, false, true]),
+            (5, [false, true, true]),
+        ];
+
+        for (cmp, results) in comparisons {
+            for (a, jumps) in [3i16, 5, 7].into_iter().zip(results) {
+                let mut state = VmState::test_default();
+                state.regs[1] = 5;
+                state.regs[2] = a;
+                let [a_hi, a_lo] = a.to_be_bytes();
+                // Compared with register 2, with a 16-bit value, and with an 8-bit value.
+                for code in [
+                    vec![0x0a, 0x80 | cmp, 0x01, 0x02, 0x01, 0x00],
+                    vec![0x0a, 0x40 | cmp, 0x01, a_hi, a_lo, 0x01, 0x00],
+                    vec![0x0a, cmp, 0x01, a_lo, 0x01, 0x00],
+                ] {
+                    let expected = if jumps { TARGET } else { code.len() as u64 };
+                    assert_eq!(
+                        run_ops(&code, &mut state),
+                        expected,
+                        "5 compared with {} using {:02x?}",
+                        a,
+                        code
+                    );
+                }
+            }
+        }
+
+        // 16-bit values are signed, 8-bit ones are not.
+        let mut state = VmState::test_default();
+        state.regs[1] = 5;
+        assert_eq!(
+            run_ops(&[0x0a, 0x42, 0x01, 0xfe, 0xd4, 0x01, 0x00], &mut state),
+            TARGET
+        );
+        assert_eq!(
+            run_ops(&[0x0a, 0x02, 0x01, 0xd4, 0x01, 0x00], &mut state),
+            6
+        );
+    }
+
+    #[test]
+    fn test_jnz() {
+        let mut state = VmState::test_default();
+        state.regs[0] = 2;
+        // The register is decremented, and the jump taken while it is not zero.
+        assert_eq!(run_ops(&[0x09, 0x00, 0x01, 0x00], &mut state), 0x100);
+        assert_eq!(state.regs[0], 1);
+        assert_eq!(run_ops(&[0x09, 0x00, 0x01, 0x00], &mut state), 4);
+        assert_eq!(state.regs[0], 0);
+
+        // Loop: addi v1, 1; jnz v0, 0x0000.
+        let mut state = VmState::test_default();
+        state.regs[0] = 3;
+        let code = [0x03, 0x01, 0x00, 0x01, 0x09, 0x00, 0x00, 0x00];
+        assert_eq!(run_ops(&code, &mut state), code.len() as u64);
+        assert_eq!(state.regs[0], 0);
+        assert_eq!(state.regs[1], 3);
+    }
+
+    #[test]
+    fn test_shifts() {
+        // Shift register 0 holding `value` left (0x16) or right (0x17) by `count`.
+        let shift = |opcode: u8, value: i16, count: u16| {
+            let mut state = VmState::test_default();
+            state.regs[0] = value;
+            let [count_hi, count_lo] = count.to_be_bytes();
+            run_ops(&[opcode, 0x00, count_hi, count_lo], &mut state);
+            state.regs[0]
+        };
+
+        for (value, count, left, right) in [
+            (0x1234, 0, 0x1234, 0x1234),
+            (1, 15, i16::MIN, 0),
+            (0x4000, 1, i16::MIN, 0x2000),
+            // Shifting by 16 bits or more clears the register.
+            (0x1234, 16, 0, 0),
+            (-1, 0xffff, 0, 0),
+            // Shifts are logical: the sign is not extended.
+            (-2, 1, -4, 0x7fff),
+            (i16::MIN, 15, 0, 1),
+        ] {
+            assert_eq!(shift(0x16, value, count), left, "{} << {}", value, count);
+            assert_eq!(shift(0x17, value, count), right, "{} >> {}", value, count);
+        }
+    }
+
+    #[test]
+    fn test_sprs_below_screen() {
+        use crate::gfx::headless::HeadlessGfx;
+        use crate::gfx::sw::RasterGameRenderer;
+        use crate::testing;
+
+        // Draw the 40x40 square of the test scene on page 0 at (100, y), and return the columns and
+        // lines covered by the pixels drawn.
+        let draw = |y: u8| {
+            let mut gfx = HeadlessGfx::new(RasterGameRenderer::new());
+            gfx.renderer
+                .init_from_scene(
+                    &testing::test_scene_resources(),
+                    &scenes::SCENES[testing::TEST_SCENE],
+                )
+                .unwrap();
+            let mut state = VmState::test_default();
+            assert_eq!(run_draw_ops(&[0x80, 0x00, 100, y], &mut state, &mut gfx), 4);
+            let page = gfx.renderer.get_buffer(0);
+            let pixels = page.pixels();
+            let x_range = (0..320)
+                .filter(|&x| (0..200).any(|y| pixels[y * 320 + x] != 0))
+                .collect::<Vec<_>>();
+            let y_range = (0..200)
+                .filter(|&y| (0..320).any(|x| pixels[y * 320 + x] != 0))
+                .collect::<Vec<_>>();
+            (
+                (x_range[0], *x_range.last().unwrap()),
+                (y_range[0], *y_range.last().unwrap()),
+            )
+        };
+
+        assert_eq!(draw(100), ((80, 120), (80, 119)));
+        // Positions below the screen are moved back to its last line, and right by as much.
+        assert_eq!(draw(219), ((100, 140), (179, 199)));
+    }
+
+    #[test]
+    fn test_resetthread() {
+        // Threads 0 to 2 are active, paused, and inactive, and thread 3 is active but outside of
+        // the range of threads changed.
+        let state = || {
+            let mut state = VmState::test_default();
+            state.threads[0].state = ThreadState::Active(0x10);
+            state.threads[1].state = ThreadState::Paused(0x20);
+            state.threads[3].state = ThreadState::Active(0x30);
+            state
+        };
+        let next = |state: &VmState| {
+            state.threads[0..4]
+                .iter()
+                .map(|t| t.next_state())
+                .collect::<Vec<_>>()
+        };
+
+        // Activating resumes the paused thread, and does not start inactive ones.
+        let mut activate = state();
+        assert_eq!(run_ops(&[0x0c, 0x00, 0x02, 0x00], &mut activate), 4);
+        assert_eq!(
+            next(&activate),
+            vec![
+                ThreadState::Active(0x10),
+                ThreadState::Active(0x20),
+                ThreadState::Inactive,
+                ThreadState::Active(0x30)
+            ]
+        );
+
+        let mut pause = state();
+        run_ops(&[0x0c, 0x00, 0x02, 0x01], &mut pause);
+        assert_eq!(
+            next(&pause),
+            vec![
+                ThreadState::Paused(0x10),
+                ThreadState::Paused(0x20),
+                ThreadState::Inactive,
+                ThreadState::Active(0x30)
+            ]
+        );
+
+        // Resetting makes all the threads inactive.
+        let mut reset = state();
+        run_ops(&[0x0c, 0x00, 0x02, 0x02], &mut reset);
+        assert_eq!(next(&reset)[0..3], [ThreadState::Inactive; 3]);
+
+        // A single thread can be changed, and the new state only applies to the next round.
+        let mut single = state();
+        run_ops(&[0x0c, 0x03, 0x03, 0x01], &mut single);
+        assert_eq!(next(&single)[3], ThreadState::Paused(0x30));
+        assert_eq!(single.threads[3].state, ThreadState::Active(0x30));
+
+        // A paused thread moved by setvec stays paused, whatever the order of the operations.
+        for code in [
+            [0x08, 0x01, 0x00, 0x50, 0x0c, 0x01, 0x01, 0x01],
+            [0x0c, 0x01, 0x01, 0x01, 0x08, 0x01, 0x00, 0x50],
+        ] {
+            let mut moved = state();
+            run_ops(&code, &mut moved);
+            assert_eq!(next(&moved)[1], ThreadState::Paused(0x50));
+        }
+    }
+
+    #[test]
+    fn test_layout_string() {
+        let chars = |pos, string| {
+            layout_string(pos, string)
+                .into_iter()
+                .map(|(pos, c)| (pos, c as char))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            chars((8, 10), "a\nbé€"),
+            vec![
+                ((8, 10), 'a'),
+                ((8, 18), 'b'),
+                ((16, 18), 'é'),
+                ((24, 18), FALLBACK_CHAR as char),
+            ]
+        );
+        // Long lines wrap at the right edge of the screen.
+        assert_eq!(
+            chars((304, 0), "abc"),
+            vec![((304, 0), 'a'), ((312, 0), 'b'), ((304, 8), 'c')]
+        );
+        // Lines below the bottom edge are dropped.
+        assert_eq!(
+            chars((0, 190), "a\nb\nc"),
+            vec![((0, 190), 'a'), ((0, 198), 'b')]
+        );
+    }
+
+    #[test]
+    fn test_music_tempo() {
+        // Module with a single silent pattern, which requests the given delay.
+        let music = |delay: u16| {
+            let mut data = vec![0u8; size_of::<MusicModuleHeader>() + size_of::<MusicPattern>()];
+            data[0..2].copy_from_slice(&delay.to_be_bytes());
+            unsafe { MusicModule::from_raw_resource(data) }
+        };
+
+        // No delay given by the game: the one of the module is used, even if it is zero.
+        assert_eq!(music_tempo(0, &music(0x4000)), 139);
+        assert_eq!(music_tempo(0, &music(0)), audio::MIN_TEMPO);
+        // The delay given by the game takes precedence, and is clamped as well.
+        assert_eq!(music_tempo(0x4000, &music(0)), 139);
+        assert_eq!(music_tempo(1, &music(0x4000)), audio::MIN_TEMPO);
+    }
+}
@@ -0,0 +1,340 @@
+//! Saving the complete state of the VM into files, and comparing saved states.
+//!
+//! Unlike checkpoints, which can only be taken when a scene starts, a state file captures the VM
+//! in the middle of a scene: registers, threads, which video buffers are used for rendering and
+//! display, and palette. The content of the video buffers is not saved. Comparing the states
+//! saved at the same round of two runs shows where they diverged.
+
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+
+use byteorder::ReadBytesExt;
+use byteorder::WriteBytesExt;
+use byteorder::BE;
+
+use super::Thread;
+use super::ThreadState;
+use super::Vm;
+use super::VmState;
+use super::VM_NUM_THREADS;
+use super::VM_NUM_VARIABLES;
+use crate::gfx::Palette;
+use crate::scenes::SCENES;
+
+const MAGIC: &[u8; 4] = b"AWVS";
+/// Version 2 adds the pause requested for threads. Version 1 files are still read.
+const VERSION: u8 = 2;
+/// Value written in place of a scene number when there is none.
+const NO_SCENE: u8 = 0xff;
+
+fn write_thread_state<W: Write>(state: ThreadState, w: &mut W) -> io::Result<()> {
+    let (tag, pc) = match state {
+        ThreadState::Inactive => (0, 0),
+        ThreadState::Active(pc) => (1, pc),
+        ThreadState::Paused(pc) => (2, pc),
+    };
+    w.write_u8(tag)?;
+    w.write_u64::<BE>(pc)
+}
+
+fn read_thread_state<R: Read>(r: &mut R) -> io::Result<ThreadState> {
+    let tag = r.read_u8()?;
+    let pc = r.read_u64::<BE>()?;
+    match tag {
+        0 => Ok(ThreadState::Inactive),
+        1 => Ok(ThreadState::Active(pc)),
+        2 => Ok(ThreadState::Paused(pc)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid thread state",
+        )),
+    }
+}
+
+fn write_scene<W: Write>(scene: Option<usize>, w: &mut W) -> io::Result<()> {
+    w.write_u8(scene.map(|s| s as u8).unwrap_or(NO_SCENE))
+}
+
+fn read_scene<R: Read>(r: &mut R) -> io::Result<Option<usize>> {
+    match r.read_u8()? {
+        NO_SCENE => Ok(None),
+        scene if (scene as usize) < SCENES.len() => Ok(Some(scene as usize)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid scene number",
+        )),
+    }
+}
+
+impl VmState {
+    fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_u8(VERSION)?;
+        for reg in self.regs {
+            w.write_i16::<BE>(reg)?;
+        }
+        for thread in &self.threads {
+            write_thread_state(thread.state, w)?;
+            match thread.requested_state {
+                None => w.write_u8(0)?,
+                Some(state) => {
+                    w.write_u8(1)?;
+                    write_thread_state(state, w)?;
+                }
+            }
+            w.write_u8(match thread.requested_pause {
+                None => 0,
+                Some(false) => 1,
+                Some(true) => 2,
+            })?;
+            w.write_u16::<BE>(thread.call_stack.len() as u16)?;
+            for &pc in &thread.call_stack {
+                w.write_u64::<BE>(pc)?;
+            }
+        }
+        write_scene(self.requested_scene, w)?;
+        write_scene(self.scene, w)?;
+        w.write_u8(self.render_buffer as u8)?;
+        w.write_u8(self.back_buffer as u8)?;
+        w.write_u8(self.front_buffer as u8)?;
+        w.write_all(&self.palette.raw())?;
+        w.write_u8(self.frame_presented as u8)?;
+
+        Ok(())
+    }
+
+    fn read<R: Read>(r: &mut R) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a VM state file"));
+        }
+        let version = r.read_u8()?;
+        if !(1..=VERSION).contains(&version) {
+            return Err(invalid("unsupported VM state version"));
+        }
+        let mut regs = [0; VM_NUM_VARIABLES];
+        r.read_i16_into::<BE>(&mut regs)?;
+        let mut threads = Vm::init_threads();
+        for thread in threads.iter_mut() {
+            thread.state = read_thread_state(r)?;
+            thread.requested_state = match r.read_u8()? {
+                0 => None,
+                _ => Some(read_thread_state(r)?),
+            };
+            thread.requested_pause = match version {
+                // Version 1 requested pauses along with the position to pause at.
+                1 => match thread.requested_state {
+                    Some(ThreadState::Paused(pc)) => {
+                        thread.requested_state = Some(ThreadState::Active(pc));
+                        Some(true)
+                    }
+                    Some(ThreadState::Active(_)) => Some(false),
+                    _ => None,
+                },
+                _ => match r.read_u8()? {
+                    0 => None,
+                    1 => Some(false),
+                    2 => Some(true),
+                    _ => return Err(invalid("invalid thread pause request")),
+                },
+            };
+            let depth = r.read_u16::<BE>()?;
+            thread.call_stack = (0..depth)
+                .map(|_| r.read_u64::<BE>())
+                .collect::<io::Result<_>>()?;
+        }
+        let requested_scene = read_scene(r)?;
+        let scene = read_scene(r)?;
+        let mut buffers = [0usize; 3];
+        for buffer in buffers.iter_mut() {
+            *buffer = r.read_u8()? as usize;
+            if *buffer > 3 {
+                return Err(invalid("invalid video buffer"));
+            }
+        }
+        let [render_buffer, back_buffer, front_buffer] = buffers;
+        let mut raw_palette = [0u8; 32];
+        r.read_exact(&mut raw_palette)?;
+        let mut palette = Palette::default();
+        palette.set(&raw_palette);
+        let frame_presented = r.read_u8()? != 0;
+
+        Ok(VmState {
+            regs,
+            threads,
+            requested_scene,
+            scene,
+            render_buffer,
+            back_buffer,
+            front_buffer,
+            palette,
+            frame_presented,
+        })
+    }
+
+    /// Write the state into the file at `path`.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write(&mut w)?;
+        w.flush()
+    }
+
+    /// Load the state saved in the file at `path`.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Self::read(&mut BufReader::new(File::open(path)?))
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+    }
+
+    /// Returns the differences between this state and `other`.
+    pub fn diff(&self, other: &VmState) -> StateDiff {
+        let mut others = Vec::new();
+        if self.scene != other.scene || self.requested_scene != other.requested_scene {
+            others.push("scene");
+        }
+        if (self.render_buffer, self.back_buffer, self.front_buffer)
+            != (other.render_buffer, other.back_buffer, other.front_buffer)
+        {
+            others.push("video buffers");
+        }
+        if self.palette.raw() != other.palette.raw() {
+            others.push("palette");
+        }
+        if self.frame_presented != other.frame_presented {
+            others.push("frame presented");
+        }
+
+        StateDiff {
+            regs: (0..VM_NUM_VARIABLES)
+                .filter(|&i| self.regs[i] != other.regs[i])
+                .map(|i| (i, self.regs[i], other.regs[i]))
+                .collect(),
+            threads: (0..VM_NUM_THREADS)
+                .filter(|&i| self.threads[i] != other.threads[i])
+                .map(|i| (i, self.threads[i].clone(), other.threads[i].clone()))
+                .collect(),
+            others,
+        }
+    }
+}
+
+/// Differences between two VM states, as returned by [`VmState::diff`].
+#[derive(Debug)]
+pub struct StateDiff {
+    /// Registers that differ, with their values in the first and second state.
+    pub regs: Vec<(usize, i16, i16)>,
+    /// Threads that differ, with their number and state in the first and second state.
+    pub threads: Vec<(usize, Thread, Thread)>,
+    /// Other parts of the state that differ.
+    pub others: Vec<&'static str>,
+}
+
+impl StateDiff {
+    /// Returns `true` if the two states are identical.
+    pub fn is_empty(&self) -> bool {
+        self.regs.is_empty() && self.threads.is_empty() && self.others.is_empty()
+    }
+}
+
+/// Describes `thread` in a few words, like "active at 0x0042, requested pause, call depth 1".
+fn describe_thread(thread: &Thread) -> String {
+    let state = |state: ThreadState| match state {
+        ThreadState::Inactive => "inactive".to_string(),
+        ThreadState::Active(pc) => format!("active at 0x{:04x}", pc),
+        ThreadState::Paused(pc) => format!("paused at 0x{:04x}", pc),
+    };
+    let mut text = state(thread.state);
+    if let Some(requested) = thread.requested_state {
+        text.push_str(&format!(", requested {}", state(requested)));
+    }
+    match thread.requested_pause {
+        Some(true) => text.push_str(", requested pause"),
+        Some(false) => text.push_str(", requested resume"),
+        None => (),
+    }
+    if !thread.call_stack.is_empty() {
+        text.push_str(&format!(", call depth {}", thread.call_stack.len()));
+    }
+
+    text
+}
+
+impl fmt::Display for StateDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &(i, a, b) in &self.regs {
+            writeln!(
+                f,
+                "register 0x{:02x}: {} -> {} ({:+})",
+                i,
+                a,
+                b,
+                b as i32 - a as i32
+            )?;
+        }
+        for (i, a, b) in &self.threads {
+            writeln!(
+                f,
+                "thread {:02}: {} -> {}",
+                i,
+                describe_thread(a),
+                describe_thread(b)
+            )?;
+        }
+        for other in &self.others {
+            writeln!(f, "{} differs", other)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::res::ResourceManager;
+    use crate::sys::Snapshotable;
+
+    #[test]
+    fn test_read_write_and_diff() {
+        let mut vm = Vm::new_with_resman(ResourceManager::new_in_memory(vec![]));
+        vm.request_scene(2);
+        vm.set_reg(0x10, -5);
+        vm.state.threads[3].state = ThreadState::Active(0x42);
+        vm.state.threads[3].requested_state = Some(ThreadState::Active(0x50));
+        vm.state.threads[3].requested_pause = Some(true);
+        vm.state.threads[3].call_stack = vec![0x10, 0x20];
+        vm.state.palette.set(&[0x12; 32]);
+        let state = vm.take_snapshot();
+
+        let mut data = Vec::new();
+        state.write(&mut data).unwrap();
+        let read = VmState::read(&mut &data[..]).unwrap();
+        assert!(state.diff(&read).is_empty());
+        assert_eq!(read.threads[3].call_stack, vec![0x10, 0x20]);
+
+        data.truncate(data.len() - 1);
+        assert!(VmState::read(&mut &data[..]).is_err());
+
+        vm.set_reg(0x10, -4);
+        vm.state.threads[5].state = ThreadState::Paused(0x100);
+        vm.state.front_buffer = 2;
+        let diff = state.diff(&vm.take_snapshot());
+        assert_eq!(diff.regs, vec![(0x10, -5, -4)]);
+        assert_eq!(diff.threads.len(), 1);
+        assert_eq!(diff.others, vec!["video buffers"]);
+        assert_eq!(
+            diff.to_string(),
+            "register 0x10: -5 -> -4 (+1)\n\
+             thread 05: inactive -> paused at 0x0100\n\
+             video buffers differs\n"
+        );
+    }
+}
@@ -0,0 +1,77 @@
+//! Log of the instructions run by a single thread, with the registers they change.
+//!
+//! The Chrome trace records every instruction of every thread, which makes it hard to follow what
+//! a single misbehaving thread does. This log only shows the instructions of the thread being
+//! looked at, decoded into text.
+
+use std::io;
+use std::io::Write;
+
+use tracing::warn;
+
+use super::disasm::disassemble;
+use super::VM_NUM_VARIABLES;
+
+/// Writes the instructions run by one thread into some output.
+pub struct ThreadTrace {
+    thread: usize,
+    out: Box<dyn Write>,
+    /// Registers before the last logged instruction, to tell which ones it changed.
+    regs: Option<[i16; VM_NUM_VARIABLES]>,
+}
+
+impl ThreadTrace {
+    /// Create a log of the instructions run by `thread`, written into `out`.
+    pub fn new(thread: usize, out: Box<dyn Write>) -> Self {
+        Self {
+            thread,
+            out,
+            regs: None,
+        }
+    }
+
+    /// Returns whether the instructions of `thread` are logged.
+    pub fn traces(&self, thread: usize) -> bool {
+        self.thread == thread
+    }
+
+    fn write(&mut self, line: std::fmt::Arguments) {
+        if let Err(e) = self.out.write_fmt(line) {
+            warn!("Cannot write the trace of thread {}: {}", self.thread, e);
+            // Do not warn again for every instruction.
+            self.out = Box::new(io::sink());
+        }
+    }
+
+    /// Log the registers changed by the last logged instruction, now that it has run.
+    pub fn log_changes(&mut self, regs: &[i16; VM_NUM_VARIABLES]) {
+        let Some(before) = self.regs.take() else {
+            return;
+        };
+        for (i, (&a, &b)) in before.iter().zip(regs).enumerate() {
+            if a != b {
+                self.write(format_args!("    v0x{:02x}: {} -> {}\n", i, a, b));
+            }
+        }
+    }
+
+    /// Log the instruction of `code` at `pc`, which is about to run during `round` with `regs` as
+    /// the registers.
+    pub fn log_instruction(
+        &mut self,
+        round: u64,
+        code: &[u8],
+        pc: u64,
+        regs: &[i16; VM_NUM_VARIABLES],
+    ) {
+        let text = disassemble(code, pc as usize)
+            .map(|(text, _)| text)
+            .unwrap_or_else(|| "<end of code>".to_string());
+        let thread = self.thread;
+        self.write(format_args!(
+            "round {} thread {:02} 0x{:04x}: {}\n",
+            round, thread, pc, text
+        ));
+        self.regs = Some(*regs);
+    }
+}